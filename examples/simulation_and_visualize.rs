@@ -8,6 +8,7 @@ use arpp::analysis::visualization::{
     create_metrics_chart, create_price_chart, create_simulation_analysis_chart,
 };
 use arpp::arpp::liquidity_pool::LiquidityPool;
+use arpp::arpp::oracle::RandomWalkOracle;
 use arpp::simulation::monte_carlo::MonteCarloSimulation;
 use arpp::simulation::strategies::MeanReversionStrategy;
 use arpp::utils::logger::setup_logger;
@@ -38,8 +39,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         iterations,
         steps_per_iteration,
         strategy,
-        Decimal::new(1, 1),
-        Decimal::new(1, 2),
+        Box::new(RandomWalkOracle::new(
+            Decimal::new(1, 1),
+            Decimal::new(1, 2),
+        )),
     );
 
     let result = simulation.run().await?;
@@ -45,6 +45,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             liquidity_depth: Decimal::new(1000, 0), // 1000
             trading_volume: Decimal::new(5000, 0),  // 5000
             impermanent_loss: Decimal::new(-2, 2),  // -0.02
+            fee_revenue_a: Decimal::new(15, 1),     // 1.5
+            fee_revenue_b: Decimal::new(15, 1),     // 1.5
+            fee_income: Decimal::new(3, 0),         // 3
+            oracle_incidents: vec![],
+            breaker: Default::default(),
+            ramp: None,
         },
         PoolMetrics {
             steps: vec![],
@@ -52,6 +58,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             liquidity_depth: Decimal::new(1100, 0), // 1100
             trading_volume: Decimal::new(5500, 0),  // 5500
             impermanent_loss: Decimal::new(-25, 3), // -0.025
+            fee_revenue_a: Decimal::new(165, 2),    // 1.65
+            fee_revenue_b: Decimal::new(165, 2),    // 1.65
+            fee_income: Decimal::new(33, 1),        // 3.3
+            oracle_incidents: vec![],
+            breaker: Default::default(),
+            ramp: None,
         },
     ];
 
@@ -0,0 +1,205 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::amm::reserves::Reserves;
+use crate::amm::traits::Amm;
+use crate::arpp::error::ArppError;
+use crate::arpp::oracle::PriceOracle;
+use crate::arpp::swap::{SwapResult, Token};
+use rust_decimal::Decimal;
+
+/// A constant-product pool, as in Uniswap v2.
+///
+/// Swaps keep `token_a * token_b` constant, before the fee is added back to the
+/// reserves, and the marginal price is `token_b / token_a`.
+///
+/// # Fields
+/// - `reserves`: The balances, fees and reference price of the pool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstantProductPool {
+    reserves: Reserves,
+}
+
+impl ConstantProductPool {
+    /// Creates a new `ConstantProductPool` with the specified initial balances.
+    ///
+    /// # Arguments
+    /// - `token_a`: Initial amount of Token A.
+    /// - `token_b`: Initial amount of Token B.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `ConstantProductPool`, charging no fee until `set_fee_bps`
+    /// is called, with its initial price as reference price.
+    pub fn new(token_a: Decimal, token_b: Decimal) -> Self {
+        Self {
+            reserves: Reserves::new(token_a, token_b, price(token_a, token_b)),
+        }
+    }
+
+    /// Sets the fee, in basis points, taken from the input of every swap.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the fee was set, or an `Err` if it is negative
+    /// or would take the whole input.
+    pub fn set_fee_bps(&mut self, fee_bps: Decimal) -> Result<(), ArppError> {
+        self.reserves.set_fee_bps(fee_bps)
+    }
+
+    /// Returns the fee, in basis points, taken from the input of every swap.
+    pub fn get_fee_bps(&self) -> Decimal {
+        self.reserves.fee_bps
+    }
+
+    fn swap(&mut self, token_in: Token, amount_in: Decimal) -> Result<SwapResult, ArppError> {
+        self.reserves.swap(
+            token_in,
+            amount_in,
+            |reserves, net_in| {
+                let (balance_in, balance_out) = reserves.balances_of(token_in);
+                Ok(balance_out * net_in / (balance_in + net_in))
+            },
+            price,
+        )
+    }
+}
+
+impl Amm for ConstantProductPool {
+    fn swap_a_to_b(&mut self, amount_a: Decimal) -> Result<SwapResult, ArppError> {
+        self.swap(Token::A, amount_a)
+    }
+
+    fn swap_b_to_a(&mut self, amount_b: Decimal) -> Result<SwapResult, ArppError> {
+        self.swap(Token::B, amount_b)
+    }
+
+    fn deposit(
+        &mut self,
+        provider: &str,
+        amount_a: Decimal,
+        amount_b: Decimal,
+    ) -> Result<Decimal, ArppError> {
+        self.reserves.deposit(provider, amount_a, amount_b, price)
+    }
+
+    fn withdraw(
+        &mut self,
+        provider: &str,
+        shares: Decimal,
+    ) -> Result<(Decimal, Decimal), ArppError> {
+        self.reserves.withdraw(provider, shares)
+    }
+
+    fn get_price(&self) -> Decimal {
+        price(self.reserves.token_a, self.reserves.token_b)
+    }
+
+    fn get_balances(&self) -> (Decimal, Decimal) {
+        (self.reserves.token_a, self.reserves.token_b)
+    }
+
+    fn get_fee_revenue(&self) -> (Decimal, Decimal) {
+        (self.reserves.fees_a, self.reserves.fees_b)
+    }
+
+    fn get_p_ref(&self) -> Decimal {
+        self.reserves.p_ref
+    }
+
+    fn update_p_ref(&mut self, oracle: &mut dyn PriceOracle) -> Result<(), ArppError> {
+        let pool_price = self.get_price();
+        self.reserves.follow_oracle(oracle, pool_price)
+    }
+}
+
+/// Returns the marginal price `token_b / token_a`, unbounded when A is drained.
+fn price(token_a: Decimal, token_b: Decimal) -> Decimal {
+    token_b.checked_div(token_a).unwrap_or(Decimal::MAX)
+}
+
+#[cfg(test)]
+mod tests_constant_product {
+    use super::*;
+    use crate::arpp::oracle::ConstantOracle;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_swap_keeps_product_constant() {
+        let mut pool = ConstantProductPool::new(dec!(1000), dec!(1000));
+        let result = pool.swap_a_to_b(dec!(250)).unwrap();
+        assert_eq!(result.amount_out, dec!(200));
+        assert_eq!(pool.get_balances(), (dec!(1250), dec!(800)));
+        assert_eq!(pool.get_price(), dec!(0.64));
+        assert_eq!(result.price_before, dec!(1));
+        assert_eq!(result.price_after, dec!(0.64));
+    }
+
+    #[test]
+    fn test_fee_stays_in_reserves() {
+        let mut pool = ConstantProductPool::new(dec!(1000), dec!(1000));
+        pool.set_fee_bps(dec!(100)).unwrap();
+        let result = pool.swap_b_to_a(dec!(100)).unwrap();
+        assert_eq!(result.fee, dec!(1));
+        // 1000 * 99 / 1099
+        assert_eq!(result.amount_out, dec!(90.0818926296633));
+        assert_eq!(pool.get_balances().1, dec!(1100));
+        assert_eq!(pool.get_fee_revenue(), (dec!(0), dec!(1)));
+    }
+
+    #[test]
+    fn test_round_trip_loses_value() {
+        let mut pool = ConstantProductPool::new(dec!(1000), dec!(1000));
+        let out = pool.swap_a_to_b(dec!(100)).unwrap().amount_out;
+        let back = pool.swap_b_to_a(out).unwrap().amount_out;
+        assert!(back <= dec!(100));
+    }
+
+    #[test]
+    fn test_invalid_swaps() {
+        let mut pool = ConstantProductPool::new(dec!(1000), dec!(1000));
+        assert_eq!(
+            pool.swap_a_to_b(dec!(0)),
+            Err(ArppError::InvalidAmount { amount: dec!(0) })
+        );
+        let mut drained = ConstantProductPool::new(dec!(1000), dec!(0));
+        assert!(matches!(
+            drained.swap_a_to_b(dec!(10)),
+            Err(ArppError::InsufficientLiquidity {
+                token: Token::B,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw_track_shares() {
+        let mut pool = ConstantProductPool::new(dec!(1000), dec!(1000));
+        assert_eq!(pool.deposit("alice", dec!(100), dec!(100)), Ok(dec!(200)));
+        assert_eq!(pool.get_balances(), (dec!(1100), dec!(1100)));
+        assert_eq!(
+            pool.withdraw("bob", dec!(1)),
+            Err(ArppError::UnknownProvider {
+                provider: "bob".to_string()
+            })
+        );
+        assert_eq!(
+            pool.withdraw("alice", dec!(200)),
+            Ok((dec!(100), dec!(100)))
+        );
+        assert_eq!(pool.get_balances(), (dec!(1000), dec!(1000)));
+    }
+
+    #[test]
+    fn test_p_ref_follows_oracle_without_moving_price() {
+        let mut pool = ConstantProductPool::new(dec!(1000), dec!(2000));
+        assert_eq!(pool.get_p_ref(), dec!(2));
+        pool.update_p_ref(&mut ConstantOracle::new(dec!(3)))
+            .unwrap();
+        assert_eq!(pool.get_p_ref(), dec!(3));
+        assert_eq!(pool.get_price(), dec!(2));
+    }
+}
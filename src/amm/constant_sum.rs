@@ -0,0 +1,152 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::amm::reserves::Reserves;
+use crate::amm::traits::Amm;
+use crate::arpp::error::ArppError;
+use crate::arpp::oracle::PriceOracle;
+use crate::arpp::swap::{SwapResult, Token};
+use rust_decimal::Decimal;
+
+/// A constant-sum pool, trading at a fixed price until one side is drained.
+///
+/// Swaps keep `price * token_a + token_b` constant, before the fee is added back
+/// to the reserves, so there is no price impact at all.
+///
+/// # Fields
+/// - `reserves`: The balances, fees and reference price of the pool.
+/// - `price`: The fixed price, in units of B per unit of A.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstantSumPool {
+    reserves: Reserves,
+    price: Decimal,
+}
+
+impl ConstantSumPool {
+    /// Creates a new `ConstantSumPool` with the specified initial balances and price.
+    ///
+    /// # Arguments
+    /// - `token_a`: Initial amount of Token A.
+    /// - `token_b`: Initial amount of Token B.
+    /// - `price`: The fixed price, in units of B per unit of A.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `ConstantSumPool`, charging no fee until `set_fee_bps` is
+    /// called, with `price` as reference price.
+    pub fn new(token_a: Decimal, token_b: Decimal, price: Decimal) -> Self {
+        Self {
+            reserves: Reserves::new(token_a, token_b, price),
+            price,
+        }
+    }
+
+    /// Sets the fee, in basis points, taken from the input of every swap.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the fee was set, or an `Err` if it is negative
+    /// or would take the whole input.
+    pub fn set_fee_bps(&mut self, fee_bps: Decimal) -> Result<(), ArppError> {
+        self.reserves.set_fee_bps(fee_bps)
+    }
+
+    /// Returns the fee, in basis points, taken from the input of every swap.
+    pub fn get_fee_bps(&self) -> Decimal {
+        self.reserves.fee_bps
+    }
+
+    fn swap(&mut self, token_in: Token, amount_in: Decimal) -> Result<SwapResult, ArppError> {
+        let price = self.price;
+        self.reserves.swap(
+            token_in,
+            amount_in,
+            |_, net_in| Ok(net_in * token_in.rate(price)),
+            |_, _| price,
+        )
+    }
+}
+
+impl Amm for ConstantSumPool {
+    fn swap_a_to_b(&mut self, amount_a: Decimal) -> Result<SwapResult, ArppError> {
+        self.swap(Token::A, amount_a)
+    }
+
+    fn swap_b_to_a(&mut self, amount_b: Decimal) -> Result<SwapResult, ArppError> {
+        self.swap(Token::B, amount_b)
+    }
+
+    fn deposit(
+        &mut self,
+        provider: &str,
+        amount_a: Decimal,
+        amount_b: Decimal,
+    ) -> Result<Decimal, ArppError> {
+        let price = self.price;
+        self.reserves
+            .deposit(provider, amount_a, amount_b, |_, _| price)
+    }
+
+    fn withdraw(
+        &mut self,
+        provider: &str,
+        shares: Decimal,
+    ) -> Result<(Decimal, Decimal), ArppError> {
+        self.reserves.withdraw(provider, shares)
+    }
+
+    fn get_price(&self) -> Decimal {
+        self.price
+    }
+
+    fn get_balances(&self) -> (Decimal, Decimal) {
+        (self.reserves.token_a, self.reserves.token_b)
+    }
+
+    fn get_fee_revenue(&self) -> (Decimal, Decimal) {
+        (self.reserves.fees_a, self.reserves.fees_b)
+    }
+
+    fn get_p_ref(&self) -> Decimal {
+        self.reserves.p_ref
+    }
+
+    fn update_p_ref(&mut self, oracle: &mut dyn PriceOracle) -> Result<(), ArppError> {
+        self.reserves.follow_oracle(oracle, self.price)
+    }
+}
+
+#[cfg(test)]
+mod tests_constant_sum {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_swaps_at_fixed_price() {
+        let mut pool = ConstantSumPool::new(dec!(1000), dec!(1000), dec!(2));
+        let result = pool.swap_a_to_b(dec!(100)).unwrap();
+        assert_eq!(result.amount_out, dec!(200));
+        assert_eq!(result.price_impact_bps, dec!(0));
+        let result = pool.swap_b_to_a(dec!(50)).unwrap();
+        assert_eq!(result.amount_out, dec!(25));
+        assert_eq!(pool.get_balances(), (dec!(1075), dec!(850)));
+        assert_eq!(pool.get_price(), dec!(2));
+    }
+
+    #[test]
+    fn test_swap_cannot_drain_pool() {
+        let mut pool = ConstantSumPool::new(dec!(1000), dec!(1000), dec!(1));
+        assert_eq!(
+            pool.swap_a_to_b(dec!(1000)),
+            Err(ArppError::InsufficientLiquidity {
+                token: Token::B,
+                needed: dec!(1000),
+                available: dec!(1000),
+            })
+        );
+        assert_eq!(pool.get_balances(), (dec!(1000), dec!(1000)));
+    }
+}
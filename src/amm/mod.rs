@@ -0,0 +1,12 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+pub mod constant_product;
+pub mod constant_sum;
+pub(crate) mod reserves;
+pub mod router;
+pub mod stable_swap;
+pub mod traits;
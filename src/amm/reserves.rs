@@ -0,0 +1,267 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::error::ArppError;
+use crate::arpp::oracle::PriceOracle;
+use crate::arpp::position::{LpPosition, INITIAL_PROVIDER};
+use crate::arpp::swap::{input_fee, round_significant, SwapResult, Token};
+use rust_decimal::{Decimal, RoundingStrategy};
+use rust_decimal_macros::dec;
+use std::collections::HashMap;
+
+/// Balances, fees and reference price shared by the baseline AMMs.
+///
+/// Fees are taken from the input of every swap and stay in the reserves, as in
+/// `LiquidityPool`, so the models can be compared at equal fees.
+///
+/// # Fields
+/// - `token_a`: The amount of Token A in the pool.
+/// - `token_b`: The amount of Token B in the pool.
+/// - `fee_bps`: The fee, in basis points, taken from the input of every swap.
+/// - `fees_a`: The cumulative fees collected in Token A.
+/// - `fees_b`: The cumulative fees collected in Token B.
+/// - `p_ref`: The reference price last reported by the oracle.
+/// - `shares`: The LP shares owned by each provider.
+/// - `total_shares`: The LP shares outstanding.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Reserves {
+    pub(crate) token_a: Decimal,
+    pub(crate) token_b: Decimal,
+    pub(crate) fee_bps: Decimal,
+    pub(crate) fees_a: Decimal,
+    pub(crate) fees_b: Decimal,
+    pub(crate) p_ref: Decimal,
+    shares: HashMap<String, Decimal>,
+    total_shares: Decimal,
+}
+
+impl Reserves {
+    /// Creates the reserves of a pool whose initial price is `p_ref`.
+    ///
+    /// The initial balances are owned by `INITIAL_PROVIDER`, with one share per
+    /// unit of their value in Token B at `p_ref`, as in `LiquidityPool::new`.
+    pub(crate) fn new(token_a: Decimal, token_b: Decimal, p_ref: Decimal) -> Self {
+        let mut reserves = Self {
+            token_a,
+            token_b,
+            fee_bps: Decimal::ZERO,
+            fees_a: Decimal::ZERO,
+            fees_b: Decimal::ZERO,
+            p_ref,
+            shares: HashMap::new(),
+            total_shares: Decimal::ZERO,
+        };
+        let value = token_a * p_ref + token_b;
+        if value > Decimal::ZERO {
+            *reserves
+                .shares
+                .entry(INITIAL_PROVIDER.to_string())
+                .or_default() += value;
+            reserves.total_shares = value;
+        }
+        reserves
+    }
+
+    /// Sets the fee taken from the input of every swap, with the same bounds as
+    /// `LiquidityPool::set_fee_bps`.
+    pub(crate) fn set_fee_bps(&mut self, fee_bps: Decimal) -> Result<(), ArppError> {
+        if fee_bps < Decimal::ZERO || fee_bps >= dec!(10000) {
+            return Err(ArppError::InvalidFee { fee_bps });
+        }
+        self.fee_bps = fee_bps;
+        Ok(())
+    }
+
+    /// Returns the balances of `token` and of the other token, in that order.
+    pub(crate) fn balances_of(&self, token: Token) -> (Decimal, Decimal) {
+        match token {
+            Token::A => (self.token_a, self.token_b),
+            Token::B => (self.token_b, self.token_a),
+        }
+    }
+
+    /// Executes a swap of `amount_in` of `token_in`.
+    ///
+    /// # Arguments
+    ///
+    /// - `token_in`: The token paid into the pool.
+    /// - `amount_in`: Amount of `token_in` paid in, fee included.
+    /// - `amount_out`: Returns the amount of the other token paid out for the amount
+    ///   left after the fee, against the current balances.
+    /// - `price`: Returns the marginal price of the pool for given balances of A and B.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the `SwapResult`, or an `Err` if the amount is not positive
+    /// or the output would drain the pool, in which case the pool is untouched.
+    pub(crate) fn swap<F, P>(
+        &mut self,
+        token_in: Token,
+        amount_in: Decimal,
+        amount_out: F,
+        price: P,
+    ) -> Result<SwapResult, ArppError>
+    where
+        F: FnOnce(&Self, Decimal) -> Result<Decimal, ArppError>,
+        P: Fn(Decimal, Decimal) -> Decimal,
+    {
+        if amount_in <= Decimal::ZERO {
+            return Err(ArppError::InvalidAmount { amount: amount_in });
+        }
+        let fee = input_fee(amount_in, self.fee_bps);
+        // Outputs are truncated like those of `LiquidityPool`, in the pool's favour
+        let amount_out = round_significant(
+            amount_out(self, amount_in - fee)?.max(Decimal::ZERO),
+            RoundingStrategy::ToZero,
+        )
+        .unwrap_or(Decimal::ZERO);
+        let (_, balance_out) = self.balances_of(token_in);
+        if amount_out >= balance_out {
+            return Err(ArppError::InsufficientLiquidity {
+                token: token_in.other(),
+                needed: amount_out,
+                available: balance_out,
+            });
+        }
+
+        let price_before = price(self.token_a, self.token_b);
+        match token_in {
+            Token::A => {
+                self.token_a += amount_in;
+                self.token_b -= amount_out;
+                self.fees_a += fee;
+            }
+            Token::B => {
+                self.token_b += amount_in;
+                self.token_a -= amount_out;
+                self.fees_b += fee;
+            }
+        }
+        let price_after = price(self.token_a, self.token_b);
+        Ok(SwapResult::new(
+            token_in,
+            amount_in,
+            amount_out,
+            fee,
+            price_before,
+            price_after,
+        ))
+    }
+
+    /// Deposits liquidity on behalf of a provider, minting LP shares as
+    /// `LiquidityPool::deposit` does.
+    ///
+    /// # Arguments
+    ///
+    /// - `provider`: The ID of the provider receiving the shares.
+    /// - `amount_a`: Amount of Token A to deposit.
+    /// - `amount_b`: Amount of Token B to deposit.
+    /// - `price`: Returns the marginal price of the pool for given balances of A and B.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which contains the number of shares minted, or an `Err` if the
+    /// amounts are negative or too small to mint any share.
+    pub(crate) fn deposit<P>(
+        &mut self,
+        provider: &str,
+        amount_a: Decimal,
+        amount_b: Decimal,
+        price: P,
+    ) -> Result<Decimal, ArppError>
+    where
+        P: Fn(Decimal, Decimal) -> Decimal,
+    {
+        if amount_a < Decimal::ZERO || amount_b < Decimal::ZERO {
+            return Err(ArppError::InvalidAmount {
+                amount: amount_a.min(amount_b),
+            });
+        }
+        // Valued at the price after the deposit, like `LiquidityPool::deposit`
+        let price = price(self.token_a + amount_a, self.token_b + amount_b);
+        let value = amount_a * price + amount_b;
+        let pool_value = self.token_a * price + self.token_b;
+        let shares = if self.total_shares.is_zero() || pool_value <= Decimal::ZERO {
+            value
+        } else {
+            value / pool_value * self.total_shares
+        };
+        let shares = round_significant(shares, RoundingStrategy::ToZero).unwrap_or(Decimal::ZERO);
+        if shares <= Decimal::ZERO {
+            return Err(ArppError::DepositTooSmall { value });
+        }
+
+        self.token_a += amount_a;
+        self.token_b += amount_b;
+        *self.shares.entry(provider.to_string()).or_default() += shares;
+        self.total_shares += shares;
+        Ok(shares)
+    }
+
+    /// Burns LP shares of a provider and pays out their pro-rata part of both
+    /// tokens, as `LiquidityPool::withdraw` does.
+    ///
+    /// # Arguments
+    ///
+    /// - `provider`: The ID of the provider redeeming the shares.
+    /// - `shares`: Number of shares to burn.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which contains the amounts of Token A and Token B paid out, or an
+    /// `Err` if the provider does not own that many shares.
+    pub(crate) fn withdraw(
+        &mut self,
+        provider: &str,
+        shares: Decimal,
+    ) -> Result<(Decimal, Decimal), ArppError> {
+        if shares <= Decimal::ZERO {
+            return Err(ArppError::InvalidAmount { amount: shares });
+        }
+        let owned =
+            self.shares
+                .get(provider)
+                .copied()
+                .ok_or_else(|| ArppError::UnknownProvider {
+                    provider: provider.to_string(),
+                })?;
+        if shares > owned {
+            return Err(ArppError::InsufficientShares {
+                provider: provider.to_string(),
+                requested: shares,
+                owned,
+            });
+        }
+
+        let position = LpPosition::new(shares, self.total_shares, self.token_a, self.token_b);
+        self.token_a -= position.amount_a;
+        self.token_b -= position.amount_b;
+        self.total_shares -= shares;
+        if shares == owned {
+            self.shares.remove(provider);
+        } else {
+            self.shares.insert(provider.to_string(), owned - shares);
+        }
+        Ok((position.amount_a, position.amount_b))
+    }
+
+    /// Takes the next price of `oracle` as the reference price, without guards.
+    pub(crate) fn follow_oracle(
+        &mut self,
+        oracle: &mut dyn PriceOracle,
+        pool_price: Decimal,
+    ) -> Result<(), ArppError> {
+        if let Some(update) = oracle.next_price(self.p_ref, pool_price) {
+            if update.price <= Decimal::ZERO {
+                return Err(ArppError::InvalidPrice {
+                    price: update.price,
+                });
+            }
+            self.p_ref = update.price;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,561 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::amm::traits::Amm;
+use crate::arpp::error::ArppError;
+use crate::arpp::liquidity_pool::LiquidityPool;
+use crate::arpp::swap::Token;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use tracing::debug;
+
+/// Maximum number of pools a route goes through unless `set_max_hops` is called.
+pub const DEFAULT_MAX_HOPS: usize = 3;
+
+/// One swap of a route, through a single pool.
+///
+/// # Fields
+/// - `pool`: The ID of the pool, as returned by `Router::add_pool`.
+/// - `token_in`: The side of the pool paid in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hop {
+    pub pool: usize,
+    pub token_in: Token,
+}
+
+/// A chain of swaps from one token to another.
+///
+/// # Fields
+/// - `tokens`: The tokens the route goes through, from the token paid in to the
+///   token paid out.
+/// - `hops`: The swaps between consecutive tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub tokens: Vec<String>,
+    pub hops: Vec<Hop>,
+}
+
+/// The part of an order sent along one route.
+///
+/// # Fields
+/// - `route`: The route.
+/// - `amount_in`: The amount paid into the first pool of the route.
+/// - `amount_out`: The amount paid out by the last pool of the route.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteLeg {
+    pub route: Route,
+    pub amount_in: Decimal,
+    pub amount_out: Decimal,
+}
+
+/// An order split across one or more routes, quoted or executed by a `Router`.
+///
+/// # Fields
+/// - `token_in`: The token paid in.
+/// - `token_out`: The token paid out.
+/// - `amount_in`: The total amount paid in.
+/// - `amount_out`: The total amount paid out.
+/// - `legs`: The routes the order is sent along, in the order they were found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutePlan {
+    pub token_in: String,
+    pub token_out: String,
+    pub amount_in: Decimal,
+    pub amount_out: Decimal,
+    pub legs: Vec<RouteLeg>,
+}
+
+/// A registry of pools for different token pairs, routing swaps through them.
+///
+/// Pools are registered with the names of their Token A and Token B, and looked up
+/// by unordered pair. A swap from one token to another can go through up to
+/// `max_hops` pools, and be split into equal parts, each sent along the route
+/// paying out the most given the parts already sent.
+///
+/// # Fields
+/// - `pools`: The registered pools, by ID.
+/// - `tokens`: The names of Token A and Token B of each pool, by ID.
+/// - `pairs`: The IDs of the pools of each pair, keyed by the pair's names in
+///   alphabetical order.
+/// - `max_hops`: The maximum number of pools a route goes through.
+#[derive(Debug, Clone)]
+pub struct Router<P: Amm + Clone = LiquidityPool> {
+    pools: Vec<P>,
+    tokens: Vec<(String, String)>,
+    pairs: BTreeMap<(String, String), Vec<usize>>,
+    max_hops: usize,
+}
+
+impl<P: Amm + Clone> Default for Router<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Amm + Clone> Router<P> {
+    /// Creates an empty `Router`, with routes of up to `DEFAULT_MAX_HOPS` pools.
+    pub fn new() -> Self {
+        Self {
+            pools: Vec::new(),
+            tokens: Vec::new(),
+            pairs: BTreeMap::new(),
+            max_hops: DEFAULT_MAX_HOPS,
+        }
+    }
+
+    /// Sets the maximum number of pools a route goes through, at least one.
+    pub fn set_max_hops(&mut self, max_hops: usize) {
+        self.max_hops = max_hops.max(1);
+    }
+
+    /// Returns the maximum number of pools a route goes through.
+    pub fn get_max_hops(&self) -> usize {
+        self.max_hops
+    }
+
+    /// Registers a pool.
+    ///
+    /// # Arguments
+    ///
+    /// - `token_a`: The name of the pool's Token A.
+    /// - `token_b`: The name of the pool's Token B.
+    /// - `pool`: The pool.
+    ///
+    /// # Returns
+    ///
+    /// The ID of the pool, or an `Err` if both tokens have the same name.
+    pub fn add_pool(&mut self, token_a: &str, token_b: &str, pool: P) -> Result<usize, ArppError> {
+        if token_a == token_b {
+            return Err(ArppError::NoRoute {
+                token_in: token_a.to_string(),
+                token_out: token_b.to_string(),
+            });
+        }
+        let id = self.pools.len();
+        self.pools.push(pool);
+        self.tokens.push((token_a.to_string(), token_b.to_string()));
+        self.pairs
+            .entry(pair_key(token_a, token_b))
+            .or_default()
+            .push(id);
+        Ok(id)
+    }
+
+    /// Returns the pool with the given ID, if there is one.
+    pub fn get_pool(&self, id: usize) -> Option<&P> {
+        self.pools.get(id)
+    }
+
+    /// Returns the IDs of the pools trading two tokens, in either order.
+    pub fn get_pools_for(&self, token_x: &str, token_y: &str) -> &[usize] {
+        self.pairs
+            .get(&pair_key(token_x, token_y))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns every route from `token_in` to `token_out` through at most
+    /// `max_hops` pools, never going through the same token twice.
+    pub fn find_routes(&self, token_in: &str, token_out: &str) -> Vec<Route> {
+        let mut routes = Vec::new();
+        if token_in != token_out {
+            let mut route = Route {
+                tokens: vec![token_in.to_string()],
+                hops: Vec::new(),
+            };
+            self.extend_routes(token_out, &mut route, &mut routes);
+        }
+        routes
+    }
+
+    /// Quotes a swap of an exact amount in without modifying any pool.
+    ///
+    /// # Arguments
+    ///
+    /// - `token_in`: The token paid in.
+    /// - `token_out`: The token paid out.
+    /// - `amount_in`: Amount of `token_in` to pay in.
+    /// - `parts`: Number of equal parts the order is split into, at least one. With
+    ///   a single part, the whole order goes along the best route.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which contains the `RoutePlan`, or an `Err` if there is no route
+    /// or a part cannot be swapped along any of them.
+    pub fn quote_exact_in(
+        &self,
+        token_in: &str,
+        token_out: &str,
+        amount_in: Decimal,
+        parts: usize,
+    ) -> Result<RoutePlan, ArppError> {
+        let mut pools = self.pools.clone();
+        self.route_on(&mut pools, token_in, token_out, amount_in, parts)
+    }
+
+    /// Swaps an exact amount in along the routes quoted by `quote_exact_in`.
+    ///
+    /// The swaps are executed atomically: they are applied to copies of the pools,
+    /// which replace the registered pools only once every leg has succeeded and the
+    /// total output is checked against `min_out`.
+    ///
+    /// # Arguments
+    ///
+    /// - `token_in`: The token paid in.
+    /// - `token_out`: The token paid out.
+    /// - `amount_in`: Amount of `token_in` to pay in.
+    /// - `min_out`: Minimum amount of `token_out` to receive.
+    /// - `parts`: Number of equal parts the order is split into, at least one.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which contains the executed `RoutePlan`, or an `Err` if any swap
+    /// fails or the output is below `min_out`, in which case no pool is modified.
+    pub fn swap_exact_in(
+        &mut self,
+        token_in: &str,
+        token_out: &str,
+        amount_in: Decimal,
+        min_out: Decimal,
+        parts: usize,
+    ) -> Result<RoutePlan, ArppError> {
+        let mut pools = self.pools.clone();
+        let plan = self.route_on(&mut pools, token_in, token_out, amount_in, parts)?;
+        if plan.amount_out < min_out {
+            return Err(ArppError::RouteSlippageExceeded {
+                token: token_out.to_string(),
+                amount: plan.amount_out,
+                limit: min_out,
+            });
+        }
+        self.pools = pools;
+        debug!(
+            "Routed {} {} for {} {} along {} routes",
+            amount_in,
+            token_in,
+            plan.amount_out,
+            token_out,
+            plan.legs.len()
+        );
+        Ok(plan)
+    }
+
+    /// Extends `route` towards `token_out` with every pool trading its last token,
+    /// collecting the routes that reach it.
+    fn extend_routes(&self, token_out: &str, route: &mut Route, routes: &mut Vec<Route>) {
+        if route.hops.len() >= self.max_hops {
+            return;
+        }
+        let from = route.tokens[route.tokens.len() - 1].clone();
+        for ids in self.pairs.values() {
+            for &pool in ids {
+                let (token_a, token_b) = &self.tokens[pool];
+                let (token_in, next) = if *token_a == from {
+                    (Token::A, token_b)
+                } else if *token_b == from {
+                    (Token::B, token_a)
+                } else {
+                    continue;
+                };
+                if route.tokens.contains(next) {
+                    continue;
+                }
+                route.tokens.push(next.clone());
+                route.hops.push(Hop { pool, token_in });
+                if next == token_out {
+                    routes.push(route.clone());
+                } else {
+                    self.extend_routes(token_out, route, routes);
+                }
+                route.tokens.pop();
+                route.hops.pop();
+            }
+        }
+    }
+
+    /// Splits an order into `parts` equal parts and sends each along the route
+    /// paying out the most for it, swapping against `pools`.
+    fn route_on(
+        &self,
+        pools: &mut [P],
+        token_in: &str,
+        token_out: &str,
+        amount_in: Decimal,
+        parts: usize,
+    ) -> Result<RoutePlan, ArppError> {
+        if amount_in <= Decimal::ZERO {
+            return Err(ArppError::InvalidAmount { amount: amount_in });
+        }
+        let routes = self.find_routes(token_in, token_out);
+        if routes.is_empty() {
+            return Err(ArppError::NoRoute {
+                token_in: token_in.to_string(),
+                token_out: token_out.to_string(),
+            });
+        }
+
+        let parts = parts.max(1);
+        let part = amount_in / Decimal::from(parts);
+        let mut sent = vec![(Decimal::ZERO, Decimal::ZERO); routes.len()];
+        for index in 0..parts {
+            // The last part takes the rounding remainder
+            let amount = if index + 1 == parts {
+                amount_in - part * Decimal::from(parts - 1)
+            } else {
+                part
+            };
+            let mut best: Result<(usize, Decimal), ArppError> = Err(ArppError::NoRoute {
+                token_in: token_in.to_string(),
+                token_out: token_out.to_string(),
+            });
+            for (index, route) in routes.iter().enumerate() {
+                match quote_route(pools, route, amount) {
+                    Ok(out) if best.as_ref().map_or(true, |&(_, best)| out > best) => {
+                        best = Ok((index, out));
+                    }
+                    Err(error) if best.is_err() => best = Err(error),
+                    _ => {}
+                }
+            }
+            let (index, _) = best?;
+            let out = swap_route(pools, &routes[index], amount)?;
+            sent[index].0 += amount;
+            sent[index].1 += out;
+        }
+
+        let legs: Vec<RouteLeg> = routes
+            .into_iter()
+            .zip(sent)
+            .filter(|(_, (amount_in, _))| *amount_in > Decimal::ZERO)
+            .map(|(route, (amount_in, amount_out))| RouteLeg {
+                route,
+                amount_in,
+                amount_out,
+            })
+            .collect();
+        Ok(RoutePlan {
+            token_in: token_in.to_string(),
+            token_out: token_out.to_string(),
+            amount_in,
+            amount_out: legs.iter().map(|leg| leg.amount_out).sum(),
+            legs,
+        })
+    }
+}
+
+/// Returns the key of a pair in the registry, independent of the order of its tokens.
+fn pair_key(token_x: &str, token_y: &str) -> (String, String) {
+    if token_x <= token_y {
+        (token_x.to_string(), token_y.to_string())
+    } else {
+        (token_y.to_string(), token_x.to_string())
+    }
+}
+
+/// Returns the output of a route on copies of its pools, leaving `pools` untouched.
+///
+/// A route never goes through the same token twice, so it never goes through the
+/// same pool twice either, and each pool can be copied on its own.
+fn quote_route<P: Amm + Clone>(
+    pools: &[P],
+    route: &Route,
+    amount_in: Decimal,
+) -> Result<Decimal, ArppError> {
+    route.hops.iter().try_fold(amount_in, |amount, hop| {
+        swap_hop(&mut pools[hop.pool].clone(), hop.token_in, amount)
+    })
+}
+
+/// Swaps an amount along a route, returning the amount paid out by its last pool.
+fn swap_route<P: Amm>(
+    pools: &mut [P],
+    route: &Route,
+    amount_in: Decimal,
+) -> Result<Decimal, ArppError> {
+    route.hops.iter().try_fold(amount_in, |amount, hop| {
+        swap_hop(&mut pools[hop.pool], hop.token_in, amount)
+    })
+}
+
+/// Swaps an amount of one side of a pool for the other, returning the amount out.
+fn swap_hop<P: Amm>(pool: &mut P, token_in: Token, amount: Decimal) -> Result<Decimal, ArppError> {
+    let result = match token_in {
+        Token::A => pool.swap_a_to_b(amount),
+        Token::B => pool.swap_b_to_a(amount),
+    }?;
+    Ok(result.amount_out)
+}
+
+#[cfg(test)]
+mod tests_router {
+    use super::*;
+    use crate::amm::constant_product::ConstantProductPool;
+    use crate::amm::constant_sum::ConstantSumPool;
+    use rust_decimal_macros::dec;
+
+    fn create_router() -> Router<ConstantProductPool> {
+        let mut router = Router::new();
+        router
+            .add_pool("A", "B", ConstantProductPool::new(dec!(1000), dec!(1000)))
+            .unwrap();
+        router
+            .add_pool("C", "B", ConstantProductPool::new(dec!(1000), dec!(1000)))
+            .unwrap();
+        router
+            .add_pool("A", "C", ConstantProductPool::new(dec!(100), dec!(100)))
+            .unwrap();
+        router
+    }
+
+    #[test]
+    fn test_registry_is_keyed_by_unordered_pair() {
+        let mut router = create_router();
+        assert_eq!(router.get_pools_for("B", "C"), &[1]);
+        assert_eq!(router.get_pools_for("C", "B"), &[1]);
+        assert!(router.get_pools_for("A", "D").is_empty());
+        assert!(router
+            .add_pool("A", "A", ConstantProductPool::new(dec!(1), dec!(1)))
+            .is_err());
+    }
+
+    #[test]
+    fn test_find_routes_within_max_hops() {
+        let mut router = create_router();
+        assert_eq!(router.find_routes("A", "C").len(), 2);
+        router.set_max_hops(1);
+        let routes = router.find_routes("A", "C");
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].tokens, vec!["A", "C"]);
+        assert!(router.find_routes("A", "A").is_empty());
+    }
+
+    #[test]
+    fn test_best_route_goes_through_deeper_pools() {
+        let mut router = create_router();
+        let plan = router
+            .swap_exact_in("A", "C", dec!(10), dec!(9), 1)
+            .unwrap();
+        assert_eq!(plan.legs.len(), 1);
+        let route = &plan.legs[0].route;
+        assert_eq!(route.tokens, vec!["A", "B", "C"]);
+        assert_eq!(
+            route.hops,
+            vec![
+                Hop {
+                    pool: 0,
+                    token_in: Token::A
+                },
+                Hop {
+                    pool: 1,
+                    token_in: Token::B
+                },
+            ]
+        );
+        // The direct pool would only pay out 100 * 10 / 110
+        assert!(plan.amount_out > dec!(9.8));
+        assert_eq!(router.get_pool(0).unwrap().get_balances().0, dec!(1010));
+        assert_eq!(
+            router.get_pool(2).unwrap().get_balances(),
+            (dec!(100), dec!(100))
+        );
+    }
+
+    #[test]
+    fn test_split_across_parallel_pools() {
+        let mut router: Router<ConstantProductPool> = Router::new();
+        for _ in 0..2 {
+            router
+                .add_pool("A", "B", ConstantProductPool::new(dec!(1000), dec!(1000)))
+                .unwrap();
+        }
+        let single = router.quote_exact_in("A", "B", dec!(100), 1).unwrap();
+        let split = router.quote_exact_in("A", "B", dec!(100), 2).unwrap();
+        assert_eq!(split.legs.len(), 2);
+        assert_eq!(split.legs[0].amount_in, dec!(50));
+        assert!(split.amount_out > single.amount_out);
+        assert_eq!(
+            router.get_pool(0).unwrap().get_balances(),
+            (dec!(1000), dec!(1000))
+        );
+    }
+
+    #[test]
+    fn test_failed_swap_rolls_back_every_pool() {
+        let mut router = create_router();
+        let before: Vec<_> = (0..3)
+            .map(|id| router.get_pool(id).unwrap().get_balances())
+            .collect();
+        assert!(matches!(
+            router.swap_exact_in("A", "C", dec!(10), dec!(100), 2),
+            Err(ArppError::RouteSlippageExceeded { .. })
+        ));
+        assert!(matches!(
+            router.swap_exact_in("A", "C", dec!(-1), dec!(0), 1),
+            Err(ArppError::InvalidAmount { .. })
+        ));
+        assert_eq!(
+            router.swap_exact_in("A", "D", dec!(10), dec!(0), 1),
+            Err(ArppError::NoRoute {
+                token_in: "A".to_string(),
+                token_out: "D".to_string()
+            })
+        );
+        let after: Vec<_> = (0..3)
+            .map(|id| router.get_pool(id).unwrap().get_balances())
+            .collect();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn test_failed_leg_rolls_back_earlier_legs() {
+        let mut router = Router::new();
+        router
+            .add_pool(
+                "A",
+                "B",
+                ConstantSumPool::new(dec!(1000), dec!(1000), dec!(1)),
+            )
+            .unwrap();
+        router
+            .add_pool("C", "B", ConstantSumPool::new(dec!(1), dec!(1000), dec!(1)))
+            .unwrap();
+        assert!(matches!(
+            router.swap_exact_in("A", "C", dec!(10), dec!(0), 1),
+            Err(ArppError::InsufficientLiquidity {
+                token: Token::A,
+                ..
+            })
+        ));
+        assert_eq!(
+            router.get_pool(0).unwrap().get_balances(),
+            (dec!(1000), dec!(1000))
+        );
+    }
+
+    #[test]
+    fn test_routes_through_arpp_pools() {
+        let mut router = Router::default();
+        router
+            .add_pool(
+                "ETH",
+                "USDC",
+                LiquidityPool::new(dec!(1000), dec!(2000000), dec!(2000), dec!(0.1), dec!(2)),
+            )
+            .unwrap();
+        router
+            .add_pool(
+                "WBTC",
+                "USDC",
+                LiquidityPool::new(dec!(50), dec!(2000000), dec!(40000), dec!(0.1), dec!(2)),
+            )
+            .unwrap();
+        let plan = router
+            .swap_exact_in("ETH", "WBTC", dec!(1), dec!(0), 1)
+            .unwrap();
+        assert_eq!(plan.legs[0].route.tokens, vec!["ETH", "USDC", "WBTC"]);
+        assert!(plan.amount_out > dec!(0.049) && plan.amount_out < dec!(0.051));
+        assert_eq!(router.get_pool(0).unwrap().get_balances().0, dec!(1001));
+    }
+}
@@ -0,0 +1,306 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::amm::reserves::Reserves;
+use crate::amm::traits::Amm;
+use crate::arpp::error::ArppError;
+use crate::arpp::oracle::PriceOracle;
+use crate::arpp::swap::{SwapResult, Token};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// Relative change below which the Newton iterations on the invariant stop.
+///
+/// A few orders of magnitude above the precision of `Decimal`, and far below the
+/// 15 significant digits kept in swap outputs.
+const CONVERGENCE: Decimal = dec!(0.000000000000000000000001);
+
+/// Maximum number of Newton iterations before giving up, as in the Curve contracts.
+const MAX_ITERATIONS: usize = 255;
+
+/// A two-token StableSwap pool, as in Curve.
+///
+/// With Token A converted to units of B at the `peg` price, `x = token_a * peg`
+/// and `y = token_b`, swaps keep the StableSwap invariant `D` constant, before
+/// the fee is added back to the reserves:
+/// ```text
+/// 4A (x + y) + D = 4A D + D^3 / (4 x y)
+/// ```
+/// A high amplification `A` keeps the price close to `peg` over a wide range of
+/// balances, like a constant-sum pool, and lets it run away like a
+/// constant-product pool once the pool is far from balance.
+///
+/// # Fields
+/// - `reserves`: The balances, fees and reference price of the pool.
+/// - `amplification`: The amplification coefficient `A`, which must be positive.
+/// - `peg`: The price, in units of B per unit of A, of the balanced pool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StableSwapPool {
+    reserves: Reserves,
+    amplification: Decimal,
+    peg: Decimal,
+}
+
+impl StableSwapPool {
+    /// Creates a new `StableSwapPool` with the specified initial balances and parameters.
+    ///
+    /// # Arguments
+    /// - `token_a`: Initial amount of Token A.
+    /// - `token_b`: Initial amount of Token B.
+    /// - `amplification`: The amplification coefficient `A`.
+    /// - `peg`: The price, in units of B per unit of A, of the balanced pool.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `StableSwapPool`, charging no fee until `set_fee_bps` is
+    /// called, with its initial price as reference price.
+    pub fn new(token_a: Decimal, token_b: Decimal, amplification: Decimal, peg: Decimal) -> Self {
+        let mut pool = Self {
+            reserves: Reserves::new(token_a, token_b, peg),
+            amplification,
+            peg,
+        };
+        pool.reserves.p_ref = pool.get_price();
+        pool
+    }
+
+    /// Sets the fee, in basis points, taken from the input of every swap.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the fee was set, or an `Err` if it is negative
+    /// or would take the whole input.
+    pub fn set_fee_bps(&mut self, fee_bps: Decimal) -> Result<(), ArppError> {
+        self.reserves.set_fee_bps(fee_bps)
+    }
+
+    /// Returns the fee, in basis points, taken from the input of every swap.
+    pub fn get_fee_bps(&self) -> Decimal {
+        self.reserves.fee_bps
+    }
+
+    /// Returns the amplification coefficient `A`.
+    pub fn get_amplification(&self) -> Decimal {
+        self.amplification
+    }
+
+    fn swap(&mut self, token_in: Token, amount_in: Decimal) -> Result<SwapResult, ArppError> {
+        let (ann, peg) = (self.ann(), self.peg);
+        self.reserves.swap(
+            token_in,
+            amount_in,
+            |reserves, net_in| amount_out(ann, peg, reserves, token_in, net_in),
+            |token_a, token_b| price(ann, peg, token_a, token_b),
+        )
+    }
+
+    /// Returns `4A`, the amplification as it appears in the invariant.
+    fn ann(&self) -> Decimal {
+        self.amplification * dec!(4)
+    }
+}
+
+impl Amm for StableSwapPool {
+    fn swap_a_to_b(&mut self, amount_a: Decimal) -> Result<SwapResult, ArppError> {
+        self.swap(Token::A, amount_a)
+    }
+
+    fn swap_b_to_a(&mut self, amount_b: Decimal) -> Result<SwapResult, ArppError> {
+        self.swap(Token::B, amount_b)
+    }
+
+    fn deposit(
+        &mut self,
+        provider: &str,
+        amount_a: Decimal,
+        amount_b: Decimal,
+    ) -> Result<Decimal, ArppError> {
+        let (ann, peg) = (self.ann(), self.peg);
+        self.reserves
+            .deposit(provider, amount_a, amount_b, |token_a, token_b| {
+                price(ann, peg, token_a, token_b)
+            })
+    }
+
+    fn withdraw(
+        &mut self,
+        provider: &str,
+        shares: Decimal,
+    ) -> Result<(Decimal, Decimal), ArppError> {
+        self.reserves.withdraw(provider, shares)
+    }
+
+    fn get_price(&self) -> Decimal {
+        price(
+            self.ann(),
+            self.peg,
+            self.reserves.token_a,
+            self.reserves.token_b,
+        )
+    }
+
+    fn get_balances(&self) -> (Decimal, Decimal) {
+        (self.reserves.token_a, self.reserves.token_b)
+    }
+
+    fn get_fee_revenue(&self) -> (Decimal, Decimal) {
+        (self.reserves.fees_a, self.reserves.fees_b)
+    }
+
+    fn get_p_ref(&self) -> Decimal {
+        self.reserves.p_ref
+    }
+
+    fn update_p_ref(&mut self, oracle: &mut dyn PriceOracle) -> Result<(), ArppError> {
+        let pool_price = self.get_price();
+        self.reserves.follow_oracle(oracle, pool_price)
+    }
+}
+
+/// Returns the amount of the other token paid out for `net_in` of `token_in`.
+fn amount_out(
+    ann: Decimal,
+    peg: Decimal,
+    reserves: &Reserves,
+    token_in: Token,
+    net_in: Decimal,
+) -> Result<Decimal, ArppError> {
+    let (x, y) = (reserves.token_a * peg, reserves.token_b);
+    let d = invariant(ann, x, y)?;
+    match token_in {
+        Token::A => Ok(y - other_balance(ann, x + net_in * peg, d)?),
+        Token::B => Ok((x - other_balance(ann, y + net_in, d)?) / peg),
+    }
+}
+
+/// Returns the marginal price, in units of B per unit of A, for given balances.
+///
+/// Falls back to the constant-product price `token_b / token_a`, which the
+/// StableSwap price tends to far from balance, if the invariant cannot be computed.
+fn price(ann: Decimal, peg: Decimal, token_a: Decimal, token_b: Decimal) -> Decimal {
+    let (x, y) = (token_a * peg, token_b);
+    let marginal = invariant(ann, x, y).ok().and_then(|d| {
+        // Both partial derivatives of the invariant involve D^3 / (4 x y)
+        let d_p = (d / (x * dec!(2))).checked_mul(d)? / (y * dec!(2));
+        let d_p = d_p.checked_mul(d)?;
+        let dx = ann + d_p / x;
+        let dy = ann + d_p / y;
+        Some(peg * dx / dy)
+    });
+    marginal.unwrap_or_else(|| token_b.checked_div(token_a).unwrap_or(Decimal::MAX))
+}
+
+/// Solves the invariant `D` of balances `x` and `y` by Newton's method.
+fn invariant(ann: Decimal, x: Decimal, y: Decimal) -> Result<Decimal, ArppError> {
+    let sum = x + y;
+    if sum.is_zero() {
+        return Ok(Decimal::ZERO);
+    }
+    check_balances(x, y)?;
+    let mut d = sum;
+    for _ in 0..MAX_ITERATIONS {
+        // D^3 / (4 x y), divided as it goes so that it stays of the order of D
+        let d_p = (d / (x * dec!(2)))
+            .checked_mul(d)
+            .and_then(|d_p| (d_p / (y * dec!(2))).checked_mul(d))
+            .ok_or(ArppError::Overflow)?;
+        let previous = d;
+        d = (ann * sum + d_p * dec!(2)) / ((ann - Decimal::ONE) * d + d_p * dec!(3)) * d;
+        if (d - previous).abs() <= d * CONVERGENCE {
+            return Ok(d);
+        }
+    }
+    Err(ArppError::NoConvergence)
+}
+
+/// Solves the balance paired with `balance` on the invariant `d` by Newton's method.
+fn other_balance(ann: Decimal, balance: Decimal, d: Decimal) -> Result<Decimal, ArppError> {
+    check_balances(balance, d)?;
+    // The other balance y solves y^2 + (b - D) y = c
+    let c = (d / (balance * dec!(2)))
+        .checked_mul(d / (ann * dec!(2)))
+        .and_then(|c| c.checked_mul(d))
+        .ok_or(ArppError::Overflow)?;
+    let b = balance + d / ann;
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let previous = y;
+        let y_squared = y.checked_mul(y).ok_or(ArppError::Overflow)?;
+        y = (y_squared + c) / (y * dec!(2) + b - d);
+        if (y - previous).abs() <= y * CONVERGENCE {
+            return Ok(y);
+        }
+    }
+    Err(ArppError::NoConvergence)
+}
+
+/// Returns an error if one of the balances is drained, where the invariant degenerates.
+fn check_balances(x: Decimal, y: Decimal) -> Result<(), ArppError> {
+    for (token, balance) in [(Token::A, x), (Token::B, y)] {
+        if balance <= Decimal::ZERO {
+            return Err(ArppError::InsufficientLiquidity {
+                token,
+                needed: balance,
+                available: balance,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests_stable_swap {
+    use super::*;
+    use crate::amm::constant_product::ConstantProductPool;
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_balanced_pool_trades_at_peg() {
+        let pool = StableSwapPool::new(dec!(1000), dec!(2000), dec!(100), dec!(2));
+        assert_approx_eq!(pool.get_price(), dec!(2), dec!(0.000000000001));
+        assert_eq!(pool.get_p_ref(), pool.get_price());
+    }
+
+    #[test]
+    fn test_invariant_is_kept_by_swaps() {
+        let mut pool = StableSwapPool::new(dec!(1000), dec!(1000), dec!(50), dec!(1));
+        let before = invariant(pool.ann(), dec!(1000), dec!(1000)).unwrap();
+        pool.swap_a_to_b(dec!(300)).unwrap();
+        let (token_a, token_b) = pool.get_balances();
+        let after = invariant(pool.ann(), token_a, token_b).unwrap();
+        assert!(after >= before);
+        assert_approx_eq!(after, before, dec!(0.000000001));
+    }
+
+    #[test]
+    fn test_price_impact_between_constant_sum_and_product() {
+        let mut stable = StableSwapPool::new(dec!(1000), dec!(1000), dec!(100), dec!(1));
+        let mut product = ConstantProductPool::new(dec!(1000), dec!(1000));
+        let stable_out = stable.swap_a_to_b(dec!(200)).unwrap().amount_out;
+        let product_out = product.swap_a_to_b(dec!(200)).unwrap().amount_out;
+        assert!(stable_out > product_out && stable_out < dec!(200));
+        assert!(stable.get_price() < dec!(1) && stable.get_price() > product.get_price());
+    }
+
+    #[test]
+    fn test_round_trip_loses_value() {
+        let mut pool = StableSwapPool::new(dec!(1000), dec!(1000), dec!(100), dec!(1));
+        pool.set_fee_bps(dec!(4)).unwrap();
+        let out = pool.swap_b_to_a(dec!(100)).unwrap().amount_out;
+        let back = pool.swap_a_to_b(out).unwrap().amount_out;
+        assert!(back < dec!(100));
+    }
+
+    #[test]
+    fn test_cannot_swap_against_drained_pool() {
+        let mut pool = StableSwapPool::new(dec!(1000), dec!(0), dec!(100), dec!(1));
+        assert!(matches!(
+            pool.swap_a_to_b(dec!(10)),
+            Err(ArppError::InsufficientLiquidity { .. })
+        ));
+        assert_eq!(pool.get_price(), dec!(0));
+    }
+}
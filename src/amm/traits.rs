@@ -0,0 +1,82 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::breaker::BreakerStats;
+use crate::arpp::error::ArppError;
+use crate::arpp::oracle::{OracleIncident, PriceOracle};
+use crate::arpp::ramp::Ramp;
+use crate::arpp::swap::SwapResult;
+use rust_decimal::Decimal;
+
+/// An automated market maker for a pair of tokens A and B.
+///
+/// Implemented by the ARPP `LiquidityPool` and by the baseline pools it is
+/// compared against, so simulations and trading strategies can run against any of
+/// them. Prices are always quoted in units of B per unit of A.
+///
+/// Every model keeps a reference price `p_ref` fed by a `PriceOracle`, but only
+/// the ARPP pool prices swaps from it; the baselines merely track it as the
+/// external market price, which strategies can trade against.
+///
+/// # Methods
+///
+/// - `swap_a_to_b`: Swaps an amount of Token A for Token B.
+/// - `swap_b_to_a`: Swaps an amount of Token B for Token A.
+/// - `deposit`: Deposits amounts of both tokens on behalf of a provider, minting
+///   LP shares.
+/// - `withdraw`: Burns LP shares of a provider for their pro-rata part of both
+///   tokens.
+/// - `get_price`: Returns the marginal price of the pool.
+/// - `get_balances`: Returns the balances of Token A and Token B.
+/// - `get_fee_revenue`: Returns the cumulative fees collected in Token A and Token B.
+/// - `get_p_ref`: Returns the reference price last reported by the oracle.
+/// - `update_p_ref`: Updates the reference price with the next price of an oracle.
+/// - `get_oracle_incidents`: Returns the breaches of the pool's oracle guards, if
+///   it has any.
+/// - `get_breaker_stats`: Returns how often and how long the pool's circuit
+///   breaker was active, if it has one.
+/// - `get_ramp`: Returns the parameter change in progress, if the pool ramps its
+///   parameters.
+pub trait Amm: Send + Sync {
+    fn swap_a_to_b(&mut self, amount_a: Decimal) -> Result<SwapResult, ArppError>;
+
+    fn swap_b_to_a(&mut self, amount_b: Decimal) -> Result<SwapResult, ArppError>;
+
+    fn deposit(
+        &mut self,
+        provider: &str,
+        amount_a: Decimal,
+        amount_b: Decimal,
+    ) -> Result<Decimal, ArppError>;
+
+    fn withdraw(
+        &mut self,
+        provider: &str,
+        shares: Decimal,
+    ) -> Result<(Decimal, Decimal), ArppError>;
+
+    fn get_price(&self) -> Decimal;
+
+    fn get_balances(&self) -> (Decimal, Decimal);
+
+    fn get_fee_revenue(&self) -> (Decimal, Decimal);
+
+    fn get_p_ref(&self) -> Decimal;
+
+    fn update_p_ref(&mut self, oracle: &mut dyn PriceOracle) -> Result<(), ArppError>;
+
+    fn get_oracle_incidents(&self) -> &[OracleIncident] {
+        &[]
+    }
+
+    fn get_breaker_stats(&self) -> Option<BreakerStats> {
+        None
+    }
+
+    fn get_ramp(&self) -> Option<&Ramp> {
+        None
+    }
+}
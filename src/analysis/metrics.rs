@@ -3,10 +3,14 @@
    Email: jb@taunais.com
    Date: 10/9/24
 ******************************************************************************/
-use crate::arpp::liquidity_pool::LiquidityPool;
+use crate::amm::traits::Amm;
+use crate::arpp::breaker::BreakerStats;
+use crate::arpp::oracle::OracleIncident;
+use crate::arpp::ramp::Ramp;
 use crate::simulation::result::SimulationResult;
 use rust_decimal::{Decimal, MathematicalOps};
 use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
 use std::ops::Neg;
 
 /// A structure representing the metrics at a particular step in a pool's lifetime.
@@ -20,7 +24,7 @@ use std::ops::Neg;
 /// - `balances_a`: The current balance of asset `A` at this step.
 /// - `balances_b`: The current balance of asset `B` at this step.
 /// - `ratio`: The current ratio between the assets at this step.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct PoolMetricsStep {
     pub price: Decimal,
     pub p_ref: Decimal,
@@ -38,14 +42,26 @@ pub struct PoolMetricsStep {
 /// - `liquidity_depth`: Represents the depth of liquidity in the pool as a `Decimal`.
 /// - `trading_volume`: Tracks the trading volume within the pool as a `Decimal`.
 /// - `impermanent_loss`: Accumulates the impermanent loss within the pool as a `Decimal`.
-///
-#[derive(Clone, Default, Debug, PartialEq)]
+/// - `fee_revenue_a`: The cumulative swap fees collected by the pool in token A.
+/// - `fee_revenue_b`: The cumulative swap fees collected by the pool in token B.
+/// - `fee_income`: The cumulative swap fees valued in token B at the pool price.
+/// - `oracle_incidents`: The breaches of the pool's oracle guards, oldest first.
+/// - `breaker`: How often and how long the pool's circuit breaker was active.
+/// - `ramp`: The parameter change of the pool in progress at the last step.
+///
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
 pub struct PoolMetrics {
     pub steps: Vec<PoolMetricsStep>,
     pub price_volatility: Decimal,
     pub liquidity_depth: Decimal,
     pub trading_volume: Decimal,
     pub impermanent_loss: Decimal,
+    pub fee_revenue_a: Decimal,
+    pub fee_revenue_b: Decimal,
+    pub fee_income: Decimal,
+    pub oracle_incidents: Vec<OracleIncident>,
+    pub breaker: BreakerStats,
+    pub ramp: Option<Ramp>,
 }
 
 impl PoolMetrics {
@@ -56,6 +72,12 @@ impl PoolMetrics {
             liquidity_depth: Decimal::ZERO,
             trading_volume: Decimal::ZERO,
             impermanent_loss: Decimal::ZERO,
+            fee_revenue_a: Decimal::ZERO,
+            fee_revenue_b: Decimal::ZERO,
+            fee_income: Decimal::ZERO,
+            oracle_incidents: Vec::new(),
+            breaker: BreakerStats::default(),
+            ramp: None,
         }
     }
 
@@ -162,7 +184,7 @@ impl PoolMetrics {
 }
 
 pub fn accumulate_pool_metrics(
-    pool: &mut LiquidityPool,
+    pool: &dyn Amm,
     metrics: &mut PoolMetrics,
     initial_step: &PoolMetricsStep,
 ) {
@@ -185,6 +207,22 @@ pub fn accumulate_pool_metrics(
 
     // Update the accumulated metrics based on the current step
     metrics.update_metrics(&current_step, initial_step);
+
+    // Fees are already cumulative in the pool
+    (metrics.fee_revenue_a, metrics.fee_revenue_b) = pool.get_fee_revenue();
+    metrics.fee_income = metrics.fee_revenue_a * current_price + metrics.fee_revenue_b;
+
+    // So is the incident log, of which only the new entries are copied
+    let incidents = pool.get_oracle_incidents();
+    if let Some(new_incidents) = incidents.get(metrics.oracle_incidents.len()..) {
+        metrics.oracle_incidents.extend_from_slice(new_incidents);
+    }
+
+    // And the circuit breaker and parameter ramp, in the pools that have them
+    if let Some(stats) = pool.get_breaker_stats() {
+        metrics.breaker = stats;
+    }
+    metrics.ramp = pool.get_ramp().copied();
 }
 
 /// Calculates the price volatility given the current price and initial price.
@@ -425,12 +463,119 @@ fn calculate_liquidity_efficiency(average_liquidity_change: Decimal) -> Decimal
     efficiency.clamp(Decimal::ZERO, Decimal::ONE)
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct SimulationAnalysis {
     pub price_stability: Decimal,
     pub average_price_impact: Decimal,
     pub liquidity_efficiency: Decimal,
 }
 
+#[cfg(test)]
+mod tests_accumulate_pool_metrics {
+    use super::*;
+    use crate::arpp::breaker::{BreakerPolicy, CircuitBreaker, Cooldown, PriceBand};
+    use crate::arpp::formula::token_ratio;
+    use crate::arpp::liquidity_pool::LiquidityPool;
+    use crate::arpp::oracle::{ConstantOracle, GuardPolicy, OracleGuard, OracleGuards};
+    use crate::arpp::ramp::{RampLength, RampParameter};
+
+    fn initial_step(pool: &LiquidityPool) -> PoolMetricsStep {
+        let (token_a, token_b) = pool.get_balances();
+        PoolMetricsStep {
+            price: pool.get_price(),
+            p_ref: pool.get_p_ref(),
+            balances_a: token_a,
+            balances_b: token_b,
+            ratio: token_ratio(token_a, token_b),
+        }
+    }
+
+    #[test]
+    fn test_fee_revenue_is_reported() {
+        let mut pool = LiquidityPool::new(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
+        pool.set_fee_bps(dec!(30)).unwrap();
+        let initial_step = initial_step(&pool);
+        let mut metrics = PoolMetrics::new();
+
+        pool.swap_a_to_b(dec!(10)).unwrap();
+        accumulate_pool_metrics(&pool, &mut metrics, &initial_step);
+        pool.swap_b_to_a(dec!(20)).unwrap();
+        accumulate_pool_metrics(&pool, &mut metrics, &initial_step);
+
+        assert_eq!(metrics.fee_revenue_a, dec!(0.03));
+        assert_eq!(metrics.fee_revenue_b, dec!(0.06));
+        assert_eq!(
+            metrics.fee_income,
+            dec!(0.03) * pool.get_price() + dec!(0.06)
+        );
+        assert_eq!(metrics.steps.len(), 2);
+    }
+
+    #[test]
+    fn test_oracle_incidents_are_reported_once() {
+        let mut pool = LiquidityPool::new(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
+        pool.set_oracle_guards(OracleGuards {
+            max_jump_bps: Some(OracleGuard::new(dec!(100), GuardPolicy::Reject)),
+            ..OracleGuards::default()
+        });
+        let initial_step = initial_step(&pool);
+        let mut metrics = PoolMetrics::new();
+        let mut oracle = ConstantOracle::new(dec!(2));
+
+        assert!(pool.update_p_ref(&mut oracle).is_err());
+        accumulate_pool_metrics(&pool, &mut metrics, &initial_step);
+        accumulate_pool_metrics(&pool, &mut metrics, &initial_step);
+        assert!(pool.update_p_ref(&mut oracle).is_err());
+        accumulate_pool_metrics(&pool, &mut metrics, &initial_step);
+
+        assert_eq!(metrics.oracle_incidents, pool.get_oracle_incidents());
+        assert_eq!(metrics.oracle_incidents.len(), 2);
+    }
+
+    #[test]
+    fn test_breaker_stats_are_reported() {
+        let mut pool = LiquidityPool::new(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
+        pool.set_circuit_breaker(Some(CircuitBreaker::new(
+            PriceBand::Percent(dec!(1)),
+            BreakerPolicy::Cooldown(Cooldown::Steps(1)),
+        )));
+        let initial_step = initial_step(&pool);
+        let mut metrics = PoolMetrics::new();
+
+        assert!(pool.swap_a_to_b(dec!(100)).is_err());
+        assert!(pool.swap_a_to_b(dec!(1)).is_err());
+        pool.update_p_ref(&mut ConstantOracle::new(dec!(1)))
+            .unwrap();
+        accumulate_pool_metrics(&pool, &mut metrics, &initial_step);
+
+        assert_eq!(metrics.breaker, pool.get_breaker_stats());
+        assert_eq!(
+            (metrics.breaker.trips, metrics.breaker.halted_swaps),
+            (1, 1)
+        );
+        assert_eq!(metrics.breaker.active_steps, 1);
+    }
+
+    #[test]
+    fn test_ramp_in_progress_is_reported() {
+        let mut pool = LiquidityPool::new(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
+        pool.schedule_ramp(RampParameter::Beta, dec!(2), RampLength::Steps(2))
+            .unwrap();
+        let initial_step = initial_step(&pool);
+        let mut metrics = PoolMetrics::new();
+        let mut oracle = ConstantOracle::new(dec!(1));
+
+        pool.update_p_ref(&mut oracle).unwrap();
+        accumulate_pool_metrics(&pool, &mut metrics, &initial_step);
+        let ramp = metrics.ramp.unwrap();
+        assert_eq!((ramp.parameter, ramp.steps_done), (RampParameter::Beta, 1));
+
+        pool.update_p_ref(&mut oracle).unwrap();
+        accumulate_pool_metrics(&pool, &mut metrics, &initial_step);
+        assert_eq!(metrics.ramp, None);
+    }
+}
+
 #[cfg(test)]
 mod tests_price_volatility {
     use super::*;
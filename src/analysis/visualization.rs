@@ -468,6 +468,12 @@ mod tests_graphs {
                 liquidity_depth: Decimal::new(2, 2),
                 trading_volume: Default::default(),
                 impermanent_loss: Default::default(),
+                fee_revenue_a: Default::default(),
+                fee_revenue_b: Default::default(),
+                fee_income: Default::default(),
+                oracle_incidents: vec![],
+                breaker: Default::default(),
+                ramp: None,
             },
             PoolMetrics {
                 steps: vec![],
@@ -475,6 +481,12 @@ mod tests_graphs {
                 liquidity_depth: Decimal::new(4, 2),
                 trading_volume: Default::default(),
                 impermanent_loss: Default::default(),
+                fee_revenue_a: Default::default(),
+                fee_revenue_b: Default::default(),
+                fee_income: Default::default(),
+                oracle_incidents: vec![],
+                breaker: Default::default(),
+                ramp: None,
             },
         ];
 
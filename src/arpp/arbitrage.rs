@@ -0,0 +1,386 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::error::ArppError;
+use crate::arpp::liquidity_pool::LiquidityPool;
+use crate::arpp::swap::{SwapResult, Token};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// The provider ID under which a cycle deposits liquidity.
+pub const ARBITRAGEUR: &str = "arbitrageur";
+
+/// The fraction of the pool's balances traded by the cycles of a default scan.
+pub const DEFAULT_CYCLE_FRACTION: Decimal = dec!(0.05);
+
+/// Relative distance from a price at which a pool is taken to be at it.
+const REACHED_TOLERANCE: Decimal = dec!(0.000000001);
+
+/// One operation of a cycle run against a pool by a single trader.
+///
+/// Amounts are given as fractions of the pool's balances when the step runs, so
+/// that the same cycle can be run against pools of any size and state.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CycleStep {
+    /// Swaps in `fraction` of the pool's balance of `token_in`.
+    SwapIn { token_in: Token, fraction: Decimal },
+    /// Swaps for `fraction` of the pool's balance of `token_out`.
+    SwapOut { token_out: Token, fraction: Decimal },
+    /// Swaps the whole output of the previous swap back in.
+    SwapBack,
+    /// Swaps in all of `token_in` the trader has gained so far in the cycle, if
+    /// any.
+    SwapGains { token_in: Token },
+    /// Deposits `fraction_a` and `fraction_b` of the pool's balances of Token A
+    /// and Token B as `ARBITRAGEUR`.
+    Deposit {
+        fraction_a: Decimal,
+        fraction_b: Decimal,
+    },
+    /// Withdraws all the shares of `ARBITRAGEUR`.
+    WithdrawAll,
+}
+
+/// A sequence of operations that leaves the trader running it without LP
+/// shares, so that its gains are measured in tokens alone.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cycle {
+    pub steps: Vec<CycleStep>,
+}
+
+impl Cycle {
+    pub fn new(steps: Vec<CycleStep>) -> Self {
+        Self { steps }
+    }
+
+    /// Swaps `fraction` of the pool's balance of `token_in` in and the whole
+    /// output back.
+    pub fn round_trip(token_in: Token, fraction: Decimal) -> Self {
+        Self::new(vec![
+            CycleStep::SwapIn { token_in, fraction },
+            CycleStep::SwapBack,
+        ])
+    }
+
+    /// Buys `fraction` of the pool's balance of `token_out` and sells it back.
+    pub fn exact_out_round_trip(token_out: Token, fraction: Decimal) -> Self {
+        Self::new(vec![
+            CycleStep::SwapOut {
+                token_out,
+                fraction,
+            },
+            CycleStep::SwapBack,
+        ])
+    }
+
+    /// Deposits `fraction` of the pool's balance of `token` alone, withdraws it
+    /// and swaps the other token received back, trying to trade through the
+    /// deposit at the price before it.
+    pub fn single_sided_deposit(token: Token, fraction: Decimal) -> Self {
+        let (fraction_a, fraction_b) = match token {
+            Token::A => (fraction, Decimal::ZERO),
+            Token::B => (Decimal::ZERO, fraction),
+        };
+        Self::new(vec![
+            CycleStep::Deposit {
+                fraction_a,
+                fraction_b,
+            },
+            CycleStep::WithdrawAll,
+            CycleStep::SwapGains {
+                token_in: token.other(),
+            },
+        ])
+    }
+
+    /// Deposits `fraction` of both balances, runs a round trip of the same
+    /// fraction of `token_in` and withdraws, trying to recover the fees of the
+    /// round trip through the deposit.
+    pub fn deposit_around_round_trip(token_in: Token, fraction: Decimal) -> Self {
+        let mut steps = vec![CycleStep::Deposit {
+            fraction_a: fraction,
+            fraction_b: fraction,
+        }];
+        steps.extend(Self::round_trip(token_in, fraction).steps);
+        steps.push(CycleStep::WithdrawAll);
+        Self::new(steps)
+    }
+
+    /// Returns every kind of cycle above, in both directions, with trades of
+    /// `fraction` of the pool's balances.
+    pub fn standard(fraction: Decimal) -> Vec<Self> {
+        let mut cycles = Vec::new();
+        for token in [Token::A, Token::B] {
+            cycles.push(Self::round_trip(token, fraction));
+            cycles.push(Self::exact_out_round_trip(token, fraction));
+            cycles.push(Self::single_sided_deposit(token, fraction));
+            cycles.push(Self::deposit_around_round_trip(token, fraction));
+        }
+        cycles
+    }
+}
+
+/// The net token flows of a trader over a cycle, positive when the trader
+/// received more than it paid.
+///
+/// # Fields
+///
+/// * `delta_a` - The net amount of Token A received.
+/// * `delta_b` - The net amount of Token B received.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CycleOutcome {
+    pub delta_a: Decimal,
+    pub delta_b: Decimal,
+}
+
+impl CycleOutcome {
+    /// Returns `true` if the trader ended with more of one token and no less of
+    /// the other: a profit taken from the pool at no cost.
+    pub fn is_profitable(&self) -> bool {
+        self.delta_a >= Decimal::ZERO
+            && self.delta_b >= Decimal::ZERO
+            && (self.delta_a > Decimal::ZERO || self.delta_b > Decimal::ZERO)
+    }
+
+    /// Returns the net flows valued in Token B at `price`.
+    pub fn value_in_b(&self, price: Decimal) -> Decimal {
+        self.delta_a * price + self.delta_b
+    }
+
+    /// Returns `true` if the trader is better off after the cycle: it is
+    /// profitable, or the net flows are worth more than nothing at `price`, such
+    /// as the price of the pool before the cycle.
+    pub fn gains_value(&self, price: Decimal) -> bool {
+        self.is_profitable() || self.value_in_b(price) > Decimal::ZERO
+    }
+
+    fn delta_mut(&mut self, token: Token) -> &mut Decimal {
+        match token {
+            Token::A => &mut self.delta_a,
+            Token::B => &mut self.delta_b,
+        }
+    }
+}
+
+/// A cycle that left the trader better off, found by `find_profitable_cycles`.
+///
+/// # Fields
+///
+/// * `price` - The price the pool was moved to before running the cycle.
+/// * `cycle` - The cycle run.
+/// * `outcome` - The net token flows of the trader.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArbitrageFinding {
+    pub price: Decimal,
+    pub cycle: Cycle,
+    pub outcome: CycleOutcome,
+}
+
+/// Runs `cycle` on a copy of `pool` and returns the net token flows of the
+/// trader.
+///
+/// # Returns
+///
+/// A `Result` with the `CycleOutcome`, or the error of the first operation the
+/// pool refused.
+pub fn run_cycle(pool: &LiquidityPool, cycle: &Cycle) -> Result<CycleOutcome, ArppError> {
+    let mut pool = pool.clone();
+    let mut outcome = CycleOutcome::default();
+    let mut last_swap: Option<SwapResult> = None;
+    for step in &cycle.steps {
+        let balance = |token| match token {
+            Token::A => pool.get_balances().0,
+            Token::B => pool.get_balances().1,
+        };
+        let swap = match *step {
+            CycleStep::SwapIn { token_in, fraction } => {
+                let amount_in = balance(token_in) * fraction;
+                Some(pool.swap_exact_in(token_in, amount_in, Decimal::ZERO, None)?)
+            }
+            CycleStep::SwapOut {
+                token_out,
+                fraction,
+            } => {
+                let amount_out = balance(token_out) * fraction;
+                Some(pool.swap_exact_out(token_out, amount_out, Decimal::MAX, None)?)
+            }
+            CycleStep::SwapBack => match last_swap {
+                Some(previous) => Some(pool.swap_exact_in(
+                    previous.token_out(),
+                    previous.amount_out,
+                    Decimal::ZERO,
+                    None,
+                )?),
+                None => None,
+            },
+            CycleStep::SwapGains { token_in } => {
+                let gains = *outcome.delta_mut(token_in);
+                if gains > Decimal::ZERO {
+                    Some(pool.swap_exact_in(token_in, gains, Decimal::ZERO, None)?)
+                } else {
+                    None
+                }
+            }
+            CycleStep::Deposit {
+                fraction_a,
+                fraction_b,
+            } => {
+                let (amount_a, amount_b) = (
+                    balance(Token::A) * fraction_a,
+                    balance(Token::B) * fraction_b,
+                );
+                pool.deposit(ARBITRAGEUR, amount_a, amount_b)?;
+                outcome.delta_a -= amount_a;
+                outcome.delta_b -= amount_b;
+                None
+            }
+            CycleStep::WithdrawAll => {
+                if let Some(position) = pool.get_position(ARBITRAGEUR) {
+                    let (amount_a, amount_b) = pool.withdraw(ARBITRAGEUR, position.shares)?;
+                    outcome.delta_a += amount_a;
+                    outcome.delta_b += amount_b;
+                }
+                None
+            }
+        };
+        if let Some(result) = swap {
+            *outcome.delta_mut(result.token_in) -= result.amount_in;
+            *outcome.delta_mut(result.token_out()) += result.amount_out;
+            last_swap = Some(result);
+        }
+    }
+    Ok(outcome)
+}
+
+/// Looks for cycles that leave the trader better off, with the pool moved to each
+/// of `prices` in turn.
+///
+/// A cycle is reported if it takes a profit from the pool at no cost, or if its
+/// net flows gain value at the price of the pool before the cycle, as checked by
+/// `CycleOutcome::gains_value`.
+///
+/// The pool is moved by the swap `LiquidityPool::amount_to_reach_price` sizes.
+/// Prices the pool cannot reach, and cycles the pool refuses, are skipped, as
+/// no profit can be taken through them.
+///
+/// # Arguments
+///
+/// * `pool` - The pool to examine, which is not modified.
+/// * `prices` - The prices at which to run the cycles.
+/// * `cycles` - The cycles to run, such as `Cycle::standard`.
+///
+/// # Returns
+///
+/// The profitable cycles found, in the order of `prices` and `cycles`.
+pub fn find_profitable_cycles(
+    pool: &LiquidityPool,
+    prices: &[Decimal],
+    cycles: &[Cycle],
+) -> Vec<ArbitrageFinding> {
+    let mut findings = Vec::new();
+    for &price in prices {
+        let Some(moved) = move_to_price(pool, price) else {
+            debug!("Skipping unreachable price {}", price);
+            continue;
+        };
+        let price_before = moved.get_price();
+        for cycle in cycles {
+            match run_cycle(&moved, cycle) {
+                Ok(outcome) if outcome.gains_value(price_before) => {
+                    warn!("Profitable cycle at price {}: {:?}", price, outcome);
+                    findings.push(ArbitrageFinding {
+                        price,
+                        cycle: cycle.clone(),
+                        outcome,
+                    });
+                }
+                Ok(_) => {}
+                Err(e) => debug!("Cycle refused at price {}: {}", price, e),
+            }
+        }
+    }
+    findings
+}
+
+/// Returns `count` prices evenly spread over the reachable price range of
+/// `pool`, both ends excluded, for use with `find_profitable_cycles`.
+pub fn price_grid(pool: &LiquidityPool, count: usize) -> Vec<Decimal> {
+    let (low, high) = pool.get_price_range();
+    let step = (high - low) / Decimal::from(count + 1);
+    (1..=count).map(|i| low + step * Decimal::from(i)).collect()
+}
+
+/// Returns a copy of `pool` moved to `price`, or `None` if it cannot get there.
+fn move_to_price(pool: &LiquidityPool, price: Decimal) -> Option<LiquidityPool> {
+    let mut moved = pool.clone();
+    let Ok((token_in, amount_in)) = moved.amount_to_reach_price(price) else {
+        // A price within dust of the current one needs a trade below resolution
+        let reached = (pool.get_price() / price - Decimal::ONE).abs() <= REACHED_TOLERANCE;
+        return reached.then_some(moved);
+    };
+    if amount_in > Decimal::ZERO {
+        moved
+            .swap_exact_in(token_in, amount_in, Decimal::ZERO, None)
+            .ok()?;
+    }
+    Some(moved)
+}
+
+#[cfg(test)]
+mod tests_arbitrage {
+    use super::*;
+    use crate::arpp::formula::AsymmetricParameters;
+
+    fn create_pool() -> LiquidityPool {
+        LiquidityPool::new(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1))
+    }
+
+    #[test]
+    fn test_round_trip_loses_the_fee() {
+        let mut pool = create_pool();
+        pool.set_fee_bps(dec!(30)).unwrap();
+        let outcome = run_cycle(&pool, &Cycle::round_trip(Token::A, dec!(0.01))).unwrap();
+        assert_eq!(outcome.delta_b, Decimal::ZERO);
+        assert!(outcome.delta_a < dec!(-0.05));
+        assert!(!outcome.is_profitable());
+    }
+
+    #[test]
+    fn test_no_profitable_cycles_across_pool_states() {
+        let mut pool = create_pool();
+        pool.set_fee_bps(dec!(5)).unwrap();
+        let prices = price_grid(&pool, 5);
+        let findings =
+            find_profitable_cycles(&pool, &prices, &Cycle::standard(DEFAULT_CYCLE_FRACTION));
+        assert_eq!(findings, vec![]);
+    }
+
+    #[test]
+    fn test_no_profitable_cycles_across_balance_of_asymmetric_pool() {
+        // Large trades cross balance, where the price changes curvature
+        let parameters = AsymmetricParameters::new(dec!(0.5), dec!(0.1), dec!(1), dec!(5));
+        let pool = LiquidityPool::new_asymmetric(dec!(1000), dec!(1000), dec!(1), parameters);
+        let findings =
+            find_profitable_cycles(&pool, &price_grid(&pool, 7), &Cycle::standard(dec!(0.3)));
+        assert_eq!(findings, vec![]);
+    }
+
+    #[test]
+    fn test_outcome_profitability() {
+        let outcome = |delta_a, delta_b| CycleOutcome { delta_a, delta_b };
+        assert!(outcome(dec!(0.1), dec!(0)).is_profitable());
+        assert!(!outcome(dec!(0), dec!(0)).is_profitable());
+        assert!(!outcome(dec!(1), dec!(-0.1)).is_profitable());
+        assert_eq!(outcome(dec!(1), dec!(-0.5)).value_in_b(dec!(2)), dec!(1.5));
+
+        // Ending with less of one token still gains value if the other is worth more
+        assert!(outcome(dec!(1), dec!(-0.5)).gains_value(dec!(2)));
+        assert!(!outcome(dec!(1), dec!(-0.5)).gains_value(dec!(0.5)));
+        assert!(outcome(dec!(0.1), dec!(0)).gains_value(dec!(1)));
+    }
+}
@@ -0,0 +1,292 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::error::ArppError;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+/// Number of bisection steps taken to find the ratio at which the price reaches
+/// the edge of the band, enough to exhaust the precision of `Decimal`.
+const BISECTION_STEPS: usize = 100;
+
+/// The prices around `p_ref` the ARPP price may move within.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PriceBand {
+    /// Prices within this percentage of `p_ref`.
+    Percent(Decimal),
+    /// Prices within this distance of `p_ref`, in units of B per unit of A.
+    Absolute(Decimal),
+}
+
+impl PriceBand {
+    /// Returns the lowest and highest prices allowed around `p_ref`.
+    pub(crate) fn around(&self, p_ref: Decimal) -> (Decimal, Decimal) {
+        let width = match *self {
+            PriceBand::Percent(percent) => p_ref * percent / dec!(100),
+            PriceBand::Absolute(width) => width,
+        };
+        (p_ref - width, p_ref + width)
+    }
+}
+
+/// How long swaps stay halted once the circuit breaker trips.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Cooldown {
+    /// Until this many `p_ref` updates, one per simulation step, have been made.
+    Steps(u64),
+    /// Until this much time has passed.
+    Duration(Duration),
+}
+
+/// What the pool does with a swap that would move the price out of the band.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BreakerPolicy {
+    /// Refuse the swap.
+    Reject,
+    /// Execute only the part of the swap that brings the price to the edge of the
+    /// band. A swap starting beyond that edge is refused.
+    Cap,
+    /// Refuse the swap and halt all swaps for the cooldown.
+    Cooldown(Cooldown),
+}
+
+/// A circuit breaker keeping the ARPP price within a band around `p_ref`.
+///
+/// Only swaps are checked. When `p_ref` moves, the price may find itself outside
+/// the band; swaps moving it back are still allowed, while swaps moving it further
+/// out breach the breaker.
+///
+/// # Fields
+///
+/// * `band` - The prices allowed around `p_ref`.
+/// * `policy` - The policy applied to swaps that breach the band.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CircuitBreaker {
+    pub band: PriceBand,
+    pub policy: BreakerPolicy,
+}
+
+impl CircuitBreaker {
+    pub fn new(band: PriceBand, policy: BreakerPolicy) -> Self {
+        Self { band, policy }
+    }
+
+    /// Checks a swap moving the price from `price_before` to `price_after`.
+    ///
+    /// # Returns
+    ///
+    /// The band around `p_ref` if the swap breaches it, or `None`.
+    pub(crate) fn check(
+        &self,
+        p_ref: Decimal,
+        price_before: Decimal,
+        price_after: Decimal,
+    ) -> Option<(Decimal, Decimal)> {
+        let (low, high) = self.band.around(p_ref);
+        let breached = (price_after > high && price_after > price_before)
+            || (price_after < low && price_after < price_before);
+        breached.then_some((low, high))
+    }
+}
+
+/// A cooldown of the circuit breaker in progress.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BreakerHalt {
+    /// Halted since `since` for `remaining` more `p_ref` updates.
+    Steps {
+        since: DateTime<Utc>,
+        remaining: u64,
+    },
+    /// Halted from `since` until `until`.
+    Until {
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    },
+}
+
+/// How often and how long the circuit breaker was active, as reported in the
+/// metrics.
+///
+/// # Fields
+///
+/// * `rejected_swaps` - Swaps refused for breaching the band.
+/// * `capped_swaps` - Swaps cut short at the edge of the band.
+/// * `trips` - Cooldowns started.
+/// * `halted_swaps` - Swaps refused during a cooldown.
+/// * `active_steps` - `p_ref` updates made during a cooldown.
+/// * `active_time` - Time spent in cooldowns that have ended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct BreakerStats {
+    pub rejected_swaps: u64,
+    pub capped_swaps: u64,
+    pub trips: u64,
+    pub halted_swaps: u64,
+    pub active_steps: u64,
+    pub active_time: Duration,
+}
+
+/// The state of the circuit breaker of a pool: the cooldown in progress, if
+/// any, and its statistics.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BreakerState {
+    pub halt: Option<BreakerHalt>,
+    pub stats: BreakerStats,
+}
+
+impl BreakerState {
+    /// Starts a cooldown at `now`. An empty cooldown only refuses the swap that
+    /// tripped it.
+    pub(crate) fn trip(&mut self, cooldown: Cooldown, now: DateTime<Utc>) {
+        self.stats.trips += 1;
+        self.halt = match cooldown {
+            Cooldown::Steps(steps) if steps > 0 => Some(BreakerHalt::Steps {
+                since: now,
+                remaining: steps,
+            }),
+            Cooldown::Duration(duration) if duration > Duration::zero() => {
+                Some(BreakerHalt::Until {
+                    since: now,
+                    until: now + duration,
+                })
+            }
+            _ => None,
+        };
+    }
+
+    /// Checks whether swaps are halted at `now`, ending an expired cooldown first.
+    ///
+    /// # Returns
+    ///
+    /// `ArppError::SwapsHalted` if a cooldown is in progress.
+    pub(crate) fn check(&mut self, now: DateTime<Utc>) -> Result<(), ArppError> {
+        self.expire(now);
+        if self.halt.is_some() {
+            self.stats.halted_swaps += 1;
+            return Err(ArppError::SwapsHalted);
+        }
+        Ok(())
+    }
+
+    /// Counts a `p_ref` update made at `now` against the cooldown in progress.
+    pub(crate) fn step(&mut self, now: DateTime<Utc>) {
+        self.expire(now);
+        if self.halt.is_some() {
+            self.stats.active_steps += 1;
+        }
+        if let Some(BreakerHalt::Steps { since, remaining }) = &mut self.halt {
+            *remaining -= 1;
+            if *remaining == 0 {
+                self.stats.active_time += now - *since;
+                self.halt = None;
+            }
+        }
+    }
+
+    /// Ends a time cooldown that has expired at `now`.
+    fn expire(&mut self, now: DateTime<Utc>) {
+        if let Some(BreakerHalt::Until { since, until }) = self.halt {
+            if now >= until {
+                self.stats.active_time += until - since;
+                self.halt = None;
+            }
+        }
+    }
+}
+
+/// Finds the ratio between `from` and `to` at which `price` reaches `target`.
+///
+/// `price` must be monotone between the two ratios and `target` lie between the
+/// prices at their ends.
+///
+/// # Returns
+///
+/// The ratio on the side of `from`, so that the price there does not go past
+/// `target`.
+pub(crate) fn ratio_at_price<F>(price: &F, from: Decimal, to: Decimal, target: Decimal) -> Decimal
+where
+    F: Fn(Decimal) -> Decimal,
+{
+    let rising = price(to) > price(from);
+    let (mut inside, mut outside) = (from, to);
+    for _ in 0..BISECTION_STEPS {
+        let middle = (inside + outside) / Decimal::TWO;
+        if middle == inside || middle == outside {
+            break;
+        }
+        if (price(middle) > target) == rising {
+            outside = middle;
+        } else {
+            inside = middle;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests_circuit_breaker {
+    use super::*;
+
+    #[test]
+    fn test_band_around_p_ref() {
+        assert_eq!(
+            PriceBand::Percent(dec!(5)).around(dec!(2)),
+            (dec!(1.9), dec!(2.1))
+        );
+        assert_eq!(
+            PriceBand::Absolute(dec!(0.5)).around(dec!(2)),
+            (dec!(1.5), dec!(2.5))
+        );
+    }
+
+    #[test]
+    fn test_only_moves_further_out_breach() {
+        let breaker = CircuitBreaker::new(PriceBand::Percent(dec!(10)), BreakerPolicy::Reject);
+        assert_eq!(breaker.check(dec!(1), dec!(1), dec!(1.05)), None);
+        assert_eq!(
+            breaker.check(dec!(1), dec!(1), dec!(1.2)),
+            Some((dec!(0.9), dec!(1.1)))
+        );
+        assert!(breaker.check(dec!(1), dec!(1), dec!(0.8)).is_some());
+        assert_eq!(breaker.check(dec!(1), dec!(1.3), dec!(1.2)), None);
+    }
+
+    #[test]
+    fn test_step_cooldown_ends_after_its_steps() {
+        let now = Utc::now();
+        let mut state = BreakerState::default();
+        state.trip(Cooldown::Steps(2), now);
+        assert_eq!(state.check(now), Err(ArppError::SwapsHalted));
+        state.step(now + Duration::seconds(1));
+        state.step(now + Duration::seconds(2));
+        assert_eq!(state.check(now), Ok(()));
+        assert_eq!(
+            state.stats,
+            BreakerStats {
+                trips: 1,
+                halted_swaps: 1,
+                active_steps: 2,
+                active_time: Duration::seconds(2),
+                ..BreakerStats::default()
+            }
+        );
+
+        // An empty cooldown only refuses the swap that tripped it
+        state.trip(Cooldown::Steps(0), now);
+        assert_eq!(state.halt, None);
+    }
+
+    #[test]
+    fn test_ratio_at_price_stays_on_its_side() {
+        let price = |r: Decimal| r * Decimal::TWO;
+        let ratio = ratio_at_price(&price, dec!(1), dec!(2), dec!(3));
+        assert!(price(ratio) <= dec!(3));
+        assert!(dec!(3) - price(ratio) < dec!(0.000000000000000001));
+        let ratio = ratio_at_price(&price, dec!(2), dec!(1), dec!(3));
+        assert!(price(ratio) >= dec!(3));
+    }
+}
@@ -0,0 +1,558 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::error::ArppError;
+use crate::utils::atan::atan;
+use rust_decimal::{Decimal, MathematicalOps};
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+/// Number of bisection steps taken by the default `PricingCurve::inverse`, enough
+/// to exhaust the precision of `Decimal`.
+const INVERSE_STEPS: usize = 100;
+
+/// Number of Newton steps refining the inverse of the arctangent computed with
+/// `tan`.
+const NEWTON_STEPS: usize = 2;
+
+/// Step of the central differences taken by the default `PricingCurve::slope` and
+/// `PricingCurve::curvature`.
+const DIFFERENCE_STEP: Decimal = dec!(0.000001);
+
+/// The anchoring function of the ARPP formula.
+///
+/// A curve maps the scaled deviation `x = beta * (r - 1)` of the token ratio from
+/// balance to the multiplier applied to `alpha`:
+/// ```text
+/// ARPP = p_ref * (1 + alpha * anchor(beta * (r - 1)))
+/// ```
+/// Every curve provided by the crate is odd, non-decreasing and has slope one at
+/// the origin, so `alpha * beta` is the price sensitivity around balance whichever
+/// curve is used, and curves only differ in how they saturate.
+///
+/// # Methods
+///
+/// - `anchor`: Returns the multiplier for a scaled deviation `x`.
+/// - `limit`: Returns the value `anchor` tends to as `x` grows, used when `x` is
+///   too large for `Decimal`.
+/// - `inverse`: Returns the deviation `x` at which `anchor` reaches `y`, or `None`
+///   if `y` is not strictly between `-limit` and `limit`. The default
+///   implementation bisects `anchor`.
+/// - `slope` and `curvature`: Return the first and second derivatives of `anchor`
+///   at `x`. The default implementations take central differences of `anchor`.
+/// - `spec`: Returns the description of the curve stored in pool snapshots, or
+///   `None` for curves defined outside the crate, which cannot be restored.
+pub trait PricingCurve: fmt::Debug + Send + Sync {
+    fn anchor(&self, x: Decimal) -> Decimal;
+
+    fn limit(&self) -> Decimal;
+
+    fn inverse(&self, y: Decimal) -> Option<Decimal> {
+        let limit = self.limit();
+        if y.abs() >= limit {
+            return None;
+        }
+        let target = y.abs();
+        // Grow the bracket until the curve passes the target, then bisect it
+        let (mut low, mut high) = (Decimal::ZERO, Decimal::ONE);
+        while self.anchor(high) < target {
+            low = high;
+            high = high.checked_mul(Decimal::TWO)?;
+        }
+        for _ in 0..INVERSE_STEPS {
+            let middle = (low + high) / Decimal::TWO;
+            if middle == low || middle == high {
+                break;
+            }
+            if self.anchor(middle) < target {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        Some(if y.is_sign_negative() { -high } else { high })
+    }
+
+    fn slope(&self, x: Decimal) -> Decimal {
+        let h = DIFFERENCE_STEP;
+        (self.anchor(x + h) - self.anchor(x - h)) / (Decimal::TWO * h)
+    }
+
+    fn curvature(&self, x: Decimal) -> Decimal {
+        let h = DIFFERENCE_STEP;
+        (self.anchor(x + h) - Decimal::TWO * self.anchor(x) + self.anchor(x - h)) / (h * h)
+    }
+
+    fn spec(&self) -> Option<CurveSpec> {
+        None
+    }
+}
+
+/// Description of one of the curves provided by the crate, as stored in pool
+/// snapshots.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CurveSpec {
+    Arctan,
+    Tanh,
+    Logistic,
+    Algebraic,
+    PiecewiseLinear { knots: Vec<(Decimal, Decimal)> },
+}
+
+impl CurveSpec {
+    /// Builds the curve described.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the curve, or `ArppError::InvalidCurve` if the knots of a
+    /// piecewise-linear curve are invalid.
+    pub fn build(&self) -> Result<Arc<dyn PricingCurve>, ArppError> {
+        Ok(match self {
+            CurveSpec::Arctan => Arc::new(ArctanCurve),
+            CurveSpec::Tanh => Arc::new(TanhCurve),
+            CurveSpec::Logistic => Arc::new(LogisticCurve),
+            CurveSpec::Algebraic => Arc::new(AlgebraicCurve),
+            CurveSpec::PiecewiseLinear { knots } => {
+                Arc::new(PiecewiseLinearCurve::new(knots.clone())?)
+            }
+        })
+    }
+}
+
+/// The arctangent curve of the original ARPP formula, saturating at `pi/2`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ArctanCurve;
+
+impl PricingCurve for ArctanCurve {
+    fn anchor(&self, x: Decimal) -> Decimal {
+        atan(x)
+    }
+
+    fn limit(&self) -> Decimal {
+        Decimal::HALF_PI
+    }
+
+    /// Inverts the curve with `tan`, refined by Newton steps on `atan`, whose error
+    /// is far below that of `tan` in `Decimal`.
+    fn inverse(&self, y: Decimal) -> Option<Decimal> {
+        if y.abs() >= Decimal::HALF_PI {
+            return None;
+        }
+        let mut x = y.checked_tan()?;
+        for _ in 0..NEWTON_STEPS {
+            let Some(step) = x
+                .checked_mul(x)
+                .and_then(|square| (atan(x) - y).checked_mul(Decimal::ONE + square))
+            else {
+                break;
+            };
+            x -= step;
+        }
+        Some(x)
+    }
+
+    /// `1 / (1 + x^2)`, which vanishes once `x^2` overflows.
+    fn slope(&self, x: Decimal) -> Decimal {
+        x.checked_mul(x).map_or(Decimal::ZERO, |square| {
+            Decimal::ONE / (Decimal::ONE + square)
+        })
+    }
+
+    /// `-2x / (1 + x^2)^2`.
+    fn curvature(&self, x: Decimal) -> Decimal {
+        let slope = self.slope(x);
+        -Decimal::TWO * x * slope * slope
+    }
+
+    fn spec(&self) -> Option<CurveSpec> {
+        Some(CurveSpec::Arctan)
+    }
+}
+
+/// The hyperbolic tangent curve, saturating at one.
+///
+/// It approaches its limit exponentially, much faster than the arctangent.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TanhCurve;
+
+impl PricingCurve for TanhCurve {
+    fn anchor(&self, x: Decimal) -> Decimal {
+        odd(x, |x| {
+            // tanh(x) = 1 - 2 / (e^(2x) + 1), where e^(2x) only overflows once
+            // tanh(x) rounds to one
+            x.checked_mul(Decimal::TWO)
+                .and_then(exp_plus_one)
+                .map_or(Decimal::ONE, |e| Decimal::ONE - Decimal::TWO / e)
+        })
+    }
+
+    fn limit(&self) -> Decimal {
+        Decimal::ONE
+    }
+
+    /// `1 - tanh(x)^2`.
+    fn slope(&self, x: Decimal) -> Decimal {
+        let t = self.anchor(x);
+        Decimal::ONE - t * t
+    }
+
+    /// `-2 tanh(x) (1 - tanh(x)^2)`.
+    fn curvature(&self, x: Decimal) -> Decimal {
+        -Decimal::TWO * self.anchor(x) * self.slope(x)
+    }
+
+    fn spec(&self) -> Option<CurveSpec> {
+        Some(CurveSpec::Tanh)
+    }
+}
+
+/// The logistic curve `4 * (1 / (1 + e^(-x)) - 1/2)`, saturating at two.
+///
+/// This is the standard logistic function centred on the origin and scaled to
+/// slope one there, which makes it equal to `2 * tanh(x / 2)`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LogisticCurve;
+
+impl PricingCurve for LogisticCurve {
+    fn anchor(&self, x: Decimal) -> Decimal {
+        odd(x, |x| {
+            let logistic =
+                exp_plus_one(x).map_or(Decimal::ONE, |e| Decimal::ONE - Decimal::ONE / e);
+            Decimal::TWO * (Decimal::TWO * logistic - Decimal::ONE)
+        })
+    }
+
+    fn limit(&self) -> Decimal {
+        Decimal::TWO
+    }
+
+    /// `1 - tanh(x / 2)^2`.
+    fn slope(&self, x: Decimal) -> Decimal {
+        let t = self.anchor(x) / Decimal::TWO;
+        Decimal::ONE - t * t
+    }
+
+    /// `-tanh(x / 2) (1 - tanh(x / 2)^2)`.
+    fn curvature(&self, x: Decimal) -> Decimal {
+        -self.anchor(x) / Decimal::TWO * self.slope(x)
+    }
+
+    fn spec(&self) -> Option<CurveSpec> {
+        Some(CurveSpec::Logistic)
+    }
+}
+
+/// The algebraic sigmoid `x / sqrt(1 + x^2)`, saturating at one.
+///
+/// It approaches its limit as `1/x^2`, between the arctangent and the hyperbolic
+/// tangent.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AlgebraicCurve;
+
+impl PricingCurve for AlgebraicCurve {
+    fn anchor(&self, x: Decimal) -> Decimal {
+        odd(x, |x| {
+            // Written as 1 / sqrt(1/x^2 + 1) above one so that x^2 cannot overflow
+            let denominator = if x > Decimal::ONE {
+                let inverse = Decimal::ONE / x;
+                inverse * inverse + Decimal::ONE
+            } else {
+                Decimal::ONE + x * x
+            };
+            let root = denominator.sqrt().unwrap_or(Decimal::ONE);
+            if x > Decimal::ONE {
+                Decimal::ONE / root
+            } else {
+                x / root
+            }
+        })
+    }
+
+    fn limit(&self) -> Decimal {
+        Decimal::ONE
+    }
+
+    /// `(1 + x^2)^(-3/2)`, from `1 / sqrt(1 + x^2) = anchor(x) / x`.
+    fn slope(&self, x: Decimal) -> Decimal {
+        let root = algebraic_root(self.anchor(x), x);
+        root * root * root
+    }
+
+    /// `-3x (1 + x^2)^(-5/2)`.
+    fn curvature(&self, x: Decimal) -> Decimal {
+        let root = algebraic_root(self.anchor(x), x);
+        -dec!(3) * self.anchor(x) * root * root * root * root
+    }
+
+    fn spec(&self) -> Option<CurveSpec> {
+        Some(CurveSpec::Algebraic)
+    }
+}
+
+/// A piecewise-linear curve through a list of knots.
+///
+/// The knots give the curve for positive deviations; it starts at the origin, is
+/// linear between consecutive knots, flat past the last one, and extended to
+/// negative deviations by odd symmetry. A knot at `(1, 1)` first keeps the slope
+/// of one at the origin shared by the other curves.
+///
+/// # Fields
+///
+/// * `knots` - The `(x, anchor)` knots, with increasing `x` and non-decreasing
+///   `anchor`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PiecewiseLinearCurve {
+    knots: Vec<(Decimal, Decimal)>,
+}
+
+impl PiecewiseLinearCurve {
+    /// Creates a curve through `knots`.
+    ///
+    /// # Arguments
+    ///
+    /// * `knots` - The `(x, anchor)` knots for positive deviations.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the curve, or `ArppError::InvalidCurve` with the first knot
+    /// that does not move right of the previous one, starting from the origin, or
+    /// moves down.
+    pub fn new(knots: Vec<(Decimal, Decimal)>) -> Result<Self, ArppError> {
+        let mut previous = (Decimal::ZERO, Decimal::ZERO);
+        for &(x, y) in &knots {
+            if x <= previous.0 || y < previous.1 {
+                return Err(ArppError::InvalidCurve { x, y });
+            }
+            previous = (x, y);
+        }
+        Ok(Self { knots })
+    }
+
+    /// Returns the knots of the curve.
+    pub fn get_knots(&self) -> &[(Decimal, Decimal)] {
+        &self.knots
+    }
+}
+
+impl PricingCurve for PiecewiseLinearCurve {
+    fn anchor(&self, x: Decimal) -> Decimal {
+        odd(x, |x| {
+            let mut previous = (Decimal::ZERO, Decimal::ZERO);
+            for &(knot_x, knot_y) in &self.knots {
+                if x <= knot_x {
+                    let slope = (knot_y - previous.1) / (knot_x - previous.0);
+                    return previous.1 + slope * (x - previous.0);
+                }
+                previous = (knot_x, knot_y);
+            }
+            previous.1
+        })
+    }
+
+    fn limit(&self) -> Decimal {
+        self.knots.last().map_or(Decimal::ZERO, |&(_, y)| y)
+    }
+
+    /// The slope of the segment `x` lies on, taking the segment on the left at a
+    /// knot.
+    fn slope(&self, x: Decimal) -> Decimal {
+        let x = x.abs();
+        let mut previous = (Decimal::ZERO, Decimal::ZERO);
+        for &(knot_x, knot_y) in &self.knots {
+            if x <= knot_x {
+                return (knot_y - previous.1) / (knot_x - previous.0);
+            }
+            previous = (knot_x, knot_y);
+        }
+        Decimal::ZERO
+    }
+
+    /// Zero, the curve being linear between knots.
+    fn curvature(&self, _x: Decimal) -> Decimal {
+        Decimal::ZERO
+    }
+
+    fn spec(&self) -> Option<CurveSpec> {
+        Some(CurveSpec::PiecewiseLinear {
+            knots: self.knots.clone(),
+        })
+    }
+}
+
+/// Returns `1 / sqrt(1 + x^2)` from the value `anchor` of the algebraic curve at `x`.
+fn algebraic_root(anchor: Decimal, x: Decimal) -> Decimal {
+    if x.is_zero() {
+        Decimal::ONE
+    } else {
+        anchor / x
+    }
+}
+
+/// Returns `e^x + 1`, or `None` if it overflows.
+fn exp_plus_one(x: Decimal) -> Option<Decimal> {
+    x.checked_exp()?.checked_add(Decimal::ONE)
+}
+
+/// Evaluates an odd function from its values on non-negative arguments.
+fn odd<F>(x: Decimal, positive: F) -> Decimal
+where
+    F: Fn(Decimal) -> Decimal,
+{
+    if x.is_sign_negative() {
+        -positive(-x)
+    } else {
+        positive(x)
+    }
+}
+
+#[cfg(test)]
+mod tests_curve {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use rust_decimal_macros::dec;
+
+    fn curves() -> Vec<Box<dyn PricingCurve>> {
+        vec![
+            Box::new(ArctanCurve),
+            Box::new(TanhCurve),
+            Box::new(LogisticCurve),
+            Box::new(AlgebraicCurve),
+            Box::new(
+                PiecewiseLinearCurve::new(vec![(dec!(1), dec!(1)), (dec!(3), dec!(1.5))]).unwrap(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_curves_have_unit_slope_at_origin() {
+        let h = dec!(0.000001);
+        for curve in curves() {
+            assert_eq!(curve.anchor(Decimal::ZERO), Decimal::ZERO);
+            assert_approx_eq!(curve.anchor(h) / h, Decimal::ONE, dec!(0.000001));
+        }
+    }
+
+    #[test]
+    fn test_curves_are_odd() {
+        for curve in curves() {
+            for x in [dec!(0.2), dec!(1.7), dec!(40)] {
+                assert_eq!(curve.anchor(-x), -curve.anchor(x));
+            }
+        }
+    }
+
+    #[test]
+    fn test_curves_are_monotonic_and_saturate() {
+        for curve in curves() {
+            let mut previous = curve.anchor(dec!(-1000000));
+            for step in -200..=200 {
+                let value = curve.anchor(Decimal::from(step) / dec!(10));
+                assert!(value >= previous, "{:?} decreases", curve);
+                previous = value;
+            }
+            assert_approx_eq!(
+                curve.anchor(Decimal::MAX),
+                curve.limit(),
+                dec!(0.0000000001)
+            );
+            assert_approx_eq!(
+                curve.anchor(Decimal::MIN),
+                -curve.limit(),
+                dec!(0.0000000001)
+            );
+        }
+    }
+
+    #[test]
+    fn test_inverse_undoes_anchor() {
+        for curve in curves() {
+            for x in [
+                dec!(-2.5),
+                dec!(-0.3),
+                dec!(0),
+                dec!(0.001),
+                dec!(1.7),
+                dec!(2.5),
+            ] {
+                let inverse = curve.inverse(curve.anchor(x)).unwrap();
+                assert_approx_eq!(inverse, x, dec!(0.0000000001));
+            }
+            assert_eq!(curve.inverse(curve.limit()), None);
+            assert_eq!(curve.inverse(-curve.limit()), None);
+        }
+        assert_approx_eq!(
+            ArctanCurve.inverse(dec!(0.5)).unwrap(),
+            dec!(0.5463024898437905132551794657),
+            dec!(1e-26)
+        );
+    }
+
+    #[test]
+    fn test_derivatives_match_finite_differences() {
+        let h = dec!(0.00000001);
+        for curve in curves() {
+            for x in [dec!(-2.5), dec!(-0.3), dec!(0.2), dec!(1.7), dec!(5)] {
+                let slope = (curve.anchor(x + h) - curve.anchor(x - h)) / (dec!(2) * h);
+                assert_approx_eq!(curve.slope(x), slope, dec!(0.000000001));
+                let slope_step = (curve.slope(x + h) - curve.slope(x - h)) / (dec!(2) * h);
+                assert_approx_eq!(curve.curvature(x), slope_step, dec!(0.000000001));
+            }
+        }
+        assert_eq!(ArctanCurve.slope(Decimal::MAX), Decimal::ZERO);
+        assert_eq!(TanhCurve.curvature(Decimal::MAX), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_reference_values() {
+        assert_approx_eq!(
+            TanhCurve.anchor(dec!(0.5)),
+            dec!(0.4621171572600097585),
+            dec!(1e-18)
+        );
+        assert_approx_eq!(
+            LogisticCurve.anchor(dec!(1)),
+            dec!(0.9242343145200195170),
+            dec!(1e-18)
+        );
+        assert_approx_eq!(
+            AlgebraicCurve.anchor(dec!(2)),
+            dec!(0.8944271909999158786),
+            dec!(1e-18)
+        );
+    }
+
+    #[test]
+    fn test_piecewise_linear_interpolates_knots() {
+        let curve =
+            PiecewiseLinearCurve::new(vec![(dec!(1), dec!(1)), (dec!(3), dec!(2))]).unwrap();
+        assert_eq!(curve.anchor(dec!(0.5)), dec!(0.5));
+        assert_eq!(curve.anchor(dec!(2)), dec!(1.5));
+        assert_eq!(curve.anchor(dec!(-2)), dec!(-1.5));
+        assert_eq!(curve.anchor(dec!(10)), dec!(2));
+        assert_eq!(curve.limit(), dec!(2));
+    }
+
+    #[test]
+    fn test_piecewise_linear_rejects_invalid_knots() {
+        assert_eq!(
+            PiecewiseLinearCurve::new(vec![(dec!(0), dec!(1))]),
+            Err(ArppError::InvalidCurve {
+                x: dec!(0),
+                y: dec!(1)
+            })
+        );
+        assert_eq!(
+            PiecewiseLinearCurve::new(vec![(dec!(1), dec!(1)), (dec!(2), dec!(0.5))]),
+            Err(ArppError::InvalidCurve {
+                x: dec!(2),
+                y: dec!(0.5)
+            })
+        );
+    }
+}
@@ -0,0 +1,387 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::ramp::RampParameter;
+use crate::arpp::swap::Token;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+/// Errors returned by the pool, the swap pricing and the simulations.
+///
+/// Every fallible operation of the crate reports its failure as one of these
+/// variants, so callers can react to each case without matching on messages.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ArppError {
+    /// An amount is negative, or zero where a positive amount is required.
+    InvalidAmount { amount: Decimal },
+    /// A fee outside `[0, 10000)` basis points.
+    InvalidFee { fee_bps: Decimal },
+    /// A dynamic fee with a negative slope.
+    InvalidFeeSlope { slope: Decimal },
+    /// The pool holds less of `token` than an operation requires.
+    ///
+    /// When a swap would drain the pool, `needed` is the whole balance.
+    InsufficientLiquidity {
+        token: Token,
+        needed: Decimal,
+        available: Decimal,
+    },
+    /// A swap would pay out less than its minimum, or cost more than its maximum.
+    ///
+    /// `amount` is the amount of `token` the swap would pay out or cost, and
+    /// `limit` the caller's bound on it.
+    SlippageExceeded {
+        token: Token,
+        amount: Decimal,
+        limit: Decimal,
+    },
+    /// A swap was submitted after its deadline.
+    DeadlineExpired {
+        deadline: DateTime<Utc>,
+        now: DateTime<Utc>,
+    },
+    /// The oracle price is older than the maximum age allowed.
+    OracleStale { age: Duration, max_age: Duration },
+    /// An oracle update moves `p_ref` by more than the maximum jump allowed.
+    OracleJump {
+        p_ref: Decimal,
+        price: Decimal,
+        max_jump_bps: Decimal,
+    },
+    /// An oracle price is further from the pool's TWAP than allowed.
+    OracleDeviation {
+        twap: Decimal,
+        price: Decimal,
+        max_deviation_bps: Decimal,
+    },
+    /// Swaps are paused after an oracle guard was breached.
+    SwapsPaused,
+    /// A swap would move the ARPP price further outside the band of the circuit
+    /// breaker, which allows prices from `low` to `high`.
+    PriceOutOfBand {
+        price: Decimal,
+        low: Decimal,
+        high: Decimal,
+    },
+    /// Swaps are halted by a cooldown of the circuit breaker.
+    SwapsHalted,
+    /// No ratio of the pool gives the ARPP price `price`, which the curve only
+    /// reaches strictly between `low` and `high`.
+    UnreachablePrice {
+        price: Decimal,
+        low: Decimal,
+        high: Decimal,
+    },
+    /// The ARPP price is not positive somewhere along a swap path.
+    InvalidPrice { price: Decimal },
+    /// A deposit is worth too little to mint a single share.
+    DepositTooSmall { value: Decimal },
+    /// No shares are held by `provider`.
+    UnknownProvider { provider: String },
+    /// A provider tried to burn more shares than it owns.
+    InsufficientShares {
+        provider: String,
+        requested: Decimal,
+        owned: Decimal,
+    },
+    /// A multi-asset pool holds less of the token at `token` than a swap requires.
+    InsufficientAssetLiquidity {
+        token: usize,
+        needed: Decimal,
+        available: Decimal,
+    },
+    /// No token of a multi-asset pool is at index `token`.
+    UnknownToken { token: usize },
+    /// A swap pays in and out the same token.
+    SameToken { token: usize },
+    /// The target weight of a token is not positive.
+    InvalidWeight { token: usize, weight: Decimal },
+    /// A list of amounts does not have one entry per token of the pool.
+    WrongTokenCount { expected: usize, actual: usize },
+    /// No route of registered pools leads from `token_in` to `token_out`.
+    NoRoute { token_in: String, token_out: String },
+    /// A routed swap would pay out less of `token` than the limit set by the trader.
+    RouteSlippageExceeded {
+        token: String,
+        amount: Decimal,
+        limit: Decimal,
+    },
+    /// A pool priced with a curve defined outside the crate cannot be snapshotted.
+    UnserializableCurve,
+    /// A snapshot was written by an incompatible version of the crate.
+    UnsupportedSnapshotVersion { version: u32, supported: u32 },
+    /// A snapshot cannot be written or read.
+    InvalidSnapshot { reason: String },
+    /// A line of a journal cannot be read, or does not follow the previous ones.
+    InvalidJournal { line: usize, reason: String },
+    /// Replaying the journal entry `sequence` did not reproduce the recorded `field`.
+    JournalMismatch { sequence: u64, field: String },
+    /// A journal entry cannot be written.
+    JournalWrite { reason: String },
+    /// A knot of a piecewise-linear curve does not move right from the previous
+    /// one, starting from the origin, or moves down.
+    InvalidCurve { x: Decimal, y: Decimal },
+    /// An ARPP parameter is outside its domain.
+    InvalidParameter { name: String, value: Decimal },
+    /// With these `alpha` and `beta` the ARPP price can fall to `low`, which is not
+    /// positive.
+    UnsafeParameters {
+        alpha: Decimal,
+        beta: Decimal,
+        low: Decimal,
+    },
+    /// `alpha * beta` differs above and below balance, so the slope of the price
+    /// would jump there.
+    KinkAtBalance {
+        slope_up: Decimal,
+        slope_down: Decimal,
+    },
+    /// A ramp of `parameter` is already in progress.
+    RampInProgress { parameter: RampParameter },
+    /// A change of `parameter` from `from` to `to` exceeds the ramp bounds.
+    RampOutOfBounds {
+        parameter: RampParameter,
+        from: Decimal,
+        to: Decimal,
+    },
+    /// A token with more decimals than the fixed-point swap math supports.
+    InvalidDecimals { decimals: u32 },
+    /// A pool uses `feature`, which the fixed-point swap math does not model.
+    UnsupportedFixedPoint { feature: String },
+    /// A computation left the range representable by `Decimal`.
+    Overflow,
+    /// The swap path solver ran out of iterations.
+    NoConvergence,
+}
+
+impl fmt::Display for ArppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArppError::InvalidAmount { amount } => {
+                write!(f, "Invalid amount {}: amounts must be positive", amount)
+            }
+            ArppError::InvalidFee { fee_bps } => write!(
+                f,
+                "Invalid fee {}: must be between 0 and 10000 basis points",
+                fee_bps
+            ),
+            ArppError::InvalidFeeSlope { slope } => {
+                write!(f, "Invalid fee slope {}: must not be negative", slope)
+            }
+            ArppError::InsufficientLiquidity {
+                token,
+                needed,
+                available,
+            } => write!(
+                f,
+                "Insufficient liquidity of {:?}: needed {}, available {}",
+                token, needed, available
+            ),
+            ArppError::SlippageExceeded {
+                token,
+                amount,
+                limit,
+            } => write!(
+                f,
+                "Slippage limit exceeded: {} {:?} against a limit of {}",
+                amount, token, limit
+            ),
+            ArppError::DeadlineExpired { deadline, now } => {
+                write!(f, "Swap deadline {} expired at {}", deadline, now)
+            }
+            ArppError::OracleStale { age, max_age } => write!(
+                f,
+                "Oracle price is stale: {}s old, maximum age {}s",
+                age.num_seconds(),
+                max_age.num_seconds()
+            ),
+            ArppError::OracleJump {
+                p_ref,
+                price,
+                max_jump_bps,
+            } => write!(
+                f,
+                "Oracle price {} jumps from p_ref {} by more than {} bps",
+                price, p_ref, max_jump_bps
+            ),
+            ArppError::OracleDeviation {
+                twap,
+                price,
+                max_deviation_bps,
+            } => write!(
+                f,
+                "Oracle price {} deviates from the pool TWAP {} by more than {} bps",
+                price, twap, max_deviation_bps
+            ),
+            ArppError::SwapsPaused => write!(f, "Swaps are paused by an oracle guard"),
+            ArppError::PriceOutOfBand { price, low, high } => write!(
+                f,
+                "Price {} would leave the circuit breaker band [{}, {}]",
+                price, low, high
+            ),
+            ArppError::SwapsHalted => write!(f, "Swaps are halted by the circuit breaker"),
+            ArppError::UnreachablePrice { price, low, high } => write!(
+                f,
+                "Price {} is outside the range ({}, {}) reachable by the curve",
+                price, low, high
+            ),
+            ArppError::InvalidPrice { price } => {
+                write!(
+                    f,
+                    "Price must be positive along the swap path, got {}",
+                    price
+                )
+            }
+            ArppError::DepositTooSmall { value } => {
+                write!(f, "Deposit worth {} is too small to mint shares", value)
+            }
+            ArppError::UnknownProvider { provider } => {
+                write!(f, "Unknown liquidity provider {}", provider)
+            }
+            ArppError::InsufficientShares {
+                provider,
+                requested,
+                owned,
+            } => write!(
+                f,
+                "Provider {} owns only {} shares, {} requested",
+                provider, owned, requested
+            ),
+            ArppError::InsufficientAssetLiquidity {
+                token,
+                needed,
+                available,
+            } => write!(
+                f,
+                "Insufficient liquidity of token {}: needed {}, available {}",
+                token, needed, available
+            ),
+            ArppError::UnknownToken { token } => write!(f, "Unknown token {}", token),
+            ArppError::SameToken { token } => {
+                write!(f, "Cannot swap token {} for itself", token)
+            }
+            ArppError::InvalidWeight { token, weight } => write!(
+                f,
+                "Invalid weight {} of token {}: weights must be positive",
+                weight, token
+            ),
+            ArppError::WrongTokenCount { expected, actual } => write!(
+                f,
+                "Expected one amount for each of the {} tokens, got {}",
+                expected, actual
+            ),
+            ArppError::NoRoute {
+                token_in,
+                token_out,
+            } => write!(f, "No route from {} to {}", token_in, token_out),
+            ArppError::RouteSlippageExceeded {
+                token,
+                amount,
+                limit,
+            } => write!(
+                f,
+                "Slippage limit exceeded: {} {} against a limit of {}",
+                amount, token, limit
+            ),
+            ArppError::UnserializableCurve => {
+                write!(f, "Custom pricing curves cannot be snapshotted")
+            }
+            ArppError::UnsupportedSnapshotVersion { version, supported } => write!(
+                f,
+                "Unsupported snapshot version {}, expected {}",
+                version, supported
+            ),
+            ArppError::InvalidSnapshot { reason } => write!(f, "Invalid snapshot: {}", reason),
+            ArppError::InvalidJournal { line, reason } => {
+                write!(f, "Invalid journal line {}: {}", line, reason)
+            }
+            ArppError::JournalMismatch { sequence, field } => write!(
+                f,
+                "Replay of journal entry {} does not match the recorded {}",
+                sequence, field
+            ),
+            ArppError::JournalWrite { reason } => {
+                write!(f, "Cannot write journal entry: {}", reason)
+            }
+            ArppError::InvalidCurve { x, y } => write!(
+                f,
+                "Invalid curve knot ({}, {}): knots must increase from the origin",
+                x, y
+            ),
+            ArppError::InvalidParameter { name, value } => {
+                write!(f, "Invalid {} {}: out of its domain", name, value)
+            }
+            ArppError::UnsafeParameters { alpha, beta, low } => write!(
+                f,
+                "Alpha {} and beta {} let the price fall to {}: it must stay positive",
+                alpha, beta, low
+            ),
+            ArppError::KinkAtBalance {
+                slope_up,
+                slope_down,
+            } => write!(
+                f,
+                "Alpha * beta is {} above balance and {} below: the slope of the price would jump at balance",
+                slope_up, slope_down
+            ),
+            ArppError::RampInProgress { parameter } => {
+                write!(f, "A ramp of {:?} is already in progress", parameter)
+            }
+            ArppError::RampOutOfBounds {
+                parameter,
+                from,
+                to,
+            } => write!(
+                f,
+                "Changing {:?} from {} to {} exceeds the ramp bounds",
+                parameter, from, to
+            ),
+            ArppError::InvalidDecimals { decimals } => write!(
+                f,
+                "Invalid token decimals {}: at most 18 are supported",
+                decimals
+            ),
+            ArppError::UnsupportedFixedPoint { feature } => {
+                write!(f, "The fixed-point swap math does not support {}", feature)
+            }
+            ArppError::Overflow => write!(f, "Value out of the representable range"),
+            ArppError::NoConvergence => write!(f, "Swap path did not converge"),
+        }
+    }
+}
+
+impl Error for ArppError {}
+
+#[cfg(test)]
+mod tests_arpp_error {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_display_includes_details() {
+        let error = ArppError::InsufficientLiquidity {
+            token: Token::A,
+            needed: dec!(1001),
+            available: dec!(1000),
+        };
+        assert_eq!(
+            error.to_string(),
+            "Insufficient liquidity of A: needed 1001, available 1000"
+        );
+    }
+
+    #[test]
+    fn test_converts_to_boxed_error() {
+        let boxed: Box<dyn Error> = ArppError::NoConvergence.into();
+        assert_eq!(
+            boxed.downcast_ref::<ArppError>(),
+            Some(&ArppError::NoConvergence)
+        );
+    }
+}
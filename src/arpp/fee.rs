@@ -0,0 +1,102 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::error::ArppError;
+use crate::arpp::swap::BASIS_POINTS;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// A fee that scales with how far a swap moves the price from `p_ref`.
+///
+/// The deviation of a price `P` is `|P / p_ref - 1|`. A swap pays the base fee
+/// plus `slope` basis points for every basis point it adds to the deviation, so
+/// trades pushing the price away from `p_ref` pay more than the base fee and
+/// trades pulling it back pay less, down to nothing. The fee never exceeds the
+/// cap.
+///
+/// # Fields
+///
+/// * `base_bps` - The fee of a swap leaving the deviation unchanged, in basis points.
+/// * `slope` - Basis points of fee per basis point of added deviation.
+/// * `max_bps` - The highest fee charged, in basis points.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DynamicFee {
+    pub base_bps: Decimal,
+    pub slope: Decimal,
+    pub max_bps: Decimal,
+}
+
+impl DynamicFee {
+    /// Creates a dynamic fee.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the fee, `ArppError::InvalidFee` if the base fee or the cap
+    /// is outside `[0, 10000)` basis points, or `ArppError::InvalidFeeSlope` if the
+    /// slope is negative.
+    pub fn new(base_bps: Decimal, slope: Decimal, max_bps: Decimal) -> Result<Self, ArppError> {
+        for fee_bps in [base_bps, max_bps] {
+            if fee_bps < Decimal::ZERO || fee_bps >= BASIS_POINTS {
+                return Err(ArppError::InvalidFee { fee_bps });
+            }
+        }
+        if slope < Decimal::ZERO {
+            return Err(ArppError::InvalidFeeSlope { slope });
+        }
+        Ok(Self {
+            base_bps,
+            slope,
+            max_bps,
+        })
+    }
+
+    /// Returns the fee, in basis points, of a swap moving the deviation from
+    /// `p_ref` from `deviation_before` to `deviation_after`.
+    pub fn fee_bps(&self, deviation_before: Decimal, deviation_after: Decimal) -> Decimal {
+        let added_bps = (deviation_after - deviation_before) * BASIS_POINTS;
+        (self.base_bps + self.slope * added_bps).clamp(Decimal::ZERO, self.max_bps)
+    }
+}
+
+/// Returns the deviation `|price / p_ref - 1|` of a price from `p_ref`.
+pub(crate) fn deviation(price: Decimal, p_ref: Decimal) -> Decimal {
+    (price / p_ref - Decimal::ONE).abs()
+}
+
+#[cfg(test)]
+mod tests_dynamic_fee {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_fee_follows_added_deviation() {
+        let fee = DynamicFee::new(dec!(30), dec!(0.5), dec!(100)).unwrap();
+        assert_eq!(fee.fee_bps(dec!(0.01), dec!(0.01)), dec!(30));
+        assert_eq!(fee.fee_bps(dec!(0.01), dec!(0.02)), dec!(80));
+        assert_eq!(fee.fee_bps(dec!(0.02), dec!(0.01)), dec!(0));
+        assert_eq!(fee.fee_bps(dec!(0.02), dec!(0.016)), dec!(10));
+        assert_eq!(fee.fee_bps(dec!(0), dec!(0.1)), dec!(100));
+        assert_eq!(deviation(dec!(0.9), dec!(1)), dec!(0.1));
+    }
+
+    #[test]
+    fn test_invalid_parameters_are_rejected() {
+        assert_eq!(
+            DynamicFee::new(dec!(-1), dec!(1), dec!(100)),
+            Err(ArppError::InvalidFee { fee_bps: dec!(-1) })
+        );
+        assert_eq!(
+            DynamicFee::new(dec!(30), dec!(1), dec!(10000)),
+            Err(ArppError::InvalidFee {
+                fee_bps: dec!(10000)
+            })
+        );
+        assert_eq!(
+            DynamicFee::new(dec!(30), dec!(-1), dec!(100)),
+            Err(ArppError::InvalidFeeSlope { slope: dec!(-1) })
+        );
+    }
+}
@@ -0,0 +1,1210 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+//! Fixed-point integer versions of `arpp()` and of the pool swap math, matching
+//! the semantics of an on-chain implementation.
+//!
+//! Token amounts are `u128` integers in units of `10^-decimals` of a token, with
+//! up to 18 decimals per token. Prices, ratios and the ARPP parameters are `u128`
+//! integers in units of `1 / WAD`, that is with 18 decimals. Every division takes
+//! an explicit `Rounding`, and the swap math always rounds in the pool's favour:
+//! amounts paid out are rounded down and amounts paid in are rounded up.
+//!
+//! The swap path is split into a fixed number of steps. The price moves against
+//! the trader along the path, so its value at the start of a step is the
+//! trader's best price of the step. Each step is therefore first settled
+//! provisionally at its start price, which overshoots the end of the exact path
+//! of the step, and then priced at the marginal price of that provisional end
+//! state, the trader's worst. An exact-input swap thus never pays out more than
+//! the exact path integral of the `Decimal` model. An exact-output swap charges
+//! the smallest input whose exact-input swap pays out the amount, so it never
+//! charges less. The difference is bounded by the move of the price over the
+//! trade divided by the number of steps.
+
+use crate::arpp::curve::{ArctanCurve, CurveSpec};
+use crate::arpp::error::ArppError;
+use crate::arpp::liquidity_pool::LiquidityPool;
+use crate::arpp::params::check_parameters;
+use crate::arpp::swap::Token;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// One, in the 18-decimal fixed-point format of prices and parameters.
+pub const WAD: u128 = 1_000_000_000_000_000_000;
+
+/// Largest number of decimals of a token, which is also the precision at which
+/// the swap path is computed.
+pub const MAX_DECIMALS: u32 = 18;
+
+/// Number of steps the swap path is split into unless set otherwise.
+pub const DEFAULT_STEPS: u32 = 64;
+
+const BASIS_POINTS: u128 = 10_000;
+
+/// `WAD` as a signed integer, for the arctangent.
+const WAD_SIGNED: i128 = WAD as i128;
+
+/// `pi / 2` rounded to 18 decimal places.
+const HALF_PI: i128 = 1_570_796_326_794_896_619;
+
+/// Bound on the absolute error of `atan_wad`, in units of `1 / WAD`.
+const ATAN_ERROR: i128 = 16;
+
+/// Number of intervals `[0, 1]` is split into by `ATAN_TABLE`.
+const TABLE_STEPS: i128 = 32;
+
+/// `atan(k / 32)` for `k` in `0..=32`, rounded to 18 decimal places.
+const ATAN_TABLE: [i128; 33] = [
+    0,
+    31239833430268276,
+    62418809995957348,
+    93476781158589464,
+    124354994546761435,
+    154996741923940982,
+    185347949995694765,
+    215357699697738048,
+    244978663126864154,
+    274167451119658798,
+    302884868374971406,
+    331096076704132095,
+    358770670270572220,
+    385882669398073776,
+    412410441597387307,
+    438336559857957805,
+    463647609000806116,
+    488333951056405524,
+    512389460310737707,
+    535811237960463700,
+    558599315343562436,
+    580756353567670399,
+    602287346134964182,
+    623199329934065931,
+    643501108793284387,
+    663202992706093255,
+    682316554874748078,
+    700854407884450172,
+    718829999621624505,
+    736257428981428132,
+    753151280962194390,
+    769526480405658260,
+    785398163397448310,
+];
+
+/// Direction in which a fixed-point result is rounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Rounding {
+    /// Towards negative infinity.
+    Down,
+    /// Towards positive infinity.
+    Up,
+}
+
+impl Rounding {
+    /// Returns the opposite direction.
+    pub fn reverse(self) -> Rounding {
+        match self {
+            Rounding::Down => Rounding::Up,
+            Rounding::Up => Rounding::Down,
+        }
+    }
+}
+
+/// Computes `a * b / d`, rounded in the direction given.
+///
+/// The product is computed on 256 bits, so the result is exact before rounding
+/// whenever it fits in a `u128`.
+///
+/// # Arguments
+///
+/// * `a` - The first factor.
+/// * `b` - The second factor.
+/// * `d` - The divisor.
+/// * `rounding` - The direction in which the quotient is rounded.
+///
+/// # Returns
+///
+/// A `Result` with the quotient, or `ArppError::Overflow` if `d` is zero or the
+/// quotient does not fit in a `u128`.
+pub fn mul_div(a: u128, b: u128, d: u128, rounding: Rounding) -> Result<u128, ArppError> {
+    if d == 0 {
+        return Err(ArppError::Overflow);
+    }
+    let (high, low) = wide_mul(a, b);
+    if high >= d {
+        return Err(ArppError::Overflow);
+    }
+    let (quotient, remainder) = if high == 0 {
+        (low / d, low % d)
+    } else {
+        wide_div(high, low, d)
+    };
+    match rounding {
+        Rounding::Up if remainder != 0 => quotient.checked_add(1).ok_or(ArppError::Overflow),
+        _ => Ok(quotient),
+    }
+}
+
+/// Computes `a * b / d` for signed factors and a positive `d`, rounded in the
+/// direction given.
+fn mul_div_signed(a: i128, b: i128, d: u128, rounding: Rounding) -> Result<i128, ArppError> {
+    let negative = (a < 0) != (b < 0);
+    let rounding = if negative {
+        rounding.reverse()
+    } else {
+        rounding
+    };
+    let magnitude = mul_div(a.unsigned_abs(), b.unsigned_abs(), d, rounding)?;
+    let magnitude = i128::try_from(magnitude).map_err(|_| ArppError::Overflow)?;
+    Ok(if negative { -magnitude } else { magnitude })
+}
+
+/// Returns the high and low halves of the 256-bit product `a * b`.
+fn wide_mul(a: u128, b: u128) -> (u128, u128) {
+    const MASK: u128 = u64::MAX as u128;
+    let (a_high, a_low) = (a >> 64, a & MASK);
+    let (b_high, b_low) = (b >> 64, b & MASK);
+    let low_low = a_low * b_low;
+    let high_low = a_high * b_low;
+    let low_high = a_low * b_high;
+    let middle = (low_low >> 64) + (high_low & MASK) + (low_high & MASK);
+    let low = (low_low & MASK) | (middle << 64);
+    let high = a_high * b_high + (high_low >> 64) + (low_high >> 64) + (middle >> 64);
+    (high, low)
+}
+
+/// Divides the 256-bit value `high * 2^128 + low` by `d`, which must be above
+/// `high`, and returns the quotient and the remainder.
+fn wide_div(high: u128, low: u128, d: u128) -> (u128, u128) {
+    let mut remainder = high;
+    let mut quotient = 0;
+    for bit in (0..128).rev() {
+        let carry = remainder >> 127;
+        remainder = (remainder << 1) | ((low >> bit) & 1);
+        quotient <<= 1;
+        // With the carry the shifted remainder is above 2^128, and so above `d`
+        if carry == 1 || remainder >= d {
+            remainder = remainder.wrapping_sub(d);
+            quotient |= 1;
+        }
+    }
+    (quotient, remainder)
+}
+
+/// Converts a `Decimal` amount into integer units with `decimals` decimals.
+///
+/// # Arguments
+///
+/// * `amount` - The amount, which must not be negative.
+/// * `decimals` - The number of decimals of the units.
+/// * `rounding` - The direction in which digits beyond `decimals` are rounded.
+///
+/// # Returns
+///
+/// A `Result` with the units, or an `Err` if the amount is negative, `decimals`
+/// is above `MAX_DECIMALS` or the units do not fit in a `u128`.
+pub fn to_units(amount: Decimal, decimals: u32, rounding: Rounding) -> Result<u128, ArppError> {
+    check_decimals(decimals)?;
+    if amount < Decimal::ZERO {
+        return Err(ArppError::InvalidAmount { amount });
+    }
+    mul_div(
+        amount.mantissa().unsigned_abs(),
+        10u128.pow(decimals),
+        10u128.pow(amount.scale()),
+        rounding,
+    )
+}
+
+/// Converts integer units with `decimals` decimals into a `Decimal` amount.
+///
+/// # Returns
+///
+/// A `Result` with the exact amount, or an `Err` if `decimals` is above
+/// `MAX_DECIMALS` or the amount is out of the range of `Decimal`.
+pub fn from_units(units: u128, decimals: u32) -> Result<Decimal, ArppError> {
+    check_decimals(decimals)?;
+    i128::try_from(units)
+        .ok()
+        .and_then(|units| Decimal::try_from_i128_with_scale(units, decimals).ok())
+        .ok_or(ArppError::Overflow)
+}
+
+/// Converts a `Decimal` price or parameter into the `WAD` format.
+pub fn to_wad(value: Decimal, rounding: Rounding) -> Result<u128, ArppError> {
+    to_units(value, MAX_DECIMALS, rounding)
+}
+
+/// Converts a value in the `WAD` format into a `Decimal`.
+pub fn from_wad(value: u128) -> Result<Decimal, ArppError> {
+    from_units(value, MAX_DECIMALS)
+}
+
+/// Converts units into a `Decimal` for an error report, saturating out of range.
+fn report(units: u128, decimals: u32) -> Decimal {
+    from_units(units, decimals).unwrap_or(Decimal::MAX)
+}
+
+fn check_decimals(decimals: u32) -> Result<(), ArppError> {
+    if decimals > MAX_DECIMALS {
+        return Err(ArppError::InvalidDecimals { decimals });
+    }
+    Ok(())
+}
+
+/// Computes the arctangent of `x`, both in the `WAD` format, with an absolute
+/// error of at most `ATAN_ERROR`.
+///
+/// This is the algorithm of `utils::atan` in integer arithmetic: arguments above
+/// one are mapped into `[0, 1]` with `atan(x) = pi/2 - atan(1/x)` and odd symmetry,
+/// then reduced against the nearest `c = k/32` with
+/// `atan(x) = atan(c) + atan((x - c) / (1 + x c))`, and the Taylor series of the
+/// remainder is summed until its terms vanish. Divisions truncate towards zero.
+fn atan_wad(x: i128) -> i128 {
+    if x < 0 {
+        return -atan_wad(x.saturating_neg());
+    }
+    if x > WAD_SIGNED {
+        return HALF_PI - atan_unit(WAD_SIGNED * WAD_SIGNED / x);
+    }
+    atan_unit(x)
+}
+
+/// Computes the arctangent of `x` in `[0, WAD]`.
+fn atan_unit(x: i128) -> i128 {
+    let k = (x * TABLE_STEPS + WAD_SIGNED / 2) / WAD_SIGNED;
+    let c = k * WAD_SIGNED / TABLE_STEPS;
+    let reduced = (x - c) * WAD_SIGNED / (WAD_SIGNED + x * c / WAD_SIGNED);
+    ATAN_TABLE[k as usize] + atan_series(reduced)
+}
+
+/// Sums the Taylor series of `atan(y)` for a small `y` in the `WAD` format.
+fn atan_series(y: i128) -> i128 {
+    let y_squared = y * y / WAD_SIGNED;
+    let mut power = y;
+    let mut sum = y;
+    let mut n = 1;
+    loop {
+        power = -power * y_squared / WAD_SIGNED;
+        n += 2;
+        let term = power / n;
+        if term == 0 {
+            return sum;
+        }
+        sum += term;
+    }
+}
+
+/// Calculates the ARPP price in fixed-point arithmetic.
+///
+/// ```text
+/// ARPP = p_ref * (1 + alpha * atan(beta * (r - 1)))
+/// ```
+///
+/// Every operation rounds in the direction given, and the error of the
+/// arctangent is added in that direction, so the price rounded down is never
+/// above the exact price at `r` and the price rounded up is never below it. The
+/// difference between the two is a few units of `1 / WAD` of `p_ref`.
+///
+/// # Arguments
+///
+/// * `p_ref` - Reference price, in the `WAD` format.
+/// * `alpha` - Amplitude of the price response, in the `WAD` format.
+/// * `beta` - Sensitivity of the price to the ratio, in the `WAD` format.
+/// * `r` - Ratio of Token B to Token A, in the `WAD` format.
+/// * `rounding` - The direction in which the price is rounded.
+///
+/// # Returns
+///
+/// A `Result` with the price in the `WAD` format, or an `Err` with
+/// `ArppError::UnsafeParameters` if the price is not positive, or
+/// `ArppError::Overflow` if it does not fit in a `u128`.
+///
+/// # Example
+///
+/// ```
+/// use arpp::arpp::fixed_point::{arpp_fixed, Rounding, WAD};
+///
+/// let p_ref = 100 * WAD;
+/// let alpha = WAD / 5; // 0.2
+/// let beta = WAD / 2;  // 0.5
+///
+/// let price = arpp_fixed(p_ref, alpha, beta, WAD, Rounding::Down).unwrap();
+/// assert!(price <= p_ref && p_ref - price < 1_000);
+/// ```
+pub fn arpp_fixed(
+    p_ref: u128,
+    alpha: u128,
+    beta: u128,
+    r: u128,
+    rounding: Rounding,
+) -> Result<u128, ArppError> {
+    let signed = |value: u128| i128::try_from(value).map_err(|_| ArppError::Overflow);
+    let deviation = i128::try_from(r).unwrap_or(i128::MAX) - WAD_SIGNED;
+    // The ratio only overflows far beyond the point where the arctangent is flat
+    let x = mul_div_signed(signed(beta)?, deviation, WAD, rounding).unwrap_or(i128::MAX);
+    let angle = match rounding {
+        Rounding::Down => atan_wad(x) - ATAN_ERROR,
+        Rounding::Up => atan_wad(x) + ATAN_ERROR,
+    };
+    let factor = WAD_SIGNED + mul_div_signed(signed(alpha)?, angle, WAD, rounding)?;
+    if factor <= 0 {
+        return Err(ArppError::UnsafeParameters {
+            alpha: report(alpha, MAX_DECIMALS),
+            beta: report(beta, MAX_DECIMALS),
+            low: Decimal::ZERO,
+        });
+    }
+    mul_div(p_ref, factor.unsigned_abs(), WAD, rounding)
+}
+
+/// Outcome of a fixed-point swap, in units of each token.
+///
+/// # Fields
+///
+/// - `token_in`: The token paid into the pool.
+/// - `amount_in`: The amount paid in, fee included.
+/// - `amount_out`: The amount of the other token paid out.
+/// - `fee`: The part of `amount_in` kept by the pool as a fee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixedSwap {
+    pub token_in: Token,
+    pub amount_in: u128,
+    pub amount_out: u128,
+    pub fee: u128,
+}
+
+impl FixedSwap {
+    /// Returns the token paid out by the pool.
+    pub fn token_out(&self) -> Token {
+        self.token_in.other()
+    }
+}
+
+/// A pool priced by the ARPP formula with the arctangent curve, computed entirely
+/// in integer arithmetic.
+///
+/// The balances are held in units of each token. The swap path is computed at a
+/// precision of 18 decimals, in `steps` steps, and the result is converted back
+/// into units rounding in the pool's favour. Fees are kept in the reserves, as in
+/// `LiquidityPool`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedPointPool {
+    token_a: u128,
+    token_b: u128,
+    decimals_a: u32,
+    decimals_b: u32,
+    p_ref: u128,
+    alpha: u128,
+    beta: u128,
+    fee_bps: u32,
+    steps: u32,
+}
+
+impl FixedPointPool {
+    /// Creates a pool without fee, pricing swaps in `DEFAULT_STEPS` steps.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_a` - The balance of Token A, in units of `10^-decimals_a`.
+    /// * `token_b` - The balance of Token B, in units of `10^-decimals_b`.
+    /// * `decimals_a` - The number of decimals of Token A.
+    /// * `decimals_b` - The number of decimals of Token B.
+    /// * `p_ref` - Reference price, in the `WAD` format.
+    /// * `alpha` - Amplitude of the price response, in the `WAD` format.
+    /// * `beta` - Sensitivity of the price to the ratio, in the `WAD` format.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the pool, or an `Err` if a token has more than
+    /// `MAX_DECIMALS` decimals or the parameters are rejected by
+    /// `check_parameters`.
+    pub fn new(
+        token_a: u128,
+        token_b: u128,
+        decimals_a: u32,
+        decimals_b: u32,
+        p_ref: u128,
+        alpha: u128,
+        beta: u128,
+    ) -> Result<Self, ArppError> {
+        check_decimals(decimals_a)?;
+        check_decimals(decimals_b)?;
+        check_parameters(
+            &ArctanCurve,
+            from_wad(p_ref)?,
+            from_wad(alpha)?,
+            from_wad(beta)?,
+        )?;
+        Ok(Self {
+            token_a,
+            token_b,
+            decimals_a,
+            decimals_b,
+            p_ref,
+            alpha,
+            beta,
+            fee_bps: 0,
+            steps: DEFAULT_STEPS,
+        })
+    }
+
+    /// Creates the fixed-point counterpart of `pool`.
+    ///
+    /// The balances are rounded down to units of each token, and `p_ref`, `alpha`
+    /// and `beta` are rounded down to 18 decimals. The oracle guards, the circuit
+    /// breaker and the liquidity positions are not carried over.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the pool, or an `Err` with
+    /// `ArppError::UnsupportedFixedPoint` if `pool` uses a curve other than the
+    /// arctangent, asymmetric parameters, a dynamic fee or a fractional fee.
+    pub fn from_pool(
+        pool: &LiquidityPool,
+        decimals_a: u32,
+        decimals_b: u32,
+    ) -> Result<Self, ArppError> {
+        let unsupported = |feature: &str| {
+            Err(ArppError::UnsupportedFixedPoint {
+                feature: feature.to_string(),
+            })
+        };
+        if pool.get_pricing_curve().spec() != Some(CurveSpec::Arctan) {
+            return unsupported("pricing curves other than the arctangent");
+        }
+        if !pool.get_parameters().is_symmetric() {
+            return unsupported("asymmetric parameters");
+        }
+        if pool.get_dynamic_fee().is_some() {
+            return unsupported("dynamic fees");
+        }
+        let Some(fee_bps) = Some(pool.get_fee_bps())
+            .filter(|fee_bps| fee_bps.fract().is_zero())
+            .and_then(|fee_bps| fee_bps.to_u32())
+        else {
+            return unsupported("fractional fees");
+        };
+
+        let (token_a, token_b) = pool.get_balances();
+        let mut fixed = Self::new(
+            to_units(token_a, decimals_a, Rounding::Down)?,
+            to_units(token_b, decimals_b, Rounding::Down)?,
+            decimals_a,
+            decimals_b,
+            to_wad(pool.get_p_ref(), Rounding::Down)?,
+            to_wad(pool.get_alpha(), Rounding::Down)?,
+            to_wad(pool.get_beta(), Rounding::Down)?,
+        )?;
+        fixed.set_fee_bps(fee_bps)?;
+        Ok(fixed)
+    }
+
+    /// Sets the fee, in whole basis points, taken from the input of every swap.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the fee was set, or an `Err` if it would take
+    /// the whole input.
+    pub fn set_fee_bps(&mut self, fee_bps: u32) -> Result<(), ArppError> {
+        if u128::from(fee_bps) >= BASIS_POINTS {
+            return Err(ArppError::InvalidFee {
+                fee_bps: Decimal::from(fee_bps),
+            });
+        }
+        self.fee_bps = fee_bps;
+        Ok(())
+    }
+
+    /// Returns the fee, in basis points, taken from the input of every swap.
+    pub fn get_fee_bps(&self) -> u32 {
+        self.fee_bps
+    }
+
+    /// Sets the number of steps the swap path is split into.
+    ///
+    /// More steps bring the swaps closer to the exact path integral, at the cost
+    /// of one price evaluation per step.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the number was set, or an `Err` if it is zero.
+    pub fn set_steps(&mut self, steps: u32) -> Result<(), ArppError> {
+        if steps == 0 {
+            return Err(ArppError::InvalidParameter {
+                name: "steps".to_string(),
+                value: Decimal::ZERO,
+            });
+        }
+        self.steps = steps;
+        Ok(())
+    }
+
+    /// Returns the number of steps the swap path is split into.
+    pub fn get_steps(&self) -> u32 {
+        self.steps
+    }
+
+    /// Returns the balances of Token A and Token B, in units of each token.
+    pub fn get_balances(&self) -> (u128, u128) {
+        (self.token_a, self.token_b)
+    }
+
+    /// Returns the number of decimals of Token A and Token B.
+    pub fn get_decimals(&self) -> (u32, u32) {
+        (self.decimals_a, self.decimals_b)
+    }
+
+    /// Returns the marginal ARPP price, in units of B per unit of A in the `WAD`
+    /// format, rounded in the direction given.
+    pub fn get_price(&self, rounding: Rounding) -> Result<u128, ArppError> {
+        let (token_a, token_b) = self.wad_balances()?;
+        self.price_at(token_a, token_b, rounding)
+    }
+
+    /// Swaps an exact amount in, rejecting the trade if it pays out too little.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the `FixedSwap` if successful, or the error of
+    /// `quote_exact_in`. `ArppError::SlippageExceeded` is returned, and the pool
+    /// is left untouched, if the output is below `min_out`.
+    pub fn swap_exact_in(
+        &mut self,
+        token_in: Token,
+        amount_in: u128,
+        min_out: u128,
+    ) -> Result<FixedSwap, ArppError> {
+        let result = self.quote_exact_in(token_in, amount_in)?;
+        if result.amount_out < min_out {
+            let decimals = self.decimals_of(result.token_out());
+            return Err(ArppError::SlippageExceeded {
+                token: result.token_out(),
+                amount: report(result.amount_out, decimals),
+                limit: report(min_out, decimals),
+            });
+        }
+        self.settle(&result);
+        Ok(result)
+    }
+
+    /// Swaps for an exact amount out, rejecting the trade if it costs too much.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the `FixedSwap` if successful, or the error of
+    /// `quote_exact_out`. `ArppError::SlippageExceeded` is returned, and the pool
+    /// is left untouched, if the input is above `max_in`.
+    pub fn swap_exact_out(
+        &mut self,
+        token_out: Token,
+        amount_out: u128,
+        max_in: u128,
+    ) -> Result<FixedSwap, ArppError> {
+        let result = self.quote_exact_out(token_out, amount_out)?;
+        if result.amount_in > max_in {
+            let decimals = self.decimals_of(result.token_in);
+            return Err(ArppError::SlippageExceeded {
+                token: result.token_in,
+                amount: report(result.amount_in, decimals),
+                limit: report(max_in, decimals),
+            });
+        }
+        self.settle(&result);
+        Ok(result)
+    }
+
+    /// Quotes a swap of an exact amount in without modifying the pool.
+    ///
+    /// The fee is rounded up, and the rest of the input is priced along the path
+    /// with each step paying out at the marginal price of the state it reaches
+    /// when settled at its start price, rounded against the trader. The total is
+    /// rounded down to units of the token out.
+    ///
+    /// # Arguments
+    ///
+    /// - `token_in`: The token paid into the pool.
+    /// - `amount_in`: Units of `token_in` to pay in.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the quoted `FixedSwap`, or an `Err` if the liquidity is
+    /// insufficient or the amount, or the amount it pays out, is zero.
+    pub fn quote_exact_in(&self, token_in: Token, amount_in: u128) -> Result<FixedSwap, ArppError> {
+        if amount_in == 0 {
+            return Err(ArppError::InvalidAmount {
+                amount: Decimal::ZERO,
+            });
+        }
+        let (balance_in, balance_out) = self.balances_of(token_in);
+        if amount_in > balance_in {
+            return Err(self.insufficient(token_in, amount_in));
+        }
+
+        let fee = mul_div(
+            amount_in,
+            u128::from(self.fee_bps),
+            BASIS_POINTS,
+            Rounding::Up,
+        )?;
+        let net_in = self.wad_amount(token_in, amount_in - fee)?;
+        let amount_out = self.units_amount(
+            token_in.other(),
+            self.path_out(token_in, net_in)?,
+            Rounding::Down,
+        )?;
+        if amount_out == 0 {
+            return Err(ArppError::InvalidAmount {
+                amount: report(amount_in, self.decimals_of(token_in)),
+            });
+        }
+        if amount_out >= balance_out {
+            return Err(self.insufficient(token_in.other(), balance_out));
+        }
+        Ok(FixedSwap {
+            token_in,
+            amount_in,
+            amount_out,
+            fee,
+        })
+    }
+
+    /// Quotes the amount that has to be paid in to receive an exact amount out,
+    /// without modifying the pool.
+    ///
+    /// The amount in, fee included, is the smallest one for which
+    /// `quote_exact_in` pays out at least `amount_out`, found by bisection. Any
+    /// excess paid out by that quote is kept by the pool.
+    ///
+    /// # Arguments
+    ///
+    /// - `token_out`: The token paid out by the pool.
+    /// - `amount_out`: Units of `token_out` to receive.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the quoted `FixedSwap`, or an `Err` if the pool cannot pay
+    /// out `amount_out` or the amount is zero.
+    pub fn quote_exact_out(
+        &self,
+        token_out: Token,
+        amount_out: u128,
+    ) -> Result<FixedSwap, ArppError> {
+        if amount_out == 0 {
+            return Err(ArppError::InvalidAmount {
+                amount: Decimal::ZERO,
+            });
+        }
+        let (balance_out, balance_in) = self.balances_of(token_out);
+        if amount_out >= balance_out {
+            return Err(self.insufficient(token_out, amount_out));
+        }
+
+        let token_in = token_out.other();
+        // An input is enough if it pays out `amount_out`, or too much for the pool
+        let enough = |amount_in: u128| match self.quote_exact_in(token_in, amount_in) {
+            Ok(quote) => quote.amount_out >= amount_out,
+            Err(ArppError::InvalidAmount { .. }) => false,
+            Err(_) => true,
+        };
+        let (mut low, mut high) = (0u128, balance_in);
+        if !enough(high) {
+            return Err(self.insufficient(token_in, balance_in.saturating_add(1)));
+        }
+        while high - low > 1 {
+            let middle = low + (high - low) / 2;
+            if enough(middle) {
+                high = middle;
+            } else {
+                low = middle;
+            }
+        }
+        match self.quote_exact_in(token_in, high) {
+            Ok(quote) if quote.amount_out >= amount_out => Ok(FixedSwap {
+                amount_out,
+                ..quote
+            }),
+            _ => Err(self.insufficient(token_out, amount_out)),
+        }
+    }
+
+    /// Returns the amount of the other token paid out along the path for `net_in`
+    /// of `token_in`, all at the precision of the `WAD` format.
+    fn path_out(&self, token_in: Token, net_in: u128) -> Result<u128, ArppError> {
+        let (mut token_a, mut token_b) = self.wad_balances()?;
+        let mut total = 0u128;
+        for step in self.split(net_in) {
+            let paid = match token_in {
+                // Paying A in lowers the price, which is lowest at the provisional end
+                Token::A => {
+                    let start = self.price_at(token_a, token_b, Rounding::Up)?;
+                    let token_a_end = token_a.checked_add(step).ok_or(ArppError::Overflow)?;
+                    let token_b_end =
+                        token_b.saturating_sub(mul_div(step, start, WAD, Rounding::Up)?);
+                    let price = self.price_at(token_a_end, token_b_end, Rounding::Down)?;
+                    let paid = mul_div(step, price, WAD, Rounding::Down)?;
+                    token_a = token_a_end;
+                    token_b = token_b
+                        .checked_sub(paid)
+                        .ok_or_else(|| self.insufficient(Token::B, self.token_b))?;
+                    paid
+                }
+                // Paying B in raises the price, which is highest at the provisional end
+                Token::B => {
+                    let start = self.price_at(token_a, token_b, Rounding::Down)?;
+                    let token_b_end = token_b.checked_add(step).ok_or(ArppError::Overflow)?;
+                    let token_a_end =
+                        token_a.saturating_sub(mul_div(step, WAD, start, Rounding::Up)?);
+                    let price = self.price_at(token_a_end, token_b_end, Rounding::Up)?;
+                    let paid = mul_div(step, WAD, price, Rounding::Down)?;
+                    token_b = token_b_end;
+                    token_a = token_a
+                        .checked_sub(paid)
+                        .ok_or_else(|| self.insufficient(Token::A, self.token_a))?;
+                    paid
+                }
+            };
+            total += paid;
+        }
+        Ok(total)
+    }
+
+    /// Splits `amount` into `steps` steps, the last one taking the remainder.
+    fn split(&self, amount: u128) -> impl Iterator<Item = u128> {
+        let steps = u128::from(self.steps);
+        let step = amount / steps;
+        (1..=steps).map(move |index| {
+            if index == steps {
+                amount - step * (steps - 1)
+            } else {
+                step
+            }
+        })
+    }
+
+    /// Returns the ARPP price at the balances given in the `WAD` format.
+    fn price_at(
+        &self,
+        token_a: u128,
+        token_b: u128,
+        rounding: Rounding,
+    ) -> Result<u128, ArppError> {
+        let ratio = mul_div(token_b, WAD, token_a, rounding).unwrap_or(u128::MAX);
+        arpp_fixed(self.p_ref, self.alpha, self.beta, ratio, rounding)
+    }
+
+    /// Returns the balances of the pool in the `WAD` format.
+    fn wad_balances(&self) -> Result<(u128, u128), ArppError> {
+        Ok((
+            self.wad_amount(Token::A, self.token_a)?,
+            self.wad_amount(Token::B, self.token_b)?,
+        ))
+    }
+
+    /// Converts units of `token` into the `WAD` format, which is exact.
+    fn wad_amount(&self, token: Token, units: u128) -> Result<u128, ArppError> {
+        units
+            .checked_mul(10u128.pow(MAX_DECIMALS - self.decimals_of(token)))
+            .ok_or(ArppError::Overflow)
+    }
+
+    /// Converts an amount of `token` in the `WAD` format into units of `token`.
+    fn units_amount(
+        &self,
+        token: Token,
+        amount: u128,
+        rounding: Rounding,
+    ) -> Result<u128, ArppError> {
+        let scale = 10u128.pow(MAX_DECIMALS - self.decimals_of(token));
+        mul_div(amount, 1, scale, rounding)
+    }
+
+    fn decimals_of(&self, token: Token) -> u32 {
+        match token {
+            Token::A => self.decimals_a,
+            Token::B => self.decimals_b,
+        }
+    }
+
+    fn balances_of(&self, token: Token) -> (u128, u128) {
+        match token {
+            Token::A => (self.token_a, self.token_b),
+            Token::B => (self.token_b, self.token_a),
+        }
+    }
+
+    fn insufficient(&self, token: Token, needed: u128) -> ArppError {
+        let decimals = self.decimals_of(token);
+        ArppError::InsufficientLiquidity {
+            token,
+            needed: report(needed, decimals),
+            available: report(self.balances_of(token).0, decimals),
+        }
+    }
+
+    fn settle(&mut self, result: &FixedSwap) {
+        match result.token_in {
+            Token::A => {
+                self.token_a += result.amount_in;
+                self.token_b -= result.amount_out;
+            }
+            Token::B => {
+                self.token_b += result.amount_in;
+                self.token_a -= result.amount_out;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_fixed_point {
+    use super::*;
+    use crate::arpp::curve::TanhCurve;
+    use crate::arpp::formula::arpp;
+    use crate::utils::atan::atan;
+    use rust_decimal_macros::dec;
+
+    /// Relative haircut and rounding applied by the `Decimal` reference swaps.
+    const REFERENCE_TOLERANCE: Decimal = dec!(0.00000000001);
+
+    /// A pool as a `LiquidityPool` and as the `FixedPointPool` built from it.
+    fn vector(
+        token_a: Decimal,
+        token_b: Decimal,
+        p_ref: Decimal,
+        alpha: Decimal,
+        beta: Decimal,
+        fee_bps: u32,
+        (decimals_a, decimals_b): (u32, u32),
+    ) -> (LiquidityPool, FixedPointPool) {
+        let mut pool = LiquidityPool::new(token_a, token_b, p_ref, alpha, beta);
+        pool.set_fee_bps(Decimal::from(fee_bps)).unwrap();
+        let fixed = FixedPointPool::from_pool(&pool, decimals_a, decimals_b).unwrap();
+        (pool, fixed)
+    }
+
+    /// Test vectors with tokens of 18, 8 and 6 decimals.
+    fn vectors() -> Vec<(LiquidityPool, FixedPointPool)> {
+        vec![
+            vector(
+                dec!(1000),
+                dec!(1000),
+                dec!(1),
+                dec!(0.5),
+                dec!(2),
+                0,
+                (18, 18),
+            ),
+            vector(
+                dec!(5000),
+                dec!(2500),
+                dec!(0.5),
+                dec!(0.3),
+                dec!(1),
+                30,
+                (18, 6),
+            ),
+            vector(
+                dec!(20000),
+                dec!(18000),
+                dec!(1.5),
+                dec!(0.6),
+                dec!(3),
+                5,
+                (8, 6),
+            ),
+            vector(
+                dec!(1000),
+                dec!(1200),
+                dec!(0.8),
+                dec!(0.2),
+                dec!(1.5),
+                30,
+                (6, 18),
+            ),
+        ]
+    }
+
+    fn balance(pool: &LiquidityPool, token: Token) -> Decimal {
+        let (token_a, token_b) = pool.get_balances();
+        match token {
+            Token::A => token_a,
+            Token::B => token_b,
+        }
+    }
+
+    /// Bound on the divergence from the path integral of `amount` of `token`, the
+    /// token paid in of a swap of an exact amount in, or paid out of a swap of an
+    /// exact amount out, priced in `steps` steps.
+    fn step_bound(
+        token: Token,
+        amount: Decimal,
+        price_before: Decimal,
+        price_after: Decimal,
+        steps: u32,
+    ) -> Decimal {
+        // The steps also drift from the exact path, which at most doubles the bound
+        let move_per_unit = match token {
+            Token::A => price_after - price_before,
+            Token::B => Decimal::ONE / price_after - Decimal::ONE / price_before,
+        };
+        dec!(2) * move_per_unit.abs() * amount / Decimal::from(steps)
+    }
+
+    #[test]
+    fn test_mul_div_rounds_in_the_requested_direction() {
+        assert_eq!(mul_div(10, 2, 3, Rounding::Down), Ok(6));
+        assert_eq!(mul_div(10, 2, 3, Rounding::Up), Ok(7));
+        assert_eq!(mul_div(9, 2, 3, Rounding::Up), Ok(6));
+        assert_eq!(mul_div_signed(-10, 2, 3, Rounding::Down), Ok(-7));
+        assert_eq!(mul_div_signed(-10, 2, 3, Rounding::Up), Ok(-6));
+
+        // Products beyond 128 bits are divided exactly
+        assert_eq!(
+            mul_div(u128::MAX, u128::MAX, u128::MAX, Rounding::Down),
+            Ok(u128::MAX)
+        );
+        assert_eq!(
+            mul_div(1 << 100, 1 << 100, 1 << 80, Rounding::Up),
+            Ok(1 << 120)
+        );
+        assert_eq!(
+            mul_div(u128::MAX, 7, 10, Rounding::Down),
+            Ok(238197656844656924424362225202237748018)
+        );
+        assert_eq!(
+            mul_div(u128::MAX, 7, 10, Rounding::Up),
+            Ok(238197656844656924424362225202237748019)
+        );
+
+        assert_eq!(
+            mul_div(u128::MAX, 3, 2, Rounding::Down),
+            Err(ArppError::Overflow)
+        );
+        assert_eq!(mul_div(1, 1, 0, Rounding::Down), Err(ArppError::Overflow));
+    }
+
+    #[test]
+    fn test_units_round_in_the_requested_direction() {
+        assert_eq!(to_units(dec!(1.5), 6, Rounding::Down), Ok(1_500_000));
+        assert_eq!(to_units(dec!(0.0000001), 6, Rounding::Down), Ok(0));
+        assert_eq!(to_units(dec!(0.0000001), 6, Rounding::Up), Ok(1));
+        assert_eq!(
+            to_units(dec!(1.2345678901234567891), 18, Rounding::Up),
+            Ok(1234567890123456790)
+        );
+        assert_eq!(
+            to_units(dec!(-1), 6, Rounding::Down),
+            Err(ArppError::InvalidAmount { amount: dec!(-1) })
+        );
+        assert_eq!(
+            to_units(dec!(1), 19, Rounding::Down),
+            Err(ArppError::InvalidDecimals { decimals: 19 })
+        );
+        assert_eq!(from_units(1_500_000, 6), Ok(dec!(1.5)));
+        assert_eq!(from_wad(WAD / 4), Ok(dec!(0.25)));
+    }
+
+    #[test]
+    fn test_atan_wad_is_within_its_error_bound() {
+        let mut arguments = vec![dec!(0), dec!(0.000000000000000001), dec!(1e6), dec!(1e9)];
+        let mut x = dec!(-40);
+        while x <= dec!(40) {
+            arguments.push(x);
+            x += dec!(0.0371);
+        }
+        for x in arguments {
+            let exact = atan(x) * Decimal::from(WAD);
+            let fixed = Decimal::from(atan_wad((x * Decimal::from(WAD)).to_i128().unwrap()));
+            assert!(
+                (fixed - exact).abs() <= Decimal::from(ATAN_ERROR),
+                "atan({}) is {} rather than {}",
+                x,
+                fixed,
+                exact
+            );
+        }
+    }
+
+    #[test]
+    fn test_arpp_fixed_brackets_the_decimal_reference() {
+        let vectors = [
+            (dec!(1), dec!(0.5), dec!(2), dec!(1)),
+            (dec!(1), dec!(0.5), dec!(2), dec!(0.25)),
+            (dec!(100), dec!(0.2), dec!(0.5), dec!(3.75)),
+            (dec!(0.01), dec!(0.6), dec!(3), dec!(0.000001)),
+            (dec!(2500), dec!(0.63), dec!(10), dec!(1.000000000000000001)),
+            (dec!(42.5), dec!(0.1), dec!(1), dec!(1000000)),
+        ];
+        for (p_ref, alpha, beta, r) in vectors {
+            let reference = arpp(p_ref, alpha, beta, r);
+            let wad = |value| to_wad(value, Rounding::Down).unwrap();
+            let price = |rounding| {
+                let price = arpp_fixed(wad(p_ref), wad(alpha), wad(beta), wad(r), rounding);
+                from_wad(price.unwrap()).unwrap()
+            };
+            let (down, up) = (price(Rounding::Down), price(Rounding::Up));
+            assert!(
+                down <= reference && reference <= up,
+                "[{}, {}] does not contain {} at r = {}",
+                down,
+                up,
+                reference,
+                r
+            );
+            assert!(up - down <= p_ref * dec!(0.0000000000000001) + dec!(0.000000000000000002));
+        }
+    }
+
+    #[test]
+    fn test_exact_in_swaps_favour_the_pool_within_the_step_bound() {
+        for (pool, fixed) in vectors() {
+            let (decimals_a, decimals_b) = fixed.get_decimals();
+            for token_in in [Token::A, Token::B] {
+                let (decimals_in, decimals_out) = match token_in {
+                    Token::A => (decimals_a, decimals_b),
+                    Token::B => (decimals_b, decimals_a),
+                };
+                for fraction in [dec!(0.001), dec!(0.05), dec!(0.2)] {
+                    let amount_in = balance(&pool, token_in) * fraction;
+                    let reference = pool.quote_exact_in(token_in, amount_in).unwrap();
+                    let units_in = to_units(amount_in, decimals_in, Rounding::Down).unwrap();
+                    let result = fixed.quote_exact_in(token_in, units_in).unwrap();
+                    assert_eq!(from_units(result.fee, decimals_in), Ok(reference.fee));
+
+                    let amount_out = from_units(result.amount_out, decimals_out).unwrap();
+                    let tolerance = reference.amount_out * REFERENCE_TOLERANCE
+                        + from_units(1, decimals_out).unwrap();
+                    let bound = step_bound(
+                        token_in,
+                        amount_in - reference.fee,
+                        reference.price_before,
+                        reference.price_after,
+                        fixed.get_steps(),
+                    );
+                    assert!(
+                        amount_out <= reference.amount_out + tolerance
+                            && reference.amount_out - amount_out <= bound + tolerance,
+                        "{:?} in {} pays out {} against {} with a bound of {}",
+                        token_in,
+                        amount_in,
+                        amount_out,
+                        reference.amount_out,
+                        bound
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_exact_out_swaps_favour_the_pool_within_the_step_bound() {
+        for (pool, fixed) in vectors() {
+            let (decimals_a, decimals_b) = fixed.get_decimals();
+            for token_out in [Token::A, Token::B] {
+                let (decimals_out, decimals_in) = match token_out {
+                    Token::A => (decimals_a, decimals_b),
+                    Token::B => (decimals_b, decimals_a),
+                };
+                for fraction in [dec!(0.001), dec!(0.05), dec!(0.2)] {
+                    let amount_out = balance(&pool, token_out) * fraction;
+                    let reference = pool.quote_exact_out(token_out, amount_out).unwrap();
+                    let units_out = to_units(amount_out, decimals_out, Rounding::Down).unwrap();
+                    let result = fixed.quote_exact_out(token_out, units_out).unwrap();
+                    assert_eq!(result.amount_out, units_out);
+
+                    let amount_in = from_units(result.amount_in, decimals_in).unwrap();
+                    let tolerance = reference.amount_in * REFERENCE_TOLERANCE
+                        + from_units(1, decimals_in).unwrap();
+                    let bound = step_bound(
+                        token_out,
+                        amount_out,
+                        reference.price_before,
+                        reference.price_after,
+                        fixed.get_steps(),
+                    ) * reference.amount_in
+                        / (reference.amount_in - reference.fee);
+                    assert!(
+                        amount_in + tolerance >= reference.amount_in
+                            && amount_in - reference.amount_in <= bound + tolerance,
+                        "{:?} out {} costs {} against {} with a bound of {}",
+                        token_out,
+                        amount_out,
+                        amount_in,
+                        reference.amount_in,
+                        bound
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_more_steps_bring_swaps_closer_to_the_reference() {
+        let (pool, mut fixed) = vectors().remove(0);
+        let reference = pool.quote_exact_in(Token::A, dec!(100)).unwrap().amount_out;
+        let mut previous = Decimal::MAX;
+        for steps in [4, 16, 64, 256] {
+            fixed.set_steps(steps).unwrap();
+            let result = fixed.quote_exact_in(Token::A, 100 * WAD).unwrap();
+            let divergence = reference - from_wad(result.amount_out).unwrap();
+            assert!(divergence > Decimal::ZERO && divergence < previous);
+            previous = divergence;
+        }
+        assert_eq!(
+            fixed.set_steps(0),
+            Err(ArppError::InvalidParameter {
+                name: "steps".to_string(),
+                value: Decimal::ZERO,
+            })
+        );
+    }
+
+    #[test]
+    fn test_round_trips_never_return_more_than_paid() {
+        for (_, fixed) in vectors() {
+            for token_in in [Token::A, Token::B] {
+                let amount_in = fixed.balances_of(token_in).0 / 10;
+                let mut pool = fixed.clone();
+                let there = pool.swap_exact_in(token_in, amount_in, 0).unwrap();
+                let back = pool
+                    .swap_exact_in(token_in.other(), there.amount_out, 0)
+                    .unwrap();
+                assert!(back.amount_out <= amount_in);
+
+                let amount_out = fixed.balances_of(token_in.other()).0 / 10;
+                let mut pool = fixed.clone();
+                let there = pool
+                    .swap_exact_out(token_in.other(), amount_out, u128::MAX)
+                    .unwrap();
+                let back = pool
+                    .swap_exact_out(token_in, there.amount_in, u128::MAX)
+                    .unwrap();
+                assert!(back.amount_in >= there.amount_out);
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_pool_rejects_what_it_cannot_model() {
+        let mut pool = LiquidityPool::new(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(2));
+        assert_eq!(
+            FixedPointPool::from_pool(&pool, 19, 18),
+            Err(ArppError::InvalidDecimals { decimals: 19 })
+        );
+        pool.set_fee_bps(dec!(2.5)).unwrap();
+        assert!(matches!(
+            FixedPointPool::from_pool(&pool, 18, 18),
+            Err(ArppError::UnsupportedFixedPoint { .. })
+        ));
+        pool.set_fee_bps(dec!(3)).unwrap();
+        pool.set_pricing_curve(TanhCurve).unwrap();
+        assert!(matches!(
+            FixedPointPool::from_pool(&pool, 18, 18),
+            Err(ArppError::UnsupportedFixedPoint { .. })
+        ));
+    }
+}
@@ -4,7 +4,9 @@
    Date: 10/9/24
 ******************************************************************************/
 
-use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use crate::arpp::curve::{ArctanCurve, PricingCurve};
+use crate::arpp::error::ArppError;
+use rust_decimal::prelude::Signed;
 use rust_decimal::Decimal;
 
 /// Computes the adjusted reference pressure (ARPP).
@@ -20,7 +22,8 @@ use rust_decimal::Decimal;
 /// - `r` is the radius or input value for the angle component.
 ///
 /// The `atan` function computes the arctangent of the angle, and all
-/// arithmetic operations are performed using the `Decimal` type.
+/// arithmetic operations, `atan` included, are performed using the `Decimal`
+/// type, with an absolute error in `atan` below `1e-27`.
 ///
 /// # Arguments
 ///
@@ -49,40 +52,470 @@ use rust_decimal::Decimal;
 /// info!("ARPP result: {}", result);
 /// ```
 pub fn arpp(p_ref: Decimal, alpha: Decimal, beta: Decimal, r: Decimal) -> Decimal {
+    arpp_with_curve(&ArctanCurve, p_ref, alpha, beta, r)
+}
+
+/// Computes the ARPP with `curve` as the anchoring function in place of `atan`.
+///
+/// ```text
+/// ARPP = p_ref * (1 + alpha * curve.anchor(beta * (r - 1)))
+/// ```
+///
+/// # Arguments
+///
+/// * `curve` - The anchoring function.
+/// * `p_ref` - Reference price.
+/// * `alpha` - Scaling parameter of the anchoring function.
+/// * `beta` - Scaling parameter of the deviation of the ratio from one.
+/// * `r` - Ratio of Token B to Token A.
+///
+/// # Returns
+///
+/// Returns the ARPP price as a `Decimal`.
+pub fn arpp_with_curve(
+    curve: &dyn PricingCurve,
+    p_ref: Decimal,
+    alpha: Decimal,
+    beta: Decimal,
+    r: Decimal,
+) -> Decimal {
     let one = Decimal::ONE;
-    let angle = beta * (r - one);
-    // Convert to f64, calculate atan, and convert back to Decimal
-    let angle_f64 = angle.to_f64().unwrap();
-    let atan_value = Decimal::from_f64(libm::atan(angle_f64)).unwrap();
-    p_ref * (one + alpha * atan_value)
+    // The ratio is never negative, so a deviation too large for `Decimal` comes from
+    // a huge ratio, where the curve has already reached its limit
+    let anchor = beta
+        .checked_mul(r - one)
+        .map_or(curve.limit() * beta.signum(), |x| curve.anchor(x));
+    p_ref * (one + alpha * anchor)
+}
+
+/// Partial derivatives of the ARPP price at a ratio.
+///
+/// With `x = beta * (r - 1)` and `P = p_ref * (1 + alpha * anchor(x))`:
+/// ```text
+/// dP/dr      = p_ref * alpha * beta * anchor'(x)
+/// dP/dalpha  = p_ref * anchor(x)
+/// dP/dbeta   = p_ref * alpha * (r - 1) * anchor'(x)
+/// dP/dp_ref  = 1 + alpha * anchor(x)
+/// d2P/dr2    = p_ref * alpha * beta^2 * anchor''(x)
+/// ```
+///
+/// # Fields
+///
+/// * `d_ratio` - Derivative with respect to the ratio of Token B to Token A.
+/// * `d_alpha` - Derivative with respect to `alpha`.
+/// * `d_beta` - Derivative with respect to `beta`.
+/// * `d_p_ref` - Derivative with respect to `p_ref`.
+/// * `curvature` - Second derivative with respect to the ratio.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sensitivities {
+    pub d_ratio: Decimal,
+    pub d_alpha: Decimal,
+    pub d_beta: Decimal,
+    pub d_p_ref: Decimal,
+    pub curvature: Decimal,
+}
+
+/// Computes the partial derivatives of `arpp` at the ratio `r`.
+///
+/// # Arguments
+///
+/// * `p_ref` - Reference price.
+/// * `alpha` - Scaling parameter of the anchoring function.
+/// * `beta` - Scaling parameter of the deviation of the ratio from one.
+/// * `r` - Ratio of Token B to Token A.
+///
+/// # Returns
+///
+/// The `Sensitivities` of the price.
+///
+/// # Example
+///
+/// ```
+/// use rust_decimal_macros::dec;
+/// use arpp::arpp::formula::sensitivities;
+///
+/// // At balance the price moves by p_ref * alpha * beta per unit of ratio
+/// let sensitivities = sensitivities(dec!(100), dec!(0.2), dec!(0.5), dec!(1));
+/// assert_eq!(sensitivities.d_ratio, dec!(10));
+/// assert_eq!(sensitivities.curvature, dec!(0));
+/// ```
+pub fn sensitivities(p_ref: Decimal, alpha: Decimal, beta: Decimal, r: Decimal) -> Sensitivities {
+    sensitivities_with_curve(&ArctanCurve, p_ref, alpha, beta, r)
+}
+
+/// Computes the partial derivatives of `arpp_with_curve` at the ratio `r`, from
+/// the derivatives of `curve`.
+///
+/// # Arguments
+///
+/// * `curve` - The anchoring function.
+/// * `p_ref` - Reference price.
+/// * `alpha` - Scaling parameter of the anchoring function.
+/// * `beta` - Scaling parameter of the deviation of the ratio from one.
+/// * `r` - Ratio of Token B to Token A.
+///
+/// # Returns
+///
+/// The `Sensitivities` of the price. Once the deviation is too large for
+/// `Decimal` the curve is saturated, and only `d_alpha` and `d_p_ref` are not
+/// zero.
+pub fn sensitivities_with_curve(
+    curve: &dyn PricingCurve,
+    p_ref: Decimal,
+    alpha: Decimal,
+    beta: Decimal,
+    r: Decimal,
+) -> Sensitivities {
+    let deviation = r - Decimal::ONE;
+    let (anchor, slope, curvature) = match beta.checked_mul(deviation) {
+        Some(x) => (curve.anchor(x), curve.slope(x), curve.curvature(x)),
+        None => (curve.limit() * beta.signum(), Decimal::ZERO, Decimal::ZERO),
+    };
+    Sensitivities {
+        d_ratio: p_ref * alpha * beta * slope,
+        d_alpha: p_ref * anchor,
+        d_beta: p_ref * alpha * deviation * slope,
+        d_p_ref: Decimal::ONE + alpha * anchor,
+        curvature: p_ref * alpha * beta * beta * curvature,
+    }
+}
+
+/// Computes the ratio at which the ARPP price equals `p_target`, inverting
+/// `arpp` in closed form:
+/// ```text
+/// r = 1 + tan((p_target / p_ref - 1) / alpha) / beta
+/// ```
+///
+/// # Arguments
+///
+/// * `p_target` - The price to reach, in units of B per unit of A.
+/// * `p_ref` - Reference price.
+/// * `alpha` - Scaling parameter of the anchoring function.
+/// * `beta` - Scaling parameter of the deviation of the ratio from one.
+///
+/// # Returns
+///
+/// A `Result` with the ratio of Token B to Token A, or
+/// `ArppError::UnreachablePrice` if no positive ratio gives `p_target`.
+///
+/// # Example
+///
+/// ```
+/// use rust_decimal_macros::dec;
+/// use arpp::arpp::formula::{arpp, ratio_for_price};
+///
+/// let ratio = ratio_for_price(dec!(105), dec!(100), dec!(0.2), dec!(0.5)).unwrap();
+/// let price = arpp(dec!(100), dec!(0.2), dec!(0.5), ratio);
+/// assert!((price - dec!(105)).abs() < dec!(0.000000001));
+/// ```
+pub fn ratio_for_price(
+    p_target: Decimal,
+    p_ref: Decimal,
+    alpha: Decimal,
+    beta: Decimal,
+) -> Result<Decimal, ArppError> {
+    ratio_for_price_with_curve(&ArctanCurve, p_target, p_ref, alpha, beta)
+}
+
+/// Computes the ratio at which the ARPP price with `curve` as the anchoring
+/// function equals `p_target`, using `PricingCurve::inverse`.
+///
+/// With a zero `alpha` or `beta` the price is `p_ref` at every ratio, and a
+/// target of `p_ref` is met at balance.
+///
+/// # Arguments
+///
+/// * `curve` - The anchoring function.
+/// * `p_target` - The price to reach, in units of B per unit of A.
+/// * `p_ref` - Reference price.
+/// * `alpha` - Scaling parameter of the anchoring function.
+/// * `beta` - Scaling parameter of the deviation of the ratio from one.
+///
+/// # Returns
+///
+/// A `Result` with the ratio of Token B to Token A, or
+/// `ArppError::UnreachablePrice` with the range of prices the curve reaches if
+/// no positive ratio gives `p_target`.
+pub fn ratio_for_price_with_curve(
+    curve: &dyn PricingCurve,
+    p_target: Decimal,
+    p_ref: Decimal,
+    alpha: Decimal,
+    beta: Decimal,
+) -> Result<Decimal, ArppError> {
+    let (low, high) = price_range(curve, p_ref, alpha, beta);
+    let unreachable = ArppError::UnreachablePrice {
+        price: p_target,
+        low,
+        high,
+    };
+    if p_ref.is_zero() || alpha.is_zero() || beta.is_zero() {
+        return if p_target == p_ref {
+            Ok(Decimal::ONE)
+        } else {
+            Err(unreachable)
+        };
+    }
+    if p_target <= low || p_target >= high {
+        return Err(unreachable);
+    }
+    let ratio = (p_target / p_ref - Decimal::ONE)
+        .checked_div(alpha)
+        .and_then(|y| curve.inverse(y))
+        .and_then(|x| x.checked_div(beta))
+        .map(|x| Decimal::ONE + x);
+    match ratio {
+        Some(ratio) if ratio > Decimal::ZERO => Ok(ratio),
+        _ => Err(unreachable),
+    }
+}
+
+/// Returns the range of ARPP prices over all ratios, from the price of a pool
+/// without Token B to the limit as the ratio grows, lowest first.
+///
+/// The ends are only approached, as neither ratio can be reached by a swap.
+pub fn price_range(
+    curve: &dyn PricingCurve,
+    p_ref: Decimal,
+    alpha: Decimal,
+    beta: Decimal,
+) -> (Decimal, Decimal) {
+    price_range_asymmetric(curve, p_ref, &AsymmetricParameters::symmetric(alpha, beta))
+}
+
+/// The `alpha` and `beta` of the ARPP formula on each side of balance: the `up`
+/// pair shapes the price for ratios above one, the `down` pair for ratios below.
+///
+/// Every anchoring function is zero at zero, so the price is `p_ref` at balance
+/// from both sides and stays continuous whatever the parameters. Its slope there
+/// is `p_ref * alpha * beta` of the side, and is only continuous when
+/// `alpha_up * beta_up == alpha_down * beta_down`.
+///
+/// # Fields
+///
+/// * `alpha_up` - Scaling parameter of the anchoring function above balance.
+/// * `alpha_down` - Scaling parameter of the anchoring function below balance.
+/// * `beta_up` - Scaling parameter of the deviation of the ratio above balance.
+/// * `beta_down` - Scaling parameter of the deviation of the ratio below balance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AsymmetricParameters {
+    pub alpha_up: Decimal,
+    pub alpha_down: Decimal,
+    pub beta_up: Decimal,
+    pub beta_down: Decimal,
+}
+
+impl AsymmetricParameters {
+    pub fn new(
+        alpha_up: Decimal,
+        alpha_down: Decimal,
+        beta_up: Decimal,
+        beta_down: Decimal,
+    ) -> Self {
+        Self {
+            alpha_up,
+            alpha_down,
+            beta_up,
+            beta_down,
+        }
+    }
+
+    /// Returns the parameters of a curve with the same `alpha` and `beta` on both
+    /// sides, priced exactly as `arpp_with_curve`.
+    pub fn symmetric(alpha: Decimal, beta: Decimal) -> Self {
+        Self::new(alpha, alpha, beta, beta)
+    }
+
+    /// Returns `true` if both sides share the same `alpha` and `beta`.
+    pub fn is_symmetric(&self) -> bool {
+        self.alpha_up == self.alpha_down && self.beta_up == self.beta_down
+    }
+
+    /// Returns the `alpha` and `beta` pricing the ratio `r`, those above balance
+    /// at balance itself.
+    pub fn at_ratio(&self, r: Decimal) -> (Decimal, Decimal) {
+        if r >= Decimal::ONE {
+            (self.alpha_up, self.beta_up)
+        } else {
+            (self.alpha_down, self.beta_down)
+        }
+    }
+
+    /// Returns the `alpha` and `beta` of the side on which the price `price` is
+    /// met: above balance for prices from `p_ref` up, as long as both `alpha` and
+    /// `beta` are positive.
+    pub fn at_price(&self, price: Decimal, p_ref: Decimal) -> (Decimal, Decimal) {
+        if price >= p_ref {
+            (self.alpha_up, self.beta_up)
+        } else {
+            (self.alpha_down, self.beta_down)
+        }
+    }
+}
+
+/// Computes the ARPP with separate `alpha` and `beta` above and below balance.
+///
+/// ```text
+/// ARPP = p_ref * (1 + alpha_up * atan(beta_up * (r - 1)))      for r >= 1
+/// ARPP = p_ref * (1 + alpha_down * atan(beta_down * (r - 1)))  for r < 1
+/// ```
+///
+/// # Arguments
+///
+/// * `p_ref` - Reference price.
+/// * `parameters` - The `alpha` and `beta` of each side.
+/// * `r` - Ratio of Token B to Token A.
+///
+/// # Returns
+///
+/// Returns the ARPP price as a `Decimal`.
+///
+/// # Example
+///
+/// ```
+/// use rust_decimal_macros::dec;
+/// use arpp::arpp::formula::{arpp, arpp_asymmetric, AsymmetricParameters};
+///
+/// // The price can fall half as far below balance as it rises above it, with the
+/// // same slope at balance
+/// let parameters = AsymmetricParameters::new(dec!(0.2), dec!(0.1), dec!(0.5), dec!(1));
+/// assert_eq!(arpp_asymmetric(dec!(100), &parameters, dec!(1)), dec!(100));
+/// assert_eq!(
+///     arpp_asymmetric(dec!(100), &parameters, dec!(0.5)),
+///     arpp(dec!(100), dec!(0.1), dec!(1), dec!(0.5))
+/// );
+/// ```
+pub fn arpp_asymmetric(p_ref: Decimal, parameters: &AsymmetricParameters, r: Decimal) -> Decimal {
+    arpp_asymmetric_with_curve(&ArctanCurve, p_ref, parameters, r)
+}
+
+/// Computes the ARPP with `curve` as the anchoring function and separate `alpha`
+/// and `beta` above and below balance.
+///
+/// # Arguments
+///
+/// * `curve` - The anchoring function.
+/// * `p_ref` - Reference price.
+/// * `parameters` - The `alpha` and `beta` of each side.
+/// * `r` - Ratio of Token B to Token A.
+///
+/// # Returns
+///
+/// Returns the ARPP price as a `Decimal`.
+pub fn arpp_asymmetric_with_curve(
+    curve: &dyn PricingCurve,
+    p_ref: Decimal,
+    parameters: &AsymmetricParameters,
+    r: Decimal,
+) -> Decimal {
+    let (alpha, beta) = parameters.at_ratio(r);
+    arpp_with_curve(curve, p_ref, alpha, beta, r)
+}
+
+/// Computes the partial derivatives of `arpp_asymmetric_with_curve` at the ratio
+/// `r`, with those of the parameters of the side pricing `r`.
+///
+/// `d_alpha`, `d_beta`, `d_p_ref` and `curvature` are continuous at balance, as
+/// the anchoring function and its second derivative are zero there. `d_ratio`
+/// is `p_ref * alpha * beta` at balance on each side, so it is continuous for the
+/// parameters accepted by `check_asymmetric_parameters`, whose products match.
+///
+/// # Arguments
+///
+/// * `curve` - The anchoring function.
+/// * `p_ref` - Reference price.
+/// * `parameters` - The `alpha` and `beta` of each side.
+/// * `r` - Ratio of Token B to Token A.
+///
+/// # Returns
+///
+/// The `Sensitivities` of the price.
+pub fn sensitivities_asymmetric_with_curve(
+    curve: &dyn PricingCurve,
+    p_ref: Decimal,
+    parameters: &AsymmetricParameters,
+    r: Decimal,
+) -> Sensitivities {
+    let (alpha, beta) = parameters.at_ratio(r);
+    sensitivities_with_curve(curve, p_ref, alpha, beta, r)
+}
+
+/// Computes the ratio at which the asymmetric ARPP price with `curve` as the
+/// anchoring function equals `p_target`, inverting the side on which the target
+/// is met.
+///
+/// # Arguments
+///
+/// * `curve` - The anchoring function.
+/// * `p_target` - The price to reach, in units of B per unit of A.
+/// * `p_ref` - Reference price.
+/// * `parameters` - The `alpha` and `beta` of each side.
+///
+/// # Returns
+///
+/// A `Result` with the ratio of Token B to Token A, or
+/// `ArppError::UnreachablePrice` with the range of prices of both sides if no
+/// positive ratio gives `p_target`.
+pub fn ratio_for_price_asymmetric_with_curve(
+    curve: &dyn PricingCurve,
+    p_target: Decimal,
+    p_ref: Decimal,
+    parameters: &AsymmetricParameters,
+) -> Result<Decimal, ArppError> {
+    let (alpha, beta) = parameters.at_price(p_target, p_ref);
+    ratio_for_price_with_curve(curve, p_target, p_ref, alpha, beta).map_err(|_| {
+        let (low, high) = price_range_asymmetric(curve, p_ref, parameters);
+        ArppError::UnreachablePrice {
+            price: p_target,
+            low,
+            high,
+        }
+    })
+}
+
+/// Returns the range of asymmetric ARPP prices over all ratios, from the price of
+/// a pool without Token B, set by the parameters below balance, to the limit as
+/// the ratio grows, set by those above, lowest first.
+pub fn price_range_asymmetric(
+    curve: &dyn PricingCurve,
+    p_ref: Decimal,
+    parameters: &AsymmetricParameters,
+) -> (Decimal, Decimal) {
+    let empty = arpp_asymmetric_with_curve(curve, p_ref, parameters, Decimal::ZERO);
+    let saturated =
+        p_ref * (Decimal::ONE + parameters.alpha_up * curve.limit() * parameters.beta_up.signum());
+    (empty.min(saturated), empty.max(saturated))
 }
 
-/// Calculates the ratio of two given decimal tokens.
+/// Calculates the ratio `R = token_b / token_a` at which the ARPP formula prices
+/// Token A.
 ///
-/// This function takes two `Decimal` values and returns the ratio of `token_a` to `token_b`.
-/// If `token_b` is zero, the function returns `Decimal::MAX` to signify an infinite ratio.
-/// If `token_a` is zero, the function returns `Decimal::ZERO`.
+/// The ratio rises as Token A becomes scarce next to Token B, so the price of A
+/// rises when A is bought from the pool and falls when it is sold into it.
+/// If `token_a` is zero, the function returns `Decimal::MAX` to signify an infinite ratio.
+/// If `token_b` is zero, the function returns `Decimal::ZERO`.
 ///
 /// # Parameters
-/// - `token_a`: The numerator represented by a `Decimal`.
-/// - `token_b`: The denominator represented by a `Decimal`.
+/// - `token_a`: The balance of Token A, the denominator.
+/// - `token_b`: The balance of Token B, the numerator.
 ///
 /// # Returns
-/// Returns a `Decimal` representation of the ratio `token_a / token_b`.
+/// Returns a `Decimal` representation of the ratio `token_b / token_a`.
 ///
 pub(crate) fn token_ratio(token_a: Decimal, token_b: Decimal) -> Decimal {
-    if token_b == Decimal::ZERO {
+    if token_a == Decimal::ZERO {
         return Decimal::MAX;
     }
-    if token_a == Decimal::ZERO {
+    if token_b == Decimal::ZERO {
         return Decimal::ZERO;
     }
-    token_a / token_b
+    token_b / token_a
 }
 
 #[cfg(test)]
 mod tests_arpp {
     use super::*;
+    use crate::arpp::curve::TanhCurve;
     use crate::utils::logger::setup_logger;
     use assert_approx_eq::assert_approx_eq;
     use rust_decimal_macros::dec;
@@ -269,4 +702,205 @@ mod tests_arpp {
         assert_approx_eq!(price1, dec!(105.350157507), Decimal::new(1, 9));
         assert_approx_eq!(price2, dec!(101.161056631), Decimal::new(1, 9));
     }
+
+    #[test]
+    fn test_price_keeps_decimal_precision() {
+        // p_ref * (1 + alpha * atan(1)) = 1 + pi / 8
+        let price = arpp(dec!(1), dec!(0.5), dec!(1), dec!(2));
+        assert_approx_eq!(
+            price,
+            dec!(1.3926990816987241548078304229),
+            dec!(0.000000000000000000000000001)
+        );
+    }
+
+    #[test]
+    fn test_drained_a_does_not_overflow() {
+        let ratio = token_ratio(Decimal::ZERO, dec!(1000));
+        let price = arpp(dec!(1), dec!(0.5), dec!(2), ratio);
+        assert_approx_eq!(price, dec!(1) + Decimal::HALF_PI / dec!(2), dec!(1e-27));
+        let price = arpp(dec!(1), dec!(0.5), dec!(-2), ratio);
+        assert_approx_eq!(price, dec!(1) - Decimal::HALF_PI / dec!(2), dec!(1e-27));
+    }
+
+    #[test]
+    fn test_sensitivities_match_finite_differences() {
+        let (p_ref, alpha, beta) = (dec!(101.06), dec!(0.1), dec!(0.4));
+        let h = dec!(0.00000001);
+        let central =
+            |f: &dyn Fn(Decimal) -> Decimal, x: Decimal| (f(x + h) - f(x - h)) / (dec!(2) * h);
+        for curve in [&ArctanCurve as &dyn PricingCurve, &TanhCurve] {
+            for r in [dec!(0.3), dec!(1), dec!(1.37), dec!(6)] {
+                let sensitivities = sensitivities_with_curve(curve, p_ref, alpha, beta, r);
+                let price = |p_ref, alpha, beta, r| arpp_with_curve(curve, p_ref, alpha, beta, r);
+                let tolerance = dec!(0.0000001);
+                assert_approx_eq!(
+                    sensitivities.d_ratio,
+                    central(&|r| price(p_ref, alpha, beta, r), r),
+                    tolerance
+                );
+                assert_approx_eq!(
+                    sensitivities.d_alpha,
+                    central(&|alpha| price(p_ref, alpha, beta, r), alpha),
+                    tolerance
+                );
+                assert_approx_eq!(
+                    sensitivities.d_beta,
+                    central(&|beta| price(p_ref, alpha, beta, r), beta),
+                    tolerance
+                );
+                assert_approx_eq!(
+                    sensitivities.d_p_ref,
+                    central(&|p_ref| price(p_ref, alpha, beta, r), p_ref),
+                    tolerance
+                );
+                let d_ratio = |r| sensitivities_with_curve(curve, p_ref, alpha, beta, r).d_ratio;
+                assert_approx_eq!(sensitivities.curvature, central(&d_ratio, r), tolerance);
+            }
+        }
+        // At balance only the slope and p_ref move the price
+        let at_balance = sensitivities(p_ref, alpha, beta, dec!(1));
+        assert_eq!(at_balance.d_ratio, p_ref * alpha * beta);
+        assert_eq!(at_balance.d_alpha, Decimal::ZERO);
+        assert_eq!(at_balance.d_beta, Decimal::ZERO);
+        assert_eq!(at_balance.d_p_ref, Decimal::ONE);
+    }
+
+    #[test]
+    fn test_sensitivities_of_drained_pool() {
+        let ratio = token_ratio(Decimal::ZERO, dec!(1000));
+        let sensitivities = sensitivities(dec!(1), dec!(0.5), dec!(2), ratio);
+        assert_eq!(sensitivities.d_ratio, Decimal::ZERO);
+        assert_eq!(sensitivities.d_beta, Decimal::ZERO);
+        assert_eq!(sensitivities.curvature, Decimal::ZERO);
+        assert_approx_eq!(sensitivities.d_alpha, Decimal::HALF_PI, dec!(1e-27));
+    }
+
+    #[test]
+    fn test_ratio_for_price_inverts_arpp() {
+        for ratio in [dec!(0.05), dec!(0.9), dec!(1), dec!(1.37), dec!(40)] {
+            let price = arpp(dec!(101.06), dec!(0.1), dec!(0.4), ratio);
+            let solved = ratio_for_price(price, dec!(101.06), dec!(0.1), dec!(0.4)).unwrap();
+            assert_approx_eq!(solved, ratio, dec!(0.0000000001));
+        }
+        let solved =
+            ratio_for_price_with_curve(&TanhCurve, dec!(105), dec!(100), dec!(0.2), dec!(0.5));
+        let price = arpp_with_curve(&TanhCurve, dec!(100), dec!(0.2), dec!(0.5), solved.unwrap());
+        assert_approx_eq!(price, dec!(105), dec!(0.000000001));
+        assert_eq!(
+            ratio_for_price(dec!(7), dec!(7), dec!(0), dec!(1)),
+            Ok(Decimal::ONE)
+        );
+    }
+
+    #[test]
+    fn test_ratio_for_price_reports_unreachable_prices() {
+        let (low, high) = price_range(&ArctanCurve, dec!(1), dec!(0.5), dec!(1));
+        // 1 - atan(1) / 2 and 1 + pi / 4
+        assert_approx_eq!(low, dec!(0.6073009183012758451921695771), dec!(1e-27));
+        assert_approx_eq!(high, dec!(1.7853981633974483096156608458), dec!(1e-27));
+        for price in [dec!(1.8), high, low, dec!(0.5)] {
+            assert_eq!(
+                ratio_for_price(price, dec!(1), dec!(0.5), dec!(1)),
+                Err(ArppError::UnreachablePrice { price, low, high })
+            );
+        }
+        assert!(ratio_for_price(dec!(1.78), dec!(1), dec!(0.5), dec!(1)).unwrap() > dec!(90));
+    }
+
+    #[test]
+    fn test_asymmetric_price_is_continuous_at_balance() {
+        let parameters = AsymmetricParameters::new(dec!(0.2), dec!(0.05), dec!(0.5), dec!(2));
+        let p_ref = dec!(101.06);
+        let epsilon = dec!(0.000000001);
+        for curve in [&ArctanCurve as &dyn PricingCurve, &TanhCurve] {
+            let at = |r| arpp_asymmetric_with_curve(curve, p_ref, &parameters, r);
+            assert_eq!(at(Decimal::ONE), p_ref);
+            assert_approx_eq!(at(Decimal::ONE - epsilon), p_ref, dec!(0.000001));
+            assert_approx_eq!(at(Decimal::ONE + epsilon), p_ref, dec!(0.000001));
+            assert_eq!(
+                at(dec!(1.4)),
+                arpp_with_curve(curve, p_ref, dec!(0.2), dec!(0.5), dec!(1.4))
+            );
+            assert_eq!(
+                at(dec!(0.6)),
+                arpp_with_curve(curve, p_ref, dec!(0.05), dec!(2), dec!(0.6))
+            );
+
+            let below = sensitivities_asymmetric_with_curve(
+                curve,
+                p_ref,
+                &parameters,
+                Decimal::ONE - epsilon,
+            );
+            let above = sensitivities_asymmetric_with_curve(
+                curve,
+                p_ref,
+                &parameters,
+                Decimal::ONE + epsilon,
+            );
+            for (below, above) in [
+                (below.d_alpha, above.d_alpha),
+                (below.d_beta, above.d_beta),
+                (below.d_p_ref, above.d_p_ref),
+                (below.curvature, above.curvature),
+            ] {
+                assert_approx_eq!(below, above, dec!(0.000001));
+            }
+            // The slope at balance is p_ref * alpha * beta, the same from both sides
+            assert_approx_eq!(below.d_ratio, above.d_ratio, dec!(0.000001));
+            assert_approx_eq!(above.d_ratio, p_ref * dec!(0.1), dec!(0.000001));
+        }
+
+        let symmetric = AsymmetricParameters::symmetric(dec!(0.1), dec!(0.4));
+        assert!(symmetric.is_symmetric());
+        assert!(!parameters.is_symmetric());
+        for r in [dec!(0.3), dec!(1), dec!(6)] {
+            assert_eq!(
+                arpp_asymmetric(p_ref, &symmetric, r),
+                arpp(p_ref, dec!(0.1), dec!(0.4), r)
+            );
+        }
+    }
+
+    #[test]
+    fn test_ratio_for_price_inverts_asymmetric_arpp() {
+        let parameters = AsymmetricParameters::new(dec!(0.5), dec!(0.1), dec!(1), dec!(5));
+        for ratio in [dec!(0.05), dec!(0.9), dec!(1), dec!(1.37), dec!(40)] {
+            let price = arpp_asymmetric(dec!(2), &parameters, ratio);
+            let solved =
+                ratio_for_price_asymmetric_with_curve(&ArctanCurve, price, dec!(2), &parameters)
+                    .unwrap();
+            assert_approx_eq!(solved, ratio, dec!(0.0000000001));
+        }
+        // The downside sets the low end and the upside the high end
+        let (low, high) = price_range_asymmetric(&ArctanCurve, dec!(2), &parameters);
+        assert_eq!(low, arpp(dec!(2), dec!(0.1), dec!(5), Decimal::ZERO));
+        assert_eq!(
+            high,
+            dec!(2) * (Decimal::ONE + dec!(0.5) * ArctanCurve.limit())
+        );
+        assert_eq!(
+            ratio_for_price_asymmetric_with_curve(&ArctanCurve, dec!(1.5), dec!(2), &parameters),
+            Err(ArppError::UnreachablePrice {
+                price: dec!(1.5),
+                low,
+                high
+            })
+        );
+    }
+
+    #[test]
+    fn test_curve_replaces_atan() {
+        assert_eq!(
+            arpp_with_curve(&ArctanCurve, dec!(100), dec!(0.2), dec!(0.5), dec!(1.6)),
+            arpp(dec!(100), dec!(0.2), dec!(0.5), dec!(1.6))
+        );
+        // 100 * (1 + 0.2 * tanh(0.3))
+        let price = arpp_with_curve(&TanhCurve, dec!(100), dec!(0.2), dec!(0.5), dec!(1.6));
+        assert_approx_eq!(price, dec!(105.8262522490), dec!(0.0000000001));
+        let ratio = token_ratio(Decimal::ZERO, dec!(1000));
+        let price = arpp_with_curve(&TanhCurve, dec!(1), dec!(0.5), dec!(-2), ratio);
+        assert_eq!(price, dec!(0.5));
+    }
 }
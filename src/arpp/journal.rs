@@ -0,0 +1,769 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::breaker::CircuitBreaker;
+use crate::arpp::curve::CurveSpec;
+use crate::arpp::error::ArppError;
+use crate::arpp::fee::DynamicFee;
+use crate::arpp::liquidity_pool::LiquidityPool;
+use crate::arpp::oracle::{OracleGuards, OraclePrice, PriceOracle};
+use crate::arpp::ramp::{RampBounds, RampLength, RampParameter};
+use crate::arpp::snapshot::{PoolState, Snapshot};
+use crate::arpp::swap::{SwapResult, Token};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+
+/// An operation applied to a `LiquidityPool`, with its inputs.
+///
+/// Operations that depend on the outside world record what they observed, so
+/// that replaying them is deterministic: a `p_ref` update records the price
+/// reported by the oracle. The time of every operation is that of its entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Operation {
+    /// The journal was opened on a pool in the state of `snapshot`.
+    Open {
+        snapshot: Box<Snapshot<PoolState>>,
+    },
+    SwapExactIn {
+        token_in: Token,
+        amount_in: Decimal,
+        min_out: Decimal,
+    },
+    SwapExactOut {
+        token_out: Token,
+        amount_out: Decimal,
+        max_in: Decimal,
+    },
+    Deposit {
+        provider: String,
+        amount_a: Decimal,
+        amount_b: Decimal,
+    },
+    Withdraw {
+        provider: String,
+        shares: Decimal,
+    },
+    UpdatePRef {
+        update: Option<OraclePrice>,
+    },
+    SetFee {
+        fee_bps: Decimal,
+    },
+    SetDynamicFee {
+        fee: Option<DynamicFee>,
+    },
+    SetOracleGuards {
+        guards: OracleGuards,
+    },
+    SetPricingCurve {
+        curve: CurveSpec,
+    },
+    SetCircuitBreaker {
+        breaker: Option<CircuitBreaker>,
+    },
+    ScheduleRamp {
+        parameter: RampParameter,
+        target: Decimal,
+        length: RampLength,
+    },
+    StopRamp,
+    SetRampBounds {
+        bounds: RampBounds,
+    },
+}
+
+/// The output of a successful operation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OperationOutput {
+    None,
+    Swap {
+        result: SwapResult,
+    },
+    Shares {
+        shares: Decimal,
+    },
+    Amounts {
+        amount_a: Decimal,
+        amount_b: Decimal,
+    },
+}
+
+/// The part of the state of a pool recorded before and after every operation.
+///
+/// # Fields
+/// - `token_a`: The amount of Token A in the pool.
+/// - `token_b`: The amount of Token B in the pool.
+/// - `price`: The ARPP price of the pool.
+/// - `p_ref`: The reference price.
+/// - `fee_bps`: The fee, in basis points, taken from the input of every swap.
+/// - `total_shares`: The LP shares outstanding.
+/// - `swaps_paused`: Whether swaps are paused by an oracle guard.
+/// - `swaps_halted`: Whether swaps are halted by the circuit breaker.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateSummary {
+    pub token_a: Decimal,
+    pub token_b: Decimal,
+    pub price: Decimal,
+    pub p_ref: Decimal,
+    pub fee_bps: Decimal,
+    pub total_shares: Decimal,
+    pub swaps_paused: bool,
+    #[serde(default)]
+    pub swaps_halted: bool,
+}
+
+impl StateSummary {
+    /// Summarizes the current state of `pool`.
+    pub fn of(pool: &LiquidityPool) -> Self {
+        let (token_a, token_b) = pool.get_balances();
+        Self {
+            token_a,
+            token_b,
+            price: pool.get_price(),
+            p_ref: pool.get_p_ref(),
+            fee_bps: pool.get_fee_bps(),
+            total_shares: pool.get_total_shares(),
+            swaps_paused: pool.is_paused(),
+            swaps_halted: pool.is_halted(),
+        }
+    }
+}
+
+/// One line of a journal.
+///
+/// Failed operations are journaled too, with the error they returned, since
+/// some of them, such as rejected oracle updates, still change the pool.
+///
+/// # Fields
+/// - `sequence`: The position of the entry in the journal, starting at zero.
+/// - `timestamp`: When the operation was applied. Operations are replayed at this
+///   time, so that cooldowns of the circuit breaker end, ramps advance and oracle
+///   prices age as they did.
+/// - `operation`: The operation and its inputs.
+/// - `outcome`: The output of the operation, or the error it returned.
+/// - `before`: The state of the pool before the operation, `None` when opening.
+/// - `after`: The state of the pool after the operation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    pub operation: Operation,
+    pub outcome: Result<OperationOutput, ArppError>,
+    pub before: Option<StateSummary>,
+    pub after: StateSummary,
+}
+
+/// A `LiquidityPool` whose operations are appended to a JSONL journal.
+///
+/// Every operation is written as one `JournalEntry` per line, flushed before the
+/// operation returns. `replay` rebuilds the pool from the journal.
+///
+/// # Fields
+/// - `pool`: The pool.
+/// - `writer`: Where the journal is written.
+/// - `sequence`: The sequence number of the next entry.
+#[derive(Debug)]
+pub struct JournaledPool<W: Write> {
+    pool: LiquidityPool,
+    writer: W,
+    sequence: u64,
+}
+
+impl<W: Write> JournaledPool<W> {
+    /// Opens a journal on `pool`, writing its full state as the first entry.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the journaled pool, or an `Err` if the pool cannot be
+    /// snapshotted or the entry cannot be written.
+    pub fn new(pool: LiquidityPool, writer: W) -> Result<Self, ArppError> {
+        let snapshot = pool.snapshot()?;
+        let mut journaled = Self {
+            pool,
+            writer,
+            sequence: 0,
+        };
+        let after = StateSummary::of(&journaled.pool);
+        journaled.write(
+            Operation::Open {
+                snapshot: Box::new(snapshot),
+            },
+            Utc::now(),
+            Ok(OperationOutput::None),
+            None,
+            after,
+        )?;
+        Ok(journaled)
+    }
+
+    /// Returns the pool.
+    pub fn get_pool(&self) -> &LiquidityPool {
+        &self.pool
+    }
+
+    /// Closes the journal, returning the pool and the writer.
+    pub fn into_inner(self) -> (LiquidityPool, W) {
+        (self.pool, self.writer)
+    }
+
+    /// Journals `LiquidityPool::swap_exact_in`, without a deadline.
+    pub fn swap_exact_in(
+        &mut self,
+        token_in: Token,
+        amount_in: Decimal,
+        min_out: Decimal,
+    ) -> Result<SwapResult, ArppError> {
+        let output = self.record(Operation::SwapExactIn {
+            token_in,
+            amount_in,
+            min_out,
+        })?;
+        Ok(swap_of(output))
+    }
+
+    /// Journals `LiquidityPool::swap_exact_out`, without a deadline.
+    pub fn swap_exact_out(
+        &mut self,
+        token_out: Token,
+        amount_out: Decimal,
+        max_in: Decimal,
+    ) -> Result<SwapResult, ArppError> {
+        let output = self.record(Operation::SwapExactOut {
+            token_out,
+            amount_out,
+            max_in,
+        })?;
+        Ok(swap_of(output))
+    }
+
+    /// Journals `LiquidityPool::deposit`.
+    pub fn deposit(
+        &mut self,
+        provider: &str,
+        amount_a: Decimal,
+        amount_b: Decimal,
+    ) -> Result<Decimal, ArppError> {
+        let output = self.record(Operation::Deposit {
+            provider: provider.to_string(),
+            amount_a,
+            amount_b,
+        })?;
+        match output {
+            OperationOutput::Shares { shares } => Ok(shares),
+            other => unreachable!("a deposit returned {:?}", other),
+        }
+    }
+
+    /// Journals `LiquidityPool::withdraw`.
+    pub fn withdraw(
+        &mut self,
+        provider: &str,
+        shares: Decimal,
+    ) -> Result<(Decimal, Decimal), ArppError> {
+        let output = self.record(Operation::Withdraw {
+            provider: provider.to_string(),
+            shares,
+        })?;
+        match output {
+            OperationOutput::Amounts { amount_a, amount_b } => Ok((amount_a, amount_b)),
+            other => unreachable!("a withdrawal returned {:?}", other),
+        }
+    }
+
+    /// Journals `LiquidityPool::update_p_ref`, recording the price reported by the
+    /// oracle.
+    ///
+    /// The pool queries the oracle itself, so the oracle sees the pool price once
+    /// a ramp in progress has advanced, as it does without a journal.
+    pub fn update_p_ref(&mut self, oracle: &mut dyn PriceOracle) -> Result<(), ArppError> {
+        self.record_with(|pool, now| {
+            let mut oracle = RecordingOracle {
+                oracle,
+                update: None,
+            };
+            let outcome = pool
+                .update_p_ref_at(&mut oracle, now)
+                .map(|_| OperationOutput::None);
+            let operation = Operation::UpdatePRef {
+                update: oracle.update,
+            };
+            (operation, outcome)
+        })
+        .map(drop)
+    }
+
+    /// Journals `LiquidityPool::set_fee_bps`.
+    pub fn set_fee_bps(&mut self, fee_bps: Decimal) -> Result<(), ArppError> {
+        self.record(Operation::SetFee { fee_bps }).map(drop)
+    }
+
+    /// Journals `LiquidityPool::set_dynamic_fee`.
+    pub fn set_dynamic_fee(&mut self, fee: Option<DynamicFee>) -> Result<(), ArppError> {
+        self.record(Operation::SetDynamicFee { fee }).map(drop)
+    }
+
+    /// Journals `LiquidityPool::set_oracle_guards`.
+    pub fn set_oracle_guards(&mut self, guards: OracleGuards) -> Result<(), ArppError> {
+        self.record(Operation::SetOracleGuards { guards }).map(drop)
+    }
+
+    /// Journals `LiquidityPool::set_pricing_curve` with one of the crate's curves.
+    pub fn set_pricing_curve(&mut self, curve: CurveSpec) -> Result<(), ArppError> {
+        self.record(Operation::SetPricingCurve { curve }).map(drop)
+    }
+
+    /// Journals `LiquidityPool::set_circuit_breaker`.
+    pub fn set_circuit_breaker(
+        &mut self,
+        breaker: Option<CircuitBreaker>,
+    ) -> Result<(), ArppError> {
+        self.record(Operation::SetCircuitBreaker { breaker })
+            .map(drop)
+    }
+
+    /// Journals `LiquidityPool::schedule_ramp`. The ramp starts at the time of the
+    /// entry, and advances at the times of the following ones when replayed.
+    pub fn schedule_ramp(
+        &mut self,
+        parameter: RampParameter,
+        target: Decimal,
+        length: RampLength,
+    ) -> Result<(), ArppError> {
+        self.record(Operation::ScheduleRamp {
+            parameter,
+            target,
+            length,
+        })
+        .map(drop)
+    }
+
+    /// Journals `LiquidityPool::stop_ramp`.
+    pub fn stop_ramp(&mut self) -> Result<(), ArppError> {
+        self.record(Operation::StopRamp).map(drop)
+    }
+
+    /// Journals `LiquidityPool::set_ramp_bounds`.
+    pub fn set_ramp_bounds(&mut self, bounds: RampBounds) -> Result<(), ArppError> {
+        self.record(Operation::SetRampBounds { bounds }).map(drop)
+    }
+
+    /// Applies an operation to the pool and journals it.
+    ///
+    /// # Returns
+    ///
+    /// The outcome of the operation, or `ArppError::JournalWrite` if it was
+    /// applied but could not be journaled.
+    fn record(&mut self, operation: Operation) -> Result<OperationOutput, ArppError> {
+        self.record_with(|pool, now| {
+            let outcome = apply(pool, &operation, now);
+            (operation, outcome)
+        })
+    }
+
+    /// Applies an operation to the pool with `apply_at`, which returns the
+    /// operation as observed and its outcome, and journals it.
+    fn record_with<F>(&mut self, apply_at: F) -> Result<OperationOutput, ArppError>
+    where
+        F: FnOnce(
+            &mut LiquidityPool,
+            DateTime<Utc>,
+        ) -> (Operation, Result<OperationOutput, ArppError>),
+    {
+        let now = Utc::now();
+        let before = StateSummary::of(&self.pool);
+        let (operation, outcome) = apply_at(&mut self.pool, now);
+        let after = StateSummary::of(&self.pool);
+        self.write(operation, now, outcome.clone(), Some(before), after)?;
+        outcome
+    }
+
+    /// Appends an entry to the journal and flushes it.
+    fn write(
+        &mut self,
+        operation: Operation,
+        timestamp: DateTime<Utc>,
+        outcome: Result<OperationOutput, ArppError>,
+        before: Option<StateSummary>,
+        after: StateSummary,
+    ) -> Result<(), ArppError> {
+        let entry = JournalEntry {
+            sequence: self.sequence,
+            timestamp,
+            operation,
+            outcome,
+            before,
+            after,
+        };
+        let write_error = |reason: String| ArppError::JournalWrite { reason };
+        let line = serde_json::to_string(&entry).map_err(|error| write_error(error.to_string()))?;
+        writeln!(self.writer, "{}", line)
+            .and_then(|_| self.writer.flush())
+            .map_err(|error| write_error(error.to_string()))?;
+        self.sequence += 1;
+        Ok(())
+    }
+}
+
+/// Rebuilds a pool from a JSONL journal, checking every entry against the replay.
+///
+/// The pool is restored from the snapshot of the first entry, and every following
+/// operation is applied to it in order. The state before the operation, its
+/// output or error, and the state after it must all match the journal.
+///
+/// # Arguments
+///
+/// * `reader` - The journal, one `JournalEntry` per line. Blank lines are skipped.
+///
+/// # Returns
+///
+/// A `Result` with the pool in the state left by the last entry,
+/// `ArppError::InvalidJournal` if a line cannot be read or entries are out of
+/// order, or `ArppError::JournalMismatch` at the first entry the replay does not
+/// reproduce.
+pub fn replay<R: BufRead>(reader: R) -> Result<LiquidityPool, ArppError> {
+    let mut pool: Option<LiquidityPool> = None;
+    let mut sequence = 0;
+    for (index, line) in reader.lines().enumerate() {
+        let invalid = |reason: String| ArppError::InvalidJournal {
+            line: index + 1,
+            reason,
+        };
+        let line = line.map_err(|error| invalid(error.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: JournalEntry =
+            serde_json::from_str(&line).map_err(|error| invalid(error.to_string()))?;
+        if entry.sequence != sequence {
+            return Err(invalid(format!(
+                "expected entry {}, found {}",
+                sequence, entry.sequence
+            )));
+        }
+        let mismatch = |field: &str| ArppError::JournalMismatch {
+            sequence,
+            field: field.to_string(),
+        };
+
+        let current = match (&mut pool, entry.operation) {
+            (opened @ None, Operation::Open { snapshot }) => {
+                opened.insert(LiquidityPool::restore(*snapshot)?)
+            }
+            (None, _) => return Err(invalid("the journal must open on a pool".to_string())),
+            (Some(_), Operation::Open { .. }) => {
+                return Err(invalid("the journal is already open".to_string()))
+            }
+            (Some(current), operation) => {
+                if entry.before.as_ref() != Some(&StateSummary::of(current)) {
+                    return Err(mismatch("state before"));
+                }
+                if apply(current, &operation, entry.timestamp) != entry.outcome {
+                    return Err(mismatch("outcome"));
+                }
+                current
+            }
+        };
+        if StateSummary::of(current) != entry.after {
+            return Err(mismatch("state after"));
+        }
+        sequence += 1;
+    }
+    pool.ok_or(ArppError::InvalidJournal {
+        line: 0,
+        reason: "the journal is empty".to_string(),
+    })
+}
+
+/// Applies an operation, other than opening the journal, to a pool at `now`.
+fn apply(
+    pool: &mut LiquidityPool,
+    operation: &Operation,
+    now: DateTime<Utc>,
+) -> Result<OperationOutput, ArppError> {
+    let done = |_| OperationOutput::None;
+    match operation {
+        Operation::Open { .. } => Ok(OperationOutput::None),
+        Operation::SwapExactIn {
+            token_in,
+            amount_in,
+            min_out,
+        } => pool
+            .swap_exact_in_at(*token_in, *amount_in, *min_out, None, now)
+            .map(|result| OperationOutput::Swap { result }),
+        Operation::SwapExactOut {
+            token_out,
+            amount_out,
+            max_in,
+        } => pool
+            .swap_exact_out_at(*token_out, *amount_out, *max_in, None, now)
+            .map(|result| OperationOutput::Swap { result }),
+        Operation::Deposit {
+            provider,
+            amount_a,
+            amount_b,
+        } => pool
+            .deposit(provider, *amount_a, *amount_b)
+            .map(|shares| OperationOutput::Shares { shares }),
+        Operation::Withdraw { provider, shares } => pool
+            .withdraw(provider, *shares)
+            .map(|(amount_a, amount_b)| OperationOutput::Amounts { amount_a, amount_b }),
+        Operation::UpdatePRef { update } => pool
+            .update_p_ref_at(&mut RecordedOracle(*update), now)
+            .map(done),
+        Operation::SetFee { fee_bps } => pool.set_fee_bps(*fee_bps).map(done),
+        Operation::SetDynamicFee { fee } => pool.set_dynamic_fee(*fee).map(done),
+        Operation::SetOracleGuards { guards } => {
+            pool.set_oracle_guards(guards.clone());
+            Ok(OperationOutput::None)
+        }
+        Operation::SetPricingCurve { curve } => pool.set_curve(curve.build()?).map(done),
+        Operation::SetCircuitBreaker { breaker } => {
+            pool.set_circuit_breaker(*breaker);
+            Ok(OperationOutput::None)
+        }
+        Operation::ScheduleRamp {
+            parameter,
+            target,
+            length,
+        } => pool
+            .schedule_ramp_at(*parameter, *target, *length, now)
+            .map(done),
+        Operation::StopRamp => {
+            pool.stop_ramp_at(now);
+            Ok(OperationOutput::None)
+        }
+        Operation::SetRampBounds { bounds } => {
+            pool.set_ramp_bounds(*bounds);
+            Ok(OperationOutput::None)
+        }
+    }
+}
+
+/// Returns the swap result of the output of a journaled swap.
+fn swap_of(output: OperationOutput) -> SwapResult {
+    match output {
+        OperationOutput::Swap { result } => result,
+        other => unreachable!("a swap returned {:?}", other),
+    }
+}
+
+/// An oracle reporting a price recorded in a journal.
+struct RecordedOracle(Option<OraclePrice>);
+
+impl PriceOracle for RecordedOracle {
+    fn next_price(&mut self, _: Decimal, _: Decimal) -> Option<OraclePrice> {
+        self.0.take()
+    }
+}
+
+/// An oracle keeping the price reported by another one, to be journaled.
+struct RecordingOracle<'a> {
+    oracle: &'a mut dyn PriceOracle,
+    update: Option<OraclePrice>,
+}
+
+impl PriceOracle for RecordingOracle<'_> {
+    fn next_price(&mut self, p_ref: Decimal, pool_price: Decimal) -> Option<OraclePrice> {
+        self.update = self.oracle.next_price(p_ref, pool_price);
+        self.update
+    }
+}
+
+#[cfg(test)]
+mod tests_journal {
+    use super::*;
+    use crate::arpp::breaker::{BreakerPolicy, Cooldown, PriceBand};
+    use crate::arpp::oracle::{ConstantOracle, GuardPolicy, OracleGuard, TwapOracle};
+    use chrono::Duration;
+    use rust_decimal_macros::dec;
+
+    fn create_journaled_pool() -> JournaledPool<Vec<u8>> {
+        let pool = LiquidityPool::new(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
+        JournaledPool::new(pool, Vec::new()).unwrap()
+    }
+
+    fn run_operations(journaled: &mut JournaledPool<Vec<u8>>) {
+        journaled.set_fee_bps(dec!(30)).unwrap();
+        journaled
+            .swap_exact_in(Token::A, dec!(10), dec!(0))
+            .unwrap();
+        journaled.deposit("alice", dec!(20), dec!(20)).unwrap();
+        journaled
+            .update_p_ref(&mut ConstantOracle::new(dec!(1.02)))
+            .unwrap();
+        // A failed swap is journaled and replayed with its error
+        assert!(journaled
+            .swap_exact_out(Token::B, dec!(5), dec!(1))
+            .is_err());
+        journaled.set_pricing_curve(CurveSpec::Tanh).unwrap();
+        journaled
+            .swap_exact_out(Token::A, dec!(5), dec!(10))
+            .unwrap();
+        journaled.withdraw("alice", dec!(10)).unwrap();
+        let shares = journaled.deposit("bob", dec!(1), dec!(2)).unwrap();
+        journaled.withdraw("bob", shares / dec!(2)).unwrap();
+        journaled
+            .set_dynamic_fee(Some(
+                DynamicFee::new(dec!(30), dec!(0.5), dec!(100)).unwrap(),
+            ))
+            .unwrap();
+        journaled
+            .swap_exact_in(Token::B, dec!(10), dec!(0))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_replay_rebuilds_pool() {
+        let mut journaled = create_journaled_pool();
+        run_operations(&mut journaled);
+        let (pool, journal) = journaled.into_inner();
+        let text = String::from_utf8(journal).unwrap();
+        assert_eq!(text.lines().count(), 13);
+
+        let replayed = replay(text.as_bytes()).unwrap();
+        assert_eq!(replayed.snapshot(), pool.snapshot());
+    }
+
+    #[test]
+    fn test_rejected_oracle_update_replays_deterministically() {
+        let mut journaled = create_journaled_pool();
+        journaled
+            .set_oracle_guards(OracleGuards {
+                max_age: Some(OracleGuard::new(Duration::seconds(60), GuardPolicy::Pause)),
+                ..OracleGuards::default()
+            })
+            .unwrap();
+        let stale = OraclePrice::new(dec!(1.1), Utc::now() - Duration::seconds(61));
+        let mut oracle = RecordedOracle(Some(stale));
+        assert!(matches!(
+            journaled.update_p_ref(&mut oracle),
+            Err(ArppError::OracleStale { .. })
+        ));
+        let (pool, journal) = journaled.into_inner();
+        assert!(pool.is_paused());
+        // The incident is logged at the recorded time of the update, not at replay
+        let text = String::from_utf8(journal).unwrap();
+        assert_eq!(replay(text.as_bytes()).unwrap().snapshot(), pool.snapshot());
+    }
+
+    #[test]
+    fn test_breaker_cooldown_replays_at_recorded_times() {
+        let mut journaled = create_journaled_pool();
+        journaled
+            .set_circuit_breaker(Some(CircuitBreaker::new(
+                PriceBand::Percent(dec!(1)),
+                BreakerPolicy::Cooldown(Cooldown::Duration(Duration::milliseconds(1))),
+            )))
+            .unwrap();
+        assert!(journaled
+            .swap_exact_in(Token::A, dec!(100), dec!(0))
+            .is_err());
+        assert!(journaled.get_pool().is_halted());
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        // The cooldown has expired when the swap is made, and when it is replayed
+        journaled.swap_exact_in(Token::A, dec!(1), dec!(0)).unwrap();
+        let (pool, journal) = journaled.into_inner();
+        let text = String::from_utf8(journal).unwrap();
+        assert_eq!(replay(text.as_bytes()).unwrap().snapshot(), pool.snapshot());
+    }
+
+    #[test]
+    fn test_ramps_replay_at_recorded_times() {
+        let mut journaled = create_journaled_pool();
+        journaled
+            .schedule_ramp(
+                RampParameter::Alpha,
+                dec!(0.4),
+                RampLength::Duration(Duration::hours(1)),
+            )
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        journaled.swap_exact_in(Token::A, dec!(1), dec!(0)).unwrap();
+        journaled.stop_ramp().unwrap();
+        let alpha = journaled.get_pool().get_alpha();
+        assert!(dec!(0.4) < alpha && alpha < dec!(0.5));
+
+        journaled
+            .set_ramp_bounds(RampBounds {
+                max_factor: dec!(2),
+                ..RampBounds::default()
+            })
+            .unwrap();
+        assert!(journaled
+            .schedule_ramp(RampParameter::Beta, dec!(3), RampLength::Steps(2))
+            .is_err());
+        journaled
+            .schedule_ramp(RampParameter::Beta, dec!(2), RampLength::Steps(2))
+            .unwrap();
+        journaled
+            .update_p_ref(&mut ConstantOracle::new(dec!(1)))
+            .unwrap();
+        assert_eq!(journaled.get_pool().get_beta(), dec!(1.5));
+
+        let (pool, journal) = journaled.into_inner();
+        let text = String::from_utf8(journal).unwrap();
+        assert_eq!(replay(text.as_bytes()).unwrap().snapshot(), pool.snapshot());
+    }
+
+    #[test]
+    fn test_oracle_sees_price_after_ramp_step() {
+        let mut journaled = create_journaled_pool();
+        journaled
+            .swap_exact_in(Token::A, dec!(100), dec!(0))
+            .unwrap();
+        let mut pool = journaled.get_pool().clone();
+        journaled
+            .schedule_ramp(RampParameter::Alpha, dec!(0.25), RampLength::Steps(1))
+            .unwrap();
+        pool.schedule_ramp(RampParameter::Alpha, dec!(0.25), RampLength::Steps(1))
+            .unwrap();
+
+        // An oracle following the pool price reports the one the ramp moved to
+        journaled.update_p_ref(&mut TwapOracle::new(1)).unwrap();
+        pool.update_p_ref(&mut TwapOracle::new(1)).unwrap();
+        assert_eq!(journaled.get_pool().get_p_ref(), pool.get_p_ref());
+        assert_ne!(pool.get_p_ref(), dec!(1));
+
+        let (pool, journal) = journaled.into_inner();
+        let text = String::from_utf8(journal).unwrap();
+        assert_eq!(replay(text.as_bytes()).unwrap().snapshot(), pool.snapshot());
+    }
+
+    #[test]
+    fn test_tampered_journal_is_detected() {
+        let mut journaled = create_journaled_pool();
+        run_operations(&mut journaled);
+        let text = String::from_utf8(journaled.into_inner().1).unwrap();
+        let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+
+        let mut entry: JournalEntry = serde_json::from_str(&lines[2]).unwrap();
+        if let Ok(OperationOutput::Swap { result }) = &mut entry.outcome {
+            result.amount_out += dec!(0.000001);
+        }
+        lines[2] = serde_json::to_string(&entry).unwrap();
+        assert_eq!(
+            replay(lines.join("\n").as_bytes()).unwrap_err(),
+            ArppError::JournalMismatch {
+                sequence: 2,
+                field: "outcome".to_string()
+            }
+        );
+
+        lines.remove(2);
+        assert!(matches!(
+            replay(lines.join("\n").as_bytes()),
+            Err(ArppError::InvalidJournal { line: 3, .. })
+        ));
+        assert!(matches!(
+            replay(lines[1..].join("\n").as_bytes()),
+            Err(ArppError::InvalidJournal { line: 1, .. })
+        ));
+    }
+}
@@ -4,11 +4,46 @@
    Date: 10/9/24
 ******************************************************************************/
 
-use crate::arpp::formula::{arpp, token_ratio};
-use crate::simulation::random_walk::random_walk_price;
-use rust_decimal::Decimal;
-use std::error::Error;
-use tracing::debug;
+use crate::amm::traits::Amm;
+use crate::arpp::breaker::{
+    ratio_at_price, BreakerPolicy, BreakerState, BreakerStats, CircuitBreaker,
+};
+use crate::arpp::curve::{ArctanCurve, PricingCurve};
+use crate::arpp::error::ArppError;
+use crate::arpp::fee::{deviation, DynamicFee};
+use crate::arpp::formula::{
+    arpp_asymmetric_with_curve, price_range_asymmetric, ratio_for_price_asymmetric_with_curve,
+    sensitivities_asymmetric_with_curve, token_ratio, AsymmetricParameters, Sensitivities,
+};
+use crate::arpp::oracle::{
+    GuardBreach, GuardPolicy, OracleGuards, OracleIncident, PriceOracle, TwapOracle,
+};
+use crate::arpp::params::{check_asymmetric_parameters, ParameterWarning};
+use crate::arpp::position::{LpPosition, INITIAL_PROVIDER};
+use crate::arpp::ramp::{Ramp, RampBounds, RampLength, RampParameter};
+use crate::arpp::snapshot::{PoolState, Snapshot, SNAPSHOT_VERSION};
+use crate::arpp::swap::{
+    check_deadline, input_fee, path_amount_in, path_amount_out, path_amount_to_ratio,
+    round_significant, SwapResult, Token, BASIS_POINTS,
+};
+use chrono::{DateTime, Utc};
+use rust_decimal::{Decimal, RoundingStrategy};
+use rust_decimal_macros::dec;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Maximum number of corrections of the target ratio of a swap capped by the
+/// circuit breaker.
+const CAP_ITERATIONS: usize = 8;
+
+/// Maximum number of corrections of the target ratio of a swap sized to reach a
+/// price.
+const TARGET_ITERATIONS: usize = 16;
+
+/// Relative distance from the target at which a swap sized to reach a price is
+/// accepted.
+const TARGET_TOLERANCE: Decimal = dec!(0.000000000001);
 
 /// Implementation of a Liquidity Pool for token trading.
 ///
@@ -20,8 +55,27 @@ use tracing::debug;
 /// - `token_a`: The amount of Token A in the pool.
 /// - `token_b`: The amount of Token B in the pool.
 /// - `p_ref`: A reference price for the swap calculation.
-/// - `alpha`: A parameter for the swap calculation.
-/// - `beta`: Another parameter for the swap calculation.
+/// - `alpha`: A parameter for the swap calculation, above balance.
+/// - `beta`: Another parameter for the swap calculation, above balance.
+/// - `alpha_down`: The `alpha` below balance, if it differs from `alpha`.
+/// - `beta_down`: The `beta` below balance, if it differs from `beta`.
+/// - `curve`: The anchoring function of the ARPP formula.
+/// - `fee_bps`: The fee, in basis points, taken from the input of every swap.
+/// - `dynamic_fee`: The fee scaling with the deviation from `p_ref`, charged
+///   instead of `fee_bps` if set.
+/// - `fees_a`: The cumulative fees collected in Token A.
+/// - `fees_b`: The cumulative fees collected in Token B.
+/// - `total_shares`: The LP shares outstanding.
+/// - `shares`: The LP shares owned by each provider, by provider ID.
+/// - `oracle_guards`: The guards checked on every `p_ref` update.
+/// - `twap`: The pool's own TWAP, observed on every `p_ref` update.
+/// - `p_ref_updated_at`: When the current `p_ref` was observed by its oracle.
+/// - `swaps_paused`: Whether swaps are paused by an oracle guard.
+/// - `oracle_incidents`: The breaches of the oracle guards, oldest first.
+/// - `circuit_breaker`: The band around `p_ref` swaps must keep the price within.
+/// - `breaker`: The cooldown of the circuit breaker in progress and its statistics.
+/// - `ramp`: The change of `alpha`, `beta` or `fee_bps` in progress.
+/// - `ramp_bounds`: The bounds on every ramped parameter change.
 ///
 #[derive(Debug, Clone)]
 pub struct LiquidityPool {
@@ -30,6 +84,24 @@ pub struct LiquidityPool {
     p_ref: Decimal,
     alpha: Decimal,
     beta: Decimal,
+    alpha_down: Option<Decimal>,
+    beta_down: Option<Decimal>,
+    curve: Arc<dyn PricingCurve>,
+    fee_bps: Decimal,
+    dynamic_fee: Option<DynamicFee>,
+    fees_a: Decimal,
+    fees_b: Decimal,
+    total_shares: Decimal,
+    shares: HashMap<String, Decimal>,
+    oracle_guards: OracleGuards,
+    twap: TwapOracle,
+    p_ref_updated_at: DateTime<Utc>,
+    swaps_paused: bool,
+    oracle_incidents: Vec<OracleIncident>,
+    circuit_breaker: Option<CircuitBreaker>,
+    breaker: BreakerState,
+    ramp: Option<Ramp>,
+    ramp_bounds: RampBounds,
 }
 
 /// Implementation of a Liquidity Pool for token trading.
@@ -42,8 +114,27 @@ pub struct LiquidityPool {
 /// - `token_a`: The amount of Token A in the pool.
 /// - `token_b`: The amount of Token B in the pool.
 /// - `p_ref`: A reference price for the swap calculation.
-/// - `alpha`: A parameter for the swap calculation.
-/// - `beta`: Another parameter for the swap calculation.
+/// - `alpha`: A parameter for the swap calculation, above balance.
+/// - `beta`: Another parameter for the swap calculation, above balance.
+/// - `alpha_down`: The `alpha` below balance, if it differs from `alpha`.
+/// - `beta_down`: The `beta` below balance, if it differs from `beta`.
+/// - `curve`: The anchoring function of the ARPP formula.
+/// - `fee_bps`: The fee, in basis points, taken from the input of every swap.
+/// - `dynamic_fee`: The fee scaling with the deviation from `p_ref`, charged
+///   instead of `fee_bps` if set.
+/// - `fees_a`: The cumulative fees collected in Token A.
+/// - `fees_b`: The cumulative fees collected in Token B.
+/// - `total_shares`: The LP shares outstanding.
+/// - `shares`: The LP shares owned by each provider, by provider ID.
+/// - `oracle_guards`: The guards checked on every `p_ref` update.
+/// - `twap`: The pool's own TWAP, observed on every `p_ref` update.
+/// - `p_ref_updated_at`: When the current `p_ref` was observed by its oracle.
+/// - `swaps_paused`: Whether swaps are paused by an oracle guard.
+/// - `oracle_incidents`: The breaches of the oracle guards, oldest first.
+/// - `circuit_breaker`: The band around `p_ref` swaps must keep the price within.
+/// - `breaker`: The cooldown of the circuit breaker in progress and its statistics.
+/// - `ramp`: The parameter change in progress.
+/// - `ramp_bounds`: The bounds on every parameter change.
 ///
 impl LiquidityPool {
     /// Creates a new `LiquidityPool` with the specified initial balances and parameters.
@@ -57,7 +148,10 @@ impl LiquidityPool {
     ///
     /// # Returns
     ///
-    /// A new instance of `LiquidityPool`.
+    /// A new instance of `LiquidityPool`, priced with the `ArctanCurve` and
+    /// charging no fee until `set_fee_bps` is called. The initial balances are
+    /// owned by `INITIAL_PROVIDER`, and `p_ref` is taken as observed now, with no
+    /// oracle guards.
     pub fn new(
         token_a: Decimal,
         token_b: Decimal,
@@ -65,46 +159,260 @@ impl LiquidityPool {
         alpha: Decimal,
         beta: Decimal,
     ) -> Self {
-        Self {
+        Self::new_asymmetric(
             token_a,
             token_b,
             p_ref,
-            alpha,
-            beta,
+            AsymmetricParameters::symmetric(alpha, beta),
+        )
+    }
+
+    /// Creates a new `LiquidityPool` like `new`, with separate `alpha` and `beta`
+    /// above and below balance.
+    ///
+    /// The price is `p_ref` at balance from both sides, so swaps and quotes crossing
+    /// it are continuous. Its slope is continuous too as long as `alpha * beta` is
+    /// the same on both sides, which `try_new_asymmetric` requires.
+    ///
+    /// # Arguments
+    /// - `token_a`: Initial amount of Token A.
+    /// - `token_b`: Initial amount of Token B.
+    /// - `p_ref`: Reference price for the swap calculation.
+    /// - `parameters`: The `alpha` and `beta` of each side.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `LiquidityPool`, set up as by `new`.
+    pub fn new_asymmetric(
+        token_a: Decimal,
+        token_b: Decimal,
+        p_ref: Decimal,
+        parameters: AsymmetricParameters,
+    ) -> Self {
+        let mut pool = Self {
+            token_a,
+            token_b,
+            p_ref,
+            alpha: parameters.alpha_up,
+            beta: parameters.beta_up,
+            alpha_down: Some(parameters.alpha_down).filter(|down| *down != parameters.alpha_up),
+            beta_down: Some(parameters.beta_down).filter(|down| *down != parameters.beta_up),
+            curve: Arc::new(ArctanCurve),
+            fee_bps: Decimal::ZERO,
+            dynamic_fee: None,
+            fees_a: Decimal::ZERO,
+            fees_b: Decimal::ZERO,
+            total_shares: Decimal::ZERO,
+            shares: HashMap::new(),
+            oracle_guards: OracleGuards::default(),
+            twap: TwapOracle::new(OracleGuards::default().twap_window),
+            p_ref_updated_at: Utc::now(),
+            swaps_paused: false,
+            oracle_incidents: Vec::new(),
+            circuit_breaker: None,
+            breaker: BreakerState::default(),
+            ramp: None,
+            ramp_bounds: RampBounds::default(),
+        };
+        let value = pool.value_in_b(token_a, token_b);
+        if value > Decimal::ZERO {
+            pool.mint_shares(INITIAL_PROVIDER, value);
         }
+        pool
     }
 
-    /// Adds liquidity to the pool.
+    /// Creates a new `LiquidityPool` like `new`, once its parameters have passed
+    /// `check_parameters`.
+    ///
+    /// Warnings about degenerate settings are logged, not returned as errors.
     ///
     /// # Arguments
+    /// - `token_a`: Initial amount of Token A.
+    /// - `token_b`: Initial amount of Token B.
+    /// - `p_ref`: Reference price for the swap calculation.
+    /// - `alpha`: Parameter for the swap calculation.
+    /// - `beta`: Parameter for the swap calculation.
     ///
-    /// - `amount_a`: Amount of Token A to add.
-    /// - `amount_b`: Amount of Token B to add.
+    /// # Returns
+    ///
+    /// A `Result` with the pool, or the error of `check_parameters` if `p_ref`,
+    /// `alpha` or `beta` is out of its domain or the price could fall to zero.
+    pub fn try_new(
+        token_a: Decimal,
+        token_b: Decimal,
+        p_ref: Decimal,
+        alpha: Decimal,
+        beta: Decimal,
+    ) -> Result<Self, ArppError> {
+        Self::try_new_asymmetric(
+            token_a,
+            token_b,
+            p_ref,
+            AsymmetricParameters::symmetric(alpha, beta),
+        )
+    }
+
+    /// Creates a new `LiquidityPool` like `new_asymmetric`, once its parameters
+    /// have passed `check_asymmetric_parameters`.
+    ///
+    /// Warnings about degenerate settings are logged, not returned as errors.
     ///
     /// # Returns
     ///
-    /// A `Result` which is `Ok` if the addition was successful, or an `Err` if
-    /// the amounts are not positive.
-    pub fn add_liquidity(
+    /// A `Result` with the pool, or the error of `check_asymmetric_parameters` if a
+    /// parameter is out of its domain, the price could fall to zero or the slopes
+    /// of the two sides differ at balance.
+    pub fn try_new_asymmetric(
+        token_a: Decimal,
+        token_b: Decimal,
+        p_ref: Decimal,
+        parameters: AsymmetricParameters,
+    ) -> Result<Self, ArppError> {
+        for warning in check_asymmetric_parameters(&ArctanCurve, p_ref, &parameters)? {
+            warn!("{}", warning);
+        }
+        Ok(Self::new_asymmetric(token_a, token_b, p_ref, parameters))
+    }
+
+    /// Checks the current parameters and pricing curve of the pool with
+    /// `check_asymmetric_parameters`.
+    ///
+    /// Pools built with `new` skip the check, so their parameters can be unsafe.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the warnings about degenerate settings, or the reason the
+    /// parameters are invalid.
+    pub fn check_parameters(&self) -> Result<Vec<ParameterWarning>, ArppError> {
+        check_asymmetric_parameters(self.curve.as_ref(), self.p_ref, &self.get_parameters())
+    }
+
+    /// Returns the range of prices the pool can reach, lowest first.
+    ///
+    /// It is the range of the curve, `p_ref * (1 +/- alpha * limit)`, cut at the
+    /// low end by the price of a pool without Token B, and neither end is reached
+    /// by a swap. The parameters below balance set the low end and those above it
+    /// the high end.
+    pub fn get_price_range(&self) -> (Decimal, Decimal) {
+        price_range_asymmetric(self.curve.as_ref(), self.p_ref, &self.get_parameters())
+    }
+
+    /// Sets the anchoring function of the ARPP formula.
+    ///
+    /// `p_ref`, `alpha` and `beta` keep their meaning; only the shape of the price
+    /// away from balance changes. LP shares keep their number, but their value
+    /// follows the new price.
+    ///
+    /// # Arguments
+    ///
+    /// - `curve`: The curve replacing the current one.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the curve was set, or the error of
+    /// `check_asymmetric_parameters` if the current parameters are invalid with it,
+    /// as curves saturate at different values. The curve is then left unchanged.
+    pub fn set_pricing_curve<C: PricingCurve + 'static>(
         &mut self,
-        amount_a: Decimal,
-        amount_b: Decimal,
-    ) -> Result<(), Box<dyn Error>> {
-        debug!(
-            "Added liquidity: Token A {:.2}, Token B {:.2}",
-            self.token_a, self.token_b
-        );
+        curve: C,
+    ) -> Result<(), ArppError> {
+        self.set_curve(Arc::new(curve))
+    }
 
-        if amount_a < Decimal::ZERO || amount_b < Decimal::ZERO {
-            return Err("Amounts must be positive".into());
+    /// Sets an anchoring function that is already built, such as one built from a
+    /// `CurveSpec`, with the checks of `set_pricing_curve`.
+    pub(crate) fn set_curve(&mut self, curve: Arc<dyn PricingCurve>) -> Result<(), ArppError> {
+        check_asymmetric_parameters(curve.as_ref(), self.p_ref, &self.get_parameters())?;
+        self.curve = curve;
+        Ok(())
+    }
+
+    /// Returns the anchoring function of the ARPP formula.
+    pub fn get_pricing_curve(&self) -> &dyn PricingCurve {
+        self.curve.as_ref()
+    }
+
+    /// Sets the fee taken from the input of every swap.
+    ///
+    /// The fee stays in the reserves, so it accrues to the liquidity providers, and
+    /// is tracked per token by `get_fee_revenue`.
+    ///
+    /// # Arguments
+    ///
+    /// - `fee_bps`: The fee in basis points of the amount paid in.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the fee was set, or an `Err` if it is negative
+    /// or would take the whole input.
+    pub fn set_fee_bps(&mut self, fee_bps: Decimal) -> Result<(), ArppError> {
+        if fee_bps < Decimal::ZERO || fee_bps >= BASIS_POINTS {
+            return Err(ArppError::InvalidFee { fee_bps });
         }
-        self.token_a += amount_a;
-        self.token_b += amount_b;
+        self.fee_bps = fee_bps;
+        Ok(())
+    }
 
+    /// Returns the fee, in basis points, taken from the input of every swap
+    /// unless a dynamic fee is set.
+    pub fn get_fee_bps(&self) -> Decimal {
+        self.fee_bps
+    }
+
+    /// Sets a fee scaling with the deviation of the price from `p_ref`, charged
+    /// instead of the flat fee of `set_fee_bps`, or goes back to the flat fee
+    /// with `None`.
+    ///
+    /// The deviation after a swap depends on its fee, so it is measured on the
+    /// swap priced at the base fee. The swap is then priced again at the fee that
+    /// deviation gives.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the fee was set, or an `Err` if its parameters
+    /// are invalid, as checked by `DynamicFee::new`.
+    pub fn set_dynamic_fee(&mut self, fee: Option<DynamicFee>) -> Result<(), ArppError> {
+        if let Some(fee) = fee {
+            DynamicFee::new(fee.base_bps, fee.slope, fee.max_bps)?;
+        }
+        self.dynamic_fee = fee;
         Ok(())
     }
 
-    /// Removes liquidity from the pool.
+    /// Returns the fee scaling with the deviation from `p_ref`, if set.
+    pub fn get_dynamic_fee(&self) -> Option<&DynamicFee> {
+        self.dynamic_fee.as_ref()
+    }
+
+    /// Returns the cumulative fees collected by the pool.
+    ///
+    /// # Returns
+    ///
+    /// A tuple with the fees collected in Token A and in Token B.
+    pub fn get_fee_revenue(&self) -> (Decimal, Decimal) {
+        (self.fees_a, self.fees_b)
+    }
+
+    /// Adds liquidity to the pool on behalf of `INITIAL_PROVIDER`.
+    ///
+    /// # Arguments
+    ///
+    /// - `amount_a`: Amount of Token A to add.
+    /// - `amount_b`: Amount of Token B to add.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the addition was successful, or an `Err` if
+    /// the amounts are negative or too small to mint any share.
+    #[deprecated(note = "use `deposit`, which credits the shares to a provider")]
+    pub fn add_liquidity(&mut self, amount_a: Decimal, amount_b: Decimal) -> Result<(), ArppError> {
+        self.deposit(INITIAL_PROVIDER, amount_a, amount_b).map(drop)
+    }
+
+    /// Removes liquidity from the pool on behalf of `INITIAL_PROVIDER`.
+    ///
+    /// The liquidity is withdrawn pro rata, burning the smallest part of the
+    /// shares that pays out at least `amount_a` and `amount_b`.
     ///
     /// # Arguments
     ///
@@ -114,389 +422,2633 @@ impl LiquidityPool {
     /// # Returns
     ///
     /// A `Result` which is `Ok` if the removal was successful, or an `Err` if
-    /// the liquidity is insufficient or amounts are not positive.
+    /// the liquidity is insufficient, the amounts are not positive or
+    /// `INITIAL_PROVIDER` does not own enough shares.
+    #[deprecated(note = "use `withdraw`, which burns the shares of a provider")]
     pub fn remove_liquidity(
         &mut self,
         amount_a: Decimal,
         amount_b: Decimal,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), ArppError> {
         if amount_a <= Decimal::ZERO || amount_b <= Decimal::ZERO {
-            return Err("Amounts must be positive".into());
+            return Err(ArppError::InvalidAmount {
+                amount: amount_a.min(amount_b),
+            });
         }
-        if amount_a > self.token_a || amount_b > self.token_b {
-            return Err("Insufficient liquidity".into());
+        for (token, amount, balance) in [
+            (Token::A, amount_a, self.token_a),
+            (Token::B, amount_b, self.token_b),
+        ] {
+            if amount > balance {
+                return Err(ArppError::InsufficientLiquidity {
+                    token,
+                    needed: amount,
+                    available: balance,
+                });
+            }
         }
-        self.token_a -= amount_a;
-        self.token_b -= amount_b;
-        Ok(())
+        let part = (amount_a / self.token_a).max(amount_b / self.token_b);
+        self.withdraw(INITIAL_PROVIDER, part * self.total_shares)
+            .map(drop)
     }
 
-    /// Swaps an amount of Token A for Token B.
+    /// Deposits liquidity on behalf of a provider, minting LP shares.
+    ///
+    /// The deposit and the pool before it are valued in Token B at the ARPP price
+    /// once the deposit has been added, and the shares minted are in the same
+    /// proportion to the outstanding shares as those two values. A deposit that
+    /// moves the ratio, followed by a withdrawal, then trades at the final marginal
+    /// price, which is never better for the provider than a swap along the path.
+    ///
+    /// The price before the deposit is deliberately not used: a one-sided deposit
+    /// would be valued at a price it moves away from, and withdrawing its shares
+    /// at once would pay out more than a swap of the same amount.
     ///
     /// # Arguments
     ///
-    /// - `amount_a`: Amount of Token A to swap.
+    /// - `provider`: The ID of the provider receiving the shares.
+    /// - `amount_a`: Amount of Token A to deposit.
+    /// - `amount_b`: Amount of Token B to deposit.
     ///
     /// # Returns
     ///
-    /// A `Result` which contains the amount of Token B received if successful,
-    /// or an `Err` if the liquidity is insufficient or the amount is not positive.
-    pub fn swap_a_to_b(&mut self, amount_a: Decimal) -> Result<Decimal, Box<dyn Error>> {
-        if amount_a <= Decimal::ZERO {
-            return Err("Amount must be positive".into());
+    /// A `Result` which contains the number of shares minted, or an `Err` if the
+    /// amounts are negative or too small to mint any share.
+    pub fn deposit(
+        &mut self,
+        provider: &str,
+        amount_a: Decimal,
+        amount_b: Decimal,
+    ) -> Result<Decimal, ArppError> {
+        if amount_a < Decimal::ZERO || amount_b < Decimal::ZERO {
+            return Err(ArppError::InvalidAmount {
+                amount: amount_a.min(amount_b),
+            });
         }
-        if amount_a > self.token_a {
-            return Err("Insufficient liquidity of A".into());
+        let price = self.price_fn()(token_ratio(
+            self.token_a + amount_a,
+            self.token_b + amount_b,
+        ));
+        let value = amount_a * price + amount_b;
+        let pool_value = self.token_a * price + self.token_b;
+        let shares = if self.total_shares.is_zero() || pool_value <= Decimal::ZERO {
+            value
+        } else {
+            value / pool_value * self.total_shares
+        };
+        let shares = round_significant(shares, RoundingStrategy::ToZero).unwrap_or(Decimal::ZERO);
+        if shares <= Decimal::ZERO {
+            return Err(ArppError::DepositTooSmall { value });
         }
 
-        // Calculate the amount of B to deliver
-        let amount_b = arpp(
-            self.p_ref,
-            self.alpha,
-            self.beta,
-            token_ratio(self.token_a, self.token_b),
-        ) * amount_a;
-
+        self.token_a += amount_a;
+        self.token_b += amount_b;
+        self.mint_shares(provider, shares);
         debug!(
-            "Swapping {} tokens from A to B, current A {} current B {}, amount of B to delive {}",
-            amount_a, self.token_a, self.token_b, amount_b
+            "Provider {} deposited A {}, B {} for {} shares",
+            provider, amount_a, amount_b, shares
         );
-        if amount_b <= Decimal::ZERO || amount_b > self.token_b {
-            return Err("Insufficient liquidity to perform swap".into());
-        }
-        self.token_a += amount_a;
-        self.token_b -= amount_b;
-
-        Ok(amount_b)
+        Ok(shares)
     }
 
-    /// Swaps an amount of Token B for Token A.
+    /// Burns LP shares of a provider and pays out their pro-rata part of both
+    /// tokens.
     ///
     /// # Arguments
     ///
-    /// - `amount_b`: Amount of Token B to swap.
+    /// - `provider`: The ID of the provider redeeming the shares.
+    /// - `shares`: Number of shares to burn.
     ///
     /// # Returns
     ///
-    /// A `Result` which contains the amount of Token A received if successful,
-    /// or an `Err` if the liquidity is insufficient or the amount is not positive.
-    pub fn swap_b_to_a(&mut self, amount_b: Decimal) -> Result<Decimal, Box<dyn Error>> {
-        if amount_b <= Decimal::ZERO {
-            return Err("Amount must be positive".into());
+    /// A `Result` which contains the amounts of Token A and Token B paid out, or an
+    /// `Err` if the provider does not own that many shares.
+    pub fn withdraw(
+        &mut self,
+        provider: &str,
+        shares: Decimal,
+    ) -> Result<(Decimal, Decimal), ArppError> {
+        if shares <= Decimal::ZERO {
+            return Err(ArppError::InvalidAmount { amount: shares });
         }
-        if amount_b > self.token_b {
-            return Err("Insufficient liquidity of B".into());
+        let owned =
+            self.shares
+                .get(provider)
+                .copied()
+                .ok_or_else(|| ArppError::UnknownProvider {
+                    provider: provider.to_string(),
+                })?;
+        if shares > owned {
+            return Err(ArppError::InsufficientShares {
+                provider: provider.to_string(),
+                requested: shares,
+                owned,
+            });
         }
 
-        // Calculate the amount of A to deliver
-        let amount_a = arpp(
-            self.p_ref,
-            self.alpha,
-            self.beta,
-            token_ratio(self.token_a, self.token_b),
-        ) * amount_b;
-
+        let position = LpPosition::new(shares, self.total_shares, self.token_a, self.token_b);
+        self.token_a -= position.amount_a;
+        self.token_b -= position.amount_b;
+        self.total_shares -= shares;
+        if shares == owned {
+            self.shares.remove(provider);
+        } else {
+            self.shares.insert(provider.to_string(), owned - shares);
+        }
         debug!(
-            "Swapping {} tokens from B to A, current B {} current A {}, amount of A to delive {}",
-            amount_b, self.token_b, self.token_a, amount_a
+            "Provider {} withdrew A {}, B {} for {} shares",
+            provider, position.amount_a, position.amount_b, shares
         );
-        if amount_a <= Decimal::ZERO || amount_a > self.token_a {
-            let error_msg = format!(
-                "Insufficient liquidity to perform swap Token A {:.4}, Token B {:.4}, A needed {:.4}",
-                self.token_a, self.token_b, amount_a
-            );
-            return Err(error_msg.into());
-        }
+        Ok((position.amount_a, position.amount_b))
+    }
 
-        self.token_a -= amount_a;
-        self.token_b += amount_b;
+    /// Returns the position of a provider, or `None` if it owns no shares.
+    pub fn get_position(&self, provider: &str) -> Option<LpPosition> {
+        self.shares
+            .get(provider)
+            .map(|&shares| LpPosition::new(shares, self.total_shares, self.token_a, self.token_b))
+    }
 
-        Ok(amount_a)
+    /// Returns the positions of every provider, sorted by provider ID.
+    pub fn get_positions(&self) -> Vec<(String, LpPosition)> {
+        let mut providers: Vec<&String> = self.shares.keys().collect();
+        providers.sort();
+        providers
+            .into_iter()
+            .filter_map(|provider| Some((provider.clone(), self.get_position(provider)?)))
+            .collect()
     }
 
-    /// Retrieves the current price based on the pool's token ratios and parameters.
+    /// Returns the LP shares outstanding.
+    pub fn get_total_shares(&self) -> Decimal {
+        self.total_shares
+    }
+
+    /// Credits `shares` newly minted shares to `provider`.
+    fn mint_shares(&mut self, provider: &str, shares: Decimal) {
+        *self.shares.entry(provider.to_string()).or_default() += shares;
+        self.total_shares += shares;
+    }
+
+    /// Values amounts of both tokens in Token B at the current ARPP price.
+    fn value_in_b(&self, amount_a: Decimal, amount_b: Decimal) -> Decimal {
+        let price = self.price_fn()(token_ratio(self.token_a, self.token_b));
+        amount_a * price + amount_b
+    }
+
+    /// Swaps an amount of Token A for Token B.
+    ///
+    /// The trade is priced by integrating the ARPP curve over the ratio path it
+    /// moves through, so large trades carry price impact and splitting an order
+    /// into several swaps yields the same total as sending it at once.
+    ///
+    /// # Arguments
+    ///
+    /// - `amount_a`: Amount of Token A to swap.
     ///
     /// # Returns
     ///
-    /// A `Decimal` representing the calculated price.
-    pub fn get_price(&mut self) -> Decimal {
-        let r = token_ratio(self.token_a, self.token_b);
-        let price = arpp(self.p_ref, self.alpha, self.beta, r);
+    /// A `Result` which contains the `SwapResult` (amount of Token B received and
+    /// average execution price) if successful, or an `Err` if the liquidity is
+    /// insufficient or the amount is not positive. The result is the one
+    /// `quote_exact_in` returns for the same trade.
+    pub fn swap_a_to_b(&mut self, amount_a: Decimal) -> Result<SwapResult, ArppError> {
+        let now = Utc::now();
+        self.advance_ramp(now, false);
+        self.check_swaps_enabled(now)?;
+        let result = self.quote_exact_in_at(Token::A, amount_a, now)?;
+        let result = self.apply_breaker(result, now)?;
         debug!(
-            "P_ref: {:.2}, Price: {:.2}, Alpha: {:}, Beta: {}, R: {:.2}",
-            self.p_ref, price, self.alpha, self.beta, r
+            "Swapping {} tokens from A to B, current A {} current B {}, amount of B to deliver {}",
+            amount_a, self.token_a, self.token_b, result.amount_out
         );
-        price
+        self.settle(&result);
+        Ok(result)
     }
 
-    /// Updates the `p_ref` field by applying a random walk to its current value using the given `alpha` and `beta` parameters.
+    /// Swaps an amount of Token B for Token A.
     ///
-    /// # Parameters
-    /// - `alpha`: The alpha value to use in the random walk calculation.
-    /// - `beta`: The beta value to use in the random walk calculation.
+    /// Priced along the ratio path in the same way as `swap_a_to_b`.
+    ///
+    /// # Arguments
     ///
-    /// This function modifies the `p_ref` field of the current instance by applying the `random_walk_price` function
-    /// to its current value along with the specified `alpha` and `beta` parameters.
+    /// - `amount_b`: Amount of Token B to swap.
+    ///
+    /// # Returns
     ///
-    pub(crate) fn set_p_ref(&mut self, alpha: Decimal, beta: Decimal) {
-        self.p_ref = random_walk_price(self.p_ref, alpha, beta);
+    /// A `Result` which contains the `SwapResult` (amount of Token A received and
+    /// average execution price) if successful, or an `Err` if the liquidity is
+    /// insufficient or the amount is not positive. The result is the one
+    /// `quote_exact_in` returns for the same trade.
+    pub fn swap_b_to_a(&mut self, amount_b: Decimal) -> Result<SwapResult, ArppError> {
+        let now = Utc::now();
+        self.advance_ramp(now, false);
+        self.check_swaps_enabled(now)?;
+        let result = self.quote_exact_in_at(Token::B, amount_b, now)?;
+        let result = self.apply_breaker(result, now)?;
+        debug!(
+            "Swapping {} tokens from B to A, current B {} current A {}, amount of A to deliver {}",
+            amount_b, self.token_b, self.token_a, result.amount_out
+        );
+        self.settle(&result);
+        Ok(result)
     }
 
-    /// Retrieves the reference pressure (`p_ref`) stored in the structure.
+    /// Swaps an exact amount in, rejecting the trade if it pays out too little.
+    ///
+    /// # Arguments
+    ///
+    /// - `token_in`: The token paid into the pool.
+    /// - `amount_in`: Amount of `token_in` to pay in.
+    /// - `min_out`: Minimum amount of the other token to receive.
+    /// - `deadline`: Optional time after which the swap must not be executed.
     ///
     /// # Returns
     ///
-    /// * `Decimal` - The reference pressure as a `Decimal`.
+    /// A `Result` which contains the `SwapResult` if successful, or an `Err` if the
+    /// swap cannot be priced. `ArppError::DeadlineExpired` or
+    /// `ArppError::SlippageExceeded` is returned, and the pool is left untouched,
+    /// if the deadline has passed or the output is below `min_out`.
     ///
-    /// Note: Ensure that the mutable reference to the structure is maintained because
-    /// this method requires mutable access.
-    pub(crate) fn get_p_ref(&mut self) -> Decimal {
-        self.p_ref
+    /// A swap capped by the circuit breaker pays in less than `amount_in`.
+    pub fn swap_exact_in(
+        &mut self,
+        token_in: Token,
+        amount_in: Decimal,
+        min_out: Decimal,
+        deadline: Option<DateTime<Utc>>,
+    ) -> Result<SwapResult, ArppError> {
+        self.swap_exact_in_at(token_in, amount_in, min_out, deadline, Utc::now())
     }
 
-    /// Returns the current balances of two tokens.
+    /// Swaps an exact amount in as `swap_exact_in` does, with the circuit breaker
+    /// checked at `now`, so that a journal can replay the swap.
+    pub(crate) fn swap_exact_in_at(
+        &mut self,
+        token_in: Token,
+        amount_in: Decimal,
+        min_out: Decimal,
+        deadline: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<SwapResult, ArppError> {
+        self.advance_ramp(now, false);
+        self.check_swaps_enabled(now)?;
+        check_deadline(deadline, now)?;
+        let result = self.quote_exact_in_at(token_in, amount_in, now)?;
+        let result = self.apply_breaker(result, now)?;
+        if result.amount_out < min_out {
+            return Err(ArppError::SlippageExceeded {
+                token: result.token_out(),
+                amount: result.amount_out,
+                limit: min_out,
+            });
+        }
+        self.settle(&result);
+        Ok(result)
+    }
+
+    /// Swaps for an exact amount out, rejecting the trade if it costs too much.
     ///
-    /// This function retrieves the balances of `token_a` and `token_b` encapsulated
-    /// within the structure.
+    /// The pool pays out exactly `amount_out` and charges the amount in given by
+    /// `quote_exact_out`.
+    ///
+    /// # Arguments
+    ///
+    /// - `token_out`: The token paid out by the pool.
+    /// - `amount_out`: Amount of `token_out` to receive.
+    /// - `max_in`: Maximum amount of the other token to pay in.
+    /// - `deadline`: Optional time after which the swap must not be executed.
     ///
     /// # Returns
     ///
-    /// A tuple containing two `Decimal` values:
-    /// - The first value corresponds to the balance of `token_a`.
-    /// - The second value corresponds to the balance of `token_b`.
+    /// A `Result` which contains the `SwapResult` if successful, or an `Err` if the
+    /// swap cannot be priced. `ArppError::DeadlineExpired` or
+    /// `ArppError::SlippageExceeded` is returned, and the pool is left untouched,
+    /// if the deadline has passed or the input is above `max_in`.
     ///
-    /// # Usage
+    /// A swap capped by the circuit breaker pays out less than `amount_out`.
+    pub fn swap_exact_out(
+        &mut self,
+        token_out: Token,
+        amount_out: Decimal,
+        max_in: Decimal,
+        deadline: Option<DateTime<Utc>>,
+    ) -> Result<SwapResult, ArppError> {
+        self.swap_exact_out_at(token_out, amount_out, max_in, deadline, Utc::now())
+    }
+
+    /// Swaps for an exact amount out as `swap_exact_out` does, with the circuit
+    /// breaker checked at `now`, so that a journal can replay the swap.
+    pub(crate) fn swap_exact_out_at(
+        &mut self,
+        token_out: Token,
+        amount_out: Decimal,
+        max_in: Decimal,
+        deadline: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<SwapResult, ArppError> {
+        self.advance_ramp(now, false);
+        self.check_swaps_enabled(now)?;
+        check_deadline(deadline, now)?;
+        let result = self.quote_exact_out_at(token_out, amount_out, now)?;
+        let result = self.apply_breaker(result, now)?;
+        if result.amount_in > max_in {
+            return Err(ArppError::SlippageExceeded {
+                token: result.token_in,
+                amount: result.amount_in,
+                limit: max_in,
+            });
+        }
+        self.settle(&result);
+        Ok(result)
+    }
+
+    /// Quotes a swap of an exact amount in without modifying the pool.
     ///
-    /// This function can be used to check the current balance of two tokens stored
-    /// within an instance of the structure. It returns a tuple where the first element
-    /// is the balance of `token_a` and the second element is the balance of `token_b`.
-    pub fn get_balances(&self) -> (Decimal, Decimal) {
-        (self.token_a, self.token_b)
+    /// The quote is computed exactly as `swap_a_to_b` and `swap_b_to_a` price the
+    /// trade, with the parameters of a ramp in progress taken at their current
+    /// value, so executing it on the same pool returns an identical `SwapResult`.
+    ///
+    /// # Arguments
+    ///
+    /// - `token_in`: The token paid into the pool.
+    /// - `amount_in`: Amount of `token_in` to pay in.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which contains the quoted `SwapResult`, including the marginal
+    /// price after the trade and its price impact, or an `Err` if the liquidity is
+    /// insufficient or the amount is not positive.
+    pub fn quote_exact_in(
+        &self,
+        token_in: Token,
+        amount_in: Decimal,
+    ) -> Result<SwapResult, ArppError> {
+        self.quote_exact_in_at(token_in, amount_in, Utc::now())
     }
-}
 
-#[cfg(test)]
-mod tests_liquidity_pool {
-    use super::*;
-    use crate::utils::logger::setup_logger;
-    use rust_decimal_macros::dec;
-    use tracing::debug;
+    /// Quotes a swap of an exact amount in as `quote_exact_in` does, with the
+    /// parameters in effect at `now`.
+    pub(crate) fn quote_exact_in_at(
+        &self,
+        token_in: Token,
+        amount_in: Decimal,
+        now: DateTime<Utc>,
+    ) -> Result<SwapResult, ArppError> {
+        let (parameters, fee_bps) = self.parameters_at(now);
+        let price = self.price_fn_with(parameters);
+        let Some(dynamic_fee) = self.dynamic_fee else {
+            return self.quote_exact_in_with_fee(token_in, amount_in, fee_bps, &price);
+        };
+        let result =
+            self.quote_exact_in_with_fee(token_in, amount_in, dynamic_fee.base_bps, &price)?;
+        match self.dynamic_fee_of(&dynamic_fee, &result) {
+            fee_bps if fee_bps == dynamic_fee.base_bps => Ok(result),
+            fee_bps => self.quote_exact_in_with_fee(token_in, amount_in, fee_bps, &price),
+        }
+    }
 
-    // Helper function to create a standard pool for testing
-    fn create_standard_pool() -> LiquidityPool {
-        LiquidityPool::new(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1))
+    /// Quotes a swap of an exact amount in, charging a fee of `fee_bps` and pricing
+    /// it with `price`.
+    fn quote_exact_in_with_fee(
+        &self,
+        token_in: Token,
+        amount_in: Decimal,
+        fee_bps: Decimal,
+        price: &impl Fn(Decimal) -> Decimal,
+    ) -> Result<SwapResult, ArppError> {
+        if amount_in <= Decimal::ZERO {
+            return Err(ArppError::InvalidAmount { amount: amount_in });
+        }
+        let (balance_in, balance_out) = self.balances_of(token_in);
+        if amount_in > balance_in {
+            return Err(ArppError::InsufficientLiquidity {
+                token: token_in,
+                needed: amount_in,
+                available: balance_in,
+            });
+        }
+
+        // Only the input left after the fee is priced along the path
+        let fee = input_fee(amount_in, fee_bps);
+        let amount_out =
+            path_amount_out(self.token_a, self.token_b, token_in, amount_in - fee, price)?;
+        if amount_out <= Decimal::ZERO {
+            return Err(ArppError::InvalidAmount { amount: amount_in });
+        }
+        if amount_out > balance_out {
+            return Err(ArppError::InsufficientLiquidity {
+                token: token_in.other(),
+                needed: amount_out,
+                available: balance_out,
+            });
+        }
+        Ok(self.swap_result(token_in, amount_in, amount_out, fee, price))
+    }
+
+    /// Quotes the amount that has to be paid in to receive an exact amount out,
+    /// without modifying the pool.
+    ///
+    /// The amount in is rounded up in the pool's favour, so swapping it with
+    /// `swap_a_to_b` or `swap_b_to_a` delivers at least `amount_out`.
+    ///
+    /// # Arguments
+    ///
+    /// - `token_out`: The token paid out by the pool.
+    /// - `amount_out`: Amount of `token_out` to receive.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which contains the quoted `SwapResult`, or an `Err` if the pool
+    /// cannot pay out `amount_out` or the amount is not positive.
+    pub fn quote_exact_out(
+        &self,
+        token_out: Token,
+        amount_out: Decimal,
+    ) -> Result<SwapResult, ArppError> {
+        self.quote_exact_out_at(token_out, amount_out, Utc::now())
+    }
+
+    /// Quotes the amount in for an exact amount out as `quote_exact_out` does, with
+    /// the parameters in effect at `now`.
+    pub(crate) fn quote_exact_out_at(
+        &self,
+        token_out: Token,
+        amount_out: Decimal,
+        now: DateTime<Utc>,
+    ) -> Result<SwapResult, ArppError> {
+        let (parameters, fee_bps) = self.parameters_at(now);
+        let price = self.price_fn_with(parameters);
+        let Some(dynamic_fee) = self.dynamic_fee else {
+            return self.quote_exact_out_with_fee(token_out, amount_out, fee_bps, &price);
+        };
+        let result =
+            self.quote_exact_out_with_fee(token_out, amount_out, dynamic_fee.base_bps, &price)?;
+        match self.dynamic_fee_of(&dynamic_fee, &result) {
+            fee_bps if fee_bps == dynamic_fee.base_bps => Ok(result),
+            fee_bps => self.quote_exact_out_with_fee(token_out, amount_out, fee_bps, &price),
+        }
+    }
+
+    /// Quotes the amount in for an exact amount out, charging a fee of `fee_bps` and
+    /// pricing it with `price`.
+    fn quote_exact_out_with_fee(
+        &self,
+        token_out: Token,
+        amount_out: Decimal,
+        fee_bps: Decimal,
+        price: &impl Fn(Decimal) -> Decimal,
+    ) -> Result<SwapResult, ArppError> {
+        if amount_out <= Decimal::ZERO {
+            return Err(ArppError::InvalidAmount { amount: amount_out });
+        }
+        let (balance_out, balance_in) = self.balances_of(token_out);
+        if amount_out >= balance_out {
+            return Err(ArppError::InsufficientLiquidity {
+                token: token_out,
+                needed: amount_out,
+                available: balance_out,
+            });
+        }
+
+        let amount_in = path_amount_in(
+            self.token_a,
+            self.token_b,
+            token_out,
+            amount_out,
+            fee_bps,
+            price,
+        )?;
+        if amount_in > balance_in {
+            return Err(ArppError::InsufficientLiquidity {
+                token: token_out.other(),
+                needed: amount_in,
+                available: balance_in,
+            });
+        }
+        let fee = input_fee(amount_in, fee_bps);
+        Ok(self.swap_result(token_out.other(), amount_in, amount_out, fee, price))
+    }
+
+    /// Computes the swap that moves the ARPP price to `target`, without modifying
+    /// the pool.
+    ///
+    /// The target ratio is found with `ratio_for_price_with_curve`, and the amount
+    /// moving the swap path there is grossed up for the fee. As the fee is kept in
+    /// the reserves, the target ratio of the path is then corrected until the quoted
+    /// swap lands on the target price.
+    ///
+    /// # Arguments
+    ///
+    /// - `target`: The price to reach, in units of B per unit of A.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the token to pay in and the amount to pay, fee included,
+    /// which is zero if the price already is at `target`. It is an `Err` with
+    /// `ArppError::UnreachablePrice` if no ratio gives `target`, or the error of the
+    /// quote if the pool cannot execute the swap.
+    pub fn amount_to_reach_price(&self, target: Decimal) -> Result<(Token, Decimal), ArppError> {
+        let now = Utc::now();
+        let (parameters, fee_bps) = self.parameters_at(now);
+        let target_ratio = ratio_for_price_asymmetric_with_curve(
+            self.curve.as_ref(),
+            target,
+            self.p_ref,
+            &parameters,
+        )?;
+        let from = token_ratio(self.token_a, self.token_b);
+        let token_in = if target_ratio <= from {
+            Token::A
+        } else {
+            Token::B
+        };
+        let price = self.price_fn_with(parameters);
+        if target_ratio == from || price(from) == target {
+            return Ok((token_in, Decimal::ZERO));
+        }
+        let fee_bps = self.dynamic_fee.map_or(fee_bps, |fee| fee.base_bps);
+        let mut gross_up = BASIS_POINTS / (BASIS_POINTS - fee_bps);
+        let mut ratio = target_ratio;
+        let mut previous = Decimal::ZERO;
+        for _ in 0..TARGET_ITERATIONS {
+            let net = path_amount_to_ratio(self.token_a, self.token_b, token_in, ratio, &price)?;
+            let amount_in = round_significant(net * gross_up, RoundingStrategy::ToZero)
+                .ok_or(ArppError::Overflow)?;
+            if amount_in == previous {
+                // The correction is below the resolution of the amount
+                return Ok((token_in, amount_in));
+            }
+            previous = amount_in;
+            let result = self.quote_exact_in_at(token_in, amount_in, now)?;
+            if (result.price_after / target - Decimal::ONE).abs() <= TARGET_TOLERANCE {
+                return Ok((token_in, amount_in));
+            }
+            let (token_a, token_b) =
+                self.balances_after(result.token_in, result.amount_in, result.amount_out);
+            ratio *= target_ratio / token_ratio(token_a, token_b);
+            gross_up = result.amount_in / (result.amount_in - result.fee);
+        }
+        Err(ArppError::NoConvergence)
+    }
+
+    /// Returns the dynamic fee, in basis points, of a swap quoted at its base fee.
+    fn dynamic_fee_of(&self, fee: &DynamicFee, result: &SwapResult) -> Decimal {
+        fee.fee_bps(
+            deviation(result.price_before, self.p_ref),
+            deviation(result.price_after, self.p_ref),
+        )
+    }
+
+    /// Returns an error if swaps are paused by an oracle guard or halted, at `now`,
+    /// by the circuit breaker.
+    fn check_swaps_enabled(&mut self, now: DateTime<Utc>) -> Result<(), ArppError> {
+        if self.swaps_paused {
+            return Err(ArppError::SwapsPaused);
+        }
+        self.breaker.check(now)
+    }
+
+    /// Applies the policy of the circuit breaker, if the quoted swap breaches it.
+    ///
+    /// # Returns
+    ///
+    /// The swap to execute, cut short at the edge of the band if the policy is
+    /// `Cap`, or `ArppError::PriceOutOfBand` if it is refused.
+    fn apply_breaker(
+        &mut self,
+        result: SwapResult,
+        now: DateTime<Utc>,
+    ) -> Result<SwapResult, ArppError> {
+        let Some(breaker) = self.circuit_breaker else {
+            return Ok(result);
+        };
+        let Some((low, high)) = breaker.check(self.p_ref, result.price_before, result.price_after)
+        else {
+            return Ok(result);
+        };
+        debug!(
+            "Circuit breaker breached ({:?}): price {} outside [{}, {}]",
+            breaker.policy, result.price_after, low, high
+        );
+        match breaker.policy {
+            BreakerPolicy::Cap => {
+                if let Some(capped) = self.cap_swap(&result, (low, high), now) {
+                    self.breaker.stats.capped_swaps += 1;
+                    return Ok(capped);
+                }
+                self.breaker.stats.rejected_swaps += 1;
+            }
+            BreakerPolicy::Reject => self.breaker.stats.rejected_swaps += 1,
+            BreakerPolicy::Cooldown(cooldown) => self.breaker.trip(cooldown, now),
+        }
+        Err(ArppError::PriceOutOfBand {
+            price: result.price_after,
+            low,
+            high,
+        })
+    }
+
+    /// Quotes the part of a swap that brings the price to the edge of the band
+    /// `(low, high)` it breaches.
+    ///
+    /// The fee is kept in the reserves, and a dynamic fee changes with the size of
+    /// the swap, so paying in the amount that moves the path to the edge misses it
+    /// slightly. The target ratio is corrected by the miss until the swap lands
+    /// within the band.
+    ///
+    /// # Returns
+    ///
+    /// The capped swap, or `None` if the price already is at or beyond that edge.
+    fn cap_swap(
+        &self,
+        result: &SwapResult,
+        (low, high): (Decimal, Decimal),
+        now: DateTime<Utc>,
+    ) -> Option<SwapResult> {
+        let edge = if result.price_after > high { high } else { low };
+        if (result.price_before - edge) * (result.price_after - edge) >= Decimal::ZERO {
+            return None;
+        }
+        let price = self.price_fn_with(self.parameters_at(now).0);
+        let from = token_ratio(self.token_a, self.token_b);
+        let (token_a, token_b) =
+            self.balances_after(result.token_in, result.amount_in, result.amount_out);
+        let edge_ratio = ratio_at_price(&price, from, token_ratio(token_a, token_b), edge);
+        let mut ratio = edge_ratio;
+        let mut previous = Decimal::ZERO;
+        for _ in 0..CAP_ITERATIONS {
+            let net =
+                path_amount_to_ratio(self.token_a, self.token_b, result.token_in, ratio, &price)
+                    .ok()?;
+            let mut amount_in = round_significant(
+                net * result.amount_in / (result.amount_in - result.fee),
+                RoundingStrategy::ToZero,
+            )?;
+            if amount_in >= previous && previous > Decimal::ZERO {
+                // The correction is below the resolution of the amount
+                amount_in = previous - Decimal::new(1, previous.scale());
+            }
+            previous = amount_in;
+            let capped = self
+                .quote_exact_in_at(result.token_in, amount_in, now)
+                .ok()?;
+            if low <= capped.price_after && capped.price_after <= high {
+                return Some(capped);
+            }
+            let (token_a, token_b) =
+                self.balances_after(capped.token_in, capped.amount_in, capped.amount_out);
+            let landed = token_ratio(token_a, token_b);
+            ratio = from + (ratio - from) * (edge_ratio - from) / (landed - from);
+        }
+        None
+    }
+
+    /// Returns the balances of `token` and of the other token, in that order.
+    fn balances_of(&self, token: Token) -> (Decimal, Decimal) {
+        match token {
+            Token::A => (self.token_a, self.token_b),
+            Token::B => (self.token_b, self.token_a),
+        }
+    }
+
+    /// Returns the ARPP price as a function of the ratio, with the pool's parameters.
+    fn price_fn(&self) -> impl Fn(Decimal) -> Decimal {
+        self.price_fn_with(self.get_parameters())
+    }
+
+    /// Returns the ARPP price as a function of the ratio, with `parameters`.
+    fn price_fn_with(&self, parameters: AsymmetricParameters) -> impl Fn(Decimal) -> Decimal {
+        let p_ref = self.p_ref;
+        let curve = Arc::clone(&self.curve);
+        move |r: Decimal| arpp_asymmetric_with_curve(curve.as_ref(), p_ref, &parameters, r)
+    }
+
+    /// Returns the balances of the pool once a swap has been settled.
+    fn balances_after(
+        &self,
+        token_in: Token,
+        amount_in: Decimal,
+        amount_out: Decimal,
+    ) -> (Decimal, Decimal) {
+        match token_in {
+            Token::A => (self.token_a + amount_in, self.token_b - amount_out),
+            Token::B => (self.token_a - amount_out, self.token_b + amount_in),
+        }
+    }
+
+    /// Builds the `SwapResult` of a trade against the current balances, priced
+    /// with `price`.
+    fn swap_result(
+        &self,
+        token_in: Token,
+        amount_in: Decimal,
+        amount_out: Decimal,
+        fee: Decimal,
+        price: &impl Fn(Decimal) -> Decimal,
+    ) -> SwapResult {
+        let price_before = price(token_ratio(self.token_a, self.token_b));
+        let (token_a, token_b) = self.balances_after(token_in, amount_in, amount_out);
+        let price_after = price(token_ratio(token_a, token_b));
+        SwapResult::new(
+            token_in,
+            amount_in,
+            amount_out,
+            fee,
+            price_before,
+            price_after,
+        )
+    }
+
+    /// Applies the balance changes of a quoted swap to the pool.
+    ///
+    /// The whole input, fee included, is added to the reserves.
+    fn settle(&mut self, result: &SwapResult) {
+        (self.token_a, self.token_b) =
+            self.balances_after(result.token_in, result.amount_in, result.amount_out);
+        match result.token_in {
+            Token::A => self.fees_a += result.fee,
+            Token::B => self.fees_b += result.fee,
+        }
+    }
+
+    /// Retrieves the current price based on the pool's token ratios and parameters.
+    ///
+    /// # Returns
+    ///
+    /// A `Decimal` representing the calculated price.
+    pub fn get_price(&self) -> Decimal {
+        let r = token_ratio(self.token_a, self.token_b);
+        let price = self.price_fn()(r);
+        let (alpha, beta) = self.get_parameters().at_ratio(r);
+        debug!(
+            "P_ref: {:.2}, Price: {:.2}, Alpha: {:}, Beta: {}, R: {:.2}",
+            self.p_ref, price, alpha, beta, r
+        );
+        price
+    }
+
+    /// Returns the partial derivatives of the ARPP price at the current balances
+    /// and parameters, with the pool's pricing curve.
+    ///
+    /// # Returns
+    ///
+    /// The `Sensitivities` of the price returned by `get_price`. For a pool with
+    /// separate parameters below balance, `d_alpha` and `d_beta` are taken with
+    /// respect to the parameters of the side the pool is on.
+    pub fn get_sensitivities(&self) -> Sensitivities {
+        sensitivities_asymmetric_with_curve(
+            self.curve.as_ref(),
+            self.p_ref,
+            &self.get_parameters(),
+            token_ratio(self.token_a, self.token_b),
+        )
+    }
+
+    /// Updates the `p_ref` field with the next price reported by an oracle.
+    ///
+    /// The oracle is given the current `p_ref` and the current pool price, which is
+    /// also recorded in the pool's own TWAP. If it has no new price, `p_ref` is kept
+    /// and only its age is checked. Otherwise the update is checked against the
+    /// oracle guards in order: age, jump from `p_ref`, then deviation from the TWAP.
+    /// Each breach is logged as an `OracleIncident` and handled by the policy of its
+    /// guard. Applying an update resumes swaps paused by an earlier breach.
+    ///
+    /// # Parameters
+    /// - `oracle`: The source of the reference price.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the update was applied, possibly clamped, or
+    /// skipped, or an `Err` with the reason it was rejected, in which case `p_ref`
+    /// is left unchanged.
+    pub fn update_p_ref(&mut self, oracle: &mut dyn PriceOracle) -> Result<(), ArppError> {
+        self.update_p_ref_at(oracle, Utc::now())
+    }
+
+    /// Updates `p_ref` as `update_p_ref` does, with the age of oracle prices
+    /// measured at `now`, so that a journal can replay the update.
+    pub(crate) fn update_p_ref_at(
+        &mut self,
+        oracle: &mut dyn PriceOracle,
+        now: DateTime<Utc>,
+    ) -> Result<(), ArppError> {
+        self.breaker.step(now);
+        self.advance_ramp(now, true);
+        let pool_price = self.price_fn()(token_ratio(self.token_a, self.token_b));
+        let twap = self.twap.observe(pool_price);
+        let Some(update) = oracle.next_price(self.p_ref, pool_price) else {
+            // Without a new price, the current one keeps ageing
+            return match self.oracle_guards.check_age(self.p_ref_updated_at, now) {
+                Some(breach) => Err(self.reject_update(breach, now)),
+                None => Ok(()),
+            };
+        };
+        if update.price <= Decimal::ZERO {
+            return Err(ArppError::InvalidPrice {
+                price: update.price,
+            });
+        }
+        if let Some(breach) = self.oracle_guards.check_age(update.timestamp, now) {
+            return Err(self.reject_update(breach, now));
+        }
+
+        let jump = self.oracle_guards.check_jump(self.p_ref, update.price);
+        let p_ref = self.apply_guard(jump, update.price, now)?;
+        let deviation = self.oracle_guards.check_deviation(twap, p_ref);
+        let p_ref = self.apply_guard(deviation, p_ref, now)?;
+
+        debug!("Updating p_ref from {} to {}", self.p_ref, p_ref);
+        self.p_ref = p_ref;
+        self.p_ref_updated_at = update.timestamp;
+        self.swaps_paused = false;
+        Ok(())
+    }
+
+    /// Sets the guards checked on every `p_ref` update.
+    ///
+    /// The pool's TWAP is restarted over the new `twap_window`.
+    pub fn set_oracle_guards(&mut self, guards: OracleGuards) {
+        self.twap = TwapOracle::new(guards.twap_window);
+        self.oracle_guards = guards;
+    }
+
+    /// Returns the guards checked on every `p_ref` update.
+    pub fn get_oracle_guards(&self) -> &OracleGuards {
+        &self.oracle_guards
+    }
+
+    /// Returns whether swaps are paused by an oracle guard.
+    pub fn is_paused(&self) -> bool {
+        self.swaps_paused
+    }
+
+    /// Returns the breaches of the oracle guards, oldest first.
+    pub fn get_oracle_incidents(&self) -> &[OracleIncident] {
+        &self.oracle_incidents
+    }
+
+    /// Sets the circuit breaker checked on every swap, or removes it with `None`.
+    ///
+    /// A cooldown in progress runs until it ends, whatever the new breaker.
+    pub fn set_circuit_breaker(&mut self, breaker: Option<CircuitBreaker>) {
+        self.circuit_breaker = breaker;
+    }
+
+    /// Returns the circuit breaker checked on every swap, if any.
+    pub fn get_circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        self.circuit_breaker.as_ref()
+    }
+
+    /// Returns whether swaps are halted by a cooldown of the circuit breaker.
+    ///
+    /// A time cooldown is only ended by the first swap or `p_ref` update made
+    /// after it expires.
+    pub fn is_halted(&self) -> bool {
+        self.breaker.halt.is_some()
+    }
+
+    /// Returns how often and how long the circuit breaker was active.
+    pub fn get_breaker_stats(&self) -> BreakerStats {
+        self.breaker.stats
+    }
+
+    /// Returns the `alpha` parameter of the ARPP formula, above balance.
+    pub fn get_alpha(&self) -> Decimal {
+        self.alpha
+    }
+
+    /// Returns the `beta` parameter of the ARPP formula, above balance.
+    pub fn get_beta(&self) -> Decimal {
+        self.beta
+    }
+
+    /// Returns the `alpha` and `beta` of the ARPP formula on both sides of
+    /// balance, which are the same unless the pool was created asymmetric.
+    pub fn get_parameters(&self) -> AsymmetricParameters {
+        AsymmetricParameters::new(
+            self.alpha,
+            self.alpha_down.unwrap_or(self.alpha),
+            self.beta,
+            self.beta_down.unwrap_or(self.beta),
+        )
+    }
+
+    /// Returns the parameters in effect at `now`, without modifying the pool.
+    ///
+    /// They are the pool's parameters, with the one changed by a ramp in progress
+    /// taken at its value at `now`, as the next swap would apply it.
+    ///
+    /// # Returns
+    ///
+    /// The `alpha` and `beta` of the ARPP formula on both sides of balance, and the
+    /// fee in basis points.
+    pub fn parameters_at(&self, now: DateTime<Utc>) -> (AsymmetricParameters, Decimal) {
+        let mut parameters = self.get_parameters();
+        let Some(ramp) = &self.ramp else {
+            return (parameters, self.fee_bps);
+        };
+        let value = ramp.value(now);
+        match ramp.parameter {
+            RampParameter::Alpha => {
+                parameters.alpha_up = value;
+                if self.alpha_down.is_none() {
+                    parameters.alpha_down = value;
+                }
+            }
+            RampParameter::Beta => {
+                parameters.beta_up = value;
+                if self.beta_down.is_none() {
+                    parameters.beta_down = value;
+                }
+            }
+            RampParameter::FeeBps => return (parameters, value),
+        }
+        (parameters, self.fee_bps)
+    }
+
+    /// Schedules a change of `parameter` to `target`, ramped linearly over `length`
+    /// so that the price does not jump.
+    ///
+    /// The parameter moves on every swap and `p_ref` update: a ramp in steps
+    /// advances by one step per `p_ref` update, a ramp in time follows the clock.
+    /// Only one ramp runs at a time, and while a fee ramp runs it overrides
+    /// `set_fee_bps`. Ramps of `alpha` and `beta` change the parameters on both
+    /// sides of balance in a pool without separate ones. In a pool with separate
+    /// ones they would only change the slope above balance, and are rejected with
+    /// `ArppError::KinkAtBalance`.
+    ///
+    /// # Arguments
+    ///
+    /// - `parameter`: The parameter to change.
+    /// - `target`: The value the parameter ends at.
+    /// - `length`: How long the ramp takes. A ramp of no length applies the change
+    ///   at once.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the ramp was scheduled, or an `Err` with
+    /// `ArppError::RampInProgress` if another ramp has not ended,
+    /// `ArppError::RampOutOfBounds` if the change exceeds the ramp bounds, or the
+    /// reason `target` is not a valid value of the parameter.
+    pub fn schedule_ramp(
+        &mut self,
+        parameter: RampParameter,
+        target: Decimal,
+        length: RampLength,
+    ) -> Result<(), ArppError> {
+        self.schedule_ramp_at(parameter, target, length, Utc::now())
+    }
+
+    /// Schedules a ramp as `schedule_ramp` does, starting at `now`, so that a
+    /// journal can replay it.
+    pub(crate) fn schedule_ramp_at(
+        &mut self,
+        parameter: RampParameter,
+        target: Decimal,
+        length: RampLength,
+        now: DateTime<Utc>,
+    ) -> Result<(), ArppError> {
+        self.advance_ramp(now, false);
+        if let Some(ramp) = &self.ramp {
+            return Err(ArppError::RampInProgress {
+                parameter: ramp.parameter,
+            });
+        }
+        let from = match parameter {
+            RampParameter::Alpha => self.alpha,
+            RampParameter::Beta => self.beta,
+            RampParameter::FeeBps => self.fee_bps,
+        };
+        self.ramp_bounds.check(parameter, from, target)?;
+        // The lowest price only falls as alpha or beta grows, so checking the
+        // target is enough for the whole ramp to be safe
+        let mut parameters = self.get_parameters();
+        match parameter {
+            RampParameter::Alpha => {
+                parameters.alpha_up = target;
+                if self.alpha_down.is_none() {
+                    parameters.alpha_down = target;
+                }
+            }
+            RampParameter::Beta => {
+                parameters.beta_up = target;
+                if self.beta_down.is_none() {
+                    parameters.beta_down = target;
+                }
+            }
+            RampParameter::FeeBps => {
+                if target < Decimal::ZERO || target >= BASIS_POINTS {
+                    return Err(ArppError::InvalidFee { fee_bps: target });
+                }
+            }
+        }
+        if parameter != RampParameter::FeeBps {
+            check_asymmetric_parameters(self.curve.as_ref(), self.p_ref, &parameters)?;
+        }
+        debug!(
+            "Ramping {:?} from {} to {} over {:?}",
+            parameter, from, target, length
+        );
+        self.ramp = Some(Ramp::new(parameter, from, target, length, now));
+        self.advance_ramp(now, false);
+        Ok(())
+    }
+
+    /// Stops the ramp in progress, leaving its parameter at its current value.
+    pub fn stop_ramp(&mut self) {
+        self.stop_ramp_at(Utc::now())
+    }
+
+    /// Stops the ramp in progress as `stop_ramp` does, at `now`.
+    pub(crate) fn stop_ramp_at(&mut self, now: DateTime<Utc>) {
+        self.advance_ramp(now, false);
+        self.ramp = None;
+    }
+
+    /// Returns the ramp in progress, if any.
+    pub fn get_ramp(&self) -> Option<&Ramp> {
+        self.ramp.as_ref()
+    }
+
+    /// Sets the bounds checked when a ramp is scheduled.
+    pub fn set_ramp_bounds(&mut self, bounds: RampBounds) {
+        self.ramp_bounds = bounds;
+    }
+
+    /// Returns the bounds checked when a ramp is scheduled.
+    pub fn get_ramp_bounds(&self) -> &RampBounds {
+        &self.ramp_bounds
+    }
+
+    /// Moves the parameter of the ramp in progress to its value at `now`, counting
+    /// a `p_ref` update first if `step` is set, and ends the ramp once it is done.
+    fn advance_ramp(&mut self, now: DateTime<Utc>, step: bool) {
+        let Some(ramp) = &mut self.ramp else {
+            return;
+        };
+        if step {
+            ramp.steps_done += 1;
+        }
+        let value = ramp.value(now);
+        match ramp.parameter {
+            RampParameter::Alpha => self.alpha = value,
+            RampParameter::Beta => self.beta = value,
+            RampParameter::FeeBps => self.fee_bps = value,
+        }
+        if ramp.progress(now) == Decimal::ONE {
+            debug!("Ramp of {:?} reached {}", ramp.parameter, value);
+            self.ramp = None;
+        }
+    }
+
+    /// Applies the policy of a guard, if breached, to an oracle price.
+    ///
+    /// # Returns
+    ///
+    /// The price to apply, clamped into the band of the guard if its policy is
+    /// `Clamp`, or the breach if the update is rejected.
+    fn apply_guard(
+        &mut self,
+        breach: Option<GuardBreach>,
+        price: Decimal,
+        now: DateTime<Utc>,
+    ) -> Result<Decimal, ArppError> {
+        let Some(breach) = breach else {
+            return Ok(price);
+        };
+        match (breach.policy, breach.band) {
+            (GuardPolicy::Clamp, Some((low, high))) => {
+                self.log_incident(breach.error, GuardPolicy::Clamp, now);
+                Ok(price.clamp(low, high))
+            }
+            _ => Err(self.reject_update(breach, now)),
+        }
+    }
+
+    /// Logs a breach for which the current `p_ref` is kept, pausing swaps if the
+    /// policy of the guard says so.
+    fn reject_update(&mut self, breach: GuardBreach, now: DateTime<Utc>) -> ArppError {
+        let policy = match breach.policy {
+            GuardPolicy::Pause => {
+                self.swaps_paused = true;
+                GuardPolicy::Pause
+            }
+            _ => GuardPolicy::Reject,
+        };
+        self.log_incident(breach.error.clone(), policy, now);
+        breach.error
+    }
+
+    fn log_incident(&mut self, error: ArppError, policy: GuardPolicy, now: DateTime<Utc>) {
+        debug!("Oracle guard breached ({:?}): {}", policy, error);
+        self.oracle_incidents.push(OracleIncident {
+            timestamp: now,
+            error,
+            policy,
+        });
+    }
+
+    /// Retrieves the reference pressure (`p_ref`) stored in the structure.
+    ///
+    /// # Returns
+    ///
+    /// * `Decimal` - The reference pressure as a `Decimal`.
+    pub fn get_p_ref(&self) -> Decimal {
+        self.p_ref
+    }
+
+    /// Returns the current balances of two tokens.
+    ///
+    /// This function retrieves the balances of `token_a` and `token_b` encapsulated
+    /// within the structure.
+    ///
+    /// # Returns
+    ///
+    /// A tuple containing two `Decimal` values:
+    /// - The first value corresponds to the balance of `token_a`.
+    /// - The second value corresponds to the balance of `token_b`.
+    ///
+    /// # Usage
+    ///
+    /// This function can be used to check the current balance of two tokens stored
+    /// within an instance of the structure. It returns a tuple where the first element
+    /// is the balance of `token_a` and the second element is the balance of `token_b`.
+    pub fn get_balances(&self) -> (Decimal, Decimal) {
+        (self.token_a, self.token_b)
+    }
+
+    /// Takes a snapshot of the full state of the pool.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the snapshot, which `to_json` writes as versioned JSON, or
+    /// `ArppError::UnserializableCurve` if the pool is priced with a curve defined
+    /// outside the crate.
+    pub fn snapshot(&self) -> Result<Snapshot<PoolState>, ArppError> {
+        Ok(Snapshot::new(PoolState {
+            token_a: self.token_a,
+            token_b: self.token_b,
+            p_ref: self.p_ref,
+            alpha: self.alpha,
+            beta: self.beta,
+            alpha_down: self.alpha_down,
+            beta_down: self.beta_down,
+            curve: self.curve.spec().ok_or(ArppError::UnserializableCurve)?,
+            fee_bps: self.fee_bps,
+            dynamic_fee: self.dynamic_fee,
+            fees_a: self.fees_a,
+            fees_b: self.fees_b,
+            total_shares: self.total_shares,
+            shares: self.shares.clone().into_iter().collect(),
+            oracle_guards: self.oracle_guards.clone(),
+            twap: self.twap.clone(),
+            p_ref_updated_at: self.p_ref_updated_at,
+            swaps_paused: self.swaps_paused,
+            oracle_incidents: self.oracle_incidents.clone(),
+            circuit_breaker: self.circuit_breaker,
+            breaker: self.breaker.clone(),
+            ramp: self.ramp,
+            ramp_bounds: self.ramp_bounds,
+        }))
+    }
+
+    /// Restores a pool from a snapshot taken by `snapshot`.
+    ///
+    /// The restored pool quotes, swaps and updates `p_ref` exactly as the pool the
+    /// snapshot was taken from.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the pool, or an `Err` if the snapshot is of another version,
+    /// its curve is invalid or its parameters are invalid with that curve, as
+    /// checked by `check_asymmetric_parameters`.
+    pub fn restore(snapshot: Snapshot<PoolState>) -> Result<Self, ArppError> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(ArppError::UnsupportedSnapshotVersion {
+                version: snapshot.version,
+                supported: SNAPSHOT_VERSION,
+            });
+        }
+        let state = snapshot.state;
+        let pool = Self {
+            token_a: state.token_a,
+            token_b: state.token_b,
+            p_ref: state.p_ref,
+            alpha: state.alpha,
+            beta: state.beta,
+            alpha_down: state.alpha_down,
+            beta_down: state.beta_down,
+            curve: state.curve.build()?,
+            fee_bps: state.fee_bps,
+            dynamic_fee: state.dynamic_fee,
+            fees_a: state.fees_a,
+            fees_b: state.fees_b,
+            total_shares: state.total_shares,
+            shares: state.shares.into_iter().collect(),
+            oracle_guards: state.oracle_guards,
+            twap: state.twap,
+            p_ref_updated_at: state.p_ref_updated_at,
+            swaps_paused: state.swaps_paused,
+            oracle_incidents: state.oracle_incidents,
+            circuit_breaker: state.circuit_breaker,
+            breaker: state.breaker,
+            ramp: state.ramp,
+            ramp_bounds: state.ramp_bounds,
+        };
+        pool.check_parameters()?;
+        Ok(pool)
+    }
+}
+
+impl Amm for LiquidityPool {
+    fn swap_a_to_b(&mut self, amount_a: Decimal) -> Result<SwapResult, ArppError> {
+        LiquidityPool::swap_a_to_b(self, amount_a)
+    }
+
+    fn swap_b_to_a(&mut self, amount_b: Decimal) -> Result<SwapResult, ArppError> {
+        LiquidityPool::swap_b_to_a(self, amount_b)
+    }
+
+    fn deposit(
+        &mut self,
+        provider: &str,
+        amount_a: Decimal,
+        amount_b: Decimal,
+    ) -> Result<Decimal, ArppError> {
+        LiquidityPool::deposit(self, provider, amount_a, amount_b)
+    }
+
+    fn withdraw(
+        &mut self,
+        provider: &str,
+        shares: Decimal,
+    ) -> Result<(Decimal, Decimal), ArppError> {
+        LiquidityPool::withdraw(self, provider, shares)
+    }
+
+    fn get_price(&self) -> Decimal {
+        LiquidityPool::get_price(self)
+    }
+
+    fn get_balances(&self) -> (Decimal, Decimal) {
+        LiquidityPool::get_balances(self)
+    }
+
+    fn get_fee_revenue(&self) -> (Decimal, Decimal) {
+        LiquidityPool::get_fee_revenue(self)
+    }
+
+    fn get_p_ref(&self) -> Decimal {
+        LiquidityPool::get_p_ref(self)
+    }
+
+    fn update_p_ref(&mut self, oracle: &mut dyn PriceOracle) -> Result<(), ArppError> {
+        LiquidityPool::update_p_ref(self, oracle)
+    }
+
+    fn get_oracle_incidents(&self) -> &[OracleIncident] {
+        LiquidityPool::get_oracle_incidents(self)
+    }
+
+    fn get_breaker_stats(&self) -> Option<BreakerStats> {
+        Some(LiquidityPool::get_breaker_stats(self))
+    }
+
+    fn get_ramp(&self) -> Option<&Ramp> {
+        LiquidityPool::get_ramp(self)
+    }
+}
+
+#[cfg(test)]
+mod tests_liquidity_pool {
+    use super::*;
+    use crate::arpp::breaker::{Cooldown, PriceBand};
+    use crate::arpp::curve::{
+        AlgebraicCurve, CurveSpec, LogisticCurve, PiecewiseLinearCurve, TanhCurve,
+    };
+    use crate::arpp::formula::sensitivities_with_curve;
+    use crate::arpp::oracle::{ConstantOracle, OracleGuard, OraclePrice, ReplayOracle, TwapOracle};
+    use crate::utils::logger::setup_logger;
+    use assert_approx_eq::assert_approx_eq;
+    use chrono::Duration;
+    use rust_decimal_macros::dec;
+    use tracing::debug;
+
+    // Helper function to create a standard pool for testing
+    fn create_standard_pool() -> LiquidityPool {
+        LiquidityPool::new(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1))
+    }
+
+    #[test]
+    fn test_new_pool_creation() {
+        let pool = create_standard_pool();
+        assert_eq!(pool.get_balances(), (dec!(1000), dec!(1000)));
+        assert_eq!(pool.get_price(), dec!(1));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_add_liquidity() {
+        let mut pool = create_standard_pool();
+        assert!(pool.add_liquidity(dec!(100), dec!(100)).is_ok());
+        assert_eq!(pool.get_balances(), (dec!(1100), dec!(1100)));
+        assert_eq!(
+            pool.get_position(INITIAL_PROVIDER).unwrap().shares,
+            dec!(2200)
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_add_liquidity_neg_amount() {
+        let mut pool = create_standard_pool();
+        assert!(pool.add_liquidity(dec!(-1), dec!(100)).is_err());
+        assert!(pool.add_liquidity(dec!(100), dec!(-1)).is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_add_liquidity_negative_amount() {
+        let mut pool = create_standard_pool();
+        assert!(pool.add_liquidity(dec!(-100), dec!(100)).is_err());
+        assert!(pool.add_liquidity(dec!(100), dec!(-100)).is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_remove_liquidity() {
+        let mut pool = create_standard_pool();
+        assert!(pool.remove_liquidity(dec!(100), dec!(100)).is_ok());
+        assert_eq!(pool.get_balances(), (dec!(900), dec!(900)));
+        assert_eq!(pool.get_total_shares(), dec!(1800));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_remove_liquidity_zero_amount() {
+        let mut pool = create_standard_pool();
+        assert!(pool.remove_liquidity(dec!(0), dec!(100)).is_err());
+        assert!(pool.remove_liquidity(dec!(100), dec!(0)).is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_remove_liquidity_negative_amount() {
+        let mut pool = create_standard_pool();
+        assert!(pool.remove_liquidity(dec!(-100), dec!(100)).is_err());
+        assert!(pool.remove_liquidity(dec!(100), dec!(-100)).is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_remove_liquidity_insufficient() {
+        let mut pool = create_standard_pool();
+        assert!(pool.remove_liquidity(dec!(1001), dec!(100)).is_err());
+        assert!(pool.remove_liquidity(dec!(100), dec!(1001)).is_err());
+
+        // Only the shares of the initial provider can be burnt
+        pool.deposit("alice", dec!(1000), dec!(1000)).unwrap();
+        assert!(matches!(
+            pool.remove_liquidity(dec!(1500), dec!(1500)),
+            Err(ArppError::InsufficientShares { .. })
+        ));
+        assert_eq!(pool.get_balances(), (dec!(2000), dec!(2000)));
+    }
+
+    #[test]
+    fn test_swap_a_to_b() {
+        setup_logger();
+        let mut pool = create_standard_pool();
+        let initial_balances = pool.get_balances();
+        let initial_price = pool.get_price();
+
+        let amount_a_to_swap = dec!(10);
+        let result = pool.swap_a_to_b(amount_a_to_swap);
+        assert!(result.is_ok());
+        let amount_b = result.unwrap().amount_out;
+
+        let (token_a, token_b) = pool.get_balances();
+        debug!(
+            "Final balances: ({}, {}), Amount of B received: {}",
+            token_a, token_b, amount_b
+        );
+
+        assert!(token_a > initial_balances.0, "Token A should increase");
+        assert!(token_b < initial_balances.1, "Token B should decrease");
+
+        // The price falls along the path, so the trade gets less than 1:1
+        assert!(
+            amount_b < amount_a_to_swap,
+            "Amount of B should be below the amount of A swapped"
+        );
+
+        // Check that the changes in balances are consistent
+        assert_eq!(
+            token_a - initial_balances.0,
+            amount_a_to_swap,
+            "Increase in A should equal amount swapped"
+        );
+        assert_eq!(
+            initial_balances.1 - token_b,
+            amount_b,
+            "Decrease in B should equal amount received"
+        );
+
+        // The average execution price lies between the spot prices before and after the swap
+        let swap_rate = amount_b / amount_a_to_swap;
+        debug!("Swap rate (B/A): {}", swap_rate);
+        assert!(
+            swap_rate < initial_price,
+            "Swap rate should be below the initial price"
+        );
+        assert!(
+            swap_rate > pool.get_price(),
+            "Swap rate should exceed the final price"
+        );
+    }
+
+    #[test]
+    fn test_swap_b_to_a() {
+        let mut pool = create_standard_pool();
+        let initial_balances = pool.get_balances();
+        let initial_price = pool.get_price();
+        debug!("Initial balances: {:?}", initial_balances);
+
+        let amount_b_to_swap = dec!(100);
+        let result = pool.swap_b_to_a(amount_b_to_swap);
+        assert!(result.is_ok());
+        let amount_a = result.unwrap().amount_out;
+
+        let (token_a, token_b) = pool.get_balances();
+        debug!(
+            "Final balances: ({}, {}), Amount of A received: {}",
+            token_a, token_b, amount_a
+        );
+
+        assert!(token_a < initial_balances.0, "Token A should decrease");
+        assert!(token_b > initial_balances.1, "Token B should increase");
+
+        // B is paid in at 1 / P and the price rises along the path, so the trade gets less than 1:1
+        assert!(
+            amount_a < amount_b_to_swap,
+            "Amount of A should be below the amount of B swapped"
+        );
+
+        // We verify that the changes in the balances are consistent
+        assert_eq!(
+            initial_balances.0 - token_a,
+            amount_a,
+            "Decrease in A should equal amount received"
+        );
+        assert_eq!(
+            token_b - initial_balances.1,
+            amount_b_to_swap,
+            "Increase in B should equal amount swapped"
+        );
+
+        // The average execution price, in B per A, lies between the spot prices before and after the swap
+        let swap_rate = amount_b_to_swap / amount_a;
+        assert!(
+            swap_rate > initial_price,
+            "Swap rate should exceed the initial price"
+        );
+        assert!(
+            swap_rate < pool.get_price(),
+            "Swap rate should be below the final price"
+        );
+    }
+
+    #[test]
+    fn test_swap_zero_amount() {
+        let mut pool = create_standard_pool();
+        assert!(pool.swap_a_to_b(dec!(0)).is_err());
+        assert!(pool.swap_b_to_a(dec!(0)).is_err());
+    }
+
+    #[test]
+    fn test_swap_negative_amount() {
+        let mut pool = create_standard_pool();
+        assert!(pool.swap_a_to_b(dec!(-100)).is_err());
+        assert!(pool.swap_b_to_a(dec!(-100)).is_err());
+    }
+
+    #[test]
+    fn test_swap_insufficient_liquidity() {
+        let mut pool = create_standard_pool();
+        assert!(pool.swap_a_to_b(dec!(1001)).is_err());
+        assert!(pool.swap_b_to_a(dec!(1001)).is_err());
+    }
+
+    #[test]
+    fn test_get_price() {
+        let pool = create_standard_pool();
+        assert!((pool.get_price() - dec!(1)).abs() < dec!(0.000001));
+    }
+
+    #[test]
+    fn test_price_changes_after_swap() {
+        let mut pool = create_standard_pool();
+        let initial_price = pool.get_price();
+        pool.swap_a_to_b(dec!(1)).unwrap();
+        let price_after_swap = pool.get_price();
+        assert!(price_after_swap < initial_price);
+    }
+
+    #[test]
+    fn test_multiple_operations() {
+        let mut pool = create_standard_pool();
+        let shares = pool.deposit("alice", dec!(500), dec!(500)).unwrap();
+        pool.swap_a_to_b(dec!(200)).unwrap();
+        pool.swap_b_to_a(dec!(100)).unwrap();
+        pool.withdraw("alice", shares * dec!(0.6)).unwrap();
+        let (token_a, token_b) = pool.get_balances();
+        assert!(token_a != dec!(1000) && token_b != dec!(1000));
+        assert!(token_a > dec!(0) && token_b > dec!(0));
+    }
+
+    #[test]
+    fn test_extreme_swap() {
+        // Selling A lowers the price along the path, so 999 A buy less than 999 B
+        let result = create_standard_pool().swap_a_to_b(dec!(999)).unwrap();
+        assert!(result.amount_out < dec!(999));
+        let mut pool = create_standard_pool();
+        let result = pool.swap_a_to_b(dec!(600));
+        assert!(result.is_ok());
+        let (token_a, token_b) = pool.get_balances();
+        assert_eq!(token_a, dec!(1600));
+        assert!(token_b > dec!(400));
+    }
+
+    #[test]
+    fn test_split_swap_matches_single_swap() {
+        let mut single = create_standard_pool();
+        let mut split = create_standard_pool();
+
+        let whole = single.swap_a_to_b(dec!(100)).unwrap();
+        let mut received = Decimal::ZERO;
+        for _ in 0..4 {
+            received += split.swap_a_to_b(dec!(25)).unwrap().amount_out;
+        }
+
+        assert!((whole.amount_out - received).abs() < dec!(0.000000001));
+        assert!((single.get_price() - split.get_price()).abs() < dec!(0.000000001));
+    }
+
+    #[test]
+    fn test_swap_reports_average_price() {
+        let mut pool = create_standard_pool();
+        let result = pool.swap_a_to_b(dec!(50)).unwrap();
+        assert_eq!(result.token_in, Token::A);
+        assert_eq!(result.amount_in, dec!(50));
+        assert_eq!(result.average_price, result.amount_out / dec!(50));
+
+        let result = pool.swap_b_to_a(dec!(50)).unwrap();
+        assert_eq!(result.token_in, Token::B);
+        assert_eq!(result.average_price, dec!(50) / result.amount_out);
+    }
+
+    #[test]
+    fn test_quote_exact_in_matches_swap() {
+        let mut pool = create_standard_pool();
+        let quote = pool.quote_exact_in(Token::A, dec!(50)).unwrap();
+        assert_eq!(pool.get_balances(), (dec!(1000), dec!(1000)));
+        assert_eq!(pool.swap_a_to_b(dec!(50)).unwrap(), quote);
+        assert_eq!(pool.get_price(), quote.price_after);
+
+        let quote = pool.quote_exact_in(Token::B, dec!(80)).unwrap();
+        assert_eq!(pool.swap_b_to_a(dec!(80)).unwrap(), quote);
+        assert_eq!(pool.get_price(), quote.price_after);
+    }
+
+    #[test]
+    fn test_quote_exact_out_delivers_amount() {
+        for p_ref in [dec!(0.001), dec!(1), dec!(1000)] {
+            // Ask for about 0.5% of the pool whichever token is cheaper
+            let amount_b = dec!(5) * p_ref.min(Decimal::ONE);
+            let amount_a = dec!(5) / p_ref.max(Decimal::ONE);
+            let mut pool = LiquidityPool::new(dec!(1000), dec!(1000), p_ref, dec!(0.5), dec!(1));
+
+            let quote = pool.quote_exact_out(Token::B, amount_b).unwrap();
+            assert_eq!(quote.token_in, Token::A);
+            assert_eq!(quote.amount_out, amount_b);
+            let result = pool.swap_a_to_b(quote.amount_in).unwrap();
+            assert!(result.amount_out >= amount_b);
+            assert_approx_eq!(result.amount_out, amount_b, amount_b * dec!(0.000001));
+
+            let quote = pool.quote_exact_out(Token::A, amount_a).unwrap();
+            assert_eq!(quote.token_in, Token::B);
+            let result = pool.swap_b_to_a(quote.amount_in).unwrap();
+            assert!(result.amount_out >= amount_a);
+            assert_approx_eq!(result.amount_out, amount_a, amount_a * dec!(0.000001));
+        }
+    }
+
+    #[test]
+    fn test_quote_price_impact_grows_with_size() {
+        let pool = create_standard_pool();
+        let small = pool.quote_exact_in(Token::A, dec!(1)).unwrap();
+        let large = pool.quote_exact_in(Token::A, dec!(100)).unwrap();
+        assert_eq!(small.price_before, dec!(1));
+        assert!(small.price_impact_bps < Decimal::ZERO);
+        assert!(large.price_impact_bps < small.price_impact_bps);
+        assert!(large.price_after < small.price_after);
+        assert!(small.price_after < small.price_before);
+        assert!(large.average_price < small.average_price);
+
+        // Buying A costs more than the price before the swap
+        let small = pool.quote_exact_in(Token::B, dec!(1)).unwrap();
+        let large = pool.quote_exact_in(Token::B, dec!(100)).unwrap();
+        assert!(small.price_impact_bps > Decimal::ZERO);
+        assert!(large.price_impact_bps > small.price_impact_bps);
+        assert!(large.average_price > small.average_price);
+        assert!(small.average_price > small.price_before);
+    }
+
+    #[test]
+    fn test_quote_invalid_amounts() {
+        let pool = create_standard_pool();
+        assert!(pool.quote_exact_in(Token::A, Decimal::ZERO).is_err());
+        assert!(pool.quote_exact_in(Token::B, dec!(1001)).is_err());
+        assert!(pool.quote_exact_out(Token::A, dec!(-1)).is_err());
+        assert!(pool.quote_exact_out(Token::B, dec!(1000)).is_err());
+
+        // Paying in more than the pool holds is rejected, as it is by the swaps
+        let pool = LiquidityPool::new(dec!(1000), dec!(1000), dec!(0.001), dec!(0.5), dec!(1));
+        assert!(pool.quote_exact_out(Token::B, dec!(5)).is_err());
+    }
+
+    #[test]
+    fn test_swap_exact_in_respects_min_out() {
+        let mut pool = create_standard_pool();
+        let quote = pool.quote_exact_in(Token::A, dec!(50)).unwrap();
+
+        let error = pool
+            .swap_exact_in(Token::A, dec!(50), quote.amount_out + dec!(0.001), None)
+            .unwrap_err();
+        assert_eq!(
+            error,
+            ArppError::SlippageExceeded {
+                token: Token::B,
+                amount: quote.amount_out,
+                limit: quote.amount_out + dec!(0.001),
+            }
+        );
+        assert_eq!(pool.get_balances(), (dec!(1000), dec!(1000)));
+
+        let result = pool
+            .swap_exact_in(Token::A, dec!(50), quote.amount_out, None)
+            .unwrap();
+        assert_eq!(result, quote);
+    }
+
+    #[test]
+    fn test_swap_exact_out_respects_max_in() {
+        let mut pool = create_standard_pool();
+        let quote = pool.quote_exact_out(Token::A, dec!(20)).unwrap();
+
+        let error = pool
+            .swap_exact_out(Token::A, dec!(20), quote.amount_in - dec!(0.001), None)
+            .unwrap_err();
+        assert_eq!(
+            error,
+            ArppError::SlippageExceeded {
+                token: Token::B,
+                amount: quote.amount_in,
+                limit: quote.amount_in - dec!(0.001),
+            }
+        );
+        assert_eq!(pool.get_balances(), (dec!(1000), dec!(1000)));
+
+        let result = pool
+            .swap_exact_out(Token::A, dec!(20), quote.amount_in, None)
+            .unwrap();
+        assert_eq!(result.amount_out, dec!(20));
+        assert_eq!(
+            pool.get_balances(),
+            (dec!(980), dec!(1000) + quote.amount_in)
+        );
+    }
+
+    #[test]
+    fn test_guarded_swaps_respect_deadline() {
+        let mut pool = create_standard_pool();
+        let expired = Some(Utc::now() - chrono::Duration::seconds(1));
+        let error = pool
+            .swap_exact_in(Token::B, dec!(10), Decimal::ZERO, expired)
+            .unwrap_err();
+        assert!(matches!(error, ArppError::DeadlineExpired { .. }));
+        let error = pool
+            .swap_exact_out(Token::B, dec!(10), dec!(1000), expired)
+            .unwrap_err();
+        assert!(matches!(error, ArppError::DeadlineExpired { .. }));
+        assert_eq!(pool.get_balances(), (dec!(1000), dec!(1000)));
+
+        let later = Some(Utc::now() + chrono::Duration::minutes(1));
+        assert!(pool
+            .swap_exact_in(Token::B, dec!(10), Decimal::ZERO, later)
+            .is_ok());
     }
 
     #[test]
-    fn test_new_pool_creation() {
+    fn test_fee_is_taken_from_input_and_kept_in_reserves() {
         let mut pool = create_standard_pool();
-        assert_eq!(pool.get_balances(), (dec!(1000), dec!(1000)));
-        assert_eq!(pool.get_price(), dec!(1));
+        pool.set_fee_bps(dec!(30)).unwrap();
+        let result = pool.swap_a_to_b(dec!(100)).unwrap();
+        assert_eq!(result.fee, dec!(0.3));
+        assert_eq!(result.amount_in, dec!(100));
+
+        // The pool pays out what a fee-free pool pays for the input net of the fee
+        let expected = create_standard_pool().swap_a_to_b(dec!(99.7)).unwrap();
+        assert_eq!(result.amount_out, expected.amount_out);
+
+        assert_eq!(pool.get_balances().0, dec!(1100));
+        assert_eq!(pool.get_fee_revenue(), (dec!(0.3), Decimal::ZERO));
+
+        let result = pool.swap_b_to_a(dec!(50)).unwrap();
+        assert_eq!(result.fee, dec!(0.15));
+        assert_eq!(pool.get_fee_revenue(), (dec!(0.3), dec!(0.15)));
     }
 
     #[test]
-    fn test_add_liquidity() {
+    fn test_fee_applies_to_exact_out_swaps() {
         let mut pool = create_standard_pool();
-        assert!(pool.add_liquidity(dec!(100), dec!(100)).is_ok());
-        assert_eq!(pool.get_balances(), (dec!(1100), dec!(1100)));
+        pool.set_fee_bps(dec!(100)).unwrap();
+        let quote = pool.quote_exact_out(Token::B, dec!(10)).unwrap();
+        assert_eq!(quote.fee, quote.amount_in * dec!(0.01));
+
+        let fee_free = create_standard_pool()
+            .quote_exact_out(Token::B, dec!(10))
+            .unwrap();
+        assert!(quote.amount_in - quote.fee >= fee_free.amount_in - dec!(0.000000001));
+
+        let result = pool
+            .swap_exact_out(Token::B, dec!(10), quote.amount_in, None)
+            .unwrap();
+        assert_eq!(result, quote);
+        assert_eq!(pool.get_fee_revenue(), (quote.fee, Decimal::ZERO));
     }
 
     #[test]
-    fn test_add_liquidity_neg_amount() {
+    fn test_fee_makes_round_trips_lose_value() {
         let mut pool = create_standard_pool();
-        assert!(pool.add_liquidity(dec!(-1), dec!(100)).is_err());
-        assert!(pool.add_liquidity(dec!(100), dec!(-1)).is_err());
+        pool.set_fee_bps(dec!(30)).unwrap();
+        let received_b = pool.swap_a_to_b(dec!(10)).unwrap().amount_out;
+        let returned_a = pool.swap_b_to_a(received_b).unwrap().amount_out;
+        // Roughly 30 bps are paid on each leg
+        assert_approx_eq!(returned_a, dec!(10) * dec!(0.994), dec!(0.001));
     }
 
     #[test]
-    fn test_add_liquidity_negative_amount() {
+    fn test_set_fee_bps_rejects_invalid_values() {
         let mut pool = create_standard_pool();
-        assert!(pool.add_liquidity(dec!(-100), dec!(100)).is_err());
-        assert!(pool.add_liquidity(dec!(100), dec!(-100)).is_err());
+        assert!(pool.set_fee_bps(dec!(-1)).is_err());
+        assert_eq!(
+            pool.set_fee_bps(dec!(10000)),
+            Err(ArppError::InvalidFee {
+                fee_bps: dec!(10000)
+            })
+        );
+        assert_eq!(pool.get_fee_bps(), Decimal::ZERO);
+        assert!(pool.set_fee_bps(dec!(5)).is_ok());
+        assert_eq!(pool.get_fee_bps(), dec!(5));
     }
 
     #[test]
-    fn test_remove_liquidity() {
+    fn test_dynamic_fee_penalises_moving_away_from_p_ref() {
         let mut pool = create_standard_pool();
-        assert!(pool.remove_liquidity(dec!(100), dec!(100)).is_ok());
-        assert_eq!(pool.get_balances(), (dec!(900), dec!(900)));
+        let dynamic_fee = DynamicFee::new(dec!(30), dec!(0.5), dec!(100)).unwrap();
+        pool.set_dynamic_fee(Some(dynamic_fee)).unwrap();
+
+        // Moving the price away pays more than the base fee, up to the cap
+        let away = pool.quote_exact_in(Token::A, dec!(10)).unwrap();
+        let fee_bps = away.fee / away.amount_in * BASIS_POINTS;
+        assert!(fee_bps > dec!(30) && fee_bps < dec!(100), "{}", fee_bps);
+        let large = pool.quote_exact_in(Token::A, dec!(200)).unwrap();
+        assert_eq!(large.fee, dec!(2));
+
+        // Pulling it back pays less
+        let quote = pool.quote_exact_in(Token::A, dec!(50)).unwrap();
+        assert_eq!(pool.swap_a_to_b(dec!(50)).unwrap(), quote);
+        let back = pool.quote_exact_in(Token::B, dec!(10)).unwrap();
+        assert!(back.fee < dec!(0.03));
+        let exact_out = pool.quote_exact_out(Token::A, dec!(10)).unwrap();
+        assert!(exact_out.fee < exact_out.amount_in * dec!(0.003));
+        assert_eq!(pool.swap_b_to_a(dec!(10)).unwrap(), back);
+
+        pool.set_dynamic_fee(None).unwrap();
+        assert_eq!(
+            pool.quote_exact_in(Token::A, dec!(10)).unwrap().fee,
+            Decimal::ZERO
+        );
+        assert!(pool
+            .set_dynamic_fee(Some(DynamicFee {
+                slope: dec!(-1),
+                ..dynamic_fee
+            }))
+            .is_err());
     }
 
     #[test]
-    fn test_remove_liquidity_zero_amount() {
+    fn test_round_trips_produce_no_free_tokens() {
+        for p_ref in [dec!(0.000001), dec!(0.001), dec!(1000), dec!(1000000)] {
+            // Trade about 1% of the pool whichever token is cheaper
+            let amount_a = dec!(10) / p_ref.max(Decimal::ONE);
+            let amount_b = dec!(10) * p_ref.min(Decimal::ONE);
+
+            let mut pool = LiquidityPool::new(dec!(1000), dec!(1000), p_ref, dec!(0.5), dec!(1));
+            let received_b = pool.swap_a_to_b(amount_a).unwrap().amount_out;
+            let returned_a = pool.swap_b_to_a(received_b).unwrap().amount_out;
+            assert!(
+                returned_a <= amount_a,
+                "A -> B -> A at p_ref {} returned {} for {}",
+                p_ref,
+                returned_a,
+                amount_a
+            );
+
+            let mut pool = LiquidityPool::new(dec!(1000), dec!(1000), p_ref, dec!(0.5), dec!(1));
+            let received_a = pool.swap_b_to_a(amount_b).unwrap().amount_out;
+            let returned_b = pool.swap_a_to_b(received_a).unwrap().amount_out;
+            assert!(
+                returned_b <= amount_b,
+                "B -> A -> B at p_ref {} returned {} for {}",
+                p_ref,
+                returned_b,
+                amount_b
+            );
+        }
+    }
+
+    #[test]
+    fn test_repeated_round_trips_do_not_drain_pool() {
+        let mut pool = LiquidityPool::new(dec!(1000), dec!(1000), dec!(1000), dec!(0.5), dec!(1));
+        let (initial_a, initial_b) = pool.get_balances();
+        let mut holdings_b = dec!(1);
+        for _ in 0..20 {
+            let received_a = pool.swap_b_to_a(holdings_b).unwrap().amount_out;
+            holdings_b = pool.swap_a_to_b(received_a).unwrap().amount_out;
+        }
+        let (final_a, final_b) = pool.get_balances();
+        assert!(holdings_b <= dec!(1));
+        assert!(final_a >= initial_a);
+        assert!(final_b >= initial_b);
+    }
+
+    #[test]
+    fn test_initial_provider_owns_pool() {
+        let pool = create_standard_pool();
+        assert_eq!(pool.get_total_shares(), dec!(2000));
+        let position = pool.get_position(INITIAL_PROVIDER).unwrap();
+        assert_eq!(position.shares, dec!(2000));
+        assert_eq!(position.share_of_pool, Decimal::ONE);
+        assert_eq!(
+            (position.amount_a, position.amount_b),
+            (dec!(1000), dec!(1000))
+        );
+    }
+
+    #[test]
+    fn test_deposit_mints_shares_in_proportion_to_value() {
         let mut pool = create_standard_pool();
-        assert!(pool.remove_liquidity(dec!(0), dec!(100)).is_err());
-        assert!(pool.remove_liquidity(dec!(100), dec!(0)).is_err());
+        let shares = pool.deposit("alice", dec!(100), dec!(100)).unwrap();
+        assert_eq!(shares, dec!(200));
+        assert_eq!(pool.get_total_shares(), dec!(2200));
+        assert_eq!(pool.get_balances(), (dec!(1100), dec!(1100)));
+
+        // A deposit that moves the ratio is valued at the price it moves the pool to,
+        // here 1 + atan(0.1) / 2, which raises the value of the pool's Token A
+        let shares = pool.deposit("bob", Decimal::ZERO, dec!(110)).unwrap();
+        assert_eq!(shares, dec!(107.325746858257));
+        assert!(shares < dec!(110));
+        assert_eq!(pool.get_position("bob").unwrap().shares, shares);
     }
 
     #[test]
-    fn test_remove_liquidity_negative_amount() {
+    fn test_one_sided_deposit_mints_no_free_shares() {
         let mut pool = create_standard_pool();
-        assert!(pool.remove_liquidity(dec!(-100), dec!(100)).is_err());
-        assert!(pool.remove_liquidity(dec!(100), dec!(-100)).is_err());
+        let swapped = pool.clone();
+        let shares = pool.deposit("alice", dec!(500), Decimal::ZERO).unwrap();
+        let (amount_a, amount_b) = pool.withdraw("alice", shares).unwrap();
+
+        // The round trip sold part of the Token A, for less than a swap pays
+        assert!(amount_a < dec!(500));
+        let quote = swapped
+            .quote_exact_in(Token::A, dec!(500) - amount_a)
+            .unwrap();
+        assert!(amount_b <= quote.amount_out);
     }
 
     #[test]
-    fn test_remove_liquidity_insufficient() {
+    fn test_late_depositor_pays_for_accrued_fees() {
         let mut pool = create_standard_pool();
-        assert!(pool.remove_liquidity(dec!(1001), dec!(100)).is_err());
-        assert!(pool.remove_liquidity(dec!(100), dec!(1001)).is_err());
+        pool.set_fee_bps(dec!(100)).unwrap();
+        let received_b = pool.swap_a_to_b(dec!(50)).unwrap().amount_out;
+        pool.swap_b_to_a(received_b).unwrap();
+
+        // Fees grew the pool, so the same value buys fewer shares
+        let shares = pool.deposit("alice", dec!(100), dec!(100)).unwrap();
+        assert!(shares < dec!(200));
     }
 
     #[test]
-    fn test_swap_a_to_b() {
-        setup_logger();
+    fn test_deposit_rejects_invalid_amounts() {
         let mut pool = create_standard_pool();
-        let initial_balances = pool.get_balances();
+        assert!(pool.deposit("alice", dec!(-1), dec!(100)).is_err());
+        assert!(pool.deposit("alice", Decimal::ZERO, Decimal::ZERO).is_err());
+        assert!(pool.get_position("alice").is_none());
+    }
 
-        let amount_a_to_swap = dec!(10);
-        let result = pool.swap_a_to_b(amount_a_to_swap);
-        assert!(result.is_ok());
-        let amount_b = result.unwrap();
+    #[test]
+    fn test_withdraw_pays_pro_rata_and_burns_shares() {
+        let mut pool = create_standard_pool();
+        let shares = pool.deposit("alice", dec!(100), dec!(100)).unwrap();
+        pool.swap_a_to_b(dec!(10)).unwrap();
+        let (balance_a, balance_b) = pool.get_balances();
 
-        let (token_a, token_b) = pool.get_balances();
-        debug!(
-            "Final balances: ({}, {}), Amount of B received: {}",
-            token_a, token_b, amount_b
-        );
+        let (amount_a, amount_b) = pool.withdraw("alice", shares / dec!(2)).unwrap();
+        assert_approx_eq!(amount_a, balance_a / dec!(22), dec!(1e-12));
+        assert_approx_eq!(amount_b, balance_b / dec!(22), dec!(1e-12));
+        assert_eq!(pool.get_position("alice").unwrap().shares, shares / dec!(2));
+        assert_eq!(pool.get_total_shares(), dec!(2100));
 
-        assert!(token_a > initial_balances.0, "Token A should increase");
-        assert!(token_b < initial_balances.1, "Token B should decrease");
+        pool.withdraw("alice", shares / dec!(2)).unwrap();
+        assert!(pool.get_position("alice").is_none());
+        assert_eq!(pool.get_total_shares(), dec!(2000));
+    }
 
-        // Verify that the amount of B received equals the amount of A swapped
+    #[test]
+    fn test_last_provider_withdraws_whole_pool() {
+        let mut pool = create_standard_pool();
+        pool.swap_a_to_b(dec!(10)).unwrap();
+        let balances = pool.get_balances();
         assert_eq!(
-            amount_b, amount_a_to_swap,
-            "Amount of B should equal the amount of A swapped"
+            pool.withdraw(INITIAL_PROVIDER, dec!(2000)).unwrap(),
+            balances
         );
+        assert_eq!(pool.get_balances(), (Decimal::ZERO, Decimal::ZERO));
+        assert_eq!(pool.get_total_shares(), Decimal::ZERO);
+    }
 
-        // Check that the changes in balances are consistent
+    #[test]
+    fn test_withdraw_rejects_invalid_requests() {
+        let mut pool = create_standard_pool();
         assert_eq!(
-            token_a - initial_balances.0,
-            amount_a_to_swap,
-            "Increase in A should equal amount swapped"
+            pool.withdraw("alice", dec!(1)).unwrap_err(),
+            ArppError::UnknownProvider {
+                provider: "alice".to_string()
+            }
         );
         assert_eq!(
-            initial_balances.1 - token_b,
-            amount_b,
-            "Decrease in B should equal amount received"
+            pool.withdraw(INITIAL_PROVIDER, dec!(2001)).unwrap_err(),
+            ArppError::InsufficientShares {
+                provider: INITIAL_PROVIDER.to_string(),
+                requested: dec!(2001),
+                owned: dec!(2000),
+            }
+        );
+        assert_eq!(
+            pool.withdraw(INITIAL_PROVIDER, Decimal::ZERO).unwrap_err(),
+            ArppError::InvalidAmount {
+                amount: Decimal::ZERO
+            }
         );
+        assert_eq!(pool.get_total_shares(), dec!(2000));
+    }
 
-        // Verify that the sum of tokens remains constant (with a small margin for rounding errors)
-        let initial_sum = initial_balances.0 + initial_balances.1;
-        let final_sum = token_a + token_b;
-        assert!(
-            (initial_sum - final_sum).abs() < dec!(0.000001),
-            "Total token amount should remain constant"
+    #[test]
+    fn test_update_p_ref_follows_oracle() {
+        let mut pool = create_standard_pool();
+        let mut oracle = ReplayOracle::new(vec![dec!(1.2), dec!(-1)]);
+        pool.update_p_ref(&mut oracle).unwrap();
+        assert_eq!(pool.get_p_ref(), dec!(1.2));
+
+        // Non-positive prices are rejected and an exhausted oracle keeps p_ref
+        assert_eq!(
+            pool.update_p_ref(&mut oracle),
+            Err(ArppError::InvalidPrice { price: dec!(-1) })
         );
+        pool.update_p_ref(&mut oracle).unwrap();
+        assert_eq!(pool.get_p_ref(), dec!(1.2));
+    }
 
-        let swap_rate = amount_b / amount_a_to_swap;
-        debug!("Swap rate (B/A): {}", swap_rate);
-        assert_eq!(swap_rate, dec!(1), "Swap rate should be 1:1");
+    #[test]
+    fn test_update_p_ref_with_twap_of_pool_price() {
+        let mut pool = create_standard_pool();
+        let mut oracle = TwapOracle::new(10);
+        pool.swap_a_to_b(dec!(100)).unwrap();
+        let price = pool.get_price();
+        pool.update_p_ref(&mut oracle).unwrap();
+        assert_eq!(pool.get_p_ref(), price);
     }
 
     #[test]
-    fn test_swap_b_to_a() {
+    fn test_stale_oracle_price_is_rejected() {
         let mut pool = create_standard_pool();
-        let initial_balances = pool.get_balances();
-        debug!("Initial balances: {:?}", initial_balances);
+        pool.set_oracle_guards(OracleGuards {
+            max_age: Some(OracleGuard::new(Duration::minutes(5), GuardPolicy::Clamp)),
+            ..OracleGuards::default()
+        });
+        let recorded = Utc::now() - Duration::minutes(10);
+        let mut oracle = ReplayOracle::with_timestamps(vec![OraclePrice::new(dec!(1.1), recorded)]);
+
+        let error = pool.update_p_ref(&mut oracle).unwrap_err();
+        assert!(matches!(error, ArppError::OracleStale { .. }));
+        assert_eq!(pool.get_p_ref(), dec!(1));
+        // A stale price cannot be clamped
+        assert_eq!(pool.get_oracle_incidents()[0].policy, GuardPolicy::Reject);
+        assert!(!pool.is_paused());
+    }
 
-        let amount_b_to_swap = dec!(100);
-        let result = pool.swap_b_to_a(amount_b_to_swap);
-        assert!(result.is_ok());
-        let amount_a = result.unwrap();
+    #[test]
+    fn test_p_ref_goes_stale_without_updates() {
+        let mut pool = create_standard_pool();
+        let recorded = Utc::now() - Duration::minutes(10);
+        let mut oracle = ReplayOracle::with_timestamps(vec![OraclePrice::new(dec!(1.1), recorded)]);
+        pool.update_p_ref(&mut oracle).unwrap();
+
+        pool.set_oracle_guards(OracleGuards {
+            max_age: Some(OracleGuard::new(Duration::minutes(5), GuardPolicy::Pause)),
+            ..OracleGuards::default()
+        });
+        let error = pool.update_p_ref(&mut oracle).unwrap_err();
+        assert!(matches!(error, ArppError::OracleStale { .. }));
+        assert!(pool.is_paused());
+        assert_eq!(pool.swap_a_to_b(dec!(1)), Err(ArppError::SwapsPaused));
+    }
 
-        let (token_a, token_b) = pool.get_balances();
-        debug!(
-            "Final balances: ({}, {}), Amount of A received: {}",
-            token_a, token_b, amount_a
+    #[test]
+    fn test_oracle_jump_is_clamped() {
+        let mut pool = create_standard_pool();
+        pool.set_oracle_guards(OracleGuards {
+            max_jump_bps: Some(OracleGuard::new(dec!(1000), GuardPolicy::Clamp)),
+            ..OracleGuards::default()
+        });
+        pool.update_p_ref(&mut ConstantOracle::new(dec!(2)))
+            .unwrap();
+        assert_eq!(pool.get_p_ref(), dec!(1.1));
+        pool.update_p_ref(&mut ConstantOracle::new(dec!(0.5)))
+            .unwrap();
+        assert_eq!(pool.get_p_ref(), dec!(0.99));
+
+        let incidents = pool.get_oracle_incidents();
+        assert_eq!(incidents.len(), 2);
+        assert_eq!(incidents[0].policy, GuardPolicy::Clamp);
+        assert_eq!(
+            incidents[0].error,
+            ArppError::OracleJump {
+                p_ref: dec!(1),
+                price: dec!(2),
+                max_jump_bps: dec!(1000),
+            }
         );
+    }
 
-        assert!(token_a < initial_balances.0, "Token A should decrease");
-        assert!(token_b > initial_balances.1, "Token B should increase");
+    #[test]
+    fn test_oracle_jump_is_rejected() {
+        let mut pool = create_standard_pool();
+        pool.set_oracle_guards(OracleGuards {
+            max_jump_bps: Some(OracleGuard::new(dec!(1000), GuardPolicy::Reject)),
+            ..OracleGuards::default()
+        });
+        assert!(pool
+            .update_p_ref(&mut ConstantOracle::new(dec!(2)))
+            .is_err());
+        assert_eq!(pool.get_p_ref(), dec!(1));
+        assert!(!pool.is_paused());
+        pool.update_p_ref(&mut ConstantOracle::new(dec!(1.05)))
+            .unwrap();
+        assert_eq!(pool.get_p_ref(), dec!(1.05));
+    }
 
-        // We verify that the amount of A received is equal to the amount of B delivered.
+    #[test]
+    fn test_twap_deviation_pauses_swaps_until_next_update() {
+        let mut pool = create_standard_pool();
+        pool.set_oracle_guards(OracleGuards {
+            max_deviation_bps: Some(OracleGuard::new(dec!(500), GuardPolicy::Pause)),
+            twap_window: 5,
+            ..OracleGuards::default()
+        });
+        let error = pool
+            .update_p_ref(&mut ConstantOracle::new(dec!(1.2)))
+            .unwrap_err();
+        assert!(matches!(error, ArppError::OracleDeviation { .. }));
+        assert!(pool.is_paused());
         assert_eq!(
-            amount_a, amount_b_to_swap,
-            "Amount of A should equal the amount of B swapped"
+            pool.swap_exact_in(Token::A, dec!(1), Decimal::ZERO, None),
+            Err(ArppError::SwapsPaused)
         );
+        assert_eq!(pool.get_balances(), (dec!(1000), dec!(1000)));
 
-        // We verify that the changes in the balances are consistent
-        assert_eq!(
-            initial_balances.0 - token_a,
-            amount_a,
-            "Decrease in A should equal amount received"
-        );
+        pool.update_p_ref(&mut ConstantOracle::new(dec!(1.01)))
+            .unwrap();
+        assert!(!pool.is_paused());
+        assert!(pool.swap_a_to_b(dec!(1)).is_ok());
+    }
+
+    #[test]
+    fn test_ramp_moves_parameter_without_price_jump() {
+        let mut pool = create_standard_pool();
+        pool.swap_a_to_b(dec!(200)).unwrap();
+        pool.schedule_ramp(RampParameter::Alpha, dec!(0.25), RampLength::Steps(4))
+            .unwrap();
         assert_eq!(
-            token_b - initial_balances.1,
-            amount_b_to_swap,
-            "Increase in B should equal amount swapped"
+            pool.schedule_ramp(RampParameter::FeeBps, dec!(30), RampLength::Steps(1)),
+            Err(ArppError::RampInProgress {
+                parameter: RampParameter::Alpha
+            })
         );
 
-        // We verify that the sum of the tokens remains constant (with a small margin of error for rounding).
-        let initial_sum = initial_balances.0 + initial_balances.1;
-        let final_sum = token_a + token_b;
+        let mut oracle = ConstantOracle::new(dec!(1));
+        let mut prices = vec![pool.get_price()];
+        for alpha in [dec!(0.4375), dec!(0.375), dec!(0.3125), dec!(0.25)] {
+            pool.update_p_ref(&mut oracle).unwrap();
+            assert_eq!(pool.get_alpha(), alpha);
+            prices.push(pool.get_price());
+        }
+        // The price moves in four equal steps rather than at once
+        for step in prices.windows(2) {
+            assert_approx_eq!(
+                step[0] - step[1],
+                (prices[0] - prices[4]) / dec!(4),
+                dec!(1e-20)
+            );
+        }
+        assert_eq!(pool.get_ramp(), None);
+
+        // The fee follows the clock and stops where it is
+        pool.schedule_ramp(
+            RampParameter::FeeBps,
+            dec!(50),
+            RampLength::Duration(Duration::hours(1)),
+        )
+        .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        pool.swap_a_to_b(dec!(1)).unwrap();
+        pool.stop_ramp();
+        assert!(Decimal::ZERO < pool.get_fee_bps() && pool.get_fee_bps() < dec!(50));
+        assert_eq!(pool.get_ramp(), None);
+    }
+
+    #[test]
+    fn test_quote_matches_swap_during_ramp() {
+        for (parameter, target) in [
+            (RampParameter::Alpha, dec!(0.25)),
+            (RampParameter::FeeBps, dec!(50)),
+        ] {
+            let mut pool = create_standard_pool();
+            let before = pool.quote_exact_in(Token::A, dec!(50)).unwrap();
+            let start = Utc::now() - Duration::minutes(30);
+            pool.schedule_ramp_at(
+                parameter,
+                target,
+                RampLength::Duration(Duration::hours(1)),
+                start,
+            )
+            .unwrap();
+            // Halfway through, the quote already prices with the ramped parameter
+            assert_ne!(pool.quote_exact_in(Token::A, dec!(50)).unwrap(), before);
+
+            let now = start + Duration::minutes(30);
+            let (parameters, fee_bps) = pool.parameters_at(now);
+            match parameter {
+                RampParameter::Alpha => assert_eq!(parameters.alpha_up, dec!(0.375)),
+                _ => assert_eq!(fee_bps, dec!(25)),
+            }
+            let quote_in = pool.quote_exact_in_at(Token::A, dec!(50), now).unwrap();
+            let quote_out = pool.quote_exact_out_at(Token::A, dec!(20), now).unwrap();
+            let mut other = pool.clone();
+            assert_eq!(
+                pool.swap_exact_in_at(Token::A, dec!(50), Decimal::ZERO, None, now),
+                Ok(quote_in)
+            );
+            assert_eq!(
+                other.swap_exact_out_at(Token::A, dec!(20), dec!(100), None, now),
+                Ok(quote_out)
+            );
+        }
+    }
+
+    #[test]
+    fn test_ramp_enforces_bounds_and_safety() {
+        let mut pool = create_standard_pool();
+        assert!(matches!(
+            pool.schedule_ramp(RampParameter::Beta, dec!(11), RampLength::Steps(10)),
+            Err(ArppError::RampOutOfBounds { .. })
+        ));
+        assert!(matches!(
+            pool.schedule_ramp(RampParameter::FeeBps, dec!(101), RampLength::Steps(10)),
+            Err(ArppError::RampOutOfBounds { .. })
+        ));
+        // alpha = 1 lets the price of a pool without Token B fall below zero
+        assert!(matches!(
+            pool.schedule_ramp(RampParameter::Alpha, dec!(1.5), RampLength::Steps(10)),
+            Err(ArppError::UnsafeParameters { .. })
+        ));
+        // A ramp of no length applies at once
+        pool.schedule_ramp(RampParameter::Beta, dec!(2), RampLength::Steps(0))
+            .unwrap();
+        assert_eq!((pool.get_beta(), pool.get_ramp()), (dec!(2), None));
+    }
+
+    #[test]
+    fn test_try_new_rejects_unsafe_parameters() {
         assert!(
-            (initial_sum - final_sum).abs() < dec!(0.000001),
-            "Total token amount should remain constant"
+            LiquidityPool::try_new(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1)).is_ok()
         );
+        assert!(matches!(
+            LiquidityPool::try_new(dec!(1000), dec!(1000), dec!(1), dec!(0.99), dec!(100)),
+            Err(ArppError::UnsafeParameters { .. })
+        ));
+        assert!(matches!(
+            LiquidityPool::try_new(dec!(1000), dec!(1000), dec!(-1), dec!(0.5), dec!(1)),
+            Err(ArppError::InvalidParameter { .. })
+        ));
+
+        // A curve with a higher limit would make safe parameters unsafe, so it is rejected
+        let mut pool =
+            LiquidityPool::try_new(dec!(1000), dec!(1000), dec!(1), dec!(0.6), dec!(5)).unwrap();
+        assert_eq!(pool.check_parameters(), Ok(vec![]));
+        assert!(matches!(
+            pool.set_pricing_curve(LogisticCurve),
+            Err(ArppError::UnsafeParameters { low, .. }) if low < Decimal::ZERO
+        ));
+        assert_eq!(pool.get_pricing_curve().spec(), Some(CurveSpec::Arctan));
+        assert_eq!(pool.check_parameters(), Ok(vec![]));
     }
 
     #[test]
-    fn test_swap_zero_amount() {
+    fn test_price_range_bounds_reachable_prices() {
+        let pool = create_standard_pool();
+        let (low, high) = pool.get_price_range();
+        // 1 - atan(1) / 2 and 1 + pi / 4
+        assert_approx_eq!(low, dec!(0.6073009183012758451921695771), dec!(1e-27));
+        assert_approx_eq!(high, dec!(1.7853981633974483096156608458), dec!(1e-27));
+        let skewed = LiquidityPool::new(dec!(1000000), dec!(1), dec!(1), dec!(0.5), dec!(1));
+        assert!(skewed.get_price() < high);
+        let skewed = LiquidityPool::new(dec!(1), dec!(1000000), dec!(1), dec!(0.5), dec!(1));
+        assert!(skewed.get_price() > low);
+    }
+
+    #[test]
+    fn test_sensitivities_follow_pool_state() {
         let mut pool = create_standard_pool();
-        assert!(pool.swap_a_to_b(dec!(0)).is_err());
-        assert!(pool.swap_b_to_a(dec!(0)).is_err());
+        assert_eq!(pool.get_sensitivities().d_ratio, dec!(0.5));
+
+        pool.set_pricing_curve(TanhCurve).unwrap();
+        pool.swap_a_to_b(dec!(100)).unwrap();
+        let (token_a, token_b) = pool.get_balances();
+        let expected =
+            sensitivities_with_curve(&TanhCurve, dec!(1), dec!(0.5), dec!(1), token_b / token_a);
+        assert_eq!(pool.get_sensitivities(), expected);
+        assert!(expected.d_ratio < dec!(0.5));
+        assert!(expected.curvature > Decimal::ZERO);
     }
 
     #[test]
-    fn test_swap_negative_amount() {
+    fn test_amount_to_reach_price_lands_on_target() {
         let mut pool = create_standard_pool();
-        assert!(pool.swap_a_to_b(dec!(-100)).is_err());
-        assert!(pool.swap_b_to_a(dec!(-100)).is_err());
+        pool.set_fee_bps(dec!(30)).unwrap();
+        for target in [dec!(1.05), dec!(0.9), dec!(1.3)] {
+            let (token_in, amount_in) = pool.amount_to_reach_price(target).unwrap();
+            assert_eq!(
+                token_in,
+                if target < pool.get_price() {
+                    Token::A
+                } else {
+                    Token::B
+                }
+            );
+            pool.swap_exact_in(token_in, amount_in, Decimal::ZERO, None)
+                .unwrap();
+            assert_approx_eq!(pool.get_price(), target, dec!(0.000000001));
+        }
+        assert_eq!(
+            pool.amount_to_reach_price(pool.get_price()).unwrap().1,
+            Decimal::ZERO
+        );
+
+        pool.set_dynamic_fee(Some(DynamicFee::new(dec!(30), dec!(1), dec!(100)).unwrap()))
+            .unwrap();
+        let (token_in, amount_in) = pool.amount_to_reach_price(dec!(1.2)).unwrap();
+        pool.swap_exact_in(token_in, amount_in, Decimal::ZERO, None)
+            .unwrap();
+        assert_approx_eq!(pool.get_price(), dec!(1.2), dec!(0.000000001));
     }
 
     #[test]
-    fn test_swap_insufficient_liquidity() {
+    fn test_amount_to_reach_price_reports_unreachable_targets() {
+        let pool = create_standard_pool();
+        assert!(matches!(
+            pool.amount_to_reach_price(dec!(2)),
+            Err(ArppError::UnreachablePrice { price, .. }) if price == dec!(2)
+        ));
+        assert!(matches!(
+            pool.amount_to_reach_price(dec!(0.5)),
+            Err(ArppError::UnreachablePrice { .. })
+        ));
+    }
+
+    #[test]
+    fn test_circuit_breaker_rejects_swaps_leaving_band() {
         let mut pool = create_standard_pool();
-        assert!(pool.swap_a_to_b(dec!(1001)).is_err());
-        assert!(pool.swap_b_to_a(dec!(1001)).is_err());
+        pool.set_circuit_breaker(Some(CircuitBreaker::new(
+            PriceBand::Percent(dec!(1)),
+            BreakerPolicy::Reject,
+        )));
+        assert!(pool.swap_a_to_b(dec!(5)).is_ok());
+        let balances = pool.get_balances();
+        assert!(matches!(
+            pool.swap_a_to_b(dec!(100)),
+            Err(ArppError::PriceOutOfBand { low, high, .. })
+                if low == dec!(0.99) && high == dec!(1.01)
+        ));
+        assert_eq!(pool.get_balances(), balances);
+        assert_eq!(pool.get_breaker_stats().rejected_swaps, 1);
+
+        // Outside the band, swaps bringing the price back are still allowed
+        let mut pool = LiquidityPool::new(dec!(1100), dec!(1000), dec!(1), dec!(0.5), dec!(1));
+        pool.set_circuit_breaker(Some(CircuitBreaker::new(
+            PriceBand::Percent(dec!(1)),
+            BreakerPolicy::Reject,
+        )));
+        assert!(pool.get_price() < dec!(0.99));
+        assert!(pool.swap_b_to_a(dec!(10)).is_ok());
+        assert!(pool.swap_a_to_b(dec!(1)).is_err());
     }
 
     #[test]
-    fn test_get_price() {
+    fn test_circuit_breaker_caps_swaps_at_band_edge() {
         let mut pool = create_standard_pool();
-        assert!((pool.get_price() - dec!(1)).abs() < dec!(0.000001));
+        pool.set_fee_bps(dec!(30)).unwrap();
+        pool.set_circuit_breaker(Some(CircuitBreaker::new(
+            PriceBand::Absolute(dec!(0.02)),
+            BreakerPolicy::Cap,
+        )));
+        let result = pool.swap_a_to_b(dec!(100)).unwrap();
+        assert!(result.amount_in < dec!(100));
+        assert!(result.price_after >= dec!(0.98));
+        assert_approx_eq!(pool.get_price(), dec!(0.98), dec!(0.000000001));
+
+        // At the edge nothing is left to execute
+        assert!(matches!(
+            pool.swap_a_to_b(dec!(1)),
+            Err(ArppError::PriceOutOfBand { .. })
+        ));
+        let result = pool
+            .swap_exact_out(Token::A, dec!(100), dec!(1000), None)
+            .unwrap();
+        assert!(result.amount_out < dec!(100));
+        assert_approx_eq!(pool.get_price(), dec!(1.02), dec!(0.000000001));
+
+        let stats = pool.get_breaker_stats();
+        assert_eq!((stats.capped_swaps, stats.rejected_swaps), (2, 1));
     }
 
     #[test]
-    fn test_price_changes_after_swap() {
+    fn test_circuit_breaker_cooldown_in_steps() {
         let mut pool = create_standard_pool();
-        let initial_price = pool.get_price();
-        pool.swap_a_to_b(dec!(1)).unwrap();
-        let price_after_swap = pool.get_price();
-        assert!(price_after_swap > initial_price);
+        pool.set_circuit_breaker(Some(CircuitBreaker::new(
+            PriceBand::Percent(dec!(1)),
+            BreakerPolicy::Cooldown(Cooldown::Steps(2)),
+        )));
+        assert!(pool.swap_a_to_b(dec!(100)).is_err());
+        assert!(pool.is_halted());
+        assert_eq!(pool.swap_a_to_b(dec!(1)), Err(ArppError::SwapsHalted));
+
+        let mut oracle = ConstantOracle::new(dec!(1));
+        pool.update_p_ref(&mut oracle).unwrap();
+        assert_eq!(pool.swap_b_to_a(dec!(1)), Err(ArppError::SwapsHalted));
+        pool.update_p_ref(&mut oracle).unwrap();
+        assert!(!pool.is_halted());
+        assert!(pool.swap_b_to_a(dec!(1)).is_ok());
+
+        let stats = pool.get_breaker_stats();
+        assert_eq!((stats.trips, stats.halted_swaps), (1, 2));
+        assert_eq!(stats.active_steps, 2);
     }
 
     #[test]
-    fn test_multiple_operations() {
+    fn test_circuit_breaker_cooldown_in_time() {
         let mut pool = create_standard_pool();
-        pool.add_liquidity(dec!(500), dec!(500)).unwrap();
-        pool.swap_a_to_b(dec!(200)).unwrap();
-        pool.swap_b_to_a(dec!(100)).unwrap();
-        pool.remove_liquidity(dec!(300), dec!(300)).unwrap();
-        let (token_a, token_b) = pool.get_balances();
-        assert!(token_a != dec!(1000) && token_b != dec!(1000));
-        assert!(token_a > dec!(0) && token_b > dec!(0));
+        pool.set_circuit_breaker(Some(CircuitBreaker::new(
+            PriceBand::Percent(dec!(1)),
+            BreakerPolicy::Cooldown(Cooldown::Duration(Duration::seconds(60))),
+        )));
+        let tripped = Utc::now();
+        let swap_at = |pool: &mut LiquidityPool, seconds: i64| {
+            let now = tripped + Duration::seconds(seconds);
+            pool.swap_exact_in_at(Token::A, dec!(1), Decimal::ZERO, None, now)
+        };
+        assert!(pool
+            .swap_exact_in_at(Token::A, dec!(100), Decimal::ZERO, None, tripped)
+            .is_err());
+        assert_eq!(swap_at(&mut pool, 59), Err(ArppError::SwapsHalted));
+        assert!(swap_at(&mut pool, 60).is_ok());
+        assert_eq!(pool.get_breaker_stats().active_time, Duration::seconds(60));
     }
 
     #[test]
-    fn test_extreme_swap() {
+    fn test_get_positions_is_sorted_by_provider() {
         let mut pool = create_standard_pool();
-        let result = pool.swap_a_to_b(dec!(999));
-        assert!(result.is_ok());
-        let (token_a, token_b) = pool.get_balances();
-        assert!(token_a > dec!(1990));
-        assert!(token_b < dec!(10));
+        pool.deposit("zoe", dec!(10), dec!(10)).unwrap();
+        pool.deposit("alice", dec!(10), dec!(10)).unwrap();
+        let providers: Vec<String> = pool
+            .get_positions()
+            .into_iter()
+            .map(|(provider, _)| provider)
+            .collect();
+        assert_eq!(providers, vec!["alice", INITIAL_PROVIDER, "zoe"]);
+    }
+
+    #[test]
+    fn test_restored_pool_trades_exactly_as_original() {
+        let mut pool = create_standard_pool();
+        pool.set_fee_bps(dec!(30)).unwrap();
+        pool.set_pricing_curve(crate::arpp::curve::TanhCurve)
+            .unwrap();
+        pool.deposit("alice", dec!(10), dec!(12.5)).unwrap();
+        pool.swap_a_to_b(dec!(25)).unwrap();
+        pool.update_p_ref(&mut ConstantOracle::new(dec!(1.05)))
+            .unwrap();
+
+        let json = pool.snapshot().unwrap().to_json().unwrap();
+        let mut restored = LiquidityPool::restore(Snapshot::from_json(&json).unwrap()).unwrap();
+        assert_eq!(restored.snapshot(), pool.snapshot());
+        assert_eq!(
+            restored.swap_b_to_a(dec!(40)).unwrap(),
+            pool.swap_b_to_a(dec!(40)).unwrap()
+        );
+        assert_eq!(
+            restored.withdraw("alice", dec!(5)),
+            pool.withdraw("alice", dec!(5))
+        );
+        assert_eq!(restored.snapshot(), pool.snapshot());
+    }
+
+    #[test]
+    fn test_restore_rejects_unsafe_curve() {
+        let pool =
+            LiquidityPool::try_new(dec!(1000), dec!(1000), dec!(1), dec!(0.6), dec!(5)).unwrap();
+        let mut snapshot = pool.snapshot().unwrap();
+        snapshot.state.curve = CurveSpec::Logistic;
+        assert!(matches!(
+            LiquidityPool::restore(snapshot),
+            Err(ArppError::UnsafeParameters { .. })
+        ));
+    }
+
+    #[test]
+    fn test_custom_curve_cannot_be_snapshotted() {
+        #[derive(Debug)]
+        struct Linear;
+        impl PricingCurve for Linear {
+            fn anchor(&self, x: Decimal) -> Decimal {
+                x
+            }
+
+            fn limit(&self) -> Decimal {
+                Decimal::MAX
+            }
+        }
+        let mut pool = create_standard_pool();
+        pool.set_pricing_curve(Linear).unwrap();
+        assert_eq!(pool.snapshot(), Err(ArppError::UnserializableCurve));
+    }
+
+    #[test]
+    fn test_pricing_curve_only_changes_price_away_from_balance() {
+        let mut arctan_pool = create_standard_pool();
+        let mut tanh_pool = create_standard_pool();
+        tanh_pool.set_pricing_curve(TanhCurve).unwrap();
+        assert_eq!(tanh_pool.get_price(), arctan_pool.get_price());
+
+        arctan_pool.swap_a_to_b(dec!(300)).unwrap();
+        tanh_pool.swap_a_to_b(dec!(300)).unwrap();
+        let arctan_price = arctan_pool.get_price();
+        let tanh_price = tanh_pool.get_price();
+        assert!(tanh_price < dec!(1) && arctan_price < tanh_price);
+    }
+
+    #[test]
+    fn test_asymmetric_pool_is_continuous_across_balance() {
+        let parameters = AsymmetricParameters::new(dec!(0.5), dec!(0.1), dec!(1), dec!(5));
+        let pool = || LiquidityPool::new_asymmetric(dec!(900), dec!(1000), dec!(1), parameters);
+
+        // A swap crossing balance matches the same swap split at balance
+        let mut single = pool();
+        let mut split = pool();
+        let whole = single.swap_a_to_b(dec!(200)).unwrap();
+        let mut received = split.swap_a_to_b(dec!(90)).unwrap().amount_out;
+        received += split.swap_a_to_b(dec!(110)).unwrap().amount_out;
+        assert!((whole.amount_out - received).abs() < dec!(0.000000001));
+        assert!((single.get_price() - split.get_price()).abs() < dec!(0.000000001));
+
+        // Tiny swaps either way from balance barely move the price
+        let balanced = LiquidityPool::new_asymmetric(dec!(1000), dec!(1000), dec!(1), parameters);
+        assert_eq!(balanced.get_price(), dec!(1));
+        for token_in in [Token::A, Token::B] {
+            let quote = balanced.quote_exact_in(token_in, dec!(0.000001)).unwrap();
+            assert_approx_eq!(quote.price_after, dec!(1), dec!(0.00000001));
+        }
+
+        // Each side trades as a symmetric pool with its own parameters
+        let mut up = LiquidityPool::new(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
+        let mut down = LiquidityPool::new(dec!(1000), dec!(1000), dec!(1), dec!(0.1), dec!(5));
+        let mut asymmetric = balanced.clone();
+        assert_eq!(
+            asymmetric.quote_exact_in(Token::B, dec!(50)),
+            up.quote_exact_in(Token::B, dec!(50))
+        );
+        assert_eq!(
+            asymmetric.swap_a_to_b(dec!(50)).unwrap(),
+            down.swap_a_to_b(dec!(50)).unwrap()
+        );
+        up.swap_b_to_a(dec!(50)).unwrap();
+        assert!(dec!(1) - asymmetric.get_price() < up.get_price() - dec!(1));
+    }
+
+    #[test]
+    fn test_asymmetric_pool_reaches_prices_on_both_sides() {
+        let parameters = AsymmetricParameters::new(dec!(0.5), dec!(0.1), dec!(1), dec!(5));
+        let pool =
+            LiquidityPool::try_new_asymmetric(dec!(1000), dec!(1000), dec!(1), parameters).unwrap();
+        assert_eq!(pool.get_parameters(), parameters);
+        let (low, high) = pool.get_price_range();
+        assert!(low > dec!(0.85) && high > dec!(1.78));
+
+        for target in [dec!(0.95), dec!(1.3)] {
+            let (token_in, amount) = pool.amount_to_reach_price(target).unwrap();
+            let quote = pool.quote_exact_in(token_in, amount).unwrap();
+            assert_approx_eq!(quote.price_after, target, dec!(0.000000001));
+        }
+        assert!(matches!(
+            pool.amount_to_reach_price(dec!(0.8)),
+            Err(ArppError::UnreachablePrice { .. })
+        ));
+
+        // The downside alone decides whether the price can fall to zero
+        let unsafe_down = AsymmetricParameters::new(dec!(0.5), dec!(0.9), dec!(1), dec!(100));
+        assert!(matches!(
+            LiquidityPool::try_new_asymmetric(dec!(1), dec!(1), dec!(1), unsafe_down),
+            Err(ArppError::UnsafeParameters { .. })
+        ));
+    }
+
+    #[test]
+    fn test_asymmetric_pool_keeps_downside_through_ramps_and_snapshots() {
+        let parameters = AsymmetricParameters::new(dec!(0.5), dec!(0.1), dec!(1), dec!(5));
+        let mut pool = LiquidityPool::new_asymmetric(dec!(1000), dec!(1000), dec!(1), parameters);
+        // Ramping the upside alone would put a kink at balance
+        assert_eq!(
+            pool.schedule_ramp(RampParameter::Alpha, dec!(0.25), RampLength::Steps(0)),
+            Err(ArppError::KinkAtBalance {
+                slope_up: dec!(0.25),
+                slope_down: dec!(0.5)
+            })
+        );
+        assert_eq!(pool.get_parameters(), parameters);
+        pool.swap_b_to_a(dec!(30)).unwrap();
+
+        let json = pool.snapshot().unwrap().to_json().unwrap();
+        let mut restored = LiquidityPool::restore(Snapshot::from_json(&json).unwrap()).unwrap();
+        assert_eq!(restored.get_parameters(), pool.get_parameters());
+        assert_eq!(
+            restored.swap_a_to_b(dec!(80)).unwrap(),
+            pool.swap_a_to_b(dec!(80)).unwrap()
+        );
+
+        // A symmetric pool ramps both sides together
+        let mut symmetric = create_standard_pool();
+        symmetric
+            .schedule_ramp(RampParameter::Beta, dec!(0.5), RampLength::Steps(0))
+            .unwrap();
+        assert!(symmetric.get_parameters().is_symmetric());
+    }
+
+    #[test]
+    fn test_swaps_round_trip_with_every_curve() {
+        let mut pools = vec![create_standard_pool(); 4];
+        pools[0].set_pricing_curve(TanhCurve).unwrap();
+        pools[1].set_pricing_curve(LogisticCurve).unwrap();
+        pools[2].set_pricing_curve(AlgebraicCurve).unwrap();
+        pools[3]
+            .set_pricing_curve(
+                PiecewiseLinearCurve::new(vec![(dec!(1), dec!(1)), (dec!(2), dec!(1.2))]).unwrap(),
+            )
+            .unwrap();
+        for mut pool in pools {
+            let price_before = pool.get_price();
+            let out = pool.swap_a_to_b(dec!(100)).unwrap().amount_out;
+            assert!(pool.get_price() < price_before);
+            let back = pool.swap_b_to_a(out).unwrap().amount_out;
+            assert!(
+                back <= dec!(100),
+                "{:?} returned {}",
+                pool.get_pricing_curve(),
+                back
+            );
+        }
     }
 }
 
@@ -520,30 +3072,30 @@ mod tests_liquidity_pool_bis {
 
     #[test]
     fn test_standard_pool() {
-        let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
+        let pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
         assert_eq!(pool.get_balances(), (dec!(1000), dec!(1000)));
         assert_eq!(pool.get_price(), dec!(1));
     }
 
     #[test]
     fn test_unbalanced_pool() {
-        let mut pool = create_custom_pool(dec!(500), dec!(2000), dec!(1), dec!(0.5), dec!(1));
+        let pool = create_custom_pool(dec!(500), dec!(2000), dec!(1), dec!(0.5), dec!(1));
         assert_eq!(pool.get_balances(), (dec!(500), dec!(2000)));
-        assert!(pool.get_price() < dec!(1));
+        assert!(pool.get_price() > dec!(1));
     }
 
     #[test]
     fn test_extreme_imbalance() {
-        let mut pool = create_custom_pool(dec!(1), dec!(1000000), dec!(1), dec!(0.5), dec!(1));
+        let pool = create_custom_pool(dec!(1), dec!(1000000), dec!(1), dec!(0.5), dec!(1));
         assert_eq!(pool.get_balances(), (dec!(1), dec!(1000000)));
 
         let price = pool.get_price();
         debug!("Price for extreme imbalance: {}", price);
 
-        // Instead of asserting a specific price, let's check if it's significantly higher than 1
+        // Instead of asserting a specific price, let's check if it's higher than 1, A being scarce
         assert!(
-            price < dec!(1),
-            "Price should be lower than 1 for extreme imbalance"
+            price > dec!(1),
+            "Price should be higher than 1 for extreme imbalance"
         );
         assert!(
             price < dec!(1000000),
@@ -571,7 +3123,7 @@ mod tests_liquidity_pool_bis {
         let result = pool.swap_a_to_b(amount_to_swap);
         assert!(result.is_ok());
 
-        let amount_received = result.unwrap();
+        let amount_received = result.unwrap().amount_out;
         debug!("Amount of A swapped: {}", amount_to_swap);
         debug!("Amount of B received: {}", amount_received);
 
@@ -581,14 +3133,11 @@ mod tests_liquidity_pool_bis {
         let exchange_rate = amount_received / amount_to_swap;
         debug!("Effective exchange rate: {}", exchange_rate);
 
-        // Assert that the exchange rate is close to 1, but slightly less
-        assert!(
-            exchange_rate <= dec!(1),
-            "Exchange rate should not exceed 1"
-        );
+        // Assert that the exchange rate is below 1, but above the final price
+        assert!(exchange_rate < dec!(1), "Exchange rate should be below 1");
         assert!(
-            exchange_rate > dec!(0.9),
-            "Exchange rate should not be too low"
+            exchange_rate > pool.get_price(),
+            "Exchange rate should not fall below the final price"
         );
 
         // Print pool balances after swap
@@ -601,7 +3150,7 @@ mod tests_liquidity_pool_bis {
         let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.01), dec!(1));
         let result = pool.swap_a_to_b(dec!(100));
         assert!(result.is_ok());
-        assert!(result.unwrap() > dec!(99));
+        assert!(result.unwrap().amount_out > dec!(99));
     }
 
     #[test]
@@ -615,7 +3164,7 @@ mod tests_liquidity_pool_bis {
         let result = pool.swap_a_to_b(amount_to_swap);
         assert!(result.is_ok());
 
-        let amount_received = result.unwrap();
+        let amount_received = result.unwrap().amount_out;
         debug!("Amount of A swapped: {}", amount_to_swap);
         debug!("Amount of B received: {}", amount_received);
 
@@ -626,15 +3175,12 @@ mod tests_liquidity_pool_bis {
         let exchange_rate = amount_received / amount_to_swap;
         debug!("Effective exchange rate: {}", exchange_rate);
 
-        // Assert that the exchange rate is close to 1, but may be slightly different
+        // With a steep curve the price saturates quickly, but never below p_ref * (1 - alpha * pi / 2)
+        assert!(exchange_rate < dec!(1), "Exchange rate should be below 1");
         assert!(
-            exchange_rate > dec!(0.9),
+            exchange_rate > dec!(0.2),
             "Exchange rate should not be too low"
         );
-        assert!(
-            exchange_rate < dec!(1.1),
-            "Exchange rate should not be too high"
-        );
 
         // Assert that the price has changed due to the high beta
         assert!(
@@ -648,18 +3194,20 @@ mod tests_liquidity_pool_bis {
         let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(0.01));
         let result = pool.swap_a_to_b(dec!(100));
         assert!(result.is_ok());
-        assert!((result.unwrap() - dec!(100)).abs() < dec!(0.01));
+        let amount_out = result.unwrap().amount_out;
+        assert!(amount_out < dec!(100));
+        assert!(dec!(100) - amount_out < dec!(0.1));
     }
 
     #[test]
     fn test_high_p_ref() {
-        let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1000), dec!(0.5), dec!(1));
+        let pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1000), dec!(0.5), dec!(1));
         assert_eq!(pool.get_price(), dec!(1000));
     }
 
     #[test]
     fn test_low_p_ref() {
-        let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(0.001), dec!(0.5), dec!(1));
+        let pool = create_custom_pool(dec!(1000), dec!(1000), dec!(0.001), dec!(0.5), dec!(1));
         assert_eq!(pool.get_price(), dec!(0.001));
     }
 
@@ -679,29 +3227,35 @@ mod tests_liquidity_pool_bis {
     }
 
     #[test]
-    fn test_add_large_liquidity() {
+    fn test_deposit_large_liquidity() {
         let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
-        assert!(pool.add_liquidity(dec!(1000000), dec!(1000000)).is_ok());
+        assert!(pool.deposit("alice", dec!(1000000), dec!(1000000)).is_ok());
     }
 
     #[test]
-    fn test_remove_large_liquidity() {
+    fn test_withdraw_large_liquidity() {
         let mut pool =
             create_custom_pool(dec!(1000000), dec!(1000000), dec!(1), dec!(0.5), dec!(1));
-        assert!(pool.remove_liquidity(dec!(999999), dec!(999999)).is_ok());
+        assert_eq!(
+            pool.withdraw(INITIAL_PROVIDER, dec!(1999998)).unwrap(),
+            (dec!(999999), dec!(999999))
+        );
+        assert_eq!(pool.get_balances(), (dec!(1), dec!(1)));
     }
 
     #[test]
+    #[allow(deprecated)]
     fn test_remove_all_liquidity() {
         let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
         assert!(pool.remove_liquidity(dec!(1000), dec!(1000)).is_ok());
         assert_eq!(pool.get_balances(), (dec!(0), dec!(0)));
+        assert_eq!(pool.get_total_shares(), Decimal::ZERO);
     }
 
     #[test]
-    fn test_swap_after_large_addition() {
+    fn test_swap_after_large_deposit() {
         let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
-        assert!(pool.add_liquidity(dec!(1000000), dec!(1000000)).is_ok());
+        assert!(pool.deposit("alice", dec!(1000000), dec!(1000000)).is_ok());
         let result = pool.swap_a_to_b(dec!(100));
         assert!(result.is_ok());
     }
@@ -717,28 +3271,33 @@ mod tests_liquidity_pool_bis {
 
     #[test]
     fn test_extreme_alpha_beta_combination() {
-        let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.99), dec!(100));
+        let pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.99), dec!(100));
         assert!(pool.get_price() > dec!(0.9) && pool.get_price() < dec!(1.1));
     }
 
     #[test]
-    fn test_uneven_liquidity_addition() {
+    fn test_uneven_deposit() {
         let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
-        assert!(pool.add_liquidity(dec!(500), dec!(1000)).is_ok());
+        assert!(pool.deposit("alice", dec!(500), dec!(1000)).is_ok());
         assert_eq!(pool.get_balances(), (dec!(1500), dec!(2000)));
     }
 
     #[test]
+    #[allow(deprecated)]
     fn test_uneven_liquidity_removal() {
+        // Liquidity leaves pro rata, so the larger part sets the shares burnt
         let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
         assert!(pool.remove_liquidity(dec!(300), dec!(500)).is_ok());
-        assert_eq!(pool.get_balances(), (dec!(700), dec!(500)));
+        assert_eq!(pool.get_balances(), (dec!(500), dec!(500)));
     }
 
     #[test]
     fn test_swap_exact_remaining_balance() {
+        // The price moves against each swap, so the pool keeps part of the other token
+        let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
+        assert!(pool.swap_a_to_b(dec!(1000)).unwrap().amount_out < dec!(1000));
         let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
-        assert!(pool.swap_a_to_b(dec!(1000)).is_ok());
+        assert!(pool.swap_b_to_a(dec!(1000)).unwrap().amount_out < dec!(1000));
     }
 
     #[test]
@@ -747,8 +3306,12 @@ mod tests_liquidity_pool_bis {
         let result = pool.swap_a_to_b(dec!(1001));
         assert!(result.is_err());
         assert_eq!(
-            result.unwrap_err().to_string(),
-            "Insufficient liquidity of A"
+            result.unwrap_err(),
+            ArppError::InsufficientLiquidity {
+                token: Token::A,
+                needed: dec!(1001),
+                available: dec!(1000),
+            }
         );
     }
 
@@ -774,12 +3337,12 @@ mod tests_liquidity_pool_bis {
     }
 
     #[test]
-    fn test_add_remove_tiny_liquidity() {
+    fn test_deposit_withdraw_tiny_liquidity() {
         let mut pool = create_custom_pool(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
-        assert!(pool.add_liquidity(dec!(0.000001), dec!(0.000001)).is_ok());
-        assert!(pool
-            .remove_liquidity(dec!(0.000001), dec!(0.000001))
-            .is_ok());
+        let shares = pool
+            .deposit("alice", dec!(0.000001), dec!(0.000001))
+            .unwrap();
+        assert!(pool.withdraw("alice", shares).is_ok());
     }
 
     #[test]
@@ -797,25 +3360,27 @@ mod tests_liquidity_pool_bis {
         let result = pool.swap_a_to_b(amount_to_swap);
         debug!("Large A to B swap result: {:?}", result);
         assert!(result.is_err());
-        assert_eq!(
-            result.unwrap_err().to_string(),
-            "Insufficient liquidity to perform swap"
-        );
+        assert!(matches!(
+            result.unwrap_err(),
+            ArppError::InsufficientLiquidity {
+                token: Token::B,
+                ..
+            }
+        ));
 
         // Test small A to B swap (should succeed)
         let small_amount = dec!(0.000001);
         let small_swap_result = pool.swap_a_to_b(small_amount);
         debug!("Small A to B swap result: {:?}", small_swap_result);
         assert!(small_swap_result.is_ok());
-        let received = small_swap_result.unwrap();
+        let received = small_swap_result.unwrap().amount_out;
         debug!("Received from small A to B swap: {}", received);
-        assert_eq!(
-            received,
-            dec!(1),
-            "Should receive exactly 1 token B for 0.000001 token A"
+        assert!(
+            received < dec!(1) && received > dec!(0.999),
+            "Should receive slightly less than 1 token B for 0.000001 token A"
         );
 
-        // Test B to A swap (should succeed but with very small return)
+        // Test B to A swap (should succeed, B buys A at the inverse of a very high price)
         let b_to_a_result = pool.swap_b_to_a(dec!(0.0001));
         debug!(
             "B to A swap result: {:?}Balances after B to A swap: {:?}",
@@ -823,9 +3388,9 @@ mod tests_liquidity_pool_bis {
             pool.get_balances()
         );
         assert!(b_to_a_result.is_ok());
-        let received_a = b_to_a_result.unwrap();
+        let received_a = b_to_a_result.unwrap().amount_out;
         debug!("Received from B to A swap: {}", received_a);
-        assert_approx_eq!(received_a, dec!(100.0500500833), dec!(0.00000001));
+        assert_approx_eq!(received_a, dec!(0.000000000100050010032801), dec!(1e-19));
 
         // Verify final state
         let (final_a, final_b) = pool.get_balances();
@@ -835,9 +3400,9 @@ mod tests_liquidity_pool_bis {
             final_a, final_b, final_price
         );
 
-        assert_approx_eq!(final_a, dec!(899.9499509), dec!(0.000001));
-        assert_approx_eq!(final_b, dec!(999.000100), dec!(0.000001));
-        assert_approx_eq!(final_price, dec!(950586.85345855), dec!(0.000001));
+        assert_approx_eq!(final_a, dec!(1000.000000999900050), dec!(0.000001));
+        assert_approx_eq!(final_b, dec!(999.000349958547696), dec!(0.000001));
+        assert_approx_eq!(final_price, dec!(999500.174646315796), dec!(0.000001));
 
         // Additional checks to understand the behavior
         debug!("Change in A balance: {}", final_a - dec!(1000));
@@ -4,5 +4,19 @@
    Date: 10/9/24
 ******************************************************************************/
 
+pub mod arbitrage;
+pub mod breaker;
+pub mod curve;
+pub mod error;
+pub mod fee;
+pub mod fixed_point;
 pub mod formula;
+pub mod journal;
 pub mod liquidity_pool;
+pub mod multi_asset;
+pub mod oracle;
+pub mod params;
+pub mod position;
+pub mod ramp;
+pub mod snapshot;
+pub mod swap;
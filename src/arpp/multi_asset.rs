@@ -0,0 +1,891 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::curve::{ArctanCurve, PricingCurve};
+use crate::arpp::error::ArppError;
+use crate::arpp::formula::{arpp_with_curve, token_ratio};
+use crate::arpp::oracle::PriceOracle;
+use crate::arpp::params::check_parameters;
+use crate::arpp::position::{pro_rata, INITIAL_PROVIDER};
+use crate::arpp::swap::{input_fee, output_margin, round_output, round_significant, BASIS_POINTS};
+use crate::utils::quadrature::integrate;
+use rust_decimal::{Decimal, RoundingStrategy};
+use rust_decimal_macros::dec;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Relative error target of the quadrature of a token's price over its balance.
+const INTEGRAL_TOLERANCE: Decimal = dec!(0.000000000000001);
+
+/// Relative Newton step below which the output of a swap is considered solved.
+const SOLVER_TOLERANCE: Decimal = dec!(0.000000000001);
+
+/// Maximum number of Newton iterations before giving up.
+const MAX_ITERATIONS: usize = 200;
+
+/// A token held by a `MultiAssetPool`.
+///
+/// # Fields
+/// - `name`: The name of the token.
+/// - `balance`: The amount of the token in the pool.
+/// - `p_ref`: The reference price of the token, in units of the pool's numeraire.
+/// - `weight`: The target share of the token in the value of the pool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Asset {
+    pub name: String,
+    pub balance: Decimal,
+    pub p_ref: Decimal,
+    pub weight: Decimal,
+}
+
+impl Asset {
+    /// Creates a new `Asset`.
+    ///
+    /// # Arguments
+    /// - `name`: The name of the token.
+    /// - `balance`: Initial amount of the token.
+    /// - `p_ref`: Reference price of the token, in units of the pool's numeraire.
+    /// - `weight`: Target share of the token in the value of the pool, relative to
+    ///   the weights of the other tokens.
+    pub fn new(name: &str, balance: Decimal, p_ref: Decimal, weight: Decimal) -> Self {
+        Self {
+            name: name.to_string(),
+            balance,
+            p_ref,
+            weight,
+        }
+    }
+}
+
+/// Outcome of a swap executed against, or quoted by, a `MultiAssetPool`.
+///
+/// Prices are pair prices, in units of `token_out` per unit of `token_in`.
+///
+/// # Fields
+/// - `token_in`: The index of the token paid into the pool.
+/// - `token_out`: The index of the token paid out by the pool.
+/// - `amount_in`: The amount of `token_in` paid into the pool, fee included.
+/// - `amount_out`: The amount of `token_out` paid out by the pool.
+/// - `fee`: The part of `amount_in`, in `token_in`, kept by the pool as a fee.
+/// - `average_price`: The average execution price, fee included.
+/// - `price_before`: The marginal pair price before the swap.
+/// - `price_after`: The marginal pair price once the swap has been settled.
+/// - `price_impact_bps`: The signed distance between the execution price of the
+///   amount left after the fee and `price_before`, in basis points of
+///   `price_before`. It is negative, as every swap executes below the marginal
+///   price of `token_in`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MultiAssetSwap {
+    pub token_in: usize,
+    pub token_out: usize,
+    pub amount_in: Decimal,
+    pub amount_out: Decimal,
+    pub fee: Decimal,
+    pub average_price: Decimal,
+    pub price_before: Decimal,
+    pub price_after: Decimal,
+    pub price_impact_bps: Decimal,
+}
+
+/// State of one token of a `MultiAssetPool`.
+///
+/// # Fields
+/// - `name`: The name of the token.
+/// - `balance`: The amount of the token in the pool.
+/// - `p_ref`: The reference price of the token.
+/// - `price`: The marginal ARPP price of the token, in units of the numeraire.
+/// - `value_weight`: The share of the token in the value of the pool at
+///   reference prices.
+/// - `target_weight`: The normalized target weight of the token.
+/// - `target_ratio`: The balance of the token over its target balance, the inverse
+///   of the ratio its ARPP price is computed from.
+/// - `fees`: The cumulative fees collected in the token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetMetrics {
+    pub name: String,
+    pub balance: Decimal,
+    pub p_ref: Decimal,
+    pub price: Decimal,
+    pub value_weight: Decimal,
+    pub target_weight: Decimal,
+    pub target_ratio: Decimal,
+    pub fees: Decimal,
+}
+
+/// An ARPP pool holding any number of tokens, each anchored to its own
+/// reference price.
+///
+/// Every token `k` is priced in a common numeraire by the ARPP formula applied to
+/// the ratio of a target balance to its balance:
+/// ```text
+/// P_k = p_ref_k * (1 + alpha * curve(beta * (t_k / x_k - 1)))
+/// t_k = w_k * V / p_ref_k,    V = sum of p_ref_j * x_j
+/// ```
+/// so a token above its target weight `w_k` is priced below its reference price,
+/// as Token A is in a `LiquidityPool` holding more A than B. The pair price of
+/// token `i` in units of token `j` is `P_i / P_j`.
+///
+/// The value `V` behind the target balances is refreshed by deposits, withdrawals
+/// and reference price updates, but not by swaps. Each price then depends on its
+/// own balance only, so a swap keeps the sum of the integrals of the prices over
+/// the balances constant: the value paid in along the price of `token_in` equals
+/// the value paid out along the price of `token_out`, and splitting a swap yields
+/// the same total as sending it at once.
+///
+/// # Fields
+/// - `assets`: The tokens of the pool, with their normalized target weights.
+/// - `targets`: The target balance of each token.
+/// - `alpha`: A parameter for the swap calculation.
+/// - `beta`: Another parameter for the swap calculation.
+/// - `curve`: The anchoring function of the ARPP formula.
+/// - `fee_bps`: The fee, in basis points, taken from the input of every swap.
+/// - `fees`: The cumulative fees collected in each token.
+/// - `total_shares`: The LP shares outstanding.
+/// - `shares`: The LP shares owned by each provider, by provider ID.
+#[derive(Debug, Clone)]
+pub struct MultiAssetPool {
+    assets: Vec<Asset>,
+    targets: Vec<Decimal>,
+    alpha: Decimal,
+    beta: Decimal,
+    curve: Arc<dyn PricingCurve>,
+    fee_bps: Decimal,
+    fees: Vec<Decimal>,
+    total_shares: Decimal,
+    shares: HashMap<String, Decimal>,
+}
+
+impl MultiAssetPool {
+    /// Creates a new `MultiAssetPool` with the specified tokens and parameters.
+    ///
+    /// # Arguments
+    /// - `assets`: The tokens of the pool, with their initial balances.
+    /// - `alpha`: Parameter for the swap calculation.
+    /// - `beta`: Parameter for the swap calculation.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the new pool, priced with the `ArctanCurve` and charging no
+    /// fee until `set_fee_bps` is called, with the initial balances owned by
+    /// `INITIAL_PROVIDER`. An `Err` is returned if a balance is negative, or a
+    /// reference price or weight is not positive.
+    pub fn new(assets: Vec<Asset>, alpha: Decimal, beta: Decimal) -> Result<Self, ArppError> {
+        for (token, asset) in assets.iter().enumerate() {
+            if asset.balance < Decimal::ZERO {
+                return Err(ArppError::InvalidAmount {
+                    amount: asset.balance,
+                });
+            }
+            if asset.p_ref <= Decimal::ZERO {
+                return Err(ArppError::InvalidPrice { price: asset.p_ref });
+            }
+            if asset.weight <= Decimal::ZERO {
+                return Err(ArppError::InvalidWeight {
+                    token,
+                    weight: asset.weight,
+                });
+            }
+        }
+        let total_weight: Decimal = assets.iter().map(|asset| asset.weight).sum();
+        let assets: Vec<Asset> = assets
+            .into_iter()
+            .map(|asset| Asset {
+                weight: asset.weight / total_weight,
+                ..asset
+            })
+            .collect();
+
+        let mut pool = Self {
+            targets: vec![Decimal::ZERO; assets.len()],
+            fees: vec![Decimal::ZERO; assets.len()],
+            assets,
+            alpha,
+            beta,
+            curve: Arc::new(ArctanCurve),
+            fee_bps: Decimal::ZERO,
+            total_shares: Decimal::ZERO,
+            shares: HashMap::new(),
+        };
+        pool.refresh_targets();
+        let value = pool.value_of(&pool.get_balances());
+        if value > Decimal::ZERO {
+            pool.mint_shares(INITIAL_PROVIDER, value);
+        }
+        Ok(pool)
+    }
+
+    /// Sets the anchoring function of the ARPP formula.
+    ///
+    /// # Arguments
+    ///
+    /// - `curve`: The curve replacing the current one.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the curve was set, or the error of
+    /// `check_parameters` if `alpha` and `beta` are invalid with it for the
+    /// reference price of any token. The curve is then left unchanged.
+    pub fn set_pricing_curve<C: PricingCurve + 'static>(
+        &mut self,
+        curve: C,
+    ) -> Result<(), ArppError> {
+        for asset in &self.assets {
+            check_parameters(&curve, asset.p_ref, self.alpha, self.beta)?;
+        }
+        self.curve = Arc::new(curve);
+        Ok(())
+    }
+
+    /// Returns the anchoring function of the ARPP formula.
+    pub fn get_pricing_curve(&self) -> &dyn PricingCurve {
+        self.curve.as_ref()
+    }
+
+    /// Sets the fee, in basis points, taken from the input of every swap.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the fee was set, or an `Err` if it is negative
+    /// or would take the whole input.
+    pub fn set_fee_bps(&mut self, fee_bps: Decimal) -> Result<(), ArppError> {
+        if fee_bps < Decimal::ZERO || fee_bps >= BASIS_POINTS {
+            return Err(ArppError::InvalidFee { fee_bps });
+        }
+        self.fee_bps = fee_bps;
+        Ok(())
+    }
+
+    /// Returns the fee, in basis points, taken from the input of every swap.
+    pub fn get_fee_bps(&self) -> Decimal {
+        self.fee_bps
+    }
+
+    /// Returns the tokens of the pool, with their normalized target weights.
+    pub fn get_assets(&self) -> &[Asset] {
+        &self.assets
+    }
+
+    /// Returns the index of the token named `name`, if the pool holds it.
+    pub fn get_index(&self, name: &str) -> Option<usize> {
+        self.assets.iter().position(|asset| asset.name == name)
+    }
+
+    /// Returns the balance of every token, in index order.
+    pub fn get_balances(&self) -> Vec<Decimal> {
+        self.assets.iter().map(|asset| asset.balance).collect()
+    }
+
+    /// Returns the cumulative fees collected in every token, in index order.
+    pub fn get_fee_revenue(&self) -> &[Decimal] {
+        &self.fees
+    }
+
+    /// Returns the marginal ARPP price of a token, in units of the numeraire.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the price, or an `Err` if there is no token at `token`.
+    pub fn get_token_price(&self, token: usize) -> Result<Decimal, ArppError> {
+        self.check_token(token)?;
+        Ok(self.price_at(token, self.assets[token].balance))
+    }
+
+    /// Returns the marginal pair price of `base` in units of `quote`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the price, or an `Err` if either token is unknown.
+    pub fn get_price(&self, base: usize, quote: usize) -> Result<Decimal, ArppError> {
+        Ok(self.get_token_price(base)? / self.get_token_price(quote)?)
+    }
+
+    /// Returns the reference price of a token, or an `Err` if it is unknown.
+    pub fn get_p_ref(&self, token: usize) -> Result<Decimal, ArppError> {
+        self.check_token(token)?;
+        Ok(self.assets[token].p_ref)
+    }
+
+    /// Quotes a swap of an exact amount in without modifying the pool.
+    ///
+    /// The value of the amount left after the fee is integrated along the price of
+    /// `token_in`, and the amount out is solved by Newton's method so that the same
+    /// value is integrated along the price of `token_out`.
+    ///
+    /// # Arguments
+    ///
+    /// - `token_in`: The index of the token paid into the pool.
+    /// - `token_out`: The index of the token paid out by the pool.
+    /// - `amount_in`: Amount of `token_in` to pay in.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which contains the quoted `MultiAssetSwap`, or an `Err` if a
+    /// token is unknown, both are the same, the amount is not positive or the pool
+    /// holds too little of `token_out`.
+    pub fn quote_swap(
+        &self,
+        token_in: usize,
+        token_out: usize,
+        amount_in: Decimal,
+    ) -> Result<MultiAssetSwap, ArppError> {
+        self.check_token(token_in)?;
+        self.check_token(token_out)?;
+        if token_in == token_out {
+            return Err(ArppError::SameToken { token: token_in });
+        }
+        if amount_in <= Decimal::ZERO {
+            return Err(ArppError::InvalidAmount { amount: amount_in });
+        }
+
+        let fee = input_fee(amount_in, self.fee_bps);
+        let net_in = amount_in - fee;
+        let balance_in = self.assets[token_in].balance;
+        let balance_out = self.assets[token_out].balance;
+        let value_in = self.value_between(token_in, balance_in, balance_in + net_in);
+        let amount_out = self.solve_amount_out(token_out, value_in)?;
+        let amount_out = round_output(amount_out, output_margin(net_in, balance_in))?;
+        if amount_out <= Decimal::ZERO {
+            return Err(ArppError::InvalidAmount { amount: amount_in });
+        }
+        if amount_out >= balance_out {
+            return Err(ArppError::InsufficientAssetLiquidity {
+                token: token_out,
+                needed: amount_out,
+                available: balance_out,
+            });
+        }
+
+        let price_before =
+            self.price_at(token_in, balance_in) / self.price_at(token_out, balance_out);
+        let price_after = self.price_at(token_in, balance_in + amount_in)
+            / self.price_at(token_out, balance_out - amount_out);
+        let net_price = amount_out / net_in;
+        Ok(MultiAssetSwap {
+            token_in,
+            token_out,
+            amount_in,
+            amount_out,
+            fee,
+            average_price: amount_out / amount_in,
+            price_before,
+            price_after,
+            price_impact_bps: (net_price - price_before) / price_before * BASIS_POINTS,
+        })
+    }
+
+    /// Swaps an amount of one token for another.
+    ///
+    /// The whole input, fee included, is added to the reserves.
+    ///
+    /// # Arguments
+    ///
+    /// - `token_in`: The index of the token paid into the pool.
+    /// - `token_out`: The index of the token paid out by the pool.
+    /// - `amount_in`: Amount of `token_in` to pay in.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which contains the `MultiAssetSwap` returned by `quote_swap` for
+    /// the same trade, or its `Err`, in which case the pool is left untouched.
+    pub fn swap(
+        &mut self,
+        token_in: usize,
+        token_out: usize,
+        amount_in: Decimal,
+    ) -> Result<MultiAssetSwap, ArppError> {
+        let result = self.quote_swap(token_in, token_out, amount_in)?;
+        self.assets[token_in].balance += result.amount_in;
+        self.assets[token_out].balance -= result.amount_out;
+        self.fees[token_in] += result.fee;
+        debug!(
+            "Swapped {} of {} for {} of {}",
+            result.amount_in,
+            self.assets[token_in].name,
+            result.amount_out,
+            self.assets[token_out].name
+        );
+        Ok(result)
+    }
+
+    /// Deposits amounts of any of the tokens and mints LP shares for them.
+    ///
+    /// The deposit is valued in the numeraire at the current ARPP prices, and the
+    /// shares minted are in the same proportion to the outstanding shares as that
+    /// value is to the value of the pool before the deposit. The target balances
+    /// are then refreshed.
+    ///
+    /// # Arguments
+    ///
+    /// - `provider`: The ID of the provider receiving the shares.
+    /// - `amounts`: Amount of each token to deposit, in index order.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which contains the number of shares minted, or an `Err` if there
+    /// is not one amount per token, an amount is negative or the deposit is too
+    /// small to mint any share.
+    pub fn deposit(&mut self, provider: &str, amounts: &[Decimal]) -> Result<Decimal, ArppError> {
+        self.check_amounts(amounts)?;
+        if let Some(&amount) = amounts.iter().find(|amount| **amount < Decimal::ZERO) {
+            return Err(ArppError::InvalidAmount { amount });
+        }
+        let value = self.value_of(amounts);
+        let pool_value = self.value_of(&self.get_balances());
+        let shares = if self.total_shares.is_zero() || pool_value <= Decimal::ZERO {
+            value
+        } else {
+            value / pool_value * self.total_shares
+        };
+        let shares = round_significant(shares, RoundingStrategy::ToZero).unwrap_or(Decimal::ZERO);
+        if shares <= Decimal::ZERO {
+            return Err(ArppError::DepositTooSmall { value });
+        }
+
+        for (asset, amount) in self.assets.iter_mut().zip(amounts) {
+            asset.balance += amount;
+        }
+        self.refresh_targets();
+        self.mint_shares(provider, shares);
+        debug!(
+            "Provider {} deposited {:?} for {} shares",
+            provider, amounts, shares
+        );
+        Ok(shares)
+    }
+
+    /// Burns LP shares of a provider and pays out their pro-rata part of every
+    /// token.
+    ///
+    /// # Arguments
+    ///
+    /// - `provider`: The ID of the provider redeeming the shares.
+    /// - `shares`: Number of shares to burn.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which contains the amount of each token paid out, in index
+    /// order, or an `Err` if the provider does not own that many shares.
+    pub fn withdraw(&mut self, provider: &str, shares: Decimal) -> Result<Vec<Decimal>, ArppError> {
+        if shares <= Decimal::ZERO {
+            return Err(ArppError::InvalidAmount { amount: shares });
+        }
+        let owned =
+            self.shares
+                .get(provider)
+                .copied()
+                .ok_or_else(|| ArppError::UnknownProvider {
+                    provider: provider.to_string(),
+                })?;
+        if shares > owned {
+            return Err(ArppError::InsufficientShares {
+                provider: provider.to_string(),
+                requested: shares,
+                owned,
+            });
+        }
+
+        // The last provider redeems the whole pool, without truncation
+        let amounts: Vec<Decimal> = if shares == self.total_shares {
+            self.get_balances()
+        } else {
+            self.assets
+                .iter()
+                .map(|asset| pro_rata(asset.balance, shares, self.total_shares))
+                .collect()
+        };
+        for (asset, amount) in self.assets.iter_mut().zip(&amounts) {
+            asset.balance -= amount;
+        }
+        self.refresh_targets();
+        self.total_shares -= shares;
+        if shares == owned {
+            self.shares.remove(provider);
+        } else {
+            self.shares.insert(provider.to_string(), owned - shares);
+        }
+        debug!(
+            "Provider {} withdrew {:?} for {} shares",
+            provider, amounts, shares
+        );
+        Ok(amounts)
+    }
+
+    /// Returns the LP shares owned by a provider, or `None` if it owns none.
+    pub fn get_shares(&self, provider: &str) -> Option<Decimal> {
+        self.shares.get(provider).copied()
+    }
+
+    /// Returns the LP shares outstanding.
+    pub fn get_total_shares(&self) -> Decimal {
+        self.total_shares
+    }
+
+    /// Updates the reference price of a token with the next price reported by an
+    /// oracle, and refreshes the target balances.
+    ///
+    /// The oracle is given the current reference price and marginal price of the
+    /// token. If it has no new price, the reference price is kept.
+    ///
+    /// # Parameters
+    /// - `token`: The index of the token.
+    /// - `oracle`: The source of the reference price.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `Ok` if the update was applied or skipped, or an `Err`
+    /// if the token is unknown or the price is not positive.
+    pub fn update_p_ref(
+        &mut self,
+        token: usize,
+        oracle: &mut dyn PriceOracle,
+    ) -> Result<(), ArppError> {
+        let pool_price = self.get_token_price(token)?;
+        let Some(update) = oracle.next_price(self.assets[token].p_ref, pool_price) else {
+            return Ok(());
+        };
+        if update.price <= Decimal::ZERO {
+            return Err(ArppError::InvalidPrice {
+                price: update.price,
+            });
+        }
+        debug!(
+            "Updating p_ref of {} from {} to {}",
+            self.assets[token].name, self.assets[token].p_ref, update.price
+        );
+        self.assets[token].p_ref = update.price;
+        self.refresh_targets();
+        Ok(())
+    }
+
+    /// Returns the state of every token, in index order.
+    pub fn get_asset_metrics(&self) -> Vec<AssetMetrics> {
+        let reference_value: Decimal = self
+            .assets
+            .iter()
+            .map(|asset| asset.p_ref * asset.balance)
+            .sum();
+        self.assets
+            .iter()
+            .enumerate()
+            .map(|(token, asset)| AssetMetrics {
+                name: asset.name.clone(),
+                balance: asset.balance,
+                p_ref: asset.p_ref,
+                price: self.price_at(token, asset.balance),
+                value_weight: (asset.p_ref * asset.balance)
+                    .checked_div(reference_value)
+                    .unwrap_or(Decimal::ZERO),
+                target_weight: asset.weight,
+                target_ratio: token_ratio(self.targets[token], asset.balance),
+                fees: self.fees[token],
+            })
+            .collect()
+    }
+
+    /// Returns an error if there is no token at index `token`.
+    fn check_token(&self, token: usize) -> Result<(), ArppError> {
+        if token >= self.assets.len() {
+            return Err(ArppError::UnknownToken { token });
+        }
+        Ok(())
+    }
+
+    /// Returns an error if `amounts` does not have one entry per token.
+    fn check_amounts(&self, amounts: &[Decimal]) -> Result<(), ArppError> {
+        if amounts.len() != self.assets.len() {
+            return Err(ArppError::WrongTokenCount {
+                expected: self.assets.len(),
+                actual: amounts.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Recomputes the target balances from the current value of the pool at
+    /// reference prices.
+    fn refresh_targets(&mut self) {
+        let value: Decimal = self
+            .assets
+            .iter()
+            .map(|asset| asset.p_ref * asset.balance)
+            .sum();
+        self.targets = self
+            .assets
+            .iter()
+            .map(|asset| asset.weight * value / asset.p_ref)
+            .collect();
+    }
+
+    /// Credits `shares` newly minted shares to `provider`.
+    fn mint_shares(&mut self, provider: &str, shares: Decimal) {
+        *self.shares.entry(provider.to_string()).or_default() += shares;
+        self.total_shares += shares;
+    }
+
+    /// Values amounts of every token in the numeraire at the current ARPP prices.
+    fn value_of(&self, amounts: &[Decimal]) -> Decimal {
+        self.assets
+            .iter()
+            .zip(amounts)
+            .enumerate()
+            .map(|(token, (asset, amount))| self.price_at(token, asset.balance) * amount)
+            .sum()
+    }
+
+    /// Returns the ARPP price of `token` if the pool held `balance` of it.
+    fn price_at(&self, token: usize, balance: Decimal) -> Decimal {
+        let asset = &self.assets[token];
+        let r = token_ratio(balance, self.targets[token]);
+        arpp_with_curve(self.curve.as_ref(), asset.p_ref, self.alpha, self.beta, r)
+    }
+
+    /// Integrates the price of `token` over its balance from `from` to `to`.
+    fn value_between(&self, token: usize, from: Decimal, to: Decimal) -> Decimal {
+        integrate(
+            &|balance| self.price_at(token, balance),
+            from,
+            to,
+            INTEGRAL_TOLERANCE,
+        )
+    }
+
+    /// Solves the amount of `token` whose removal from the pool is worth `value`
+    /// along its price.
+    fn solve_amount_out(&self, token: usize, value: Decimal) -> Result<Decimal, ArppError> {
+        let balance = self.assets[token].balance;
+        let price = self.price_at(token, balance);
+        if price <= Decimal::ZERO {
+            return Err(ArppError::InvalidPrice { price });
+        }
+        // The whole balance is worth less than the input if its price stays positive
+        let floor = self.price_at(token, Decimal::ZERO);
+        if floor > Decimal::ZERO {
+            let reserve_value = self.value_between(token, Decimal::ZERO, balance);
+            if value >= reserve_value {
+                return Err(ArppError::InsufficientAssetLiquidity {
+                    token,
+                    needed: balance + (value - reserve_value) / floor,
+                    available: balance,
+                });
+            }
+        }
+
+        let mut amount = (value / price).min(balance / dec!(2));
+        for _ in 0..MAX_ITERATIONS {
+            let end = balance - amount;
+            let price = self.price_at(token, end);
+            if price <= Decimal::ZERO {
+                return Err(ArppError::InvalidPrice { price });
+            }
+            let excess = self.value_between(token, end, balance) - value;
+            let mut next = amount - excess / price;
+            // Keep the iterate inside the balance
+            if next >= balance {
+                next = (amount + balance) / dec!(2);
+            } else if next <= Decimal::ZERO {
+                next = amount / dec!(2);
+            }
+            let step = (next - amount).abs();
+            amount = next;
+            if step <= amount * SOLVER_TOLERANCE {
+                return Ok(amount);
+            }
+        }
+        Err(ArppError::NoConvergence)
+    }
+}
+
+#[cfg(test)]
+mod tests_multi_asset {
+    use super::*;
+    use crate::arpp::curve::{CurveSpec, LogisticCurve, TanhCurve};
+    use crate::arpp::oracle::ConstantOracle;
+    use assert_approx_eq::assert_approx_eq;
+
+    fn create_pool() -> MultiAssetPool {
+        MultiAssetPool::new(
+            vec![
+                Asset::new("ETH", dec!(100), dec!(2000), dec!(1)),
+                Asset::new("BTC", dec!(5), dec!(40000), dec!(1)),
+                Asset::new("USDC", dec!(200000), dec!(1), dec!(1)),
+            ],
+            dec!(0.1),
+            dec!(2),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_balanced_pool_trades_at_reference_prices() {
+        let pool = create_pool();
+        assert_eq!(pool.get_token_price(0).unwrap(), dec!(2000));
+        assert_eq!(pool.get_price(1, 0).unwrap(), dec!(20));
+        assert_eq!(pool.get_price(2, 1).unwrap(), dec!(0.000025));
+        assert_eq!(pool.get_total_shares(), dec!(600000));
+        for metrics in pool.get_asset_metrics() {
+            assert_approx_eq!(metrics.target_ratio, Decimal::ONE, dec!(0.000000000001));
+            assert_approx_eq!(
+                metrics.value_weight,
+                metrics.target_weight,
+                dec!(0.000000000001)
+            );
+        }
+    }
+
+    #[test]
+    fn test_swap_moves_only_the_pair() {
+        let mut pool = create_pool();
+        let result = pool.swap(0, 2, dec!(10)).unwrap();
+        // 10 ETH at 2000 each, priced down as ETH grows abundant and USDC scarce
+        assert_eq!(result.amount_out, dec!(19609.1717974666));
+        assert_eq!(pool.get_balances()[0], dec!(110));
+        assert_eq!(pool.get_balances()[1], dec!(5));
+        // Abundant ETH is priced down and scarce USDC up, as in a `LiquidityPool`
+        assert!(pool.get_token_price(0).unwrap() < dec!(2000));
+        assert!(pool.get_token_price(2).unwrap() > dec!(1));
+        assert_eq!(pool.get_token_price(1).unwrap(), dec!(40000));
+        assert_eq!(result.price_before, dec!(2000));
+        assert_eq!(result.price_after, pool.get_price(0, 2).unwrap());
+        assert!(result.average_price < result.price_before);
+        assert!(result.price_impact_bps < Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_split_swap_matches_single_swap() {
+        let mut single = create_pool();
+        let mut split = create_pool();
+        let total = single.swap(1, 0, dec!(1)).unwrap().amount_out;
+        let first = split.swap(1, 0, dec!(0.4)).unwrap().amount_out;
+        let second = split.swap(1, 0, dec!(0.6)).unwrap().amount_out;
+        assert_approx_eq!(first + second, total, dec!(0.0000000001));
+    }
+
+    #[test]
+    fn test_round_trip_cannot_gain() {
+        let mut pool = create_pool();
+        let out = pool.swap(2, 1, dec!(50000)).unwrap().amount_out;
+        let back = pool.swap(1, 2, out).unwrap().amount_out;
+        assert!(back <= dec!(50000));
+        assert_approx_eq!(back, dec!(50000), dec!(0.000001));
+    }
+
+    #[test]
+    fn test_fee_stays_in_reserves() {
+        let mut pool = create_pool();
+        pool.set_fee_bps(dec!(30)).unwrap();
+        let result = pool.swap(0, 1, dec!(10)).unwrap();
+        assert_eq!(result.fee, dec!(0.03));
+        assert_eq!(pool.get_balances()[0], dec!(110));
+        assert_eq!(pool.get_fee_revenue(), &[dec!(0.03), dec!(0), dec!(0)]);
+    }
+
+    #[test]
+    fn test_quote_matches_swap() {
+        let mut pool = create_pool();
+        let quote = pool.quote_swap(1, 2, dec!(2)).unwrap();
+        assert_eq!(pool.get_balances()[1], dec!(5));
+        assert_eq!(pool.swap(1, 2, dec!(2)).unwrap(), quote);
+    }
+
+    #[test]
+    fn test_invalid_swaps() {
+        let mut pool = create_pool();
+        assert_eq!(
+            pool.swap(0, 3, dec!(1)),
+            Err(ArppError::UnknownToken { token: 3 })
+        );
+        assert_eq!(
+            pool.swap(1, 1, dec!(1)),
+            Err(ArppError::SameToken { token: 1 })
+        );
+        assert_eq!(
+            pool.swap(0, 1, dec!(0)),
+            Err(ArppError::InvalidAmount { amount: dec!(0) })
+        );
+        assert!(matches!(
+            pool.swap(2, 1, dec!(10000000)),
+            Err(ArppError::InsufficientAssetLiquidity { token: 1, .. })
+        ));
+        assert_eq!(pool.get_balances(), vec![dec!(100), dec!(5), dec!(200000)]);
+    }
+
+    #[test]
+    fn test_unsafe_pricing_curve_is_rejected() {
+        let assets = create_pool().get_assets().to_vec();
+        let mut pool = MultiAssetPool::new(assets, dec!(0.6), dec!(5)).unwrap();
+        assert!(matches!(
+            pool.set_pricing_curve(LogisticCurve),
+            Err(ArppError::UnsafeParameters { .. })
+        ));
+        assert_eq!(pool.get_pricing_curve().spec(), Some(CurveSpec::Arctan));
+        assert!(pool.set_pricing_curve(TanhCurve).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_assets() {
+        let assets = vec![
+            Asset::new("ETH", dec!(100), dec!(2000), dec!(1)),
+            Asset::new("USDC", dec!(200000), dec!(1), dec!(0)),
+        ];
+        assert_eq!(
+            MultiAssetPool::new(assets, dec!(0.1), dec!(2)).unwrap_err(),
+            ArppError::InvalidWeight {
+                token: 1,
+                weight: dec!(0)
+            }
+        );
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw() {
+        let mut pool = create_pool();
+        let shares = pool
+            .deposit("alice", &[dec!(10), dec!(0.5), dec!(20000)])
+            .unwrap();
+        assert_eq!(shares, dec!(60000));
+        assert_eq!(pool.get_shares("alice"), Some(dec!(60000)));
+        let amounts = pool.withdraw("alice", dec!(30000)).unwrap();
+        assert_eq!(amounts, vec![dec!(5), dec!(0.25), dec!(10000)]);
+        assert_eq!(
+            pool.deposit("bob", &[dec!(1)]),
+            Err(ArppError::WrongTokenCount {
+                expected: 3,
+                actual: 1
+            })
+        );
+        let amounts = pool.withdraw(INITIAL_PROVIDER, dec!(600000)).unwrap();
+        assert_eq!(amounts, vec![dec!(100), dec!(5), dec!(200000)]);
+        assert_eq!(pool.withdraw("alice", dec!(30000)).unwrap()[0], dec!(5));
+        assert_eq!(pool.get_total_shares(), dec!(0));
+    }
+
+    #[test]
+    fn test_single_sided_deposit_moves_weights() {
+        let mut pool = create_pool();
+        pool.deposit("alice", &[dec!(0), dec!(0), dec!(100000)])
+            .unwrap();
+        let metrics = pool.get_asset_metrics();
+        assert!(metrics[2].target_ratio > Decimal::ONE);
+        assert!(metrics[0].target_ratio < Decimal::ONE);
+        assert!(metrics[2].price < dec!(1) && metrics[0].price > dec!(2000));
+    }
+
+    #[test]
+    fn test_p_ref_update_reprices_token() {
+        let mut pool = create_pool();
+        pool.update_p_ref(1, &mut ConstantOracle::new(dec!(50000)))
+            .unwrap();
+        assert_eq!(pool.get_p_ref(1).unwrap(), dec!(50000));
+        // BTC now weighs more than its target, so it trades below its new reference
+        assert!(pool.get_token_price(1).unwrap() < dec!(50000));
+        assert!(pool.get_token_price(0).unwrap() > dec!(2000));
+        assert_eq!(
+            pool.update_p_ref(3, &mut ConstantOracle::new(dec!(1))),
+            Err(ArppError::UnknownToken { token: 3 })
+        );
+    }
+}
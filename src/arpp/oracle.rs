@@ -0,0 +1,420 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::error::ArppError;
+use crate::arpp::swap::BASIS_POINTS;
+use crate::simulation::random_walk::random_walk_price;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Number of pool prices averaged by the pool's own TWAP unless configured otherwise.
+pub const DEFAULT_TWAP_WINDOW: usize = 30;
+
+/// A price reported by a `PriceOracle`.
+///
+/// # Fields
+///
+/// * `price` - The reference price.
+/// * `timestamp` - When the price was observed by the oracle.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OraclePrice {
+    pub price: Decimal,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl OraclePrice {
+    pub fn new(price: Decimal, timestamp: DateTime<Utc>) -> Self {
+        Self { price, timestamp }
+    }
+
+    /// Returns `price` observed at the current time.
+    pub fn now(price: Decimal) -> Self {
+        Self::new(price, Utc::now())
+    }
+}
+
+/// A source for the reference price `p_ref` a `LiquidityPool` is anchored to.
+///
+/// The pool asks the oracle for a new price through `LiquidityPool::update_p_ref`,
+/// once per simulation step.
+///
+/// # Methods
+///
+/// - `next_price`: Returns the next reference price with the time it was observed,
+///   or `None` if the oracle has no new price and the current one should be kept.
+///
+/// # Arguments
+///
+/// * `p_ref` - The reference price currently used by the pool.
+/// * `pool_price` - The current ARPP price of the pool.
+pub trait PriceOracle: Send + Sync {
+    fn next_price(&mut self, p_ref: Decimal, pool_price: Decimal) -> Option<OraclePrice>;
+}
+
+/// An oracle moving the reference price along a random walk.
+///
+/// # Fields
+///
+/// * `std_dev` - The standard deviation of each price change.
+/// * `std_dev_of_std_dev` - The standard deviation of `std_dev` itself.
+#[derive(Debug, Clone)]
+pub struct RandomWalkOracle {
+    std_dev: Decimal,
+    std_dev_of_std_dev: Decimal,
+}
+
+impl RandomWalkOracle {
+    pub fn new(std_dev: Decimal, std_dev_of_std_dev: Decimal) -> Self {
+        Self {
+            std_dev,
+            std_dev_of_std_dev,
+        }
+    }
+}
+
+impl PriceOracle for RandomWalkOracle {
+    fn next_price(&mut self, p_ref: Decimal, _: Decimal) -> Option<OraclePrice> {
+        Some(OraclePrice::now(random_walk_price(
+            p_ref,
+            self.std_dev,
+            self.std_dev_of_std_dev,
+        )))
+    }
+}
+
+/// An oracle that always reports the same price.
+#[derive(Debug, Clone)]
+pub struct ConstantOracle {
+    price: Decimal,
+}
+
+impl ConstantOracle {
+    pub fn new(price: Decimal) -> Self {
+        Self { price }
+    }
+}
+
+impl PriceOracle for ConstantOracle {
+    fn next_price(&mut self, _: Decimal, _: Decimal) -> Option<OraclePrice> {
+        Some(OraclePrice::now(self.price))
+    }
+}
+
+/// An oracle replaying a recorded price series, one price per update.
+///
+/// Once the series is exhausted it reports no new price, so the pool keeps the
+/// last one.
+#[derive(Debug, Clone)]
+pub struct ReplayOracle {
+    prices: Vec<(Decimal, Option<DateTime<Utc>>)>,
+    next: usize,
+}
+
+impl ReplayOracle {
+    /// Replays `prices` as if each one was observed when it is reported.
+    pub fn new(prices: Vec<Decimal>) -> Self {
+        Self {
+            prices: prices.into_iter().map(|price| (price, None)).collect(),
+            next: 0,
+        }
+    }
+
+    /// Replays `prices` with the timestamps they were recorded at.
+    pub fn with_timestamps(prices: Vec<OraclePrice>) -> Self {
+        Self {
+            prices: prices
+                .into_iter()
+                .map(|price| (price.price, Some(price.timestamp)))
+                .collect(),
+            next: 0,
+        }
+    }
+}
+
+impl PriceOracle for ReplayOracle {
+    fn next_price(&mut self, _: Decimal, _: Decimal) -> Option<OraclePrice> {
+        let (price, timestamp) = self.prices.get(self.next).copied()?;
+        self.next += 1;
+        Some(timestamp.map_or_else(
+            || OraclePrice::now(price),
+            |timestamp| OraclePrice::new(price, timestamp),
+        ))
+    }
+}
+
+/// An oracle reporting the time-weighted average of the pool's own price.
+///
+/// The pool price is observed on every update, and updates are one simulation
+/// step apart, so the average over the last `window` observations weights each
+/// of them equally.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TwapOracle {
+    window: usize,
+    observations: VecDeque<Decimal>,
+}
+
+impl TwapOracle {
+    /// Creates a TWAP oracle over the last `window` pool prices, at least one.
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        Self {
+            window,
+            observations: VecDeque::with_capacity(window),
+        }
+    }
+
+    /// Records a pool price and returns the average over the window.
+    pub(crate) fn observe(&mut self, pool_price: Decimal) -> Decimal {
+        if self.observations.len() == self.window {
+            self.observations.pop_front();
+        }
+        self.observations.push_back(pool_price);
+        let total: Decimal = self.observations.iter().sum();
+        total / Decimal::from(self.observations.len())
+    }
+}
+
+impl PriceOracle for TwapOracle {
+    fn next_price(&mut self, _: Decimal, pool_price: Decimal) -> Option<OraclePrice> {
+        Some(OraclePrice::now(self.observe(pool_price)))
+    }
+}
+
+/// What the pool does with an oracle update that breaches a guard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GuardPolicy {
+    /// Keep the current `p_ref` and report the breach.
+    Reject,
+    /// Apply the update, limited to the band allowed by the guard.
+    ///
+    /// A stale price cannot be brought into a band, so it is rejected instead.
+    Clamp,
+    /// Keep the current `p_ref` and refuse swaps until an update is applied.
+    Pause,
+}
+
+/// A limit on oracle updates and the policy applied when it is breached.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OracleGuard<T> {
+    pub limit: T,
+    pub policy: GuardPolicy,
+}
+
+impl<T> OracleGuard<T> {
+    pub fn new(limit: T, policy: GuardPolicy) -> Self {
+        Self { limit, policy }
+    }
+}
+
+/// Guards checked by `LiquidityPool::update_p_ref` on every oracle update.
+///
+/// Guards left as `None` are not checked.
+///
+/// # Fields
+///
+/// * `max_age` - How old a price may be before it counts as stale, measured from
+///   when it was observed. Without a new price, the current `p_ref` keeps ageing.
+/// * `max_jump_bps` - Largest change from the current `p_ref` in a single update,
+///   in basis points of `p_ref`.
+/// * `max_deviation_bps` - Largest distance from the pool's own TWAP, in basis
+///   points of the TWAP.
+/// * `twap_window` - Number of pool prices, one per update, averaged by the TWAP.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OracleGuards {
+    pub max_age: Option<OracleGuard<Duration>>,
+    pub max_jump_bps: Option<OracleGuard<Decimal>>,
+    pub max_deviation_bps: Option<OracleGuard<Decimal>>,
+    pub twap_window: usize,
+}
+
+impl Default for OracleGuards {
+    fn default() -> Self {
+        Self {
+            max_age: None,
+            max_jump_bps: None,
+            max_deviation_bps: None,
+            twap_window: DEFAULT_TWAP_WINDOW,
+        }
+    }
+}
+
+impl OracleGuards {
+    /// Checks the age, at `now`, of a price observed at `timestamp`.
+    pub(crate) fn check_age(
+        &self,
+        timestamp: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Option<GuardBreach> {
+        let guard = self.max_age?;
+        let age = now - timestamp;
+        (age > guard.limit).then_some(GuardBreach {
+            error: ArppError::OracleStale {
+                age,
+                max_age: guard.limit,
+            },
+            policy: guard.policy,
+            band: None,
+        })
+    }
+
+    /// Checks the change from `p_ref` to `price`.
+    pub(crate) fn check_jump(&self, p_ref: Decimal, price: Decimal) -> Option<GuardBreach> {
+        let guard = self.max_jump_bps?;
+        let band = band_around(p_ref, guard.limit);
+        (!in_band(price, band)).then_some(GuardBreach {
+            error: ArppError::OracleJump {
+                p_ref,
+                price,
+                max_jump_bps: guard.limit,
+            },
+            policy: guard.policy,
+            band: Some(band),
+        })
+    }
+
+    /// Checks the distance from the pool's `twap` to `price`.
+    pub(crate) fn check_deviation(&self, twap: Decimal, price: Decimal) -> Option<GuardBreach> {
+        let guard = self.max_deviation_bps?;
+        let band = band_around(twap, guard.limit);
+        (!in_band(price, band)).then_some(GuardBreach {
+            error: ArppError::OracleDeviation {
+                twap,
+                price,
+                max_deviation_bps: guard.limit,
+            },
+            policy: guard.policy,
+            band: Some(band),
+        })
+    }
+}
+
+/// A guard breached by an oracle update.
+///
+/// `band` holds the lowest and highest prices the guard allows, if it has one.
+#[derive(Debug, Clone)]
+pub(crate) struct GuardBreach {
+    pub(crate) error: ArppError,
+    pub(crate) policy: GuardPolicy,
+    pub(crate) band: Option<(Decimal, Decimal)>,
+}
+
+/// Returns the prices within `limit_bps` basis points of `reference`.
+fn band_around(reference: Decimal, limit_bps: Decimal) -> (Decimal, Decimal) {
+    let width = reference * limit_bps / BASIS_POINTS;
+    (reference - width, reference + width)
+}
+
+fn in_band(price: Decimal, (low, high): (Decimal, Decimal)) -> bool {
+    low <= price && price <= high
+}
+
+/// A breach of an oracle guard, as logged by the pool and reported in the metrics.
+///
+/// # Fields
+///
+/// * `timestamp` - When the breach was detected.
+/// * `error` - The guard breached, with the values that breached it.
+/// * `policy` - The policy applied to the update.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OracleIncident {
+    pub timestamp: DateTime<Utc>,
+    pub error: ArppError,
+    pub policy: GuardPolicy,
+}
+
+#[cfg(test)]
+mod tests_price_oracle {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn price_of(update: Option<OraclePrice>) -> Option<Decimal> {
+        update.map(|update| update.price)
+    }
+
+    #[test]
+    fn test_random_walk_oracle_stays_positive() {
+        let mut oracle = RandomWalkOracle::new(dec!(0.1), dec!(0.01));
+        let mut p_ref = dec!(1);
+        for _ in 0..100 {
+            p_ref = oracle.next_price(p_ref, dec!(1)).unwrap().price;
+            assert!(p_ref > Decimal::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_constant_oracle() {
+        let mut oracle = ConstantOracle::new(dec!(2.5));
+        assert_eq!(
+            price_of(oracle.next_price(dec!(1), dec!(3))),
+            Some(dec!(2.5))
+        );
+        assert_eq!(
+            price_of(oracle.next_price(dec!(7), dec!(0.5))),
+            Some(dec!(2.5))
+        );
+    }
+
+    #[test]
+    fn test_replay_oracle_stops_at_end_of_series() {
+        let mut oracle = ReplayOracle::new(vec![dec!(1), dec!(1.1)]);
+        assert_eq!(price_of(oracle.next_price(dec!(1), dec!(1))), Some(dec!(1)));
+        assert_eq!(
+            price_of(oracle.next_price(dec!(1), dec!(1))),
+            Some(dec!(1.1))
+        );
+        assert_eq!(oracle.next_price(dec!(1.1), dec!(1)), None);
+    }
+
+    #[test]
+    fn test_twap_oracle_averages_last_window() {
+        let mut oracle = TwapOracle::new(2);
+        assert_eq!(price_of(oracle.next_price(dec!(1), dec!(1))), Some(dec!(1)));
+        assert_eq!(
+            price_of(oracle.next_price(dec!(1), dec!(2))),
+            Some(dec!(1.5))
+        );
+        assert_eq!(price_of(oracle.next_price(dec!(1), dec!(4))), Some(dec!(3)));
+    }
+
+    #[test]
+    fn test_replay_oracle_keeps_recorded_timestamps() {
+        let recorded = Utc::now() - Duration::hours(1);
+        let mut oracle = ReplayOracle::with_timestamps(vec![OraclePrice::new(dec!(2), recorded)]);
+        assert_eq!(
+            oracle.next_price(dec!(1), dec!(1)),
+            Some(OraclePrice::new(dec!(2), recorded))
+        );
+    }
+
+    #[test]
+    fn test_guards_detect_breaches() {
+        let guards = OracleGuards {
+            max_age: Some(OracleGuard::new(Duration::minutes(5), GuardPolicy::Pause)),
+            max_jump_bps: Some(OracleGuard::new(dec!(100), GuardPolicy::Clamp)),
+            max_deviation_bps: Some(OracleGuard::new(dec!(500), GuardPolicy::Reject)),
+            ..OracleGuards::default()
+        };
+        let now = Utc::now();
+        assert!(guards.check_age(now - Duration::minutes(1), now).is_none());
+        let stale = guards.check_age(now - Duration::minutes(6), now).unwrap();
+        assert_eq!(stale.policy, GuardPolicy::Pause);
+
+        assert!(guards.check_jump(dec!(1), dec!(1.01)).is_none());
+        let jump = guards.check_jump(dec!(1), dec!(1.02)).unwrap();
+        assert_eq!(jump.band, Some((dec!(0.99), dec!(1.01))));
+
+        assert!(guards.check_deviation(dec!(2), dec!(1.9)).is_none());
+        assert!(matches!(
+            guards.check_deviation(dec!(2), dec!(1.8)).unwrap().error,
+            ArppError::OracleDeviation { .. }
+        ));
+        assert!(OracleGuards::default()
+            .check_jump(dec!(1), dec!(10))
+            .is_none());
+    }
+}
@@ -0,0 +1,263 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::curve::PricingCurve;
+use crate::arpp::error::ArppError;
+use crate::arpp::formula::{price_range_asymmetric, AsymmetricParameters};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Magnitude of `beta` from which the curve is reported as step-like.
+///
+/// The curve covers most of its range while the ratio moves by a few `1 / beta`
+/// from balance, so past this value the price jumps between the ends of its
+/// range within a tenth of a percent of balance.
+pub const STEP_LIKE_BETA: Decimal = dec!(1000);
+
+/// A setting of the ARPP parameters that is safe but degenerate.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ParameterWarning {
+    /// `alpha` or `beta` is zero, so the price is `p_ref` at every ratio.
+    ConstantPrice,
+    /// `beta` is at least `STEP_LIKE_BETA`, so the price behaves like a step
+    /// function of the ratio.
+    StepLike { beta: Decimal },
+}
+
+impl fmt::Display for ParameterWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParameterWarning::ConstantPrice => {
+                write!(
+                    f,
+                    "Alpha or beta is zero: the price is p_ref at every ratio"
+                )
+            }
+            ParameterWarning::StepLike { beta } => write!(
+                f,
+                "Beta {} is so large that the price acts like a step function of the ratio",
+                beta
+            ),
+        }
+    }
+}
+
+/// Checks ARPP parameters with `curve` as the anchoring function.
+///
+/// `p_ref` must be positive and `alpha` and `beta` must not be negative. The price
+/// must also stay positive at every ratio, that is above zero at the low end of
+/// `price_range`. With the arctangent this always holds for `alpha <= 2/pi`;
+/// larger values of `alpha` are only safe with a `beta` small enough to keep the
+/// price of a pool without Token B above zero.
+///
+/// # Arguments
+///
+/// * `curve` - The anchoring function.
+/// * `p_ref` - Reference price.
+/// * `alpha` - Scaling parameter of the anchoring function.
+/// * `beta` - Scaling parameter of the deviation of the ratio from one.
+///
+/// # Returns
+///
+/// A `Result` with the warnings about degenerate settings, which may be empty,
+/// `ArppError::InvalidParameter` if a parameter is out of its domain, or
+/// `ArppError::UnsafeParameters` if the price can fall to zero or below.
+pub fn check_parameters(
+    curve: &dyn PricingCurve,
+    p_ref: Decimal,
+    alpha: Decimal,
+    beta: Decimal,
+) -> Result<Vec<ParameterWarning>, ArppError> {
+    check_asymmetric_parameters(curve, p_ref, &AsymmetricParameters::symmetric(alpha, beta))
+}
+
+/// Checks ARPP parameters with separate `alpha` and `beta` above and below
+/// balance, as `check_parameters` does for a single pair.
+///
+/// Only the parameters below balance set the lowest price, so only they can make
+/// the parameters unsafe. The slope of the price at balance is `p_ref * alpha *
+/// beta` on each side, so `alpha * beta` must be the same on both for the price
+/// and its sensitivities to stay continuous there.
+/// A pair whose `alpha` or `beta` is zero keeps its side at `p_ref`.
+///
+/// # Arguments
+///
+/// * `curve` - The anchoring function.
+/// * `p_ref` - Reference price.
+/// * `parameters` - The `alpha` and `beta` of each side.
+///
+/// # Returns
+///
+/// A `Result` with the warnings about degenerate settings, which may be empty,
+/// `ArppError::InvalidParameter` if a parameter is out of its domain,
+/// `ArppError::UnsafeParameters` if the price can fall to zero or below, or
+/// `ArppError::KinkAtBalance` if the slopes of the two sides differ at balance.
+pub fn check_asymmetric_parameters(
+    curve: &dyn PricingCurve,
+    p_ref: Decimal,
+    parameters: &AsymmetricParameters,
+) -> Result<Vec<ParameterWarning>, ArppError> {
+    let symmetric = parameters.is_symmetric();
+    let (alpha_up, beta_up) = if symmetric {
+        ("alpha", "beta")
+    } else {
+        ("alpha_up", "beta_up")
+    };
+    for (name, value, valid) in [
+        ("p_ref", p_ref, p_ref > Decimal::ZERO),
+        (
+            alpha_up,
+            parameters.alpha_up,
+            parameters.alpha_up >= Decimal::ZERO,
+        ),
+        (
+            "alpha_down",
+            parameters.alpha_down,
+            parameters.alpha_down >= Decimal::ZERO,
+        ),
+        (
+            beta_up,
+            parameters.beta_up,
+            parameters.beta_up >= Decimal::ZERO,
+        ),
+        (
+            "beta_down",
+            parameters.beta_down,
+            parameters.beta_down >= Decimal::ZERO,
+        ),
+    ] {
+        if !valid {
+            return Err(ArppError::InvalidParameter {
+                name: name.to_string(),
+                value,
+            });
+        }
+    }
+    let (low, _) = price_range_asymmetric(curve, p_ref, parameters);
+    if low <= Decimal::ZERO {
+        return Err(ArppError::UnsafeParameters {
+            alpha: parameters.alpha_down,
+            beta: parameters.beta_down,
+            low,
+        });
+    }
+
+    let slope_up = parameters.alpha_up * parameters.beta_up;
+    let slope_down = parameters.alpha_down * parameters.beta_down;
+    if slope_up != slope_down {
+        return Err(ArppError::KinkAtBalance {
+            slope_up,
+            slope_down,
+        });
+    }
+    let mut warnings = Vec::new();
+    if slope_up.is_zero() {
+        warnings.push(ParameterWarning::ConstantPrice);
+        return Ok(warnings);
+    }
+    let betas = if symmetric {
+        vec![parameters.beta_up]
+    } else {
+        vec![parameters.beta_up, parameters.beta_down]
+    };
+    for beta in betas {
+        if beta >= STEP_LIKE_BETA {
+            warnings.push(ParameterWarning::StepLike { beta });
+        }
+    }
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests_parameters {
+    use super::*;
+    use crate::arpp::curve::{ArctanCurve, LogisticCurve};
+
+    #[test]
+    fn test_parameters_out_of_domain_are_rejected() {
+        for (p_ref, alpha, beta, name, value) in [
+            (dec!(0), dec!(0.5), dec!(1), "p_ref", dec!(0)),
+            (dec!(1), dec!(-0.1), dec!(1), "alpha", dec!(-0.1)),
+            (dec!(1), dec!(0.5), dec!(-2), "beta", dec!(-2)),
+        ] {
+            assert_eq!(
+                check_parameters(&ArctanCurve, p_ref, alpha, beta),
+                Err(ArppError::InvalidParameter {
+                    name: name.to_string(),
+                    value
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_price_must_stay_positive() {
+        // Below 2/pi any beta is safe with the arctangent
+        assert_eq!(
+            check_parameters(&ArctanCurve, dec!(1), dec!(0.63), dec!(999)),
+            Ok(vec![])
+        );
+        // Above it, only a small enough beta keeps the price positive
+        assert_eq!(
+            check_parameters(&ArctanCurve, dec!(1), dec!(0.9), dec!(1)),
+            Ok(vec![])
+        );
+        assert!(matches!(
+            check_parameters(&ArctanCurve, dec!(1), dec!(0.9), dec!(100)),
+            Err(ArppError::UnsafeParameters { low, .. }) if low < Decimal::ZERO
+        ));
+        // The logistic curve saturates at two rather than pi/2
+        assert!(check_parameters(&ArctanCurve, dec!(1), dec!(0.6), dec!(10)).is_ok());
+        assert!(check_parameters(&LogisticCurve, dec!(1), dec!(0.6), dec!(10)).is_err());
+    }
+
+    #[test]
+    fn test_degenerate_settings_are_reported() {
+        assert_eq!(
+            check_parameters(&ArctanCurve, dec!(1), dec!(0), dec!(1)),
+            Ok(vec![ParameterWarning::ConstantPrice])
+        );
+        assert_eq!(
+            check_parameters(&ArctanCurve, dec!(1), dec!(0.5), dec!(5000)),
+            Ok(vec![ParameterWarning::StepLike { beta: dec!(5000) }])
+        );
+    }
+
+    #[test]
+    fn test_asymmetric_parameters_are_checked_by_side() {
+        // A large upside alpha is safe as long as the downside keeps the price positive
+        let parameters = AsymmetricParameters::new(dec!(0.9), dec!(0.3), dec!(1), dec!(3));
+        assert_eq!(
+            check_asymmetric_parameters(&ArctanCurve, dec!(1), &parameters),
+            Ok(vec![])
+        );
+        let parameters = AsymmetricParameters::new(dec!(0.3), dec!(0.9), dec!(1), dec!(100));
+        assert!(matches!(
+            check_asymmetric_parameters(&ArctanCurve, dec!(1), &parameters),
+            Err(ArppError::UnsafeParameters { alpha, beta, .. })
+                if alpha == dec!(0.9) && beta == dec!(100)
+        ));
+        // The slopes at balance must match
+        let parameters = AsymmetricParameters::new(dec!(0.9), dec!(0.3), dec!(100), dec!(1));
+        assert_eq!(
+            check_asymmetric_parameters(&ArctanCurve, dec!(1), &parameters),
+            Err(ArppError::KinkAtBalance {
+                slope_up: dec!(90),
+                slope_down: dec!(0.3)
+            })
+        );
+        let parameters = AsymmetricParameters::new(dec!(0.2), dec!(-0.1), dec!(1), dec!(2));
+        assert_eq!(
+            check_asymmetric_parameters(&ArctanCurve, dec!(1), &parameters),
+            Err(ArppError::InvalidParameter {
+                name: "alpha_down".to_string(),
+                value: dec!(-0.1)
+            })
+        );
+    }
+}
@@ -0,0 +1,87 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::swap::round_significant;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// Provider that owns the shares minted for the balances a pool is created with.
+pub const INITIAL_PROVIDER: &str = "initial";
+
+/// Position of a liquidity provider in a `LiquidityPool`.
+///
+/// # Fields
+/// - `shares`: The pool shares owned by the provider.
+/// - `share_of_pool`: The fraction of all outstanding shares owned by the provider.
+/// - `amount_a`: The amount of Token A the shares can currently be redeemed for.
+/// - `amount_b`: The amount of Token B the shares can currently be redeemed for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LpPosition {
+    pub shares: Decimal,
+    pub share_of_pool: Decimal,
+    pub amount_a: Decimal,
+    pub amount_b: Decimal,
+}
+
+impl LpPosition {
+    /// Builds the position of `shares` out of `total_shares` in a pool holding
+    /// `token_a` and `token_b`.
+    ///
+    /// Redeemable amounts are truncated like swap outputs, in the pool's favour,
+    /// except for the last provider, who can redeem the whole pool.
+    pub fn new(shares: Decimal, total_shares: Decimal, token_a: Decimal, token_b: Decimal) -> Self {
+        if shares == total_shares {
+            return Self {
+                shares,
+                share_of_pool: Decimal::ONE,
+                amount_a: token_a,
+                amount_b: token_b,
+            };
+        }
+        Self {
+            shares,
+            share_of_pool: shares / total_shares,
+            amount_a: pro_rata(token_a, shares, total_shares),
+            amount_b: pro_rata(token_b, shares, total_shares),
+        }
+    }
+}
+
+/// Returns the part of `balance` owned by `shares` out of `total_shares`.
+pub(crate) fn pro_rata(balance: Decimal, shares: Decimal, total_shares: Decimal) -> Decimal {
+    let amount = balance.checked_mul(shares).map_or_else(
+        || balance * (shares / total_shares),
+        |owed| owed / total_shares,
+    );
+    round_significant(amount, RoundingStrategy::ToZero).unwrap_or(Decimal::ZERO)
+}
+
+#[cfg(test)]
+mod tests_position {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_full_position_owns_whole_pool() {
+        let position = LpPosition::new(dec!(10), dec!(10), dec!(100), dec!(250));
+        assert_eq!(position.share_of_pool, Decimal::ONE);
+        assert_eq!(position.amount_a, dec!(100));
+        assert_eq!(position.amount_b, dec!(250));
+    }
+
+    #[test]
+    fn test_partial_position_is_pro_rata() {
+        let position = LpPosition::new(dec!(1), dec!(4), dec!(100), dec!(250));
+        assert_eq!(position.share_of_pool, dec!(0.25));
+        assert_eq!(position.amount_a, dec!(25));
+        assert_eq!(position.amount_b, dec!(62.5));
+    }
+
+    #[test]
+    fn test_redeemable_amounts_round_down() {
+        let position = LpPosition::new(dec!(1), dec!(3), dec!(1), dec!(1));
+        assert_eq!(position.amount_a, dec!(0.333333333333333));
+    }
+}
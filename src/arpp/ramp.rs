@@ -0,0 +1,204 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::error::ArppError;
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+/// A parameter of a pool whose changes are ramped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RampParameter {
+    Alpha,
+    Beta,
+    /// The fee of the pool in basis points, charged unless a dynamic fee is set.
+    FeeBps,
+}
+
+/// How long a ramp takes to reach its target.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RampLength {
+    /// This many `p_ref` updates, one per simulation step.
+    Steps(u64),
+    /// This much time.
+    Duration(Duration),
+}
+
+/// Bounds on a single parameter change, checked when a ramp is scheduled.
+///
+/// # Fields
+///
+/// * `max_factor` - The largest factor `alpha` or `beta` may be multiplied or
+///   divided by in one change. A parameter at zero therefore stays there.
+/// * `max_fee_change_bps` - The largest change of the fee in one change, in basis
+///   points.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RampBounds {
+    pub max_factor: Decimal,
+    pub max_fee_change_bps: Decimal,
+}
+
+impl Default for RampBounds {
+    /// Changes of `alpha` and `beta` up to a factor of ten and of the fee up to one
+    /// percent.
+    fn default() -> Self {
+        Self {
+            max_factor: dec!(10),
+            max_fee_change_bps: dec!(100),
+        }
+    }
+}
+
+impl RampBounds {
+    /// Checks a change of `parameter` from `from` to `to`.
+    ///
+    /// # Returns
+    ///
+    /// `ArppError::RampOutOfBounds` if the change is larger than allowed.
+    pub(crate) fn check(
+        &self,
+        parameter: RampParameter,
+        from: Decimal,
+        to: Decimal,
+    ) -> Result<(), ArppError> {
+        let allowed = match parameter {
+            RampParameter::Alpha | RampParameter::Beta => {
+                to <= from * self.max_factor && from <= to * self.max_factor
+            }
+            RampParameter::FeeBps => (to - from).abs() <= self.max_fee_change_bps,
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(ArppError::RampOutOfBounds {
+                parameter,
+                from,
+                to,
+            })
+        }
+    }
+}
+
+/// A parameter change in progress, moving linearly from `from` to `to`.
+///
+/// # Fields
+///
+/// * `parameter` - The parameter changed.
+/// * `from` - The value of the parameter when the ramp was scheduled.
+/// * `to` - The value the ramp ends at.
+/// * `length` - How long the ramp takes.
+/// * `started_at` - When the ramp was scheduled.
+/// * `steps_done` - The `p_ref` updates made since the ramp was scheduled.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Ramp {
+    pub parameter: RampParameter,
+    pub from: Decimal,
+    pub to: Decimal,
+    pub length: RampLength,
+    pub started_at: DateTime<Utc>,
+    pub steps_done: u64,
+}
+
+impl Ramp {
+    pub fn new(
+        parameter: RampParameter,
+        from: Decimal,
+        to: Decimal,
+        length: RampLength,
+        started_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            parameter,
+            from,
+            to,
+            length,
+            started_at,
+            steps_done: 0,
+        }
+    }
+
+    /// Returns the fraction of the ramp done at `now`, from zero to one. A ramp of
+    /// no length is done at once.
+    pub fn progress(&self, now: DateTime<Utc>) -> Decimal {
+        let (done, total) = match self.length {
+            RampLength::Steps(steps) => (self.steps_done as i64, steps as i64),
+            RampLength::Duration(duration) => (
+                (now - self.started_at).num_milliseconds(),
+                duration.num_milliseconds(),
+            ),
+        };
+        if done >= total {
+            return Decimal::ONE;
+        }
+        (Decimal::from(done) / Decimal::from(total)).max(Decimal::ZERO)
+    }
+
+    /// Returns the value of the parameter at `now`.
+    pub fn value(&self, now: DateTime<Utc>) -> Decimal {
+        self.from + (self.to - self.from) * self.progress(now)
+    }
+}
+
+#[cfg(test)]
+mod tests_ramp {
+    use super::*;
+
+    #[test]
+    fn test_ramp_moves_linearly() {
+        let now = Utc::now();
+        let mut ramp = Ramp::new(
+            RampParameter::Alpha,
+            dec!(0.5),
+            dec!(0.3),
+            RampLength::Steps(4),
+            now,
+        );
+        assert_eq!(ramp.value(now), dec!(0.5));
+        ramp.steps_done = 1;
+        assert_eq!(ramp.value(now), dec!(0.45));
+        ramp.steps_done = 6;
+        assert_eq!(ramp.value(now), dec!(0.3));
+
+        let ramp = Ramp::new(
+            RampParameter::FeeBps,
+            dec!(30),
+            dec!(10),
+            RampLength::Duration(Duration::seconds(100)),
+            now,
+        );
+        assert_eq!(ramp.value(now + Duration::seconds(25)), dec!(25));
+        assert_eq!(ramp.value(now + Duration::hours(1)), dec!(10));
+        assert_eq!(ramp.value(now - Duration::seconds(1)), dec!(30));
+    }
+
+    #[test]
+    fn test_bounds_limit_each_change() {
+        let bounds = RampBounds::default();
+        assert!(bounds.check(RampParameter::Beta, dec!(1), dec!(10)).is_ok());
+        assert!(bounds
+            .check(RampParameter::Beta, dec!(1), dec!(0.1))
+            .is_ok());
+        assert_eq!(
+            bounds.check(RampParameter::Alpha, dec!(0.5), dec!(0.01)),
+            Err(ArppError::RampOutOfBounds {
+                parameter: RampParameter::Alpha,
+                from: dec!(0.5),
+                to: dec!(0.01)
+            })
+        );
+        assert!(bounds
+            .check(RampParameter::Alpha, dec!(0), dec!(0.1))
+            .is_err());
+        assert!(bounds
+            .check(RampParameter::FeeBps, dec!(0), dec!(100))
+            .is_ok());
+        assert!(bounds
+            .check(RampParameter::FeeBps, dec!(30), dec!(131))
+            .is_err());
+    }
+}
@@ -0,0 +1,192 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::breaker::{BreakerState, CircuitBreaker};
+use crate::arpp::curve::CurveSpec;
+use crate::arpp::error::ArppError;
+use crate::arpp::fee::DynamicFee;
+use crate::arpp::oracle::{OracleGuards, OracleIncident, TwapOracle};
+use crate::arpp::ramp::{Ramp, RampBounds};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Version of the snapshot format written by this version of the crate.
+///
+/// Snapshots of any other version are refused rather than read into the wrong
+/// fields.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A versioned snapshot of some state, stored as JSON.
+///
+/// Decimals are written as strings, so a snapshot restores every amount with its
+/// exact value and scale.
+///
+/// # Fields
+/// - `version`: The version of the snapshot format.
+/// - `state`: The state, such as a `PoolState` or a `SimulationResult`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot<T> {
+    pub version: u32,
+    pub state: T,
+}
+
+impl<T: Serialize + DeserializeOwned> Snapshot<T> {
+    /// Wraps `state` in a snapshot of the current version.
+    pub fn new(state: T) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            state,
+        }
+    }
+
+    /// Writes the snapshot as pretty-printed JSON.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the JSON, or `ArppError::InvalidSnapshot` if the state
+    /// cannot be serialized.
+    pub fn to_json(&self) -> Result<String, ArppError> {
+        serde_json::to_string_pretty(self).map_err(|error| ArppError::InvalidSnapshot {
+            reason: error.to_string(),
+        })
+    }
+
+    /// Reads a snapshot written by `to_json`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the snapshot, `ArppError::UnsupportedSnapshotVersion` if it
+    /// was written in another version of the format, or
+    /// `ArppError::InvalidSnapshot` if it cannot be parsed.
+    pub fn from_json(json: &str) -> Result<Self, ArppError> {
+        let invalid = |error: serde_json::Error| ArppError::InvalidSnapshot {
+            reason: error.to_string(),
+        };
+        // The version is checked before the state, whose layout depends on it
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+        let Version { version } = serde_json::from_str(json).map_err(invalid)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(ArppError::UnsupportedSnapshotVersion {
+                version,
+                supported: SNAPSHOT_VERSION,
+            });
+        }
+        serde_json::from_str(json).map_err(invalid)
+    }
+}
+
+/// The full state of a `LiquidityPool`, as stored in a snapshot.
+///
+/// Built by `LiquidityPool::snapshot` and turned back into a pool by
+/// `LiquidityPool::restore`, which continues trading exactly as the original.
+///
+/// # Fields
+/// - `token_a`: The amount of Token A in the pool.
+/// - `token_b`: The amount of Token B in the pool.
+/// - `p_ref`: The reference price.
+/// - `alpha`: The `alpha` parameter of the ARPP formula, above balance.
+/// - `beta`: The `beta` parameter of the ARPP formula, above balance.
+/// - `alpha_down`: The `alpha` below balance, if it differs from `alpha`.
+/// - `beta_down`: The `beta` below balance, if it differs from `beta`.
+/// - `curve`: The anchoring function of the ARPP formula.
+/// - `fee_bps`: The fee, in basis points, taken from the input of every swap.
+/// - `dynamic_fee`: The fee scaling with the deviation from `p_ref`, charged
+///   instead of `fee_bps` if set.
+/// - `fees_a`: The cumulative fees collected in Token A.
+/// - `fees_b`: The cumulative fees collected in Token B.
+/// - `total_shares`: The LP shares outstanding.
+/// - `shares`: The LP shares owned by each provider, sorted by provider ID.
+/// - `oracle_guards`: The guards checked on every `p_ref` update.
+/// - `twap`: The pool's own TWAP.
+/// - `p_ref_updated_at`: When the current `p_ref` was observed by its oracle.
+/// - `swaps_paused`: Whether swaps are paused by an oracle guard.
+/// - `oracle_incidents`: The breaches of the oracle guards, oldest first.
+/// - `circuit_breaker`: The band around `p_ref` swaps must keep the price within.
+/// - `breaker`: The cooldown of the circuit breaker in progress and its statistics.
+/// - `ramp`: The parameter change in progress.
+/// - `ramp_bounds`: The bounds on every parameter change.
+///
+/// Snapshots taken before dynamic fees, the circuit breaker, ramps and separate
+/// parameters below balance existed restore without them, with the default ramp
+/// bounds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoolState {
+    pub token_a: Decimal,
+    pub token_b: Decimal,
+    pub p_ref: Decimal,
+    pub alpha: Decimal,
+    pub beta: Decimal,
+    #[serde(default)]
+    pub alpha_down: Option<Decimal>,
+    #[serde(default)]
+    pub beta_down: Option<Decimal>,
+    pub curve: CurveSpec,
+    pub fee_bps: Decimal,
+    #[serde(default)]
+    pub dynamic_fee: Option<DynamicFee>,
+    pub fees_a: Decimal,
+    pub fees_b: Decimal,
+    pub total_shares: Decimal,
+    pub shares: BTreeMap<String, Decimal>,
+    pub oracle_guards: OracleGuards,
+    pub twap: TwapOracle,
+    pub p_ref_updated_at: DateTime<Utc>,
+    pub swaps_paused: bool,
+    pub oracle_incidents: Vec<OracleIncident>,
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreaker>,
+    #[serde(default)]
+    pub breaker: BreakerState,
+    #[serde(default)]
+    pub ramp: Option<Ramp>,
+    #[serde(default)]
+    pub ramp_bounds: RampBounds,
+}
+
+#[cfg(test)]
+mod tests_snapshot {
+    use super::*;
+    use crate::simulation::result::SimulationResult;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_round_trip_keeps_decimal_scale() {
+        let result = SimulationResult {
+            max_price: dec!(1.500),
+            ..Default::default()
+        };
+        let json = Snapshot::new(result.clone()).to_json().unwrap();
+        assert!(json.contains("\"1.500\""));
+        let restored = Snapshot::<SimulationResult>::from_json(&json).unwrap();
+        assert_eq!(restored.state, result);
+        assert_eq!(restored.state.max_price.scale(), 3);
+    }
+
+    #[test]
+    fn test_other_versions_are_refused() {
+        let json = Snapshot::new(SimulationResult::default())
+            .to_json()
+            .unwrap()
+            .replace("\"version\": 1", "\"version\": 2");
+        assert_eq!(
+            Snapshot::<SimulationResult>::from_json(&json),
+            Err(ArppError::UnsupportedSnapshotVersion {
+                version: 2,
+                supported: 1
+            })
+        );
+        assert!(matches!(
+            Snapshot::<SimulationResult>::from_json("{"),
+            Err(ArppError::InvalidSnapshot { .. })
+        ));
+    }
+}
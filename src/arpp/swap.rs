@@ -0,0 +1,1091 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use crate::arpp::error::ArppError;
+use crate::arpp::formula::token_ratio;
+use crate::utils::quadrature::integrate;
+use chrono::{DateTime, Utc};
+use rust_decimal::{Decimal, MathematicalOps, RoundingStrategy};
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+
+/// Relative tolerance used when integrating the log-balance along the ratio path.
+///
+/// Swap outputs keep `OUTPUT_SIGNIFICANT_DIGITS`, so asking for more precision
+/// than this only makes the quadrature bisect further without changing them.
+const PATH_TOLERANCE: Decimal = dec!(0.000000000000001);
+
+/// Relative Newton step below which the ratio solver is considered converged.
+///
+/// Convergence is quadratic, so once a step is this small the error left after
+/// taking it is of the order of its square, well below the noise of the integrand.
+const RATIO_TOLERANCE: Decimal = dec!(0.0000000001);
+
+/// Fraction of a balance below which the pool is considered drained.
+///
+/// Kept well above the precision of `arpp`, so the solver can still resolve the
+/// residual when the path gets close to draining the pool.
+const DUST: Decimal = dec!(0.000000000001);
+
+/// Maximum factor by which the ratio may move in a single solver iteration.
+///
+/// Keeping every step geometric lets the solver cross several orders of magnitude
+/// in a handful of iterations.
+const BRACKET_FACTOR: Decimal = dec!(8);
+
+/// Maximum ratio between the ends of a single quadrature panel.
+///
+/// The kernels decay like `1 / R^2` at worst, which the 15-point rule resolves to
+/// full precision on panels this narrow without having to bisect them.
+const PANEL_FACTOR: Decimal = dec!(2);
+
+/// Displacement beyond which the kernel of A is integrated over `1 / (1 + t)`.
+const TAIL_START: Decimal = Decimal::ONE;
+
+/// Magnitude below which `exp_rel` and `ln_1p` sum their Taylor series instead of
+/// calling `exp` and `ln`.
+const SERIES_LIMIT: Decimal = dec!(0.001);
+
+/// Maximum number of solver iterations before giving up.
+const MAX_ITERATIONS: usize = 200;
+
+/// Significant digits kept in every amount paid out by the pool.
+///
+/// Outputs are truncated towards zero, so rounding always favours the pool.
+pub(crate) const OUTPUT_SIGNIFICANT_DIGITS: u32 = 15;
+
+/// Rounds `amount` to `OUTPUT_SIGNIFICANT_DIGITS` significant digits with
+/// `strategy`.
+///
+/// `Decimal::round_sf_with_strategy` pads an amount with fewer significant digits
+/// with trailing zeros, which overflows the largest scale of `Decimal` for tiny
+/// amounts, so such amounts are returned as they are.
+pub(crate) fn round_significant(amount: Decimal, strategy: RoundingStrategy) -> Option<Decimal> {
+    if amount.mantissa().unsigned_abs() < 10u128.pow(OUTPUT_SIGNIFICANT_DIGITS) {
+        return Some(amount);
+    }
+    amount.round_sf_with_strategy(OUTPUT_SIGNIFICANT_DIGITS, strategy)
+}
+
+/// Number of basis points in one unit.
+pub(crate) const BASIS_POINTS: Decimal = dec!(10000);
+
+/// Relative haircut applied to every amount paid out by the pool before truncation.
+///
+/// It is a few orders of magnitude above the error of the path solver, so a
+/// sequence of swaps that returns the pool to its starting ratio can never pay out
+/// more than was paid in.
+const OUTPUT_MARGIN: Decimal = dec!(0.000000000001);
+
+/// Relative change of a balance below which the path is limited by `Decimal` rounding.
+///
+/// A trade of `amount_in` against `balance_in` carries an additional relative
+/// haircut of `BALANCE_RESOLUTION * balance_in / amount_in`, which is negligible
+/// for any realistic trade but keeps dust trades from rounding in the trader's favour.
+const BALANCE_RESOLUTION: Decimal = dec!(0.0000000000000000000000001);
+
+/// Identifies one of the two tokens held by a `LiquidityPool`.
+///
+/// Token A is the base token and token B the quote token: the ARPP price is the
+/// amount of B exchanged for one unit of A.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Token {
+    A,
+    B,
+}
+
+impl Token {
+    /// The token whose price is given by the ARPP formula.
+    pub const BASE: Token = Token::A;
+    /// The token in which the ARPP price is expressed.
+    pub const QUOTE: Token = Token::B;
+
+    /// Returns the opposite token of the pair.
+    pub fn other(self) -> Token {
+        match self {
+            Token::A => Token::B,
+            Token::B => Token::A,
+        }
+    }
+
+    /// Returns the amount of the other token exchanged for one unit of this token
+    /// at the pool price `price` (quote per base).
+    ///
+    /// Paying in the base token is settled at `price`, paying in the quote token at
+    /// `1 / price`.
+    pub fn rate(self, price: Decimal) -> Decimal {
+        match self {
+            Token::A => price,
+            Token::B => Decimal::ONE / price,
+        }
+    }
+}
+
+/// Outcome of a swap executed against, or quoted by, a `LiquidityPool`.
+///
+/// Quotes and executed swaps are built from the same path solution, so a quote
+/// compares equal to the result of executing it on an unchanged pool.
+///
+/// # Fields
+/// - `token_in`: The token paid into the pool.
+/// - `amount_in`: The amount of `token_in` paid into the pool, fee included.
+/// - `amount_out`: The amount of the other token paid out by the pool.
+/// - `fee`: The part of `amount_in`, in `token_in`, kept by the pool as a fee.
+/// - `average_price`: The average execution price, fee included, in units of B per
+///   unit of A, so it can be compared directly with `LiquidityPool::get_price`.
+/// - `price_before`: The marginal pool price before the swap.
+/// - `price_after`: The marginal pool price once the swap has been settled.
+/// - `price_impact_bps`: The signed distance between the execution price of the
+///   amount left after the fee and `price_before`, in basis points of
+///   `price_before`. It is negative when Token A is sold into the pool and positive
+///   when it is bought from it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SwapResult {
+    pub token_in: Token,
+    pub amount_in: Decimal,
+    pub amount_out: Decimal,
+    pub fee: Decimal,
+    pub average_price: Decimal,
+    pub price_before: Decimal,
+    pub price_after: Decimal,
+    pub price_impact_bps: Decimal,
+}
+
+impl SwapResult {
+    pub fn new(
+        token_in: Token,
+        amount_in: Decimal,
+        amount_out: Decimal,
+        fee: Decimal,
+        price_before: Decimal,
+        price_after: Decimal,
+    ) -> Self {
+        let execution_price = |amount_in: Decimal| match token_in {
+            Token::A => amount_out / amount_in,
+            Token::B => amount_in / amount_out,
+        };
+        let net_price = execution_price(amount_in - fee);
+        Self {
+            token_in,
+            amount_in,
+            amount_out,
+            fee,
+            average_price: execution_price(amount_in),
+            price_before,
+            price_after,
+            price_impact_bps: (net_price - price_before) / price_before * BASIS_POINTS,
+        }
+    }
+
+    /// Returns the token paid out by the pool.
+    pub fn token_out(&self) -> Token {
+        self.token_in.other()
+    }
+}
+
+/// Checks that a swap made at `now` is no later than `deadline`, if one is given.
+pub(crate) fn check_deadline(
+    deadline: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Result<(), ArppError> {
+    match deadline {
+        Some(deadline) if now > deadline => Err(ArppError::DeadlineExpired { deadline, now }),
+        _ => Ok(()),
+    }
+}
+
+/// Change of a pool balance that a swap path is solved against.
+///
+/// Keeping the change rather than the final balance preserves the precision of
+/// trades that are tiny next to the pool.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum PathTarget {
+    /// Change of the balance of token A.
+    A(Decimal),
+    /// Change of the balance of token B.
+    B(Decimal),
+}
+
+impl PathTarget {
+    /// Returns the token whose balance change is known.
+    pub(crate) fn token(self) -> Token {
+        match self {
+            PathTarget::A(_) => Token::A,
+            PathTarget::B(_) => Token::B,
+        }
+    }
+}
+
+/// Computes the amount paid out for `amount_in` of `token_in` by integrating the
+/// price over the ratio path the trade moves through.
+///
+/// Along the path every infinitesimal trade is settled at the instantaneous price
+/// `price(R)` (units of B per unit of A), where `R = token_b / token_a`: paying in
+/// A returns `price` B per unit, paying in B returns `1 / price` A per unit. Paying
+/// in A lowers `R` and paying in B raises it, so with a price that rises with the
+/// ratio every trade moves the price against the trader.
+///
+/// The path is followed in the inverse ratio `Q = token_a / token_b`, along which
+/// both directions satisfy, with `x = Q * price`,
+///
+/// ```text
+/// d ln(token_a) =  k_a(Q) dQ,   k_a(Q) = 1 / (Q * (1 + x))
+/// d ln(token_b) = -k_b(Q) dQ,   k_b(Q) = price / (1 + x)
+/// ```
+///
+/// so every swap moves the pool along the same curve, and the final ratio is the
+/// point of that curve where the input balance reaches its new value. It is found
+/// with a Newton iteration on top of adaptive Gauss-Kronrod quadrature of the
+/// kernel of the input token. The result only depends on the start and end points
+/// of the path, which makes it independent of how an order is split, and a round
+/// trip back to the starting ratio returns what was paid in, minus the rounding
+/// kept by the pool.
+///
+/// # Returns
+///
+/// The amount of the other token paid out, rounded down by `round_output`, or an
+/// error if the pool cannot pay it.
+pub(crate) fn path_amount_out<F>(
+    token_a: Decimal,
+    token_b: Decimal,
+    token_in: Token,
+    amount_in: Decimal,
+    price: &F,
+) -> Result<Decimal, ArppError>
+where
+    F: Fn(Decimal) -> Decimal,
+{
+    let target = match token_in {
+        Token::A => PathTarget::A(amount_in),
+        Token::B => PathTarget::B(amount_in),
+    };
+    let change = solve_path(token_a, token_b, target, price)?;
+    let balance_in = match token_in {
+        Token::A => token_a,
+        Token::B => token_b,
+    };
+    round_output(-change, output_margin(amount_in, balance_in))
+}
+
+/// Computes the amount of the other token that has to be paid in for the pool to
+/// pay out `amount_out` of `token_out`, solving the same path as `path_amount_out`.
+///
+/// The amount includes the input fee of `fee_bps` basis points. It is rounded up in
+/// the pool's favour and then checked against `path_amount_out`, so paying it in as
+/// an exact-input swap always delivers at least `amount_out`.
+///
+/// # Returns
+///
+/// The amount of the other token to pay in, or an error if the pool cannot pay
+/// out `amount_out`.
+pub(crate) fn path_amount_in<F>(
+    token_a: Decimal,
+    token_b: Decimal,
+    token_out: Token,
+    amount_out: Decimal,
+    fee_bps: Decimal,
+    price: &F,
+) -> Result<Decimal, ArppError>
+where
+    F: Fn(Decimal) -> Decimal,
+{
+    let (target, balance_out) = match token_out {
+        Token::A => (PathTarget::A(-amount_out), token_a),
+        Token::B => (PathTarget::B(-amount_out), token_b),
+    };
+    let change = solve_path(token_a, token_b, target, price)?;
+    let gross = change * BASIS_POINTS / (BASIS_POINTS - fee_bps);
+    let mut margin = output_margin(amount_out, balance_out);
+    for _ in 0..MAX_ITERATIONS {
+        let amount_in = round_input(gross, margin)?;
+        let net = amount_in - input_fee(amount_in, fee_bps);
+        let delivered = path_amount_out(token_a, token_b, token_out.other(), net, price)?;
+        if delivered >= amount_out {
+            return Ok(amount_in);
+        }
+        // The exact-input haircut was not covered, typically close to draining the pool
+        margin *= Decimal::TWO;
+    }
+    Err(ArppError::NoConvergence)
+}
+
+/// Computes the amount of `token_in`, after fees, that moves the pool along the
+/// swap path from `(token_a, token_b)` to `ratio`.
+///
+/// The log-balance of `token_in` is integrated directly up to `ratio`, so no
+/// solver is involved. `ratio` is a ratio `token_b / token_a`, as passed to the
+/// price, and must lie on the side of the current ratio that paying in `token_in`
+/// moves towards: below it for A, above it for B.
+///
+/// # Returns
+///
+/// The amount to pay in, before rounding, or an error if the pool is empty or
+/// `ratio` cannot be reached by paying in `token_in`.
+pub(crate) fn path_amount_to_ratio<F>(
+    token_a: Decimal,
+    token_b: Decimal,
+    token_in: Token,
+    ratio: Decimal,
+    price: &F,
+) -> Result<Decimal, ArppError>
+where
+    F: Fn(Decimal) -> Decimal,
+{
+    if token_a <= Decimal::ZERO || token_b <= Decimal::ZERO {
+        return Err(ArppError::InvalidAmount {
+            amount: token_a.min(token_b),
+        });
+    }
+    if ratio <= Decimal::ZERO {
+        return Err(ArppError::InvalidAmount { amount: ratio });
+    }
+    let r0 = token_a / token_b;
+    // Displacement of the inverse ratio the path is followed in
+    let t = token_ratio(token_a, token_b) / ratio - Decimal::ONE;
+    let (log_change, balance_in) = match token_in {
+        Token::A if t >= Decimal::ZERO => {
+            let kernel = PathKernel::new(price, Token::A, r0);
+            (
+                kernel.log_of(integrate_path(&kernel, Decimal::ZERO, t)),
+                token_a,
+            )
+        }
+        Token::B if t <= Decimal::ZERO => {
+            let kernel = PathKernel::new(price, Token::B, r0);
+            (
+                -kernel.log_of(integrate_path(&kernel, Decimal::ZERO, t)),
+                token_b,
+            )
+        }
+        _ => return Err(ArppError::InvalidAmount { amount: ratio }),
+    };
+    Ok(balance_in * log_change * exp_rel(log_change))
+}
+
+/// Returns the fee of `fee_bps` basis points charged on `amount_in`.
+pub(crate) fn input_fee(amount_in: Decimal, fee_bps: Decimal) -> Decimal {
+    amount_in * fee_bps / BASIS_POINTS
+}
+
+/// Returns the relative haircut for a trade of `amount_in` against `balance_in`.
+pub(crate) fn output_margin(amount_in: Decimal, balance_in: Decimal) -> Decimal {
+    balance_in
+        .checked_div(amount_in)
+        .and_then(|size| size.checked_mul(BALANCE_RESOLUTION))
+        .map_or(Decimal::ONE, |dust| {
+            (OUTPUT_MARGIN + dust).min(Decimal::ONE)
+        })
+}
+
+/// Rounds an amount paid out by the pool in the pool's favour.
+///
+/// The amount is reduced by the relative `margin` and truncated to
+/// `OUTPUT_SIGNIFICANT_DIGITS`.
+pub(crate) fn round_output(amount: Decimal, margin: Decimal) -> Result<Decimal, ArppError> {
+    round_significant(amount - amount * margin, RoundingStrategy::ToZero).ok_or(ArppError::Overflow)
+}
+
+/// Rounds an amount paid into the pool in the pool's favour.
+///
+/// The amount is increased by the relative `margin` and rounded up to
+/// `OUTPUT_SIGNIFICANT_DIGITS`.
+fn round_input(amount: Decimal, margin: Decimal) -> Result<Decimal, ArppError> {
+    amount
+        .checked_add(amount * margin)
+        .and_then(|amount| round_significant(amount, RoundingStrategy::AwayFromZero))
+        .ok_or(ArppError::Overflow)
+}
+
+/// Solves the swap path from `(token_a, token_b)` until `target` is reached.
+///
+/// # Returns
+///
+/// The change of the balance of the other token at the end of the path: negative
+/// when it is paid out by the pool, positive when it has to be paid in.
+pub(crate) fn solve_path<F>(
+    token_a: Decimal,
+    token_b: Decimal,
+    target: PathTarget,
+    price: &F,
+) -> Result<Decimal, ArppError>
+where
+    F: Fn(Decimal) -> Decimal,
+{
+    if token_a <= Decimal::ZERO || token_b <= Decimal::ZERO {
+        let (token, available) = if token_a <= Decimal::ZERO {
+            (Token::A, token_a)
+        } else {
+            (Token::B, token_b)
+        };
+        return Err(ArppError::InsufficientLiquidity {
+            token,
+            needed: available,
+            available,
+        });
+    }
+    let r0 = token_a / token_b;
+    let initial_price = price(token_ratio(token_a, token_b));
+    if initial_price <= Decimal::ZERO {
+        return Err(ArppError::InvalidPrice {
+            price: initial_price,
+        });
+    }
+    // The target is reached by integrating the log-balance of its own token, which
+    // keeps the residual well conditioned when its kernel is much smaller than `1 / R`.
+    let kernel = PathKernel::new(price, target.token(), r0);
+    let mut path = PathState::new(&kernel, token_a, token_b, target)?;
+    let t1 = path.solve()?;
+    let final_price = price(price_ratio(r0 * (Decimal::ONE + t1)));
+    if final_price <= Decimal::ZERO {
+        return Err(ArppError::InvalidPrice { price: final_price });
+    }
+
+    // The other balance follows from its own kernel rather than from the final
+    // ratio, so small trades keep their precision next to large balances.
+    let other = PathKernel::new(price, target.token().other(), r0);
+    let j = integrate_path(&other, Decimal::ZERO, t1);
+    Ok(match target {
+        PathTarget::A(_) => {
+            let log_change = -other.log_of(j);
+            -other.log_of(token_b * j) * exp_rel(log_change)
+        }
+        PathTarget::B(_) => {
+            let log_change = other.log_of(j);
+            other.log_of(token_a * j) * exp_rel(log_change)
+        }
+    })
+}
+
+/// Converts the inverse ratio `token_a / token_b` a path is followed in into the
+/// ratio its price is evaluated at, saturating for a vanishing `inverse`.
+fn price_ratio(inverse: Decimal) -> Decimal {
+    Decimal::ONE.checked_div(inverse).unwrap_or(Decimal::MAX)
+}
+
+/// Kernel of the log-balance of one token along the path, normalised at its start.
+///
+/// The path is parametrised by the relative displacement of the inverse ratio,
+/// `Q = token_a / token_b = r0 * (1 + t)`, and the price is evaluated at `1 / Q`.
+/// With `x = Q * price` the log-balances evolve as
+///
+/// ```text
+/// d ln(token_a) =  s_a * kernel_a(t) dt,   s_a = 1 / (1 + x0)
+/// d ln(token_b) = -s_b * kernel_b(t) dt,   s_b = x0 / (1 + x0)
+/// ```
+///
+/// where both kernels equal 1 at `t = 0`. Keeping every quantity of order one
+/// preserves the precision of `Decimal`, whose fixed 28 decimal places would
+/// otherwise truncate the kernels of very unbalanced pools.
+///
+/// A price with separate parameters on each side of balance changes curvature at
+/// `Q = 1`, so the kernels are only smooth on either side of `balance_t`, the
+/// displacement at which the path crosses it.
+struct PathKernel<'a, F: Fn(Decimal) -> Decimal> {
+    price: &'a F,
+    token: Token,
+    r0: Decimal,
+    price0: Decimal,
+    x0: Decimal,
+    denominator0: Decimal,
+    balance_t: Option<Decimal>,
+}
+
+impl<'a, F: Fn(Decimal) -> Decimal> PathKernel<'a, F> {
+    fn new(price: &'a F, token: Token, r0: Decimal) -> Self {
+        let price0 = price(price_ratio(r0));
+        let x0 = r0 * price0;
+        Self {
+            price,
+            token,
+            r0,
+            price0,
+            x0,
+            denominator0: Decimal::ONE + x0,
+            balance_t: Decimal::ONE.checked_div(r0).map(|r| r - Decimal::ONE),
+        }
+    }
+
+    /// Evaluates the normalised kernel at displacement `t`.
+    fn at(&self, t: Decimal) -> Decimal {
+        let q = self.r0 * (Decimal::ONE + t);
+        let price = (self.price)(price_ratio(q));
+        let ratio = self.denominator0 / (Decimal::ONE + q * price);
+        match self.token {
+            Token::A => ratio / (Decimal::ONE + t),
+            Token::B => ratio * price / self.price0,
+        }
+    }
+
+    /// Evaluates `(1 + t)^2` times the normalised kernel at `u = 1 / (1 + t)`, the
+    /// integrand of the kernel over `u`.
+    fn at_inverse(&self, u: Decimal) -> Decimal {
+        let price = (self.price)(u / self.r0);
+        let ratio = self.denominator0 / (u + self.r0 * price);
+        match self.token {
+            Token::A => ratio,
+            Token::B => ratio * price / (self.price0 * u),
+        }
+    }
+
+    /// Converts an integral of the kernel into a log-balance change, `s * j`.
+    fn log_of(&self, j: Decimal) -> Decimal {
+        match self.token {
+            Token::A => j / self.denominator0,
+            Token::B => j * self.x0 / self.denominator0,
+        }
+    }
+
+    /// Converts a log-balance change into an integral of the kernel, `l / s`.
+    fn integral_of(&self, l: Decimal) -> Decimal {
+        match self.token {
+            Token::A => l * self.denominator0,
+            Token::B => l * self.denominator0 / self.x0,
+        }
+    }
+}
+
+/// Integrates `kernel` from `from` to `to`.
+///
+/// Beyond `TAIL_START` the kernel of A decays like `1 / (1 + t)^2`, so that part is
+/// integrated over `u = 1 / (1 + t)`, where it tends to a constant. Everything else
+/// is integrated over panels whose ends differ in ratio by at most `PANEL_FACTOR`.
+fn integrate_path<F>(kernel: &PathKernel<F>, from: Decimal, to: Decimal) -> Decimal
+where
+    F: Fn(Decimal) -> Decimal,
+{
+    if kernel.token != Token::A {
+        return integrate_panels(kernel, from, to);
+    }
+    let near = integrate_panels(kernel, from.min(TAIL_START), to.min(TAIL_START));
+    let (from, to) = (from.max(TAIL_START), to.max(TAIL_START));
+    if from == to {
+        return near;
+    }
+    let k = |u: Decimal| kernel.at_inverse(u);
+    let u_from = Decimal::ONE / (Decimal::ONE + from);
+    let u_to = Decimal::ONE / (Decimal::ONE + to);
+    // Balance is crossed at `u = r0`
+    near + integrate_across(&k, u_to, u_from, Some(kernel.r0))
+}
+
+/// Integrates `kernel` from `from` to `to` over panels whose ends differ in ratio
+/// by at most `PANEL_FACTOR`.
+fn integrate_panels<F>(kernel: &PathKernel<F>, from: Decimal, to: Decimal) -> Decimal
+where
+    F: Fn(Decimal) -> Decimal,
+{
+    let k = |t: Decimal| kernel.at(t);
+    let mut total = Decimal::ZERO;
+    let mut start = from;
+    while start != to {
+        let end = if to > start {
+            to.min((Decimal::ONE + start) * PANEL_FACTOR - Decimal::ONE)
+        } else {
+            to.max((Decimal::ONE + start) / PANEL_FACTOR - Decimal::ONE)
+        };
+        total += integrate_across(&k, start, end, kernel.balance_t);
+        start = end;
+    }
+    total
+}
+
+/// Integrates `f` from `a` to `b`, in two parts if `kink` lies between them, so
+/// that the quadrature never spans a change of slope of the integrand.
+fn integrate_across<F>(f: &F, a: Decimal, b: Decimal, kink: Option<Decimal>) -> Decimal
+where
+    F: Fn(Decimal) -> Decimal,
+{
+    match kink {
+        Some(kink) if a.min(b) < kink && kink < a.max(b) => {
+            integrate(f, a, kink, PATH_TOLERANCE) + integrate(f, kink, b, PATH_TOLERANCE)
+        }
+        _ => integrate(f, a, b, PATH_TOLERANCE),
+    }
+}
+
+/// Computes `(e^x - 1) / x`, keeping full relative precision for small `x`.
+fn exp_rel(x: Decimal) -> Decimal {
+    if x.abs() >= SERIES_LIMIT {
+        return (x.exp() - Decimal::ONE) / x;
+    }
+    let mut term = Decimal::ONE;
+    let mut sum = Decimal::ONE;
+    let mut n = Decimal::ONE;
+    while !term.is_zero() {
+        n += Decimal::ONE;
+        term = term * x / n;
+        sum += term;
+    }
+    sum
+}
+
+/// Computes `ln(1 + x)`, keeping full relative precision for small `x`.
+fn ln_1p(x: Decimal) -> Result<Decimal, ArppError> {
+    if x.abs() >= SERIES_LIMIT {
+        return (Decimal::ONE + x).checked_ln().ok_or(ArppError::Overflow);
+    }
+    let mut power = x;
+    let mut sum = x;
+    let mut n = Decimal::ONE;
+    loop {
+        n += Decimal::ONE;
+        power *= -x;
+        let term = power / n;
+        if term.is_zero() {
+            return Ok(sum);
+        }
+        sum += term;
+    }
+}
+
+/// Incremental evaluation of the swap path residual as a function of the
+/// displacement `t` of the ratio.
+///
+/// The residual is `f(t) = J(t) - ln(a1 / a0) / s_a` when the change of A is known
+/// and `f(t) = J(t) - ln(b0 / b1) / s_b` when the change of B is, where `J(t)` is the
+/// integral of the matching kernel from the start of the path. Both are strictly
+/// increasing in `t` and vanish at the end of the path.
+struct PathState<'a, 'p, F: Fn(Decimal) -> Decimal> {
+    kernel: &'a PathKernel<'p, F>,
+    token_a: Decimal,
+    token_b: Decimal,
+    offset: Decimal,
+    t_min: Decimal,
+    t_max: Option<Decimal>,
+    last_t: Decimal,
+    last_j: Decimal,
+}
+
+impl<'a, 'p, F: Fn(Decimal) -> Decimal> PathState<'a, 'p, F> {
+    fn new(
+        kernel: &'a PathKernel<'p, F>,
+        token_a: Decimal,
+        token_b: Decimal,
+        target: PathTarget,
+    ) -> Result<Self, ArppError> {
+        // Bounds on the displacement beyond which the balance being paid out is drained.
+        let (log_change, t_min, t_max) = match target {
+            PathTarget::A(change) if token_a + change > Decimal::ZERO => {
+                let growth = change / token_a;
+                let t_max =
+                    (change > Decimal::ZERO).then(|| (Decimal::ONE + growth) / DUST - Decimal::ONE);
+                (ln_1p(growth)?, -Decimal::ONE, t_max)
+            }
+            PathTarget::B(change) if token_b + change > Decimal::ZERO => {
+                let growth = change / token_b;
+                let t_min = if change > Decimal::ZERO {
+                    DUST / (Decimal::ONE + growth) - Decimal::ONE
+                } else {
+                    -Decimal::ONE
+                };
+                (-ln_1p(growth)?, t_min, None)
+            }
+            _ => {
+                let (token, change, available) = match target {
+                    PathTarget::A(change) => (Token::A, change, token_a),
+                    PathTarget::B(change) => (Token::B, change, token_b),
+                };
+                return Err(ArppError::InsufficientLiquidity {
+                    token,
+                    needed: -change,
+                    available,
+                });
+            }
+        };
+        Ok(Self {
+            kernel,
+            token_a,
+            token_b,
+            offset: kernel.integral_of(log_change),
+            t_min,
+            t_max,
+            last_t: Decimal::ZERO,
+            last_j: Decimal::ZERO,
+        })
+    }
+
+    /// Returns `J(t)`, integrating the kernel from the last evaluated displacement.
+    fn integral_to(&mut self, t: Decimal) -> Decimal {
+        self.last_j += integrate_path(self.kernel, self.last_t, t);
+        self.last_t = t;
+        self.last_j
+    }
+
+    /// Evaluates the residual and its derivative at `t`.
+    fn residual(&mut self, t: Decimal) -> (Decimal, Decimal) {
+        let j = self.integral_to(t);
+        (j - self.offset, self.kernel.at(t))
+    }
+
+    /// Returns the error of a path that would drain the balance of `token`.
+    fn drained(&self, token: Token) -> ArppError {
+        let balance = match token {
+            Token::A => self.token_a,
+            Token::B => self.token_b,
+        };
+        ArppError::InsufficientLiquidity {
+            token,
+            needed: balance,
+            available: balance,
+        }
+    }
+
+    /// Finds the displacement at the end of the path with a bracketed Newton iteration.
+    fn solve(&mut self) -> Result<Decimal, ArppError> {
+        let mut t = Decimal::ZERO;
+        let mut lower: Option<Decimal> = None;
+        let mut upper: Option<Decimal> = None;
+
+        for _ in 0..MAX_ITERATIONS {
+            let (h, dh) = self.residual(t);
+            if h.is_zero() {
+                return Ok(t);
+            }
+            if h < Decimal::ZERO {
+                if self.t_max == Some(t) {
+                    return Err(self.drained(Token::B));
+                }
+                lower = Some(t);
+            } else {
+                if t == self.t_min {
+                    return Err(self.drained(Token::A));
+                }
+                upper = Some(t);
+            }
+
+            // A vanishing kernel leaves no usable Newton step, only the bracket.
+            let scale = Decimal::ONE + t;
+            let step = (dh > Decimal::ZERO).then(|| h.checked_div(dh)).flatten();
+            let newton = step.map_or(t, |step| t - step);
+            if step.is_some_and(|step| step.abs() <= RATIO_TOLERANCE * scale) {
+                return Ok(newton);
+            }
+
+            // Keep the next ratio within a geometric distance of the current one and
+            // inside the bracket, walking towards the drain limits otherwise.
+            let down = scale / BRACKET_FACTOR - Decimal::ONE;
+            let up = scale * BRACKET_FACTOR - Decimal::ONE;
+            let mut next = newton.clamp(down, up);
+            if next <= lower.unwrap_or(self.t_min) || upper.is_some_and(|hi| next >= hi) {
+                next = match (lower, upper) {
+                    (Some(lo), Some(hi)) => (lo + hi) / Decimal::TWO,
+                    (None, Some(_)) => self.t_min.max(down),
+                    (_, None) => up,
+                };
+            }
+            t = match self.t_max {
+                Some(t_max) => next.min(t_max),
+                None => next,
+            };
+        }
+        Err(ArppError::NoConvergence)
+    }
+}
+
+#[cfg(test)]
+mod tests_swap_path {
+    use super::*;
+    use crate::arpp::formula::arpp;
+    use assert_approx_eq::assert_approx_eq;
+    use rust_decimal_macros::dec;
+
+    fn price(r: Decimal) -> Decimal {
+        arpp(dec!(1), dec!(0.5), dec!(1), r)
+    }
+
+    #[test]
+    fn test_constant_rate_is_linear() {
+        let out =
+            path_amount_out(dec!(1000), dec!(1000), Token::A, dec!(10), &|_| dec!(2)).unwrap();
+        assert_approx_eq!(out, dec!(20), dec!(0.0000000001));
+    }
+
+    #[test]
+    fn test_constant_rate_uses_inverse_price_for_quote() {
+        let out =
+            path_amount_out(dec!(1000), dec!(1000), Token::B, dec!(10), &|_| dec!(2)).unwrap();
+        assert_approx_eq!(out, dec!(5), dec!(0.0000000001));
+    }
+
+    #[test]
+    fn test_round_trip_returns_no_more_than_paid() {
+        for p_ref in [dec!(0.000001), dec!(0.001), dec!(1000), dec!(1000000)] {
+            let price = |r: Decimal| arpp(p_ref, dec!(0.5), dec!(1), r);
+            for token_in in [Token::A, Token::B] {
+                // Keep the trade at a few percent of the pool whichever token is cheaper
+                let amount_in = dec!(10) / token_in.rate(p_ref).max(Decimal::ONE);
+                let out =
+                    path_amount_out(dec!(1000), dec!(1000), token_in, amount_in, &price).unwrap();
+                let (token_a, token_b) = match token_in {
+                    Token::A => (dec!(1000) + amount_in, dec!(1000) - out),
+                    Token::B => (dec!(1000) - out, dec!(1000) + amount_in),
+                };
+                let back =
+                    path_amount_out(token_a, token_b, token_in.other(), out, &price).unwrap();
+                assert!(
+                    back <= amount_in,
+                    "{:?} in at p_ref {}: {}",
+                    token_in,
+                    p_ref,
+                    back
+                );
+                assert_approx_eq!(back, amount_in, amount_in * dec!(0.000001));
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_independence() {
+        let whole = path_amount_out(dec!(1000), dec!(1000), Token::A, dec!(100), &price).unwrap();
+
+        let first = path_amount_out(dec!(1000), dec!(1000), Token::A, dec!(60), &price).unwrap();
+        let second =
+            path_amount_out(dec!(1060), dec!(1000) - first, Token::A, dec!(40), &price).unwrap();
+
+        assert_approx_eq!(whole, first + second, dec!(0.000000001));
+    }
+
+    #[test]
+    fn test_amount_to_ratio_lands_on_ratio() {
+        for (token_in, ratio) in [(Token::A, dec!(0.8)), (Token::B, dec!(1.2))] {
+            let amount_in =
+                path_amount_to_ratio(dec!(1000), dec!(1000), token_in, ratio, &price).unwrap();
+            let out = path_amount_out(dec!(1000), dec!(1000), token_in, amount_in, &price).unwrap();
+            let (token_a, token_b) = match token_in {
+                Token::A => (dec!(1000) + amount_in, dec!(1000) - out),
+                Token::B => (dec!(1000) - out, dec!(1000) + amount_in),
+            };
+            assert_approx_eq!(token_ratio(token_a, token_b), ratio, dec!(0.000000001));
+        }
+        assert!(path_amount_to_ratio(dec!(1000), dec!(1000), Token::A, dec!(1.2), &price).is_err());
+        assert!(path_amount_to_ratio(dec!(1000), dec!(1000), Token::B, dec!(0.8), &price).is_err());
+    }
+
+    #[test]
+    fn test_split_independence_b_to_a() {
+        let whole = path_amount_out(dec!(1000), dec!(1000), Token::B, dec!(100), &price).unwrap();
+
+        let mut token_a = dec!(1000);
+        let mut token_b = dec!(1000);
+        let mut total = Decimal::ZERO;
+        for _ in 0..10 {
+            let out = path_amount_out(token_a, token_b, Token::B, dec!(10), &price).unwrap();
+            token_a -= out;
+            token_b += dec!(10);
+            total += out;
+        }
+
+        assert_approx_eq!(whole, total, dec!(0.000000001));
+    }
+
+    #[test]
+    fn test_price_impact() {
+        let small = path_amount_out(dec!(1000), dec!(1000), Token::A, dec!(1), &price).unwrap();
+        let large = path_amount_out(dec!(1000), dec!(1000), Token::A, dec!(100), &price).unwrap();
+        assert!(large / dec!(100) < small);
+    }
+
+    #[test]
+    fn test_drained_pool() {
+        let result = path_amount_out(dec!(1000), dec!(1000), Token::A, dec!(5000), &price);
+        assert_eq!(
+            result,
+            Err(ArppError::InsufficientLiquidity {
+                token: Token::B,
+                needed: dec!(1000),
+                available: dec!(1000),
+            })
+        );
+        let result = path_amount_out(dec!(1000), dec!(1000), Token::B, dec!(5000), &price);
+        assert!(matches!(
+            result,
+            Err(ArppError::InsufficientLiquidity {
+                token: Token::A,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_output_is_truncated() {
+        let out = path_amount_out(dec!(1000), dec!(1000), Token::B, dec!(3), &price).unwrap();
+        assert!(out.mantissa().abs() < 10i128.pow(OUTPUT_SIGNIFICANT_DIGITS));
+    }
+
+    #[test]
+    fn test_tiny_amounts_round_to_themselves() {
+        let tiny = dec!(0.0000000000000000000000001001);
+        assert_eq!(
+            round_significant(tiny, RoundingStrategy::ToZero),
+            Some(tiny)
+        );
+        assert_eq!(
+            round_significant(dec!(1.23456789012345678), RoundingStrategy::AwayFromZero),
+            Some(dec!(1.23456789012346))
+        );
+    }
+
+    #[test]
+    fn test_average_price_is_quoted_in_b_per_a() {
+        let a_in = SwapResult::new(
+            Token::A,
+            dec!(10),
+            dec!(20),
+            Decimal::ZERO,
+            dec!(2),
+            dec!(2),
+        );
+        let b_in = SwapResult::new(
+            Token::B,
+            dec!(20),
+            dec!(10),
+            Decimal::ZERO,
+            dec!(2),
+            dec!(2),
+        );
+        assert_eq!(a_in.average_price, dec!(2));
+        assert_eq!(b_in.average_price, dec!(2));
+        assert_eq!(a_in.token_out(), Token::B);
+        assert_eq!(b_in.token_out(), Token::A);
+    }
+
+    #[test]
+    fn test_price_impact_in_basis_points() {
+        let result = SwapResult::new(
+            Token::A,
+            dec!(10),
+            dec!(19.8),
+            Decimal::ZERO,
+            dec!(2),
+            dec!(1.96),
+        );
+        assert_eq!(result.price_impact_bps, dec!(-100));
+        assert_eq!(result.price_after, dec!(1.96));
+        let result = SwapResult::new(
+            Token::B,
+            dec!(20.2),
+            dec!(10),
+            Decimal::ZERO,
+            dec!(2),
+            dec!(2.04),
+        );
+        assert_eq!(result.price_impact_bps, dec!(100));
+        assert_eq!(result.price_after, dec!(2.04));
+
+        // The fee is not part of the impact
+        let result = SwapResult::new(Token::A, dec!(10.1), dec!(20), dec!(0.1), dec!(2), dec!(2));
+        assert_eq!(result.price_impact_bps, Decimal::ZERO);
+        assert!(result.average_price < dec!(2));
+    }
+
+    #[test]
+    fn test_amount_in_delivers_requested_amount_out() {
+        for token_out in [Token::A, Token::B] {
+            for amount_out in [dec!(0.000001), dec!(10), dec!(500), dec!(999)] {
+                let amount_in = path_amount_in(
+                    dec!(1000),
+                    dec!(1000),
+                    token_out,
+                    amount_out,
+                    Decimal::ZERO,
+                    &price,
+                )
+                .unwrap();
+                let delivered =
+                    path_amount_out(dec!(1000), dec!(1000), token_out.other(), amount_in, &price)
+                        .unwrap();
+                assert!(
+                    delivered >= amount_out,
+                    "{:?} out {}: {}",
+                    token_out,
+                    amount_out,
+                    delivered
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_amount_in_is_close_to_path_integral() {
+        let amount_in = path_amount_in(
+            dec!(1000),
+            dec!(1000),
+            Token::B,
+            dec!(20),
+            Decimal::ZERO,
+            &|_| dec!(2),
+        )
+        .unwrap();
+        assert!(amount_in >= dec!(10));
+        assert_approx_eq!(amount_in, dec!(10), dec!(0.0000000001));
+    }
+
+    #[test]
+    fn test_amount_in_includes_fee() {
+        let fee_bps = dec!(30);
+        let amount_in =
+            path_amount_in(dec!(1000), dec!(1000), Token::B, dec!(20), fee_bps, &|_| {
+                dec!(2)
+            })
+            .unwrap();
+        let net = amount_in - input_fee(amount_in, fee_bps);
+        assert!(net >= dec!(10));
+        assert_approx_eq!(amount_in, dec!(10) / dec!(0.997), dec!(0.0000000001));
+    }
+
+    #[test]
+    fn test_input_fee() {
+        assert_eq!(input_fee(dec!(1000), dec!(30)), dec!(3));
+        assert_eq!(input_fee(dec!(1000), Decimal::ZERO), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_amount_in_for_drained_pool_fails() {
+        assert!(path_amount_in(
+            dec!(1000),
+            dec!(1000),
+            Token::B,
+            dec!(1000),
+            Decimal::ZERO,
+            &price
+        )
+        .is_err());
+        assert!(path_amount_in(
+            dec!(1000),
+            dec!(1000),
+            Token::A,
+            dec!(2000),
+            Decimal::ZERO,
+            &price
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_check_deadline() {
+        let deadline = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        assert!(check_deadline(None, deadline).is_ok());
+        assert!(check_deadline(Some(deadline), deadline - chrono::Duration::minutes(1)).is_ok());
+        assert!(check_deadline(Some(deadline), deadline).is_ok());
+
+        let now = deadline + chrono::Duration::seconds(1);
+        let error = check_deadline(Some(deadline), now).unwrap_err();
+        assert_eq!(error, ArppError::DeadlineExpired { deadline, now });
+        assert!(error.to_string().starts_with("Swap deadline"));
+    }
+
+    #[test]
+    fn test_token_rate() {
+        assert_eq!(Token::BASE.rate(dec!(4)), dec!(4));
+        assert_eq!(Token::QUOTE.rate(dec!(4)), dec!(0.25));
+    }
+
+    #[test]
+    fn test_token_other() {
+        assert_eq!(Token::A.other(), Token::B);
+        assert_eq!(Token::B.other(), Token::A);
+    }
+}
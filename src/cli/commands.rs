@@ -4,7 +4,9 @@
    Date: 10/9/24
 ******************************************************************************/
 
+use crate::arpp::fee::DynamicFee;
 use crate::arpp::liquidity_pool::LiquidityPool;
+use crate::arpp::oracle::RandomWalkOracle;
 use crate::simulation::monte_carlo::MonteCarloSimulation;
 use crate::simulation::result::run_timed_simulation;
 use crate::simulation::strategies::{MeanReversionStrategy, RandomStrategy, TradingStrategy};
@@ -39,6 +41,11 @@ pub enum SimulationCommand {
 /// * `max_swap_amount` - The maximum amount that can be swapped during a simulation step. Default value is 10.
 /// * `initial_token_a` - The initial amount of token A. Default value is 1000.
 /// * `initial_token_b` - The initial amount of token B. Default value is 1000.
+/// * `fee_bps` - The swap fee of the pool in basis points. Default value is 0.
+/// * `fee_slope` - Basis points of fee per basis point a swap moves the price away
+///   from `p_ref`. A positive slope makes `fee_bps` the base of a dynamic fee.
+///   Default value is 0.
+/// * `max_fee_bps` - The cap of the dynamic fee in basis points. Default value is 100.
 ///
 /// The `Args` derive macro is used to parse command line arguments based on the struct definition.
 #[derive(Args)]
@@ -55,6 +62,12 @@ pub struct RandomSimulationArgs {
     initial_token_a: Decimal,
     #[arg(long, default_value = "1000")]
     initial_token_b: Decimal,
+    #[arg(long, default_value = "0")]
+    fee_bps: Decimal,
+    #[arg(long, default_value = "0")]
+    fee_slope: Decimal,
+    #[arg(long, default_value = "100")]
+    max_fee_bps: Decimal,
 }
 
 /// Struct representing the arguments for mean reversion simulation.
@@ -71,6 +84,10 @@ pub struct RandomSimulationArgs {
 /// - `swap_amount`: The amount to swap when the threshold is breached (default: 10).
 /// - `initial_token_a`: The initial amount of token A for the simulation (default: 1000).
 /// - `initial_token_b`: The initial amount of token B for the simulation (default: 1000).
+/// - `fee_bps`: The swap fee of the pool in basis points (default: 0).
+/// - `fee_slope`: Basis points of fee per basis point a swap moves the price away
+///   from `p_ref`; a positive slope makes `fee_bps` the base of a dynamic fee (default: 0).
+/// - `max_fee_bps`: The cap of the dynamic fee in basis points (default: 100).
 #[derive(Args)]
 pub struct MeanReversionSimulationArgs {
     #[arg(long, default_value = "1000")]
@@ -87,6 +104,12 @@ pub struct MeanReversionSimulationArgs {
     initial_token_a: Decimal,
     #[arg(long, default_value = "1000")]
     initial_token_b: Decimal,
+    #[arg(long, default_value = "0")]
+    fee_bps: Decimal,
+    #[arg(long, default_value = "0")]
+    fee_slope: Decimal,
+    #[arg(long, default_value = "100")]
+    max_fee_bps: Decimal,
 }
 
 /// Asynchronously runs a simulation based on the provided simulation command.
@@ -116,6 +139,8 @@ pub async fn run_simulation(cmd: &SimulationCommand) -> Result<(), Box<dyn Error
                 args.steps,
                 args.initial_token_a,
                 args.initial_token_b,
+                args.fee_bps,
+                dynamic_fee(args.fee_bps, args.fee_slope, args.max_fee_bps)?,
             )
             .await
         }
@@ -130,12 +155,30 @@ pub async fn run_simulation(cmd: &SimulationCommand) -> Result<(), Box<dyn Error
                 args.steps,
                 args.initial_token_a,
                 args.initial_token_b,
+                args.fee_bps,
+                dynamic_fee(args.fee_bps, args.fee_slope, args.max_fee_bps)?,
             )
             .await
         }
     }
 }
 
+/// Builds the dynamic fee of the simulated pool, if `fee_slope` is positive.
+///
+/// # Errors
+///
+/// Returns an error if the fee, the slope or the cap is invalid.
+fn dynamic_fee(
+    fee_bps: Decimal,
+    fee_slope: Decimal,
+    max_fee_bps: Decimal,
+) -> Result<Option<DynamicFee>, Box<dyn Error>> {
+    if fee_slope.is_zero() {
+        return Ok(None);
+    }
+    Ok(Some(DynamicFee::new(fee_bps, fee_slope, max_fee_bps)?))
+}
+
 /// Runs a Monte Carlo simulation for a given trading strategy.
 ///
 /// This asynchronous function sets up and executes a Monte Carlo simulation
@@ -148,6 +191,9 @@ pub async fn run_simulation(cmd: &SimulationCommand) -> Result<(), Box<dyn Error
 /// * `steps` - The number of steps to perform in each iteration of the simulation.
 /// * `initial_token_a` - The initial amount of token A in the liquidity pool.
 /// * `initial_token_b` - The initial amount of token B in the liquidity pool.
+/// * `fee_bps` - The swap fee of the liquidity pool in basis points.
+/// * `dynamic_fee` - The fee scaling with the deviation from `p_ref`, charged
+///   instead of `fee_bps` if set.
 ///
 /// # Returns
 ///
@@ -158,28 +204,32 @@ pub async fn run_simulation(cmd: &SimulationCommand) -> Result<(), Box<dyn Error
 ///
 /// This function will return an error if any of the following scenarios occur:
 /// - The `run_timed_simulation` function fails to execute or returns an error.
+/// - `fee_bps` is not a valid fee.
 async fn run_monte_carlo(
     strategy: Box<dyn TradingStrategy>,
     iterations: usize,
     steps: usize,
     initial_token_a: Decimal,
     initial_token_b: Decimal,
+    fee_bps: Decimal,
+    dynamic_fee: Option<DynamicFee>,
 ) -> Result<(), Box<dyn Error>> {
-    let initial_pool = LiquidityPool::new(
+    let mut initial_pool = LiquidityPool::try_new(
         initial_token_a,
         initial_token_b,
         Decimal::ONE,       // p_ref
         Decimal::new(5, 1), // alpha (0.5)
         Decimal::ONE,       // beta
-    );
+    )?;
+    initial_pool.set_fee_bps(fee_bps)?;
+    initial_pool.set_dynamic_fee(dynamic_fee)?;
 
     let mut simulation = MonteCarloSimulation::new(
         initial_pool,
         iterations,
         steps,
         strategy,
-        Decimal::ONE,
-        Decimal::ONE,
+        Box::new(RandomWalkOracle::new(Decimal::ONE, Decimal::ONE)),
     );
     let (result, duration) = run_timed_simulation(&mut simulation).await?;
 
@@ -191,6 +241,11 @@ async fn run_monte_carlo(
     );
     info!("Maximum price: {}", result.max_price);
     info!("Minimum price: {}", result.min_price);
+    info!(
+        "Fee revenue: Token A {}, Token B {}",
+        result.metrics.fee_revenue_a, result.metrics.fee_revenue_b
+    );
+    info!("Fee income: {}", result.metrics.fee_income);
 
     Ok(())
 }
@@ -198,6 +253,8 @@ async fn run_monte_carlo(
 #[cfg(test)]
 mod tests_commands {
     use super::*;
+    use crate::amm::traits::Amm;
+    use crate::arpp::error::ArppError;
     use rust_decimal::prelude::Decimal;
     use std::error::Error;
     use std::future::Future;
@@ -210,9 +267,9 @@ mod tests_commands {
     impl TradingStrategy for DummyStrategy {
         fn execute<'a>(
             &'a self,
-            _pool: &'a mut LiquidityPool,
+            _pool: &'a mut dyn Amm,
             _current_price: Decimal,
-        ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>> + 'a>> {
+        ) -> Pin<Box<dyn Future<Output = Result<(), ArppError>> + 'a>> {
             Box::pin(async { Ok(()) })
         }
     }
@@ -226,6 +283,9 @@ mod tests_commands {
             max_swap_amount: Decimal::new(10, 0),
             initial_token_a: Decimal::new(1000, 0),
             initial_token_b: Decimal::new(1000, 0),
+            fee_bps: Decimal::ZERO,
+            fee_slope: Decimal::ZERO,
+            max_fee_bps: Decimal::new(100, 0),
         };
         let cmd = SimulationCommand::Random(args);
         let result = run_simulation(&cmd).await;
@@ -243,6 +303,9 @@ mod tests_commands {
             swap_amount: Decimal::new(10, 0),
             initial_token_a: Decimal::new(1000, 0),
             initial_token_b: Decimal::new(1000, 0),
+            fee_bps: Decimal::new(30, 0),
+            fee_slope: Decimal::new(5, 1),
+            max_fee_bps: Decimal::new(100, 0),
         };
         let cmd = SimulationCommand::MeanReversion(args);
         let result = run_simulation(&cmd).await;
@@ -261,6 +324,8 @@ mod tests_commands {
                 10,
                 Decimal::new(1000, 0),
                 Decimal::new(1000, 0),
+                Decimal::ZERO,
+                None,
             )
             .await;
             assert!(result.is_ok());
@@ -279,6 +344,8 @@ mod tests_commands {
                 10,
                 Decimal::new(1000, 0),
                 Decimal::new(1000, 0),
+                Decimal::ZERO,
+                None,
             )
             .await;
             assert!(result.is_ok());
@@ -4,6 +4,7 @@
    Date: 10/9/24
 ******************************************************************************/
 
+pub mod amm;
 pub mod analysis;
 pub mod arpp;
 pub mod cli;
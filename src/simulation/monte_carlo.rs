@@ -4,7 +4,10 @@
    Date: 10/9/24
 ******************************************************************************/
 
+use crate::amm::traits::Amm;
+use crate::arpp::error::ArppError;
 use crate::arpp::liquidity_pool::LiquidityPool;
+use crate::arpp::oracle::{PriceOracle, RandomWalkOracle};
 use crate::simulation::strategies::TradingStrategy;
 
 use crate::analysis::metrics::{
@@ -20,32 +23,34 @@ use rust_decimal_macros::dec;
 use std::error::Error;
 use tracing::{debug, info};
 
+/// The provider ID under which a simulation tops up the liquidity of its pool.
+pub const SIMULATION_PROVIDER: &str = "simulation";
+
 /// A struct representing a Monte Carlo Simulation for a liquidity pool with a specific trading strategy.
 ///
 /// The `MonteCarloSimulation` struct is used to run a Monte Carlo simulation on a given liquidity pool,
+/// the ARPP `LiquidityPool` by default or any other `Amm`,
 /// with a specified strategy and other parameters such as the number of iterations and steps per iteration.
 /// This simulation helps in analyzing the behavior of the liquidity pool under different market conditions
 /// by using probabilistic methods.
 ///
 /// # Fields
-/// - `pool`: The liquidity pool on which the simulation is performed.
+/// - `pool`: The AMM on which the simulation is performed.
 /// - `iterations`: The number of iterations the simulation will run.
 /// - `steps_per_iteration`: The number of steps per iteration in the simulation.
 /// - `strategy`: The trading strategy used during the simulation.
-/// - `alpha`: A parameter that influences the reference price setting.
-/// - `beta`: A parameter that influences the reference price setting.
+/// - `oracle`: The source of the reference price, queried at every step.
 /// - `price_history`: A vector that records the price history during the simulation.
 /// - `metrics_history`: A vector that records various metrics of the pool during the simulation.
 ///
-pub struct MonteCarloSimulation {
-    pool: LiquidityPool,
+pub struct MonteCarloSimulation<P: Amm = LiquidityPool> {
+    pool: P,
     iterations: usize,
     steps_per_iteration: usize,
     strategy: Box<dyn TradingStrategy>,
     price_history: Vec<Decimal>,
     metrics_history: Vec<PoolMetrics>,
-    alpha: Decimal,
-    beta: Decimal,
+    oracle: Box<dyn PriceOracle>,
 }
 
 /// A struct representing a Monte Carlo Simulation for a liquidity pool with a specific trading strategy.
@@ -53,18 +58,18 @@ pub struct MonteCarloSimulation {
 /// # Methods
 /// - `new`: Constructs a new `MonteCarloSimulation` instance.
 /// - `run`: Runs the Monte Carlo simulation with the given strategy.
-/// - `add_liquidity_if_needed`: Adds liquidity to the pool if it falls below a certain threshold.
+/// - `add_liquidity_if_needed`: Deposits liquidity as `SIMULATION_PROVIDER` if a balance
+///   falls below a certain threshold.
 /// - `get_price_history`: Returns the price history recorded during the simulation.
 /// - `get_metrics_history`: Returns the metrics history recorded during the simulation.
 /// - `get_final_pool`: Returns the final state of the liquidity pool after the simulation.
-impl MonteCarloSimulation {
+impl<P: Amm + Clone> MonteCarloSimulation<P> {
     pub fn new(
-        pool: LiquidityPool,
+        pool: P,
         iterations: usize,
         steps_per_iteration: usize,
         strategy: Box<dyn TradingStrategy>,
-        alpha: Decimal,
-        beta: Decimal,
+        oracle: Box<dyn PriceOracle>,
     ) -> Self {
         Self {
             pool,
@@ -73,8 +78,7 @@ impl MonteCarloSimulation {
             strategy,
             price_history: Vec::new(),
             metrics_history: Vec::new(),
-            alpha,
-            beta,
+            oracle,
         }
     }
 
@@ -83,7 +87,7 @@ impl MonteCarloSimulation {
     ///
     /// # Returns
     /// `SimulationResult` with the average price change, liquidity change, max and min price.
-    pub async fn run(&mut self) -> Result<SimulationResult, Box<dyn Error>> {
+    pub async fn run(&mut self) -> Result<SimulationResult, ArppError> {
         if self.iterations == 0 {
             return Ok(SimulationResult::default());
         }
@@ -114,9 +118,12 @@ impl MonteCarloSimulation {
 
             for _ in 0..self.steps_per_iteration {
                 let current_price = self.pool.get_price();
-                self.pool.set_p_ref(self.alpha, self.beta); // set the reference price for this step
+                // set the reference price for this step
+                if let Err(e) = self.pool.update_p_ref(self.oracle.as_mut()) {
+                    debug!("Oracle update error: {}", e);
+                }
 
-                accumulate_pool_metrics(&mut self.pool, &mut pool_metrics, &initial_step);
+                accumulate_pool_metrics(&self.pool, &mut pool_metrics, &initial_step);
 
                 self.add_liquidity_if_needed()?;
 
@@ -143,19 +150,22 @@ impl MonteCarloSimulation {
         })
     }
 
-    /// Adds liquidity to the pool if it falls below a certain threshold.
-    fn add_liquidity_if_needed(&mut self) -> Result<(), Box<dyn Error>> {
+    /// Deposits liquidity as `SIMULATION_PROVIDER` if a balance falls below half
+    /// of the other.
+    fn add_liquidity_if_needed(&mut self) -> Result<(), ArppError> {
         let token_a_liquidity = self.pool.get_balances().0;
         let token_b_liquidity = self.pool.get_balances().1;
 
         if token_a_liquidity < (token_b_liquidity / dec!(2)) {
             let amount_a_to_add = (token_b_liquidity / dec!(2)) - token_a_liquidity;
-            self.pool.add_liquidity(amount_a_to_add, dec!(0))?;
+            self.pool
+                .deposit(SIMULATION_PROVIDER, amount_a_to_add, dec!(0))?;
             debug!("Adding liquidity to token A: {}", amount_a_to_add);
         }
         if token_b_liquidity < (token_a_liquidity / dec!(2)) {
             let amount_b_to_add = (token_a_liquidity / dec!(2)) - token_b_liquidity;
-            self.pool.add_liquidity(dec!(0), amount_b_to_add)?;
+            self.pool
+                .deposit(SIMULATION_PROVIDER, dec!(0), amount_b_to_add)?;
             debug!("Adding liquidity to token B: {}", amount_b_to_add);
         }
 
@@ -170,7 +180,7 @@ impl MonteCarloSimulation {
         self.metrics_history.clone()
     }
 
-    pub fn get_final_pool(&self) -> LiquidityPool {
+    pub fn get_final_pool(&self) -> P {
         self.pool.clone()
     }
 }
@@ -223,8 +233,7 @@ async fn run_monte_carlo(
         iterations,
         steps,
         strategy,
-        alpha,
-        beta,
+        Box::new(RandomWalkOracle::new(alpha, beta)),
     );
     let (result, duration) = run_timed_simulation(&mut simulation).await?;
 
@@ -244,6 +253,11 @@ async fn run_monte_carlo(
     info!("Liquidity Depth: {}", pool_metrics.liquidity_depth);
     info!("Trading Volume: {}", pool_metrics.trading_volume);
     info!("Impermanent Loss: {}", pool_metrics.impermanent_loss);
+    info!(
+        "Fee Revenue: Token A {}, Token B {}",
+        pool_metrics.fee_revenue_a, pool_metrics.fee_revenue_b
+    );
+    info!("Fee Income: {}", pool_metrics.fee_income);
 
     let analysis = analyze_simulation_results(&result);
     info!("Simulation Analysis:");
@@ -272,6 +286,11 @@ async fn run_monte_carlo(
 #[cfg(test)]
 mod tests_monte_carlo {
     use super::*;
+    use crate::amm::constant_product::ConstantProductPool;
+    use crate::amm::constant_sum::ConstantSumPool;
+    use crate::amm::stable_swap::StableSwapPool;
+    use crate::arpp::oracle::{ConstantOracle, ReplayOracle};
+    use crate::simulation::strategies::MeanReversionStrategy;
     use rust_decimal_macros::dec;
     use std::future::Future;
     use std::pin::Pin;
@@ -283,20 +302,75 @@ mod tests_monte_carlo {
     impl TradingStrategy for MockTradingStrategy {
         fn execute<'a>(
             &'a self,
-            pool: &'a mut LiquidityPool,
+            pool: &'a mut dyn Amm,
             _: Decimal,
-        ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>> + 'a>> {
+        ) -> Pin<Box<dyn Future<Output = Result<(), ArppError>> + 'a>> {
             Box::pin(async move {
                 let amount_a = Decimal::new(10, 0);
                 let amount_b = Decimal::new(5, 0);
-                pool.add_liquidity(amount_a, amount_b)?;
-                let swapped_b = pool.swap_a_to_b(amount_a)?;
+                pool.deposit("mock", amount_a, amount_b)?;
+                let swapped_b = pool.swap_a_to_b(amount_a)?.amount_out;
                 pool.swap_b_to_a(swapped_b)?;
                 Ok(())
             })
         }
     }
 
+    fn random_walk() -> Box<dyn PriceOracle> {
+        Box::new(RandomWalkOracle::new(dec!(1), dec!(1)))
+    }
+
+    #[tokio::test]
+    async fn test_monte_carlo_with_constant_oracle() {
+        let initial_pool = LiquidityPool::new(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1));
+
+        let strategy = Box::new(MockTradingStrategy {});
+        let oracle = Box::new(ConstantOracle::new(dec!(2)));
+        let mut simulation = MonteCarloSimulation::new(initial_pool, 3, 5, strategy, oracle);
+        let result = simulation.run().await.unwrap();
+
+        let p_ref_history = result.metrics.get_p_ref();
+        assert_eq!(p_ref_history.len(), 15);
+        assert!(p_ref_history.iter().all(|&p_ref| p_ref == dec!(2)));
+    }
+
+    async fn run_on_path<P: Amm + Clone>(pool: P, path: &[Decimal]) -> SimulationResult {
+        let strategy = Box::new(MeanReversionStrategy::new(dec!(0.01), dec!(20)));
+        let oracle = Box::new(ReplayOracle::new(path.to_vec()));
+        let mut simulation = MonteCarloSimulation::new(pool, 1, path.len(), strategy, oracle);
+        simulation.run().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_same_path_replays_through_every_amm() {
+        let path: Vec<Decimal> = (0..20)
+            .map(|step| dec!(1) + Decimal::from(step % 5) / dec!(50))
+            .collect();
+        let results = [
+            run_on_path(
+                LiquidityPool::new(dec!(1000), dec!(1000), dec!(1), dec!(0.5), dec!(1)),
+                &path,
+            )
+            .await,
+            run_on_path(ConstantProductPool::new(dec!(1000), dec!(1000)), &path).await,
+            run_on_path(ConstantSumPool::new(dec!(1000), dec!(1000), dec!(1)), &path).await,
+            run_on_path(
+                StableSwapPool::new(dec!(1000), dec!(1000), dec!(100), dec!(1)),
+                &path,
+            )
+            .await,
+        ];
+
+        for result in &results {
+            assert_eq!(result.metrics.get_p_ref(), path);
+        }
+        // The constant-sum pool never moves, the others all react to the trades
+        assert_eq!(results[2].max_price, dec!(1));
+        for result in [&results[0], &results[1], &results[3]] {
+            assert_ne!(result.max_price, dec!(1));
+        }
+    }
+
     #[tokio::test]
     async fn test_monte_carlo_simulation() {
         let initial_pool = LiquidityPool::new(
@@ -309,7 +383,7 @@ mod tests_monte_carlo {
 
         let strategy = Box::new(MockTradingStrategy {});
         let mut simulation =
-            MonteCarloSimulation::new(initial_pool, 10, 5, strategy, dec!(1), dec!(1));
+            MonteCarloSimulation::new(initial_pool, 10, 5, strategy, random_walk());
         let result = simulation.run().await.unwrap();
 
         assert!(result.average_price_change > Decimal::ZERO);
@@ -330,7 +404,7 @@ mod tests_monte_carlo {
 
         let strategy = Box::new(MockTradingStrategy {});
         let mut simulation =
-            MonteCarloSimulation::new(initial_pool, 10, 5, strategy, dec!(1), dec!(1));
+            MonteCarloSimulation::new(initial_pool, 10, 5, strategy, random_walk());
         let (result, _duration) = run_timed_simulation(&mut simulation).await.unwrap();
 
         assert!(result.average_price_change > Decimal::ZERO);
@@ -352,7 +426,7 @@ mod tests_monte_carlo {
 
         let strategy = Box::new(MockTradingStrategy {});
         let mut simulation =
-            MonteCarloSimulation::new(initial_pool, 100, 50, strategy, dec!(1), dec!(1));
+            MonteCarloSimulation::new(initial_pool, 100, 50, strategy, random_walk());
         let result = simulation.run().await.unwrap();
 
         assert!(result.average_price_change > Decimal::ZERO);
@@ -373,7 +447,7 @@ mod tests_monte_carlo {
 
         let strategy = Box::new(MockTradingStrategy {});
         let mut simulation =
-            MonteCarloSimulation::new(initial_pool, 10, 5, strategy, dec!(1), dec!(1));
+            MonteCarloSimulation::new(initial_pool, 10, 5, strategy, random_walk());
         let result = simulation.run().await.unwrap();
 
         assert!(result.average_price_change >= Decimal::ZERO);
@@ -393,8 +467,7 @@ mod tests_monte_carlo {
         );
 
         let strategy = Box::new(MockTradingStrategy {});
-        let mut simulation =
-            MonteCarloSimulation::new(initial_pool, 0, 0, strategy, dec!(1), dec!(1));
+        let mut simulation = MonteCarloSimulation::new(initial_pool, 0, 0, strategy, random_walk());
         let result = simulation.run().await.unwrap();
 
         assert_eq!(result.average_price_change, Decimal::ZERO);
@@ -415,7 +488,7 @@ mod tests_monte_carlo {
 
         let strategy = Box::new(MockTradingStrategy {});
         let mut simulation =
-            MonteCarloSimulation::new(initial_pool, 10_000, 5, strategy, dec!(1), dec!(1));
+            MonteCarloSimulation::new(initial_pool, 10_000, 5, strategy, random_walk());
         let result = simulation.run().await.unwrap();
 
         assert!(result.average_price_change > Decimal::ZERO);
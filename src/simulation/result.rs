@@ -3,10 +3,12 @@
    Email: jb@taunais.com
    Date: 10/9/24
 ******************************************************************************/
+use crate::amm::traits::Amm;
 use crate::analysis::metrics::PoolMetrics;
+use crate::arpp::error::ArppError;
 use crate::simulation::monte_carlo::MonteCarloSimulation;
 use rust_decimal::Decimal;
-use std::error::Error;
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
 /// Represents the result of a simulation, including various metrics such as
@@ -20,7 +22,7 @@ use std::time::Duration;
 /// * `max_price` - The maximum price recorded during the simulation.
 /// * `min_price` - The minimum price recorded during the simulation.
 /// * `metrics` - A collection of additional metrics related to the pool performance during the simulation.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SimulationResult {
     pub average_price_change: Decimal,
     pub average_liquidity_change: Decimal,
@@ -89,15 +91,15 @@ impl SimulationResult {
 /// * `SimulationResult` - The result of the Monte Carlo simulation.
 /// * `Duration` - The duration it took to run the simulation.
 ///
-/// On failure, it returns a boxed `dyn Error` indicating what went wrong during the simulation.
+/// On failure, it returns the `ArppError` indicating what went wrong during the simulation.
 ///
 /// # Errors
 ///
 /// This function will return an error if the simulation fails to run properly.
 ///
-pub async fn run_timed_simulation(
-    simulation: &mut MonteCarloSimulation,
-) -> Result<(SimulationResult, Duration), Box<dyn Error>> {
+pub async fn run_timed_simulation<P: Amm + Clone>(
+    simulation: &mut MonteCarloSimulation<P>,
+) -> Result<(SimulationResult, Duration), ArppError> {
     let start = std::time::Instant::now();
     let result = simulation.run().await?;
     let duration = start.elapsed();
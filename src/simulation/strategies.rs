@@ -4,16 +4,19 @@
    Date: 10/9/24
 ******************************************************************************/
 
-use crate::arpp::liquidity_pool::LiquidityPool;
+use crate::amm::traits::Amm;
+use crate::arpp::error::ArppError;
 use crate::utils::helpers::random_decimal;
 use rand::prelude::SliceRandom;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
-use std::error::Error;
 use std::future::Future;
 use std::pin::Pin;
 use tracing::debug;
 
+/// The provider ID under which a strategy deposits liquidity.
+pub const STRATEGY_PROVIDER: &str = "strategy";
+
 /// A trait for defining trading strategies in a liquidity pool context.
 ///
 /// This trait requires the implementation of the `execute` method, which will
@@ -27,21 +30,21 @@ use tracing::debug;
 ///
 /// # Arguments
 ///
-/// * `pool` - A mutable reference to a `LiquidityPool`, representing the pool
-///   of liquidity where trades are conducted.
+/// * `pool` - A mutable reference to the `Amm` where trades are conducted, the
+///   ARPP `LiquidityPool` or one of the baseline pools.
 /// * `current_price` - A `Decimal` representing the current price of the asset.
 ///
 /// # Returns
 ///
-/// A `Future` that resolves to a `Result<(), Box<dyn Error>>`, indicating the
+/// A `Future` that resolves to a `Result<(), ArppError>`, indicating the
 /// success or failure of the strategy execution.
 #[allow(clippy::type_complexity)]
 pub trait TradingStrategy: Send + Sync {
     fn execute<'a>(
         &'a self,
-        pool: &'a mut LiquidityPool,
+        pool: &'a mut dyn Amm,
         current_price: Decimal,
-    ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>> + 'a>>;
+    ) -> Pin<Box<dyn Future<Output = Result<(), ArppError>> + 'a>>;
 }
 
 /// A struct representing a strategy that uses randomness for decision making.
@@ -74,30 +77,32 @@ impl TradingStrategy for RandomStrategy {
     /// # Arguments
     ///
     /// * `self` - A reference to the struct or instance which implements this function.
-    /// * `pool` - A mutable reference to a `LiquidityPool` where the operation will take place.
+    /// * `pool` - A mutable reference to the `Amm` where the operation will take place.
     /// * `_` - A `Decimal` value, not currently used in this function but reserved for future use.
     ///
     /// # Returns
     ///
-    /// A `Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>> + 'a>>` representing the async result
-    /// of the operation. The operation may succeed or yield an error encapsulated in a `Box<dyn Error>`.
+    /// A `Pin<Box<dyn Future<Output = Result<(), ArppError>> + 'a>>` representing the async result
+    /// of the operation. The operation may succeed or yield the `ArppError` of the pool.
     ///
     /// # Details
     ///
     /// This function uses a randomly generated number to determine if a swap should occur based on a
     /// predefined swap probability (`self.swap_probability`). If the swap condition is met, it
     /// retrieves the balances of two assets within the pool and randomly decides to swap a certain
-    /// amount from one asset to the other. The swap amount is bounded by half of each balance or a
-    /// predefined maximum swap amount (`self.max_swap_amount`).
+    /// amount from one asset to the other. The swap amount is bounded by `self.max_swap_amount`
+    /// and so that neither balance moves by more than 1%, valuing token A in token B at the
+    /// current pool price. Liquidity added to rebalance the pool is bounded by
+    /// `self.max_swap_amount` as well.
     ///
     /// During the operation, debug logs are generated to indicate the direction of the swap and the
     /// amount being swapped.
     ///
     fn execute<'a>(
         &'a self,
-        pool: &'a mut LiquidityPool,
+        pool: &'a mut dyn Amm,
         _: Decimal,
-    ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>> + 'a>> {
+    ) -> Pin<Box<dyn Future<Output = Result<(), ArppError>> + 'a>> {
         Box::pin(async move {
             let mut rng = rand::thread_rng();
             let list = [1, 2, 3];
@@ -105,9 +110,15 @@ impl TradingStrategy for RandomStrategy {
                 .choose(&mut rng)
                 .expect("La lista no puede estar vacía");
             let (balance_a, balance_b) = pool.get_balances();
+            let price = pool.get_price();
 
-            let amount_a = balance_a / dec!(100);
-            let amount_b = balance_b / dec!(100);
+            // Trade at most 1% of either balance, valuing A in B at the pool price
+            let amount_a = (balance_a / dec!(100))
+                .min(balance_b / dec!(100) / price)
+                .min(self.max_swap_amount);
+            let amount_b = (balance_b / dec!(100))
+                .min(balance_a / dec!(100) * price)
+                .min(self.max_swap_amount);
 
             match random_number {
                 3 => {
@@ -125,12 +136,12 @@ impl TradingStrategy for RandomStrategy {
 
                     let diff = balance_a - balance_b;
                     if diff > dec!(0) {
-                        let swap_amount = random_decimal(diff);
-                        pool.add_liquidity(dec!(0), swap_amount)?;
+                        let swap_amount = random_decimal(diff.min(self.max_swap_amount));
+                        pool.deposit(STRATEGY_PROVIDER, dec!(0), swap_amount)?;
                     }
                     if diff < dec!(0) {
-                        let swap_amount = random_decimal(diff.abs());
-                        pool.add_liquidity(swap_amount, dec!(0))?;
+                        let swap_amount = random_decimal(diff.abs().min(self.max_swap_amount));
+                        pool.deposit(STRATEGY_PROVIDER, swap_amount, dec!(0))?;
                     }
                     (balance_a, balance_b) = pool.get_balances();
                     debug!("Balances: A: {:.4} B: {:.4}", balance_a, balance_b);
@@ -171,32 +182,33 @@ impl MeanReversionStrategy {
 impl TradingStrategy for MeanReversionStrategy {
     /// Executes a liquidity pool swap operation asynchronously based on the current price.
     ///
-    /// This function will initiate a swap from token B to token A or vice versa, depending
+    /// This function will initiate a swap from token A to token B or vice versa, depending
     /// on the current price relative to a reference price (`p_ref`) plus or minus a threshold.
+    /// Token A is sold when it trades above `p_ref` and bought when it trades below, so
+    /// every swap moves the pool price back towards `p_ref`.
     ///
     /// # Arguments
     ///
-    /// * `pool` - A mutable reference to the `LiquidityPool` instance where the swap
-    ///            operations will occur.
+    /// * `pool` - A mutable reference to the `Amm` where the swap operations will occur.
     /// * `current_price` - A `Decimal` representing the current price of the token.
     ///
     /// # Returns
     ///
     /// A pinned `Box` containing a `Future` which resolves to a `Result` type:
     /// * `Ok(())` - If the swap operation is executed successfully.
-    /// * `Err(Box<dyn Error>)` - If an error occurs during the swap operation.
+    /// * `Err(ArppError)` - If an error occurs during the swap operation.
     ///
     fn execute<'a>(
         &'a self,
-        pool: &'a mut LiquidityPool,
+        pool: &'a mut dyn Amm,
         current_price: Decimal,
-    ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>> + 'a>> {
+    ) -> Pin<Box<dyn Future<Output = Result<(), ArppError>> + 'a>> {
         Box::pin(async move {
             let diff = current_price / pool.get_p_ref();
             if current_price > pool.get_p_ref() * (dec!(1) + self.swap_threshold) {
-                pool.swap_b_to_a(diff * self.swap_amount)?;
-            } else if current_price < pool.get_p_ref() * (dec!(1) - self.swap_threshold) {
                 pool.swap_a_to_b(diff * self.swap_amount)?;
+            } else if current_price < pool.get_p_ref() * (dec!(1) - self.swap_threshold) {
+                pool.swap_b_to_a(diff * self.swap_amount)?;
             }
             Ok(())
         })
@@ -206,6 +218,8 @@ impl TradingStrategy for MeanReversionStrategy {
 #[cfg(test)]
 mod tests_trading_strategy {
     use super::*;
+    use crate::amm::constant_product::ConstantProductPool;
+    use crate::arpp::liquidity_pool::LiquidityPool;
     use rust_decimal_macros::dec;
     use std::sync::Arc;
     use tokio::sync::Mutex;
@@ -235,7 +249,7 @@ mod tests_trading_strategy {
         let mut pool_guard = pool.lock().await;
         let initial_balance = pool_guard.get_balances();
 
-        strategy.execute(&mut pool_guard, dec!(1)).await.unwrap();
+        strategy.execute(&mut *pool_guard, dec!(1)).await.unwrap();
 
         let final_balance = pool_guard.get_balances();
         assert_ne!(
@@ -258,7 +272,7 @@ mod tests_trading_strategy {
         let mut pool_guard = pool.lock().await;
         let initial_balance = pool_guard.get_balances();
 
-        strategy.execute(&mut pool_guard, dec!(1.2)).await.unwrap();
+        strategy.execute(&mut *pool_guard, dec!(1.2)).await.unwrap();
 
         let final_balance = pool_guard.get_balances();
         assert_ne!(
@@ -266,12 +280,12 @@ mod tests_trading_strategy {
             "Balances should change after swap"
         );
         assert!(
-            final_balance.0 < initial_balance.0,
-            "Token A balance should decrease"
+            final_balance.0 > initial_balance.0,
+            "Token A balance should increase"
         );
         assert!(
-            final_balance.1 > initial_balance.1,
-            "Token B balance should increase"
+            final_balance.1 < initial_balance.1,
+            "Token B balance should decrease"
         );
     }
 
@@ -282,7 +296,7 @@ mod tests_trading_strategy {
         let mut pool_guard = pool.lock().await;
         let initial_balance = pool_guard.get_balances();
 
-        strategy.execute(&mut pool_guard, dec!(0.8)).await.unwrap();
+        strategy.execute(&mut *pool_guard, dec!(0.8)).await.unwrap();
 
         let final_balance = pool_guard.get_balances();
         assert_ne!(
@@ -290,15 +304,50 @@ mod tests_trading_strategy {
             "Balances should change after swap"
         );
         assert!(
-            final_balance.0 > initial_balance.0,
-            "Token A balance should increase"
+            final_balance.0 < initial_balance.0,
+            "Token A balance should decrease"
         );
         assert!(
-            final_balance.1 < initial_balance.1,
-            "Token B balance should decrease"
+            final_balance.1 > initial_balance.1,
+            "Token B balance should increase"
         );
     }
 
+    #[tokio::test]
+    async fn test_mean_reversion_strategy_moves_price_towards_p_ref() {
+        let strategy = MeanReversionStrategy::new(dec!(0.01), dec!(10));
+        for above in [true, false] {
+            let pools: [Box<dyn Amm>; 2] = [
+                Box::new(LiquidityPool::new(
+                    dec!(1000),
+                    dec!(1000),
+                    dec!(1),
+                    dec!(0.5),
+                    dec!(1),
+                )),
+                Box::new(ConstantProductPool::new(dec!(1000), dec!(1000))),
+            ];
+            for mut pool in pools {
+                // Buying A pushes its price above p_ref, selling it pushes it below
+                if above {
+                    pool.swap_b_to_a(dec!(100)).unwrap();
+                } else {
+                    pool.swap_a_to_b(dec!(100)).unwrap();
+                }
+                let (price, p_ref) = (pool.get_price(), pool.get_p_ref());
+                assert_eq!(price > p_ref, above);
+
+                strategy.execute(pool.as_mut(), price).await.unwrap();
+
+                let distance = |price: Decimal| (price - p_ref).abs();
+                assert!(
+                    distance(pool.get_price()) < distance(price),
+                    "Price should move towards p_ref"
+                );
+            }
+        }
+    }
+
     #[tokio::test]
     async fn test_mean_reversion_strategy_within_threshold() {
         let strategy = MeanReversionStrategy::new(dec!(0.1), dec!(10));
@@ -306,7 +355,10 @@ mod tests_trading_strategy {
         let mut pool_guard = pool.lock().await;
         let initial_balance = pool_guard.get_balances();
 
-        strategy.execute(&mut pool_guard, dec!(1.05)).await.unwrap();
+        strategy
+            .execute(&mut *pool_guard, dec!(1.05))
+            .await
+            .unwrap();
 
         let final_balance = pool_guard.get_balances();
         assert_eq!(initial_balance, final_balance, "Balances should not change");
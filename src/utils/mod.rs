@@ -4,6 +4,10 @@
    Date: 10/9/24
 ******************************************************************************/
 
+pub(crate) mod atan;
+
 pub mod helpers;
 
 pub mod logger;
+
+pub(crate) mod quadrature;
@@ -0,0 +1,156 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// Abscissae of the 15-point Kronrod rule on `[-1, 1]` (non-negative half).
+///
+/// Odd indices are the nodes of the embedded 7-point Gauss rule.
+const XGK: [Decimal; 8] = [
+    dec!(0.9914553711208126392068546975),
+    dec!(0.9491079123427585245261896840),
+    dec!(0.8648644233597690727897127886),
+    dec!(0.7415311855993944398638647733),
+    dec!(0.5860872354676911302941448383),
+    dec!(0.4058451513773971669066064121),
+    dec!(0.2077849550078984676006894038),
+    dec!(0),
+];
+
+/// Weights of the 15-point Kronrod rule, matching `XGK`.
+const WGK: [Decimal; 8] = [
+    dec!(0.0229353220105292249637320081),
+    dec!(0.0630920926299785532907006632),
+    dec!(0.1047900103222501838398763225),
+    dec!(0.1406532597155259187451895905),
+    dec!(0.1690047266392679028265834266),
+    dec!(0.1903505780647854099132564024),
+    dec!(0.2044329400752988924141619992),
+    dec!(0.2094821410847278280129991749),
+];
+
+/// Weights of the embedded 7-point Gauss rule (nodes `XGK[1]`, `XGK[3]`, `XGK[5]`, `XGK[7]`).
+const WG: [Decimal; 4] = [
+    dec!(0.1294849661688696932706114327),
+    dec!(0.2797053914892766679014677714),
+    dec!(0.3818300505051189449503697755),
+    dec!(0.4179591836734693877551020408),
+];
+
+/// Maximum number of interval bisections performed by `integrate`.
+const MAX_DEPTH: u32 = 12;
+
+/// Error estimate per unit of panel width below which a panel is always accepted.
+///
+/// `Decimal` keeps at most 28 decimal places, so an integrand that small carries
+/// rounding noise of about this size that no amount of bisection can remove.
+const ROUNDING_FLOOR: Decimal = dec!(0.00000000000000000000000001);
+
+/// Integrates `f` over `[a, b]` using adaptive Gauss-Kronrod (G7/K15) quadrature.
+///
+/// Each panel is evaluated with the 15-point Kronrod rule and its error is estimated
+/// as the difference with the embedded 7-point Gauss rule. A panel is accepted when
+/// that estimate is within `tolerance` times the absolute value of its integral, or
+/// below `ROUNDING_FLOOR` times its width; otherwise it is bisected, up to
+/// `MAX_DEPTH` times. The tolerance is relative, so the integrand should not change
+/// sign inside `[a, b]`.
+/// If `b < a` the result is negated, as usual for a definite integral.
+///
+/// # Arguments
+///
+/// * `f` - The integrand.
+/// * `a` - Lower limit of integration.
+/// * `b` - Upper limit of integration.
+/// * `tolerance` - Relative error target for each panel.
+///
+/// # Returns
+///
+/// The estimated value of the integral as a `Decimal`.
+pub(crate) fn integrate<F>(f: &F, a: Decimal, b: Decimal, tolerance: Decimal) -> Decimal
+where
+    F: Fn(Decimal) -> Decimal,
+{
+    if a == b {
+        return Decimal::ZERO;
+    }
+    adaptive_panel(f, a, b, tolerance, 0)
+}
+
+fn adaptive_panel<F>(f: &F, a: Decimal, b: Decimal, tolerance: Decimal, depth: u32) -> Decimal
+where
+    F: Fn(Decimal) -> Decimal,
+{
+    let (kronrod, gauss) = kronrod_panel(f, a, b);
+    let error = (kronrod - gauss).abs();
+    let floor = ROUNDING_FLOOR * (b - a).abs();
+    if error <= floor.max(tolerance * kronrod.abs()) || depth >= MAX_DEPTH {
+        return kronrod;
+    }
+    let mid = (a + b) / Decimal::TWO;
+    adaptive_panel(f, a, mid, tolerance, depth + 1)
+        + adaptive_panel(f, mid, b, tolerance, depth + 1)
+}
+
+/// Evaluates the 15-point Kronrod and 7-point Gauss rules on a single panel.
+fn kronrod_panel<F>(f: &F, a: Decimal, b: Decimal) -> (Decimal, Decimal)
+where
+    F: Fn(Decimal) -> Decimal,
+{
+    let center = (a + b) / Decimal::TWO;
+    let half_length = (b - a) / Decimal::TWO;
+
+    let f_center = f(center);
+    let mut kronrod = WGK[7] * f_center;
+    let mut gauss = WG[3] * f_center;
+
+    for (i, (&node, &weight)) in XGK.iter().zip(WGK.iter()).take(7).enumerate() {
+        let dx = half_length * node;
+        let sum = f(center - dx) + f(center + dx);
+        kronrod += weight * sum;
+        if i % 2 == 1 {
+            gauss += WG[i / 2] * sum;
+        }
+    }
+
+    (kronrod * half_length, gauss * half_length)
+}
+
+#[cfg(test)]
+mod tests_quadrature {
+    use super::*;
+    use assert_approx_eq::assert_approx_eq;
+    use rust_decimal::MathematicalOps;
+
+    #[test]
+    fn test_polynomial_is_exact() {
+        let result = integrate(&|x: Decimal| x * x * x - x, dec!(0), dec!(2), dec!(1e-24));
+        assert_approx_eq!(result, dec!(2), dec!(1e-24));
+    }
+
+    #[test]
+    fn test_reversed_limits() {
+        let forward = integrate(&|x: Decimal| x * x, dec!(1), dec!(3), dec!(1e-24));
+        let backward = integrate(&|x: Decimal| x * x, dec!(3), dec!(1), dec!(1e-24));
+        assert_eq!(forward, -backward);
+    }
+
+    #[test]
+    fn test_empty_interval() {
+        assert_eq!(
+            integrate(&|x: Decimal| x, dec!(1.5), dec!(1.5), dec!(1e-24)),
+            Decimal::ZERO
+        );
+    }
+
+    #[test]
+    fn test_smooth_non_polynomial() {
+        // Integral of 1/x over [1, e^2] is exactly 2.
+        let upper = Decimal::TWO.exp();
+        let result = integrate(&|x: Decimal| Decimal::ONE / x, dec!(1), upper, dec!(1e-20));
+        assert_approx_eq!(result, dec!(2), dec!(1e-18));
+    }
+}
@@ -3,3 +3,5 @@
    Email: jb@taunais.com
    Date: 10/9/24
 ******************************************************************************/
+
+mod no_free_lunch;
@@ -0,0 +1,266 @@
+/******************************************************************************
+   Author: Joaquín Béjar García
+   Email: jb@taunais.com
+   Date: 10/9/24
+******************************************************************************/
+
+//! Property tests running random sequences of swaps, deposits and withdrawals
+//! against random pools. After every operation the pool must hold exactly what
+//! was paid in minus what was paid out, keep non-negative balances and shares,
+//! and offer no cycle that returns more than it costs.
+
+use arpp::arpp::arbitrage::{run_cycle, Cycle};
+use arpp::arpp::curve::TanhCurve;
+use arpp::arpp::formula::AsymmetricParameters;
+use arpp::arpp::liquidity_pool::LiquidityPool;
+use arpp::arpp::swap::Token;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+/// Number of random pools, each with its own sequence of operations.
+const CASES: u64 = 48;
+
+/// Number of operations run against each pool.
+const OPERATIONS: usize = 30;
+
+/// Relative difference allowed between the total shares and the sum of the
+/// positions.
+const SHARES_TOLERANCE: Decimal = dec!(0.0000000000000000000000001);
+
+const PROVIDERS: [&str; 3] = ["alice", "bob", "carol"];
+
+#[derive(Debug, Clone)]
+enum Op {
+    SwapIn {
+        token: Token,
+        fraction: Decimal,
+    },
+    SwapOut {
+        token: Token,
+        fraction: Decimal,
+    },
+    Deposit {
+        provider: &'static str,
+        fraction_a: Decimal,
+        fraction_b: Decimal,
+    },
+    Withdraw {
+        provider: &'static str,
+        fraction: Decimal,
+    },
+}
+
+/// Returns a fraction between 0.001 and `max_thousandths / 1000`.
+fn fraction(rng: &mut StdRng, max_thousandths: i64) -> Decimal {
+    Decimal::new(rng.gen_range(1..=max_thousandths), 3)
+}
+
+fn token(rng: &mut StdRng) -> Token {
+    if rng.gen_bool(0.5) {
+        Token::A
+    } else {
+        Token::B
+    }
+}
+
+fn random_pool(rng: &mut StdRng) -> LiquidityPool {
+    let p_ref = [dec!(0.01), dec!(1), dec!(250)][rng.gen_range(0..3)];
+    let token_a = Decimal::from(rng.gen_range(100..5000));
+    let token_b = Decimal::from(rng.gen_range(100..5000)) * p_ref;
+    let alpha_up = fraction(rng, 600);
+    let beta_up = Decimal::from(rng.gen_range(1..5));
+    let parameters = if rng.gen_bool(0.5) {
+        AsymmetricParameters::symmetric(alpha_up, beta_up)
+    } else {
+        // A flatter and steeper downside, with the same slope at balance
+        let scale = [dec!(0.5), dec!(0.25)][rng.gen_range(0..2)];
+        AsymmetricParameters::new(alpha_up, alpha_up * scale, beta_up, beta_up / scale)
+    };
+    let mut pool = LiquidityPool::try_new_asymmetric(token_a, token_b, p_ref, parameters)
+        .expect("the generated parameters are safe");
+    pool.set_fee_bps([dec!(0), dec!(5), dec!(30)][rng.gen_range(0..3)])
+        .unwrap();
+    if rng.gen_bool(0.25) {
+        pool.set_pricing_curve(TanhCurve).unwrap();
+    }
+    pool
+}
+
+fn random_op(rng: &mut StdRng) -> Op {
+    match rng.gen_range(0..4) {
+        0 => Op::SwapIn {
+            token: token(rng),
+            fraction: fraction(rng, 300),
+        },
+        1 => Op::SwapOut {
+            token: token(rng),
+            fraction: fraction(rng, 300),
+        },
+        2 => Op::Deposit {
+            provider: PROVIDERS[rng.gen_range(0..PROVIDERS.len())],
+            fraction_a: fraction(rng, 200),
+            fraction_b: fraction(rng, 200),
+        },
+        _ => Op::Withdraw {
+            provider: PROVIDERS[rng.gen_range(0..PROVIDERS.len())],
+            fraction: fraction(rng, 1000),
+        },
+    }
+}
+
+/// Applies `op` to `pool` and returns the net amounts of Token A and Token B paid
+/// into the pool, or `None` if the pool refused the operation.
+fn apply(pool: &mut LiquidityPool, op: &Op) -> Option<(Decimal, Decimal)> {
+    let (token_a, token_b) = pool.get_balances();
+    let balance = |token| match token {
+        Token::A => token_a,
+        Token::B => token_b,
+    };
+    let flows = |token_in, amount_in: Decimal, amount_out: Decimal| match token_in {
+        Token::A => (amount_in, -amount_out),
+        Token::B => (-amount_out, amount_in),
+    };
+    match *op {
+        Op::SwapIn { token, fraction } => pool
+            .swap_exact_in(token, balance(token) * fraction, Decimal::ZERO, None)
+            .ok()
+            .map(|result| flows(result.token_in, result.amount_in, result.amount_out)),
+        Op::SwapOut { token, fraction } => pool
+            .swap_exact_out(token, balance(token) * fraction, Decimal::MAX, None)
+            .ok()
+            .map(|result| flows(result.token_in, result.amount_in, result.amount_out)),
+        Op::Deposit {
+            provider,
+            fraction_a,
+            fraction_b,
+        } => {
+            let (amount_a, amount_b) = (token_a * fraction_a, token_b * fraction_b);
+            pool.deposit(provider, amount_a, amount_b)
+                .ok()
+                .map(|_| (amount_a, amount_b))
+        }
+        Op::Withdraw { provider, fraction } => {
+            let shares = pool.get_position(provider)?.shares * fraction;
+            pool.withdraw(provider, shares)
+                .ok()
+                .map(|(amount_a, amount_b)| (-amount_a, -amount_b))
+        }
+    }
+}
+
+/// Checks the invariants of `pool` after `history`, given the balances the
+/// operations so far should have left.
+fn check_invariants(pool: &LiquidityPool, expected: (Decimal, Decimal), history: &str) {
+    let (token_a, token_b) = pool.get_balances();
+    assert_eq!(
+        (token_a, token_b),
+        expected,
+        "balances differ from the net flows after {}",
+        history
+    );
+    assert!(
+        token_a >= Decimal::ZERO && token_b >= Decimal::ZERO,
+        "negative balance after {}",
+        history
+    );
+    let positions = pool.get_positions();
+    let shares: Decimal = positions.iter().map(|(_, position)| position.shares).sum();
+    // Summing many shares rounds at the 28th significant digit
+    assert!(
+        (shares - pool.get_total_shares()).abs() <= pool.get_total_shares() * SHARES_TOLERANCE,
+        "shares add up to {} rather than {} after {}",
+        shares,
+        pool.get_total_shares(),
+        history
+    );
+    for (provider, position) in positions {
+        assert!(
+            position.shares > Decimal::ZERO
+                && position.amount_a >= Decimal::ZERO
+                && position.amount_b >= Decimal::ZERO
+                && position.amount_a <= token_a
+                && position.amount_b <= token_b,
+            "position of {} is invalid after {}: {:?}",
+            provider,
+            history,
+            position
+        );
+    }
+}
+
+#[test]
+fn test_random_operations_keep_pool_consistent_and_offer_no_free_profit() {
+    for seed in 0..CASES {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut pool = random_pool(&mut rng);
+        let mut expected = pool.get_balances();
+        let mut history = format!("seed {}", seed);
+        for _ in 0..OPERATIONS {
+            let op = random_op(&mut rng);
+            history.push_str(&format!(", {:?}", op));
+            let before = pool.clone();
+            match apply(&mut pool, &op) {
+                Some((flow_a, flow_b)) => {
+                    expected.0 += flow_a;
+                    expected.1 += flow_b;
+                }
+                None => {
+                    // A refused operation leaves the pool untouched
+                    assert_eq!(pool.get_balances(), before.get_balances(), "{}", history);
+                    assert_eq!(pool.get_positions(), before.get_positions(), "{}", history);
+                }
+            }
+            check_invariants(&pool, expected, &history);
+
+            let cycles = Cycle::standard(fraction(&mut rng, 300));
+            let cycle = &cycles[rng.gen_range(0..cycles.len())];
+            if let Ok(outcome) = run_cycle(&pool, cycle) {
+                assert!(
+                    !outcome.is_profitable(),
+                    "{:?} returns {:?} after {}",
+                    cycle,
+                    outcome,
+                    history
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_round_trips_never_return_more_than_paid() {
+    for seed in 0..CASES {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut pool = random_pool(&mut rng);
+        // Move the pool away from balance first, on either side
+        let token_in = token(&mut rng);
+        let (token_a, token_b) = pool.get_balances();
+        let amount = match token_in {
+            Token::A => token_a,
+            Token::B => token_b,
+        } * fraction(&mut rng, 500);
+        if pool
+            .swap_exact_in(token_in, amount, Decimal::ZERO, None)
+            .is_err()
+        {
+            continue;
+        }
+
+        for token_in in [Token::A, Token::B] {
+            let fraction = fraction(&mut rng, 400);
+            let cycle = Cycle::round_trip(token_in, fraction);
+            let Ok(outcome) = run_cycle(&pool, &cycle) else {
+                continue;
+            };
+            assert!(
+                !outcome.is_profitable(),
+                "seed {}: {:?} returns {:?}",
+                seed,
+                cycle,
+                outcome
+            );
+        }
+    }
+}